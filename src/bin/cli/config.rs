@@ -0,0 +1,224 @@
+//! Load settings from a TOML config file as an alternative to flags and environment variables.
+
+use std::{collections::BTreeMap, ffi::OsString, fs, path::Path};
+
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+
+use super::Cli;
+
+/// Contents of a config file.
+///
+/// Each key is the name of a command-line flag without the leading dashes, e.g. `interval = 30` or `cloudflare-api-token = "..."`.
+/// Underscores may be used instead of dashes. Lists are used for flags that accept multiple values.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct ConfigFile {
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<ConfigFile, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("Could not parse config file {}: {}", path.display(), e))
+    }
+
+    /// Convert all settings that were not already set on the command line or through an environment variable into arguments.
+    /// Returns an error if the file contains unknown settings or values of an unsupported type.
+    fn to_args(&self, matches: &ArgMatches) -> Result<Vec<OsString>, String> {
+        let cmd = Cli::command();
+        let mut args = Vec::new();
+        for (key, value) in &self.settings {
+            let long = key.replace('_', "-");
+            let arg = cmd
+                .get_arguments()
+                .find(|a| a.get_long() == Some(long.as_str()))
+                .ok_or_else(|| format!("Unknown setting in config file: {}", key))?;
+            if matches!(
+                matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            ) {
+                continue;
+            }
+
+            let values =
+                match value {
+                    toml::Value::Boolean(true) => {
+                        args.push(format!("--{}", long).into());
+                        continue;
+                    }
+                    toml::Value::Boolean(false) => continue,
+                    // Each element is passed as a separate flag, as not all flags split their values at commas
+                    toml::Value::Array(values) => values
+                        .iter()
+                        .map(value_string)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| format!("Invalid value for {}: {}", key, e))?,
+                    value => vec![value_string(value)
+                        .map_err(|e| format!("Invalid value for {}: {}", key, e))?],
+                };
+            args.extend(
+                values
+                    .into_iter()
+                    .map(|value| format!("--{}={}", long, value).into()),
+            );
+        }
+        Ok(args)
+    }
+}
+
+fn value_string(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.to_owned()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        v => Err(format!("unsupported value {}", v)),
+    }
+}
+
+/// Parse the command line, merging in settings from the file given with `--config` (if any).
+///
+/// Precedence (highest first): command-line flags, environment variables, config file, default values.
+pub fn parse_with_config(args: Vec<OsString>) -> Result<Cli, clap::Error> {
+    // Parse once leniently, as required settings may only be set in the config file
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(args.clone())?;
+    let Some(path) = matches.get_one::<std::path::PathBuf>("config") else {
+        return Cli::try_parse_from(args);
+    };
+
+    let file_args = ConfigFile::load(path)
+        .and_then(|f| f.to_args(&matches))
+        .map_err(|e| Cli::command().error(clap::error::ErrorKind::Io, e))?;
+    // Config file settings go right after the binary name, the remaining arguments are never overridden
+    let mut merged = args;
+    let rest = merged.split_off(1.min(merged.len()));
+    merged.extend(file_args);
+    merged.extend(rest);
+
+    let matches = Cli::command().try_get_matches_from(merged)?;
+    Cli::from_arg_matches(&matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, fs};
+
+    use super::parse_with_config;
+    use crate::cli::{Policy, Provider};
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("clouddns-nat-helper")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect()
+    }
+
+    fn config(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "clouddns-nat-helper-config-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn should_load_settings_from_file() {
+        let path = config(
+            "load",
+            r#"
+source = "fixed"
+ipv4_fixed_address = "203.0.113.1"
+provider = "rfc2136"
+rfc2136-server = "192.0.2.53:53"
+rfc2136-zone = "example.com"
+rfc2136-tsig-key-name = "key"
+rfc2136-tsig-secret = "c2VjcmV0"
+policy = "upsert"
+include-domains = ["a.example.com", "*.b.example.com"]
+dry-run = true
+run-once = false
+"#,
+        );
+
+        let cli = parse_with_config(args(&["--config", &path])).unwrap();
+        assert_eq!(cli.provider, Provider::Rfc2136);
+        assert_eq!(cli.policy, Policy::Upsert);
+        assert_eq!(
+            cli.include_domains,
+            vec!["a.example.com", "*.b.example.com"]
+        );
+        assert!(cli.dry_run);
+        assert!(!cli.run_once);
+        // Not set in the file, uses the default
+        assert_eq!(cli.interval, 60);
+    }
+
+    #[test]
+    fn should_prefer_flags_over_file() {
+        let path = config(
+            "precedence",
+            r#"
+source = "fixed"
+ipv4-fixed-address = "203.0.113.1"
+cloudflare-api-token = "abc"
+interval = 30
+include-domains = ["file.example.com"]
+"#,
+        );
+
+        let cli = parse_with_config(args(&[
+            "--config",
+            &path,
+            "--interval",
+            "10",
+            "--include-domains",
+            "cli.example.com",
+        ]))
+        .unwrap();
+        assert_eq!(cli.interval, 10);
+        assert_eq!(cli.include_domains, vec!["cli.example.com"]);
+    }
+
+    #[test]
+    fn should_pass_list_elements_as_separate_values() {
+        let path = config(
+            "list",
+            r#"
+source = "exec"
+ipv4-exec-command = "sh"
+ipv4-exec-arg = ["-c", "echo 1.2.3.4"]
+cloudflare-api-token = "abc"
+"#,
+        );
+
+        let cli = parse_with_config(args(&["--config", &path])).unwrap();
+        assert_eq!(cli.ipv4_exec_args, vec!["-c", "echo 1.2.3.4"]);
+    }
+
+    #[test]
+    fn should_reject_unknown_settings() {
+        let path = config("unknown", "no-such-setting = 1\n");
+
+        parse_with_config(args(&["--config", &path, "--source", "fixed"])).unwrap_err();
+    }
+
+    #[test]
+    fn should_work_without_config_file() {
+        let cli = parse_with_config(args(&[
+            "--source",
+            "fixed",
+            "--ipv4-fixed-address",
+            "203.0.113.1",
+            "--cloudflare-api-token",
+            "abc",
+        ]))
+        .unwrap();
+        assert_eq!(cli.config, None);
+    }
+}
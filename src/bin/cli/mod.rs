@@ -1,8 +1,17 @@
 #![allow(non_camel_case_types)]
 
+mod config;
+
 use clap::Parser;
-use clouddns_nat_helper::provider::TTL;
-use std::net::Ipv4Addr;
+use clouddns_nat_helper::{provider::TTL, registry::DEFAULT_TXT_PREFIX};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    num::NonZeroUsize,
+    path::PathBuf,
+    str::FromStr,
+};
+
+pub use config::parse_with_config;
 
 macro_rules! env_prefix {
     () => {
@@ -13,6 +22,14 @@ macro_rules! env_prefix {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Load settings from this TOML file. Flags and environment variables take precedence over settings in the file
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = concat!(env_prefix!(), "CONFIG")
+    )]
+    pub config: Option<PathBuf>,
+
     /// Source of the IPv4 address to set in all A records
     #[arg(
         value_enum,
@@ -49,6 +66,46 @@ pub struct Cli {
     #[arg(long, default_value_t = false, action)]
     pub run_once: bool,
 
+    /// Release ownership of all domains owned by this instance, then exit.
+    /// Existing A records are kept, but are no longer managed by this instance
+    #[arg(long, default_value_t = false, action)]
+    pub release_all: bool,
+
+    /// Release ownership of all owned domains that no longer have any A records (e.g. because they were deleted manually), then exit.
+    /// This removes left-over ownership records. Combine with '--dry-run' to only list the affected domains
+    #[arg(long, default_value_t = false, action, conflicts_with = "release_all")]
+    pub prune_orphans: bool,
+
+    /// DANGEROUS: Claim the given domain even if it is owned by other instances, removing their ownership records, then exit.
+    /// Only meant for recovering from conflicting ownership records, e.g. after a botched migration.
+    /// Make sure that no other instance still manages the domain. Combine with '--dry-run' to only check whether the domain can be claimed
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        conflicts_with_all = ["release_all", "prune_orphans"]
+    )]
+    pub force_claim: Option<String>,
+
+    /// Generate the plan for the current records, print it (see '--output') and exit.
+    /// No domains are claimed and no records are changed
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        conflicts_with_all = ["release_all", "prune_orphans", "force_claim"]
+    )]
+    pub plan_only: bool,
+
+    /// Print all domains owned by other tenants or managed externally, along with their owners, then exit.
+    /// Useful for debugging ownership conflicts. No domains are claimed and no records are changed
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        conflicts_with_all = ["release_all", "prune_orphans", "force_claim", "plan_only"]
+    )]
+    pub report_ownership: bool,
+
     /// Time to wait between update operations in seconds
     #[arg(
         short = 'i',
@@ -58,7 +115,54 @@ pub struct Cli {
     )]
     pub interval: u64,
 
-    /// What A record actions are permitted. createonly: create, upsert: create,update, sync: create,update,delete.
+    /// Wait up to this many additional seconds between runs, chosen randomly for each run.
+    /// Spreads out the load of many instances that were started at the same time
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "INTERVAL_JITTER")
+    )]
+    pub interval_jitter: u64,
+
+    /// After a failed run, double the wait time until the next run with each consecutive failure, up to this many seconds.
+    /// The regular interval is used again after a successful run
+    #[arg(
+        long,
+        default_value_t = 600,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "MAX_BACKOFF")
+    )]
+    pub max_backoff: u64,
+
+    /// How many actions to apply in parallel. Registry changes (claiming/releasing domains) are always performed one at a time
+    #[arg(
+        long,
+        default_value_t = NonZeroUsize::MIN,
+        value_name = "ACTIONS",
+        env = concat!(env_prefix!(), "CONCURRENCY")
+    )]
+    pub concurrency: NonZeroUsize,
+
+    /// How often to retry an action that failed with a transient error, such as a network error
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_name = "RETRIES",
+        env = concat!(env_prefix!(), "RETRY_ATTEMPTS")
+    )]
+    pub retry_attempts: u32,
+
+    /// Seconds to wait before retrying a failed action. Doubles with each retry
+    #[arg(
+        long,
+        default_value_t = 2,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "RETRY_DELAY")
+    )]
+    pub retry_delay: u64,
+
+    /// What A record actions are permitted. createonly: create, upsert: create,update, sync: create,update,delete, deleteonly: delete.
     #[arg(
         value_enum,
         long,
@@ -67,18 +171,237 @@ pub struct Cli {
     )]
     pub policy: Policy,
 
+    /// How to remove A records of owned domains that no longer have AAAA records.
+    /// delete: delete the records and release the domain, park: point the records at '--park-address' and keep ownership
+    #[arg(
+        value_enum,
+        long,
+        default_value_t = DeleteMode::Delete,
+        env = concat!(env_prefix!(), "DELETE_MODE")
+    )]
+    pub delete_mode: DeleteMode,
+
+    /// Parking address for A records when using '--delete-mode park'
+    #[arg(
+        long,
+        required_if_eq("delete_mode", "park"),
+        value_name = "IPV4_ADDRESS",
+        env = concat!(env_prefix!(), "PARK_ADDRESS")
+    )]
+    pub park_address: Option<Ipv4Addr>,
+
+    /// With the createonly policy, do not recreate missing A records for domains that are already owned.
+    /// By default, owned domains always get their A record back
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "CREATEONLY_SKIP_OWNED")
+    )]
+    pub createonly_skip_owned: bool,
+
+    /// Only manage A records for these domains, as a comma-separated string.
+    /// A leading '*.' matches all subdomains. Manages all domains by default
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "INCLUDE_DOMAINS")
+    )]
+    pub include_domains: Vec<String>,
+
+    /// Never manage A records for these domains, as a comma-separated string.
+    /// A leading '*.' matches all subdomains. Takes precedence over '--include-domains'
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "EXCLUDE_DOMAINS")
+    )]
+    pub exclude_domains: Vec<String>,
+
+    /// Manage wildcard domains (e.g. '*.example.com') like any other domain. By default, they are never claimed, updated or deleted
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "MANAGE_WILDCARDS")
+    )]
+    pub manage_wildcards: bool,
+
+    /// Only create or update A records for the domains listed in this file, one per line. Unlike '--include-domains', domains must match exactly.
+    /// Owned domains that are not listed are still deleted once they lose their AAAA records
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = concat!(env_prefix!(), "DOMAINS_FILE")
+    )]
+    pub domains_file: Option<PathBuf>,
+
+    /// Do not update A records that were modified within their TTL, deferring the update to a later run.
+    /// Reduces churn if the IPv4 address changes rapidly
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "DEFER_RECENT_UPDATES")
+    )]
+    pub defer_recent_updates: bool,
+
+    /// Do not remove other A records from owned domains that already have A records for all target addresses
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "KEEP_EXTRA_ADDRESSES")
+    )]
+    pub keep_extra_addresses: bool,
+
+    /// Also manage domains whose AAAA records are all non-global (unique local, link-local or loopback addresses).
+    /// By default, such domains are skipped with a warning, as publishing A records for them is usually a misconfiguration
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "ALLOW_NON_GLOBAL_AAAA")
+    )]
+    pub allow_non_global_aaaa: bool,
+
     /// Do not make any changes to the DNS records, only show what would happen
     #[arg(long, short = 'd', action, default_value_t = false)]
     pub dry_run: bool,
 
-    /// Optionally set a TTL for newly created records.
+    /// In dry-run mode, exit with an error if the plan would delete records.
+    /// Optionally takes the number of deletions to tolerate (default: 0)
+    #[arg(
+        long,
+        value_name = "MAX_DELETES",
+        num_args = 0..=1,
+        default_missing_value = "0",
+        env = concat!(env_prefix!(), "FAIL_ON_DESTRUCTIVE")
+    )]
+    pub fail_on_destructive: Option<usize>,
+
+    /// Refuse to apply a plan with more than this many actions, e.g. to protect against a faulty address source rewriting every record at once.
+    /// The run fails without changing anything. Use '--force' to apply such a plan anyway
+    #[arg(
+        long,
+        value_name = "N",
+        env = concat!(env_prefix!(), "MAX_CHANGES")
+    )]
+    pub max_changes: Option<usize>,
+
+    /// Apply plans even if they exceed '--max-changes'
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "FORCE")
+    )]
+    pub force: bool,
+
+    /// After applying each action, read the records of the domain again and fail the action if the provider did not persist the change.
+    /// Costs an additional API request per action. Has no effect in dry-run mode
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "VERIFY")
+    )]
+    pub verify: bool,
+
+    /// How to display the generated plan when running in dry-run mode.
+    /// text: log each action, table: print a summary table, json: print the plan as JSON for auditing
+    #[arg(
+        value_enum,
+        long,
+        short = 'o',
+        default_value_t = OutputFormat::Text,
+        env = concat!(env_prefix!(), "OUTPUT")
+    )]
+    pub output: OutputFormat,
+
+    /// In dry-run mode, print a diff of the current and desired A records of each affected domain
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "SHOW_DIFF")
+    )]
+    pub show_diff: bool,
+
+    /// In dry-run mode, create and delete a throwaway TXT record to confirm that the provider credentials allow writing records.
+    /// A records are never touched. Only supported by the cloudflare provider
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "WRITE_PROBE")
+    )]
+    pub write_probe: bool,
+
+    /// Stream events (applied actions and address changes) as newline-delimited JSON to clients of a Unix socket created at this path
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = concat!(env_prefix!(), "EVENT_SOCKET")
+    )]
+    pub event_socket: Option<PathBuf>,
+
+    /// Serve Prometheus metrics (applied/failed actions, runs, last run timestamp) over HTTP on this address, e.g. 0.0.0.0:9100
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        env = concat!(env_prefix!(), "METRICS_LISTEN")
+    )]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Serve a health check over HTTP on this address, e.g. 0.0.0.0:8080.
+    /// Responds with 200 while the daemon is healthy and 503 if the last run failed or no run succeeded within --health-max-age
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        env = concat!(env_prefix!(), "HEALTH_LISTEN")
+    )]
+    pub health_listen: Option<SocketAddr>,
+
+    /// Maximum number of seconds since the last successful run before the health check fails.
+    /// Defaults to three times the update interval (plus jitter)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "HEALTH_MAX_AGE")
+    )]
+    pub health_max_age: Option<u64>,
+
+    /// POST a JSON summary of each run that changed records to this URL
+    #[arg(
+        long,
+        value_name = "URL",
+        env = concat!(env_prefix!(), "WEBHOOK_URL")
+    )]
+    pub webhook_url: Option<String>,
+
+    /// Also send webhook notifications in dry-run mode
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        requires = "webhook_url",
+        env = concat!(env_prefix!(), "WEBHOOK_DRY_RUN")
+    )]
+    pub webhook_dry_run: bool,
+
+    /// Optionally set a TTL for newly created records, or 'auto' to let the provider choose one (Cloudflare only).
     /// Will use the provider default if no specified
     #[arg(
         long,
         value_name = "TTL",
         env = concat!(env_prefix!(), "RECORD_TTL"),
     )]
-    pub record_ttl: Option<TTL>,
+    pub record_ttl: Option<RecordTtl>,
 
     /// Cloudflare API Token to authenticate with
     #[arg(
@@ -99,15 +422,226 @@ pub struct Cli {
     )]
     pub cloudflare_proxied: bool,
 
-    /// Ipv4 address to put into all A records when using the 'fixed` address source
+    /// Always proxy the A records of these domains, as a comma-separated string. Overrides '--cloudflare-proxied'.
+    /// Patterns starting with '*.' match all subdomains
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "CLOUDFLARE_PROXIED_DOMAINS")
+    )]
+    pub cloudflare_proxied_domains: Vec<String>,
+
+    /// Never proxy the A records of these domains, as a comma-separated string. Overrides '--cloudflare-proxied' and '--cloudflare-proxied-domains'.
+    /// Patterns starting with '*.' match all subdomains
+    #[arg(
+        long,
+        value_name = "DOMAIN",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "CLOUDFLARE_UNPROXIED_DOMAINS")
+    )]
+    pub cloudflare_unproxied_domains: Vec<String>,
+
+    /// Only manage records in these Cloudflare zones, as a comma-separated string (e.g. 'example.com,example.org').
+    /// Manages all zones accessible with the API token by default
+    #[arg(
+        long,
+        value_name = "ZONE",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "CLOUDFLARE_INCLUDE_ZONES")
+    )]
+    pub cloudflare_include_zones: Vec<String>,
+
+    /// Never manage records in these Cloudflare zones, as a comma-separated string
+    #[arg(
+        long,
+        value_name = "ZONE",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "CLOUDFLARE_EXCLUDE_ZONES")
+    )]
+    pub cloudflare_exclude_zones: Vec<String>,
+
+    /// Skip verifying the Cloudflare API token on startup
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "CLOUDFLARE_SKIP_TOKEN_VERIFICATION")
+    )]
+    pub cloudflare_skip_token_verification: bool,
+
+    /// How often to retry a Cloudflare API request that was rejected due to rate-limiting
+    #[arg(
+        long,
+        default_value_t = 3,
+        value_name = "RETRIES",
+        env = concat!(env_prefix!(), "CLOUDFLARE_MAX_RETRIES")
+    )]
+    pub cloudflare_max_retries: u32,
+
+    /// Seconds to wait before retrying a rate-limited Cloudflare API request. Doubles with each retry
+    #[arg(
+        long,
+        default_value_t = 5,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "CLOUDFLARE_RETRY_DELAY")
+    )]
+    pub cloudflare_retry_delay: u64,
+
+    /// Timeout in seconds for each Cloudflare API request
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "CLOUDFLARE_HTTP_TIMEOUT")
+    )]
+    pub cloudflare_http_timeout: Option<u64>,
+
+    /// Send Cloudflare API requests to this base URL instead of the official API, e.g. a proxy or mock server
+    #[arg(
+        long,
+        value_name = "URL",
+        env = concat!(env_prefix!(), "CLOUDFLARE_API_URL")
+    )]
+    pub cloudflare_api_url: Option<String>,
+
+    /// How many Cloudflare zones to list records for in parallel
+    #[arg(
+        long,
+        default_value = "4",
+        value_name = "ZONES",
+        env = concat!(env_prefix!(), "CLOUDFLARE_MAX_ZONE_FETCHES")
+    )]
+    pub cloudflare_max_zone_fetches: NonZeroUsize,
+
+    /// DigitalOcean personal access token with read and write access to domains
+    #[arg(
+        long,
+        required_if_eq("provider", "digitalocean"),
+        value_name = "API_TOKEN",
+        env = concat!(env_prefix!(), "DIGITALOCEAN_API_TOKEN")
+    )]
+    pub digitalocean_api_token: Option<String>,
+
+    /// Timeout in seconds for each DigitalOcean API request
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "DIGITALOCEAN_HTTP_TIMEOUT")
+    )]
+    pub digitalocean_http_timeout: Option<u64>,
+
+    /// Send DigitalOcean API requests to this base URL instead of the official API, e.g. a proxy or mock server
+    #[arg(
+        long,
+        value_name = "URL",
+        env = concat!(env_prefix!(), "DIGITALOCEAN_API_URL")
+    )]
+    pub digitalocean_api_url: Option<String>,
+
+    /// Njalla API token, must be allowed to list, add and remove records
+    #[arg(
+        long,
+        required_if_eq("provider", "njalla"),
+        value_name = "API_TOKEN",
+        env = concat!(env_prefix!(), "NJALLA_API_TOKEN")
+    )]
+    pub njalla_api_token: Option<String>,
+
+    /// Timeout in seconds for each Njalla API request
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "NJALLA_HTTP_TIMEOUT")
+    )]
+    pub njalla_http_timeout: Option<u64>,
+
+    /// Send Njalla API requests to this URL instead of the official API, e.g. a proxy or mock server
+    #[arg(
+        long,
+        value_name = "URL",
+        env = concat!(env_prefix!(), "NJALLA_API_URL")
+    )]
+    pub njalla_api_url: Option<String>,
+
+    /// Address of the DNS server to send RFC2136 updates to, as 'ip:port'
+    #[arg(
+        long,
+        required_if_eq("provider", "rfc2136"),
+        value_name = "SERVER",
+        env = concat!(env_prefix!(), "RFC2136_SERVER")
+    )]
+    pub rfc2136_server: Option<SocketAddr>,
+
+    /// The zone to manage using RFC2136 updates
+    #[arg(
+        long,
+        required_if_eq("provider", "rfc2136"),
+        value_name = "ZONE",
+        env = concat!(env_prefix!(), "RFC2136_ZONE")
+    )]
+    pub rfc2136_zone: Option<String>,
+
+    /// Name of the TSIG key used to authenticate RFC2136 updates and zone transfers
+    #[arg(
+        long,
+        required_if_eq("provider", "rfc2136"),
+        value_name = "KEY_NAME",
+        env = concat!(env_prefix!(), "RFC2136_TSIG_KEY_NAME")
+    )]
+    pub rfc2136_tsig_key_name: Option<String>,
+
+    /// Base64-encoded secret of the TSIG key. You may want to pass this via an environment variable
+    #[arg(
+        long,
+        required_if_eq("provider", "rfc2136"),
+        value_name = "SECRET",
+        env = concat!(env_prefix!(), "RFC2136_TSIG_SECRET")
+    )]
+    pub rfc2136_tsig_secret: Option<String>,
+
+    /// Algorithm of the TSIG key
+    #[arg(
+        value_enum,
+        long,
+        default_value_t = TsigAlgorithm::HmacSha256,
+        env = concat!(env_prefix!(), "RFC2136_TSIG_ALGORITHM")
+    )]
+    pub rfc2136_tsig_algorithm: TsigAlgorithm,
+
+    /// Path of the zone file to manage when using the 'zonefile' provider
+    #[arg(
+        long,
+        required_if_eq("provider", "zonefile"),
+        value_name = "PATH",
+        env = concat!(env_prefix!(), "ZONEFILE_PATH")
+    )]
+    pub zonefile_path: Option<PathBuf>,
+
+    /// The zone stored in the zone file, used for relative names until the file sets an $ORIGIN
+    #[arg(
+        long,
+        required_if_eq("provider", "zonefile"),
+        value_name = "ZONE",
+        env = concat!(env_prefix!(), "ZONEFILE_ORIGIN")
+    )]
+    pub zonefile_origin: Option<String>,
+
+    /// Ipv4 address(es) to put into all A records when using the 'fixed` address source, as a comma-separated string.
+    /// Multiple addresses result in multiple A records per domain (round-robin)
     #[arg(
         long,
         required_if_eq("source", "fixed"),
         value_name = "IPV4_ADDRESS",
+        use_value_delimiter = true,
+        value_delimiter = ',',
         env = concat!(env_prefix!(), "IPV4_FIXED_ADDRESS"),
         conflicts_with = "ipv4_hostname"
     )]
-    pub ipv4_fixed_address: Option<Ipv4Addr>,
+    pub ipv4_fixed_address: Vec<Ipv4Addr>,
 
     /// Resolve this hostname to get the Ipv4 address to put into a records.
     /// Only has an effect if 'source' == 'hostname'
@@ -121,6 +655,7 @@ pub struct Cli {
     pub ipv4_hostname: Option<String>,
 
     /// List of DNS servers to query when resolving 'ipv4_hostname', as a comma-separated string.
+    /// Pass 'system' to use the nameservers from /etc/resolv.conf instead.
     /// Only has an effect if 'source' == 'hostname'
     #[arg(
         long,
@@ -131,7 +666,121 @@ pub struct Cli {
         conflicts_with = "ipv4_fixed_address",
         env = concat!(env_prefix!(), "IPV4_HOSTNAME_DNS_SERVERS")
     )]
-    pub ipv4_hostname_dns_servers: Vec<Ipv4Addr>,
+    pub ipv4_hostname_dns_servers: Vec<DnsServer>,
+
+    /// Timeout in seconds for each DNS query when resolving 'ipv4_hostname'.
+    /// Only has an effect if 'source' == 'hostname'
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 5,
+        env = concat!(env_prefix!(), "IPV4_HOSTNAME_TIMEOUT")
+    )]
+    pub ipv4_hostname_timeout: u64,
+
+    /// How often to attempt resolving 'ipv4_hostname' before failing, with a short jittered delay between attempts.
+    /// Only has an effect if 'source' == 'hostname'
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 3,
+        env = concat!(env_prefix!(), "IPV4_HOSTNAME_ATTEMPTS")
+    )]
+    pub ipv4_hostname_attempts: u32,
+
+    /// How to send DNS queries when resolving 'ipv4_hostname'. 'tls' uses DNS-over-TLS on port 853 and requires '--ipv4-hostname-tls-name'.
+    /// Only has an effect if 'source' == 'hostname'
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = HostnameTransport::Udp,
+        env = concat!(env_prefix!(), "IPV4_HOSTNAME_TRANSPORT")
+    )]
+    pub ipv4_hostname_transport: HostnameTransport,
+
+    /// Name that the certificates of the DNS servers must be valid for when using DNS-over-TLS, e.g. 'dns.google'
+    #[arg(
+        long,
+        required_if_eq("ipv4_hostname_transport", "tls"),
+        value_name = "NAME",
+        env = concat!(env_prefix!(), "IPV4_HOSTNAME_TLS_NAME")
+    )]
+    pub ipv4_hostname_tls_name: Option<String>,
+
+    /// STUN server to query for the public Ipv4 address, as 'host:port' (e.g. 'stun.l.google.com:19302').
+    /// Only has an effect if 'source' == 'stun'
+    #[arg(
+        long,
+        required_if_eq("source", "stun"),
+        value_name = "SERVER",
+        env = concat!(env_prefix!(), "IPV4_STUN_SERVER")
+    )]
+    pub ipv4_stun_server: Option<String>,
+
+    /// WARNING: runs an arbitrary command. Command to run to get the Ipv4 address, it must print a single address to stdout.
+    /// Only has an effect if 'source' == 'exec'
+    #[arg(
+        long,
+        required_if_eq("source", "exec"),
+        value_name = "COMMAND",
+        env = concat!(env_prefix!(), "IPV4_EXEC_COMMAND")
+    )]
+    pub ipv4_exec_command: Option<String>,
+
+    /// Argument to pass to 'ipv4_exec_command', can be specified multiple times.
+    /// Only has an effect if 'source' == 'exec'
+    #[arg(long = "ipv4-exec-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub ipv4_exec_args: Vec<String>,
+
+    /// Name of the environment variable to read the Ipv4 address from.
+    /// Only has an effect if 'source' == 'env'
+    #[arg(
+        long,
+        default_value = "WAN_IPV4",
+        value_name = "VAR",
+        env = concat!(env_prefix!(), "IPV4_ENV_VAR")
+    )]
+    pub ipv4_env_var: String,
+
+    /// Refuse to use private, shared (CGNAT) and otherwise reserved Ipv4 addresses returned by the source
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "IPV4_REJECT_RESERVED")
+    )]
+    pub ipv4_reject_reserved: bool,
+
+    /// Reserved ranges to accept anyway when using '--ipv4-reject-reserved', as a comma-separated string
+    #[arg(
+        value_enum,
+        long,
+        value_name = "RANGE",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "IPV4_ALLOW_RESERVED")
+    )]
+    pub ipv4_allow_reserved: Vec<ReservedRange>,
+
+    /// Where to keep track of domain ownership. txt: ownership records in the DNS zone itself, file: a local JSON file,
+    /// sqlite: a local SQLite database (only available if built with the 'sqlite' feature).
+    /// The file and sqlite registries require '--registry-path'
+    #[arg(
+        value_enum,
+        long,
+        default_value_t = Registry::Txt,
+        env = concat!(env_prefix!(), "REGISTRY")
+    )]
+    pub registry: Registry,
+
+    /// Path to the ownership file or database when using '--registry file' or '--registry sqlite'. Created if it does not exist yet
+    #[arg(
+        long,
+        required_if_eq_any([("registry", "file"), ("registry", "sqlite")]),
+        value_name = "PATH",
+        env = concat!(env_prefix!(), "REGISTRY_PATH")
+    )]
+    pub registry_path: Option<PathBuf>,
 
     /// Unique identifier (tenant) to use for the registry to identify this instance of nat-helper
     #[arg(
@@ -141,6 +790,66 @@ pub struct Cli {
         env = concat!(env_prefix!(), "REGISTRY_TENANT")
     )]
     pub registry_tenant: String,
+
+    /// Prefix of the ownership records created by the registry.
+    /// Only change this if you need to match the ownership records of another tool, as existing records with the old prefix are no longer recognized
+    #[arg(
+        long,
+        default_value = DEFAULT_TXT_PREFIX,
+        value_name = "PREFIX",
+        env = concat!(env_prefix!(), "REGISTRY_TXT_PREFIX")
+    )]
+    pub registry_txt_prefix: String,
+
+    /// Store ownership records in this subdomain of each managed domain instead of the domain itself,
+    /// e.g. with '_clouddns', ownership of 'www.example.com' is stored at '_clouddns.www.example.com'.
+    /// Only supported with the txt ownership backend
+    #[arg(
+        long,
+        value_name = "LABEL",
+        env = concat!(env_prefix!(), "REGISTRY_TXT_SUBDOMAIN")
+    )]
+    pub registry_txt_subdomain: Option<String>,
+
+    /// Where the registry stores ownership information. txt: separate TXT records, native: directly on the A records as record comments (cloudflare only)
+    #[arg(
+        value_enum,
+        long,
+        default_value_t = OwnershipBackend::Txt,
+        env = concat!(env_prefix!(), "OWNERSHIP_BACKEND")
+    )]
+    pub ownership_backend: OwnershipBackend,
+
+    /// Take over unowned domains whose existing A records only point to these addresses, as a comma-separated string.
+    /// By default, any existing A record prevents nat-helper from managing a domain
+    #[arg(
+        long,
+        value_name = "IPV4_ADDRESS",
+        use_value_delimiter = true,
+        value_delimiter = ',',
+        env = concat!(env_prefix!(), "REGISTRY_ADOPT_ADDRESSES")
+    )]
+    pub registry_adopt_addresses: Vec<Ipv4Addr>,
+
+    /// Add a last-seen timestamp to ownership records and take over domains whose ownership records have not been renewed for this many seconds.
+    /// Owned domains are renewed on every run, so this should be much larger than the update interval.
+    /// Only supported with the txt ownership backend
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = concat!(env_prefix!(), "REGISTRY_STALE_AFTER")
+    )]
+    pub registry_stale_after: Option<u64>,
+
+    /// Before claiming a domain, check again that it still has AAAA records.
+    /// Prevents creating A records for domains whose AAAA records were removed during a run, but requires an additional request per claimed domain
+    #[arg(
+        long,
+        action,
+        default_value_t = false,
+        env = concat!(env_prefix!(), "REGISTRY_VERIFY_AAAA")
+    )]
+    pub registry_verify_aaaa: bool,
 }
 
 use clap::ValueEnum;
@@ -151,6 +860,101 @@ use log::LevelFilter;
 pub enum Ipv4AddressSource {
     Hostname,
     Fixed,
+    Stun,
+    /// Runs an arbitrary user-provided command, see '--ipv4-exec-command'
+    Exec,
+    /// Reads the address from an environment variable, see '--ipv4-env-var'
+    Env,
+}
+
+/// A DNS server to query when resolving a hostname
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DnsServer {
+    /// Use the nameservers configured on the system
+    System,
+    Addr(Ipv4Addr),
+}
+impl FromStr for DnsServer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(DnsServer::System),
+            s => s
+                .parse()
+                .map(DnsServer::Addr)
+                .map_err(|e| format!("{} (expected an IPv4 address or 'system')", e)),
+        }
+    }
+}
+
+/// TTL of newly created records
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RecordTtl {
+    /// Let the provider choose the TTL
+    Auto,
+    Fixed(TTL),
+}
+impl FromStr for RecordTtl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(RecordTtl::Auto),
+            s => s
+                .parse()
+                .map(RecordTtl::Fixed)
+                .map_err(|e| format!("{} (expected a TTL in seconds or 'auto')", e)),
+        }
+    }
+}
+impl From<RecordTtl> for clouddns_nat_helper::provider::RecordTtl {
+    fn from(value: RecordTtl) -> Self {
+        match value {
+            RecordTtl::Auto => clouddns_nat_helper::provider::RecordTtl::Auto,
+            RecordTtl::Fixed(ttl) => clouddns_nat_helper::provider::RecordTtl::Fixed(ttl),
+        }
+    }
+}
+
+/// How to send DNS queries when resolving a hostname
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum HostnameTransport {
+    Udp,
+    Tcp,
+    /// DNS-over-TLS
+    Tls,
+}
+
+/// Non-global Ipv4 address ranges
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum ReservedRange {
+    Private,
+    Shared,
+    Loopback,
+    LinkLocal,
+    Documentation,
+    Unspecified,
+    Broadcast,
+    Multicast,
+}
+impl From<ReservedRange> for clouddns_nat_helper::ipv4source::ReservedRange {
+    fn from(value: ReservedRange) -> Self {
+        match value {
+            ReservedRange::Private => clouddns_nat_helper::ipv4source::ReservedRange::Private,
+            ReservedRange::Shared => clouddns_nat_helper::ipv4source::ReservedRange::Shared,
+            ReservedRange::Loopback => clouddns_nat_helper::ipv4source::ReservedRange::Loopback,
+            ReservedRange::LinkLocal => clouddns_nat_helper::ipv4source::ReservedRange::LinkLocal,
+            ReservedRange::Documentation => {
+                clouddns_nat_helper::ipv4source::ReservedRange::Documentation
+            }
+            ReservedRange::Unspecified => {
+                clouddns_nat_helper::ipv4source::ReservedRange::Unspecified
+            }
+            ReservedRange::Broadcast => clouddns_nat_helper::ipv4source::ReservedRange::Broadcast,
+            ReservedRange::Multicast => clouddns_nat_helper::ipv4source::ReservedRange::Multicast,
+        }
+    }
 }
 
 /// Used to set the applications loglevel
@@ -174,6 +978,18 @@ impl From<Loglevel> for LevelFilter {
         }
     }
 }
+#[cfg(feature = "tracing")]
+impl From<Loglevel> for tracing_subscriber::filter::LevelFilter {
+    fn from(ll: Loglevel) -> Self {
+        match ll {
+            Loglevel::Error => tracing_subscriber::filter::LevelFilter::ERROR,
+            Loglevel::Warn => tracing_subscriber::filter::LevelFilter::WARN,
+            Loglevel::Info => tracing_subscriber::filter::LevelFilter::INFO,
+            Loglevel::Debug => tracing_subscriber::filter::LevelFilter::DEBUG,
+            Loglevel::Trace => tracing_subscriber::filter::LevelFilter::TRACE,
+        }
+    }
+}
 
 /// What actions to allow
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
@@ -181,6 +997,7 @@ pub enum Policy {
     CreateOnly,
     Upsert,
     Sync,
+    DeleteOnly,
 }
 impl From<Policy> for clouddns_nat_helper::plan::Policy {
     fn from(value: Policy) -> Self {
@@ -188,12 +1005,74 @@ impl From<Policy> for clouddns_nat_helper::plan::Policy {
             Policy::CreateOnly => clouddns_nat_helper::plan::Policy::CreateOnly,
             Policy::Upsert => clouddns_nat_helper::plan::Policy::Upsert,
             Policy::Sync => clouddns_nat_helper::plan::Policy::Sync,
+            Policy::DeleteOnly => clouddns_nat_helper::plan::Policy::DeleteOnly,
         }
     }
 }
 
-/// Which dns provider to use. Currently only contains Cloudflare
+/// How to present the generated plan in dry-run mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Table,
+    Json,
+}
+
+/// How to remove records of domains that lost their AAAA records
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum DeleteMode {
+    Delete,
+    Park,
+}
+
+/// Which registry to use for tracking domain ownership
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Registry {
+    Txt,
+    File,
+    Sqlite,
+}
+
+/// Where to store ownership information
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum OwnershipBackend {
+    Txt,
+    Native,
+}
+impl From<OwnershipBackend> for clouddns_nat_helper::registry::OwnershipBackend {
+    fn from(value: OwnershipBackend) -> Self {
+        match value {
+            OwnershipBackend::Txt => clouddns_nat_helper::registry::OwnershipBackend::Txt,
+            OwnershipBackend::Native => clouddns_nat_helper::registry::OwnershipBackend::Native,
+        }
+    }
+}
+
+/// Which dns provider to use
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
 pub enum Provider {
     Cloudflare,
+    #[value(name = "digitalocean")]
+    DigitalOcean,
+    Njalla,
+    Rfc2136,
+    #[value(name = "zonefile")]
+    ZoneFile,
+}
+
+/// Algorithm of a TSIG key
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+impl From<TsigAlgorithm> for clouddns_nat_helper::provider::TsigAlgorithm {
+    fn from(value: TsigAlgorithm) -> Self {
+        match value {
+            TsigAlgorithm::HmacSha256 => clouddns_nat_helper::provider::TsigAlgorithm::HmacSha256,
+            TsigAlgorithm::HmacSha384 => clouddns_nat_helper::provider::TsigAlgorithm::HmacSha384,
+            TsigAlgorithm::HmacSha512 => clouddns_nat_helper::provider::TsigAlgorithm::HmacSha512,
+        }
+    }
 }
@@ -1,46 +1,212 @@
 mod cli;
+mod events;
 mod executor;
+mod health;
+mod http;
+mod metrics;
+mod notify;
 
 use core::panic;
-use std::net::{IpAddr, SocketAddr};
-
-use clap::Parser;
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    process::ExitCode,
+    sync::Arc,
+};
 
-use env_logger::Builder;
-use itertools::Itertools;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
     task::{self},
     time::{sleep, Duration},
 };
 
+#[cfg(feature = "sqlite")]
+use clouddns_nat_helper::registry::{SqliteRegistry, SqliteRegistryConfig};
 use clouddns_nat_helper::{
     ipv4source::{self, Ipv4Source, SourceError},
+    plan::{parse_domain_list, DeleteMode, DomainFilter, Plan, PlanOptions},
     provider::{self, Provider, ProviderError},
-    registry::{ARegistry, RegistryError, TxtRegistry},
+    registry::{
+        ARegistry, FileRegistry, FileRegistryConfig, RegistryError, TxtRegistry, TxtRegistryConfig,
+    },
 };
 
 use cli::Cli;
-use executor::Executor;
+use events::EventSocket;
+use executor::{
+    address_change_message, backoff_interval, jittered_interval, run_exit_code, Executor,
+    RetryPolicy, RunResult, EXIT_ERROR,
+};
+use health::Health;
+use metrics::Metrics;
+use notify::{notify_changes, WebhookNotifier};
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), String> {
-    let cli = Cli::parse();
+async fn main() -> ExitCode {
+    let cli = cli::parse_with_config(std::env::args_os().collect()).unwrap_or_else(|e| e.exit());
+
+    #[cfg(not(feature = "tracing"))]
+    env_logger::Builder::new()
+        .filter_level(cli.loglevel.into())
+        .init();
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(cli.loglevel)
+        .init();
+
+    let events = match &cli.event_socket {
+        Some(path) => match EventSocket::bind(path) {
+            Ok(socket) => Some(Arc::new(socket)),
+            Err(e) => {
+                error!("Could not create event socket {}: {}", path.display(), e);
+                return ExitCode::from(EXIT_ERROR);
+            }
+        },
+        None => None,
+    };
+
+    let metrics = match cli.metrics_listen {
+        Some(addr) => {
+            let metrics = Arc::new(Metrics::default());
+            match metrics.serve(addr) {
+                Ok(addr) => info!("Serving metrics on http://{}/metrics", addr),
+                Err(e) => {
+                    error!("Could not start metrics exporter on {}: {}", addr, e);
+                    return ExitCode::from(EXIT_ERROR);
+                }
+            }
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    let health = match cli.health_listen {
+        Some(addr) => {
+            let max_age = cli
+                .health_max_age
+                .unwrap_or(3 * (cli.interval + cli.interval_jitter));
+            let health = Arc::new(Health::new(Duration::from_secs(max_age)));
+            match health.serve(addr) {
+                Ok(addr) => info!("Serving health check on http://{}/", addr),
+                Err(e) => {
+                    error!("Could not start health check on {}: {}", addr, e);
+                    return ExitCode::from(EXIT_ERROR);
+                }
+            }
+            Some(health)
+        }
+        None => None,
+    };
+
+    if cli.plan_only {
+        return match task::spawn_blocking(move || plan_only(&cli)).await {
+            Ok(Ok(_)) => ExitCode::SUCCESS,
+            Ok(Err(_)) => ExitCode::from(EXIT_ERROR),
+            Err(_) => {
+                error!("Task panicked, aborting...");
+                panic!();
+            }
+        };
+    }
+
+    if cli.report_ownership {
+        return match task::spawn_blocking(move || report_ownership(&cli)).await {
+            Ok(Ok(_)) => ExitCode::SUCCESS,
+            Ok(Err(_)) => ExitCode::from(EXIT_ERROR),
+            Err(_) => {
+                error!("Task panicked, aborting...");
+                panic!();
+            }
+        };
+    }
 
-    Builder::new().filter_level(cli.loglevel.into()).init();
+    if cli.release_all {
+        return match task::spawn_blocking(move || release_all(&cli)).await {
+            Ok(Ok(_)) => ExitCode::SUCCESS,
+            Ok(Err(_)) => ExitCode::from(EXIT_ERROR),
+            Err(_) => {
+                error!("Task panicked, aborting...");
+                panic!();
+            }
+        };
+    }
+
+    if cli.prune_orphans {
+        return match task::spawn_blocking(move || prune_orphans(&cli)).await {
+            Ok(Ok(_)) => ExitCode::SUCCESS,
+            Ok(Err(_)) => ExitCode::from(EXIT_ERROR),
+            Err(_) => {
+                error!("Task panicked, aborting...");
+                panic!();
+            }
+        };
+    }
+
+    if let Some(domain) = cli.force_claim.clone() {
+        return match task::spawn_blocking(move || force_claim(&cli, &domain)).await {
+            Ok(Ok(_)) => ExitCode::SUCCESS,
+            Ok(Err(_)) => ExitCode::from(EXIT_ERROR),
+            Err(_) => {
+                error!("Task panicked, aborting...");
+                panic!();
+            }
+        };
+    }
+
+    // Set once SIGINT or SIGTERM is received. The current run is always completed before exiting
+    let (shutdown_tx, mut shutdown) = watch::channel(false);
+    task::spawn(async move {
+        match shutdown_signal().await {
+            Ok(_) => {
+                info!("Received shutdown signal, exiting after the current run");
+                let _ = shutdown_tx.send(true);
+            }
+            Err(e) => warn!("Could not listen for shutdown signals: {}", e),
+        }
+    });
+
+    // Addresses published during the last successful run, used to detect address changes
+    let mut last_addrs: Option<Vec<Ipv4Addr>> = None;
+    let mut consecutive_failures: u32 = 0;
 
     loop {
         let job_cfg = cli.clone();
+        let job_last_addrs = last_addrs.clone();
+        let job_events = events.clone();
+        let job_metrics = metrics.clone();
 
         trace!("Starting worker thread");
-        let r = task::spawn_blocking(|| run_job(job_cfg)).await;
+        let r =
+            task::spawn_blocking(move || run_job(job_cfg, job_last_addrs, job_events, job_metrics))
+                .await;
         match r {
             Ok(r) => {
-                if r.is_err() {
-                    error!("Last task completed with errors")
+                if let Some(health) = &health {
+                    health.record(r.is_ok());
+                }
+                consecutive_failures = if r.is_ok() {
+                    0
+                } else {
+                    consecutive_failures.saturating_add(1)
+                };
+                match &r {
+                    Ok(res) => {
+                        last_addrs = Some(res.target_addrs.to_owned());
+                        if !res.failures.is_empty() {
+                            error!(
+                                "{} action(s) failed during the last run",
+                                res.failures.len()
+                            );
+                        }
+                    }
+                    Err(_) => error!("Last task completed with errors"),
                 }
+                let code = run_exit_code(&r);
                 if cli.run_once {
-                    return r.map_err(|_| "".to_string());
+                    return ExitCode::from(code);
                 }
             }
             Err(_) => {
@@ -48,34 +214,180 @@ async fn main() -> Result<(), String> {
                 panic!();
             }
         }
-        sleep(Duration::from_secs(cli.interval)).await;
+        if *shutdown.borrow() {
+            return ExitCode::SUCCESS;
+        }
+        let interval = backoff_interval(
+            Duration::from_secs(cli.interval),
+            consecutive_failures,
+            Duration::from_secs(cli.max_backoff),
+        );
+        if consecutive_failures > 0 {
+            warn!(
+                "{} consecutive run(s) failed, waiting {}s before the next run",
+                consecutive_failures,
+                interval.as_secs()
+            );
+        }
+        tokio::select! {
+            _ = sleep(jittered_interval(
+                interval,
+                Duration::from_secs(cli.interval_jitter),
+            )) => (),
+            Ok(_) = shutdown.changed() => {
+                info!("Shutting down");
+                return ExitCode::SUCCESS;
+            }
+        }
+    }
+}
+
+/// Resolves once SIGINT or SIGTERM has been received
+async fn shutdown_signal() -> Result<(), std::io::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        r = tokio::signal::ctrl_c() => r,
+        _ = terminate.recv() => Ok(()),
     }
 }
 
 fn get_source(cli: &Cli) -> Result<Box<dyn Ipv4Source>, SourceError> {
+    let source = get_base_source(cli)?;
+    if cli.ipv4_reject_reserved {
+        Ok(ipv4source::ValidatingSource::new(
+            source,
+            cli.ipv4_allow_reserved.iter().map(|&r| r.into()).collect(),
+        ))
+    } else {
+        Ok(ipv4source::ValidatingSource::rejecting_unusable(source))
+    }
+}
+
+/// The DNS servers to resolve the hostname with, or `None` to use the system nameservers
+fn hostname_dns_servers(servers: &[cli::DnsServer], port: u16) -> Option<Vec<SocketAddr>> {
+    servers
+        .iter()
+        .map(|s| match s {
+            cli::DnsServer::System => None,
+            cli::DnsServer::Addr(ip4) => Some(SocketAddr::new(IpAddr::V4(ip4.to_owned()), port)),
+        })
+        .collect()
+}
+
+fn hostname_transport(cli: &Cli) -> ipv4source::DnsTransport {
+    match cli.ipv4_hostname_transport {
+        cli::HostnameTransport::Udp => ipv4source::DnsTransport::Udp,
+        cli::HostnameTransport::Tcp => ipv4source::DnsTransport::Tcp,
+        // clap ensures that the TLS name is set
+        cli::HostnameTransport::Tls => ipv4source::DnsTransport::Tls {
+            hostname: cli.ipv4_hostname_tls_name.to_owned().unwrap(),
+        },
+    }
+}
+
+fn get_base_source(cli: &Cli) -> Result<Box<dyn Ipv4Source>, SourceError> {
     match cli.source {
         cli::Ipv4AddressSource::Hostname => {
+            let transport = hostname_transport(cli);
             ipv4source::HostnameSource::from_config(&ipv4source::HostnameSourceConfig {
                 hostname: cli.ipv4_hostname.to_owned().unwrap(),
-                servers: cli
-                    .ipv4_hostname_dns_servers
-                    .iter()
-                    .map(|ip4| SocketAddr::new(IpAddr::V4(ip4.to_owned()), 53))
-                    .collect_vec(),
+                servers: hostname_dns_servers(
+                    &cli.ipv4_hostname_dns_servers,
+                    transport.default_port(),
+                ),
+                timeout: Some(Duration::from_secs(cli.ipv4_hostname_timeout)),
+                transport,
+                attempts: cli.ipv4_hostname_attempts,
             })
         }
-        cli::Ipv4AddressSource::Fixed => Ok(ipv4source::FixedSource::from_addr(
-            cli.ipv4_fixed_address.unwrap(),
+        cli::Ipv4AddressSource::Fixed => Ok(ipv4source::FixedSource::from_addrs(
+            cli.ipv4_fixed_address.to_owned(),
         )),
+        cli::Ipv4AddressSource::Stun => {
+            let server = cli.ipv4_stun_server.as_deref().unwrap();
+            let server = server
+                .to_socket_addrs()
+                .map_err(|e| format!("could not resolve STUN server {}: {}", server, e))?
+                // The mapped address reported by the server has the same family as our request, so only IPv4 is useful
+                .find(SocketAddr::is_ipv4)
+                .ok_or_else(|| {
+                    format!("STUN server {} did not resolve to an IPv4 address", server)
+                })?;
+            ipv4source::StunSource::from_config(&ipv4source::StunSourceConfig { server })
+        }
+        cli::Ipv4AddressSource::Exec => {
+            ipv4source::ExecSource::from_config(&ipv4source::ExecSourceConfig {
+                command: cli.ipv4_exec_command.to_owned().unwrap(),
+                args: cli.ipv4_exec_args.to_owned(),
+            })
+        }
+        cli::Ipv4AddressSource::Env => {
+            ipv4source::EnvSource::from_config(&ipv4source::EnvSourceConfig {
+                var: cli.ipv4_env_var.to_owned(),
+            })
+        }
     }
 }
 
-fn get_provider(cli: &Cli) -> Result<Box<dyn Provider>, ProviderError> {
+fn get_provider(cli: &Cli) -> Result<Box<dyn Provider + Sync>, ProviderError> {
     match cli.provider {
         cli::Provider::Cloudflare => {
             match provider::CloudflareProvider::from_config(&provider::CloudflareProviderConfig {
                 api_token: cli.cloudflare_api_token.to_owned().unwrap().as_str(),
                 proxied: Some(cli.cloudflare_proxied),
+                proxied_domains: cli.cloudflare_proxied_domains.to_owned(),
+                unproxied_domains: cli.cloudflare_unproxied_domains.to_owned(),
+                max_retries: cli.cloudflare_max_retries,
+                retry_base_delay: Duration::from_secs(cli.cloudflare_retry_delay),
+                include_zones: cli.cloudflare_include_zones.to_owned(),
+                exclude_zones: cli.cloudflare_exclude_zones.to_owned(),
+                verify_token: !cli.cloudflare_skip_token_verification,
+                http_timeout: cli.cloudflare_http_timeout.map(Duration::from_secs),
+                api_url: cli.cloudflare_api_url.to_owned(),
+                max_zone_fetches: cli.cloudflare_max_zone_fetches,
+            }) {
+                Ok(p) => Ok(Box::new(p)),
+                Err(e) => Err(e),
+            }
+        }
+        cli::Provider::DigitalOcean => {
+            match provider::DigitalOceanProvider::from_config(
+                &provider::DigitalOceanProviderConfig {
+                    api_token: cli.digitalocean_api_token.as_deref().unwrap(),
+                    http_timeout: cli.digitalocean_http_timeout.map(Duration::from_secs),
+                    api_url: cli.digitalocean_api_url.to_owned(),
+                },
+            ) {
+                Ok(p) => Ok(Box::new(p)),
+                Err(e) => Err(e),
+            }
+        }
+        cli::Provider::Njalla => {
+            match provider::NjallaProvider::from_config(&provider::NjallaProviderConfig {
+                api_token: cli.njalla_api_token.as_deref().unwrap(),
+                http_timeout: cli.njalla_http_timeout.map(Duration::from_secs),
+                api_url: cli.njalla_api_url.to_owned(),
+            }) {
+                Ok(p) => Ok(Box::new(p)),
+                Err(e) => Err(e),
+            }
+        }
+        cli::Provider::Rfc2136 => {
+            match provider::Rfc2136Provider::from_config(&provider::Rfc2136ProviderConfig {
+                server: cli.rfc2136_server.unwrap(),
+                zone: cli.rfc2136_zone.as_deref().unwrap(),
+                tsig_key_name: cli.rfc2136_tsig_key_name.as_deref().unwrap(),
+                tsig_secret: cli.rfc2136_tsig_secret.as_deref().unwrap(),
+                tsig_algorithm: cli.rfc2136_tsig_algorithm.into(),
+            }) {
+                Ok(p) => Ok(Box::new(p)),
+                Err(e) => Err(e),
+            }
+        }
+        cli::Provider::ZoneFile => {
+            match provider::ZoneFileProvider::from_config(&provider::ZoneFileProviderConfig {
+                path: cli.zonefile_path.as_deref().unwrap(),
+                origin: cli.zonefile_origin.as_deref().unwrap(),
             }) {
                 Ok(p) => Ok(Box::new(p)),
                 Err(e) => Err(e),
@@ -88,13 +400,80 @@ fn get_registry<'a>(
     cli: &Cli,
     provider: &'a (dyn clouddns_nat_helper::provider::Provider + 'a),
 ) -> Result<Box<dyn ARegistry + 'a>, RegistryError> {
-    // For now, there is only a single registry and that is TXT. in the future, we could match here
-    TxtRegistry::from_provider(cli.registry_tenant.to_owned(), provider)
+    if cli.registry != cli::Registry::Txt && cli.ownership_backend != cli::OwnershipBackend::Txt {
+        return Err(RegistryError::Internal(
+            "Native ownership metadata is only supported with the txt registry".to_string(),
+        ));
+    }
+    match cli.registry {
+        cli::Registry::Txt => TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: cli.registry_tenant.to_owned(),
+                adoptable_addresses: cli.registry_adopt_addresses.to_owned(),
+                backend: cli.ownership_backend.into(),
+                prefix: cli.registry_txt_prefix.to_owned(),
+                stale_after: cli.registry_stale_after.map(Duration::from_secs),
+                ownership_subdomain: cli.registry_txt_subdomain.to_owned(),
+                verify_aaaa_on_claim: cli.registry_verify_aaaa,
+                manage_wildcards: cli.manage_wildcards,
+            },
+            provider,
+        ),
+        cli::Registry::File => FileRegistry::from_config(
+            FileRegistryConfig {
+                tenant: cli.registry_tenant.to_owned(),
+                // clap ensures that the path is set
+                path: cli.registry_path.to_owned().unwrap(),
+            },
+            provider,
+        ),
+        #[cfg(feature = "sqlite")]
+        cli::Registry::Sqlite => SqliteRegistry::from_config(
+            SqliteRegistryConfig {
+                tenant: cli.registry_tenant.to_owned(),
+                // clap ensures that the path is set
+                path: cli.registry_path.to_owned().unwrap(),
+            },
+            provider,
+        ),
+        #[cfg(not(feature = "sqlite"))]
+        cli::Registry::Sqlite => Err(RegistryError::Internal(
+            "The sqlite registry is not available, nat-helper was built without the 'sqlite' feature"
+                .to_string(),
+        )),
+    }
 }
 
-fn run_job(cli: Cli) -> Result<(), ()> {
-    // TODO: Create the provider and source in main() and pass them to the worker instead of recreating them every time
-    let mut provider = match get_provider(&cli) {
+/// Plan options set on the command line. Reads the domains file, if any
+fn plan_options(cli: &Cli) -> Result<PlanOptions, String> {
+    let domain_allowlist = match &cli.domains_file {
+        Some(path) => Some(parse_domain_list(&fs::read_to_string(path).map_err(
+            |e| format!("Could not read domains file {}: {}", path.display(), e),
+        )?)),
+        None => None,
+    };
+    Ok(PlanOptions {
+        defer_recent_updates: cli.defer_recent_updates,
+        create_only_skip_owned: cli.createonly_skip_owned,
+        delete_mode: match cli.delete_mode {
+            cli::DeleteMode::Delete => DeleteMode::Delete,
+            // clap ensures that the park address is set
+            cli::DeleteMode::Park => DeleteMode::Park(cli.park_address.unwrap()),
+        },
+        domain_filter: DomainFilter {
+            include: cli.include_domains.clone(),
+            exclude: cli.exclude_domains.clone(),
+        },
+        manage_wildcards: cli.manage_wildcards,
+        domain_allowlist,
+        keep_extra_addresses: cli.keep_extra_addresses,
+        skip_non_global_aaaa: !cli.allow_non_global_aaaa,
+    })
+}
+
+/// Generate a plan and print it without applying it or claiming any domains
+fn plan_only(cli: &Cli) -> Result<(), ()> {
+    let mut provider = match get_provider(cli) {
         Ok(p) => {
             info!("Connected to provider");
             p
@@ -104,12 +483,173 @@ fn run_job(cli: Cli) -> Result<(), ()> {
             return Err(());
         }
     };
-    if cli.record_ttl.is_some() {
-        provider.set_ttl(cli.record_ttl.unwrap());
+    // Generating a plan never changes any records, dry-run mode makes sure of that
+    if let Err(e) = provider.enable_dry_run() {
+        error!("Could not enable dry-run mode: {}", e);
+        return Err(());
     }
 
-    // Create a second provider for our TXT registry. TODO: ugly, should be able to reuse the previous provider if its TXTRegistry
-    let mut reg_provider = match get_provider(&cli) {
+    let source = match get_source(cli) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to create ipv4source: {}", e.to_string());
+            return Err(());
+        }
+    };
+    let target_addrs = match source.addrs() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Could not determine target addresses: {}", e);
+            return Err(());
+        }
+    };
+
+    let mut registry = match get_registry(cli, provider.as_ref()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Could not create registry: {}", e);
+            return Err(());
+        }
+    };
+    if let Err(e) = registry.enable_dry_run() {
+        error!("Could not enable dry-run mode: {}", e);
+        return Err(());
+    }
+
+    let options = match plan_options(cli) {
+        Ok(o) => o,
+        Err(e) => {
+            error!("{}", e);
+            return Err(());
+        }
+    };
+    let domains = registry.all_domains();
+    let plan = Plan::generate_with_options(
+        registry.as_mut(),
+        &target_addrs,
+        cli.policy.into(),
+        &options,
+    );
+    match cli.output {
+        cli::OutputFormat::Text => {
+            for action in plan.actions() {
+                println!("{}", action);
+            }
+        }
+        cli::OutputFormat::Table => print!("{}", plan.table(&domains)),
+        cli::OutputFormat::Json => match plan.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                error!("Could not serialize plan: {}", e);
+                return Err(());
+            }
+        },
+    }
+    if cli.show_diff {
+        print!("{}", plan.diff(&domains));
+    }
+    Ok(())
+}
+
+/// Release all domains owned by this instance
+fn release_all(cli: &Cli) -> Result<(), ()> {
+    with_writable_registry(cli, |registry| match registry.release_all() {
+        Ok(released) if released.is_empty() => {
+            info!("No owned domains to release");
+            Ok(())
+        }
+        Ok(released) => {
+            info!("Released domains: {}", released.join(", "));
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not release all domains: {}", e);
+            Err(())
+        }
+    })
+}
+
+fn prune_orphans(cli: &Cli) -> Result<(), ()> {
+    with_writable_registry(cli, |registry| match registry.prune_orphans() {
+        Ok(pruned) if pruned.is_empty() => {
+            info!("No orphaned ownership records found");
+            Ok(())
+        }
+        Ok(pruned) => {
+            info!(
+                "Removed orphaned ownership of domains: {}",
+                pruned.join(", ")
+            );
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not prune all orphaned domains: {}", e);
+            Err(())
+        }
+    })
+}
+
+/// Claim a domain regardless of its current owners
+fn force_claim(cli: &Cli, domain: &str) -> Result<(), ()> {
+    let domain = provider::normalize_domain(domain.trim_end_matches('.'));
+    warn!(
+        "Force-claiming domain {}. Other instances still managing it will conflict with this one",
+        domain
+    );
+    with_writable_registry(cli, |registry| match registry.claim_force(&domain) {
+        Ok(_) => {
+            info!("Claimed domain {}", domain);
+            Ok(())
+        }
+        Err(e) => {
+            error!("{}", e);
+            Err(())
+        }
+    })
+}
+
+/// Connect to the provider and registry (both in dry-run mode if requested) and pass the registry to `f`
+fn with_writable_registry(
+    cli: &Cli,
+    f: impl FnOnce(&mut dyn ARegistry) -> Result<(), ()>,
+) -> Result<(), ()> {
+    let mut provider = match get_provider(cli) {
+        Ok(p) => {
+            info!("Connected to provider");
+            p
+        }
+        Err(e) => {
+            error!("Unable to create provider: {}", e.to_string());
+            return Err(());
+        }
+    };
+    if cli.dry_run {
+        if let Err(e) = provider.enable_dry_run() {
+            error!("Could not enable dry-run mode: {}", e);
+            return Err(());
+        }
+    }
+
+    let mut registry = match get_registry(cli, provider.as_ref()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Could not create registry: {}", e);
+            return Err(());
+        }
+    };
+    if cli.dry_run {
+        if let Err(e) = registry.enable_dry_run() {
+            error!("Could not enable dry-run mode: {}", e);
+            return Err(());
+        }
+    }
+
+    f(registry.as_mut())
+}
+
+/// Print all taken domains and their owners
+fn report_ownership(cli: &Cli) -> Result<(), ()> {
+    let provider = match get_provider(cli) {
         Ok(p) => {
             info!("Connected to provider");
             p
@@ -119,8 +659,67 @@ fn run_job(cli: Cli) -> Result<(), ()> {
             return Err(());
         }
     };
-    if cli.record_ttl.is_some() {
-        reg_provider.set_ttl(cli.record_ttl.unwrap());
+    let registry = match get_registry(cli, provider.as_ref()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Could not create registry: {}", e);
+            return Err(());
+        }
+    };
+
+    let taken = registry.taken_domains_with_owners();
+    if taken.is_empty() {
+        info!("No domains are taken by other tenants");
+    }
+    for (domain, owners) in taken {
+        match owners.len() {
+            0 => println!("{}: no ownership record (externally managed)", domain.name),
+            1 => println!("{}: {}", domain.name, owners[0]),
+            _ => println!("{}: conflicting owners {}", domain.name, owners.join(", ")),
+        }
+    }
+    Ok(())
+}
+
+/// Run a single update job. Returns the result of the run, including any failed actions, if the job could be executed
+fn run_job(
+    cli: Cli,
+    last_addrs: Option<Vec<Ipv4Addr>>,
+    events: Option<Arc<EventSocket>>,
+    metrics: Option<Arc<Metrics>>,
+) -> Result<RunResult, ()> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("job", tenant = %cli.registry_tenant).entered();
+
+    // TODO: Create the provider and source in main() and pass them to the worker instead of recreating them every time
+    // The provider is shared between the executor and the registry, so it needs to be fully configured before either is created
+    let mut provider = match get_provider(&cli) {
+        Ok(p) => {
+            info!("Connected to provider");
+            p
+        }
+        Err(e) => {
+            error!("Unable to create provider: {}", e.to_string());
+            return Err(());
+        }
+    };
+    if let Some(ttl) = cli.record_ttl {
+        if let Err(e) = provider.set_record_ttl(ttl.into()) {
+            error!("Unable to set record TTL: {}", e);
+            return Err(());
+        }
+    }
+    if cli.ownership_backend == cli::OwnershipBackend::Native {
+        provider.set_record_comment(Some(TxtRegistry::ownership_marker(
+            &cli.registry_txt_prefix,
+            &cli.registry_tenant,
+        )));
+    }
+    if cli.dry_run {
+        if let Err(e) = provider.enable_dry_run() {
+            error!("Could not enable dry-run mode: {}", e);
+            return Err(());
+        }
     }
 
     let source = match get_source(&cli) {
@@ -146,12 +745,29 @@ fn run_job(cli: Cli) -> Result<(), ()> {
     };
     info!("Initialized registry");
 
+    let options = match plan_options(&cli) {
+        Ok(o) => o,
+        Err(e) => {
+            error!("{}", e);
+            return Err(());
+        }
+    };
     let mut exec = match Executor::try_new(
         source.as_ref(),
-        reg_provider.as_mut(),
+        provider.as_ref(),
         registry.as_mut(),
         cli.policy,
+        options,
         cli.dry_run,
+        cli.output,
+        cli.show_diff,
+        cli.fail_on_destructive,
+        cli.registry_stale_after.is_some(),
+        cli.concurrency,
+        RetryPolicy {
+            max_retries: cli.retry_attempts,
+            base_delay: Duration::from_secs(cli.retry_delay),
+        },
     ) {
         Ok(e) => e,
         Err(e) => {
@@ -159,6 +775,20 @@ fn run_job(cli: Cli) -> Result<(), ()> {
             return Err(());
         }
     };
+    if let Some(metrics) = metrics {
+        exec.set_metrics(metrics);
+    }
+    match (cli.max_changes, cli.force) {
+        (Some(max), false) => exec.set_max_changes(max),
+        (Some(_), true) => warn!("--force is set, not enforcing --max-changes"),
+        (None, _) => (),
+    }
+    exec.set_verify(cli.verify);
+    exec.set_write_probe(cli.write_probe);
+    exec.set_on_action(Box::new(|action, result| match result {
+        Ok(_) => debug!("Applied {}", action),
+        Err(e) => debug!("Could not apply {}: {}", action, e),
+    }));
     debug!("Initialized Executor");
 
     let res = match exec.run() {
@@ -168,19 +798,50 @@ fn run_job(cli: Cli) -> Result<(), ()> {
             return Err(());
         }
     };
+    for action in res.skipped() {
+        debug!("Planned action was not attempted: {}", action);
+    }
+
+    if let Some(msg) = address_change_message(
+        last_addrs.as_deref(),
+        &res.target_addrs,
+        res.successes.len() + res.failures.len(),
+    ) {
+        info!("{}", msg);
+        if let Some(events) = &events {
+            events.emit_address_change(last_addrs.as_deref(), &res.target_addrs);
+        }
+    }
+    if let Some(events) = &events {
+        for action in &res.successes {
+            events.emit_action(action, Ok(()));
+        }
+        for (action, e) in &res.failures {
+            events.emit_action(action, Err(e));
+        }
+    }
+
+    if let Some(url) = &cli.webhook_url {
+        if !cli.dry_run || cli.webhook_dry_run {
+            match WebhookNotifier::new(url).and_then(|n| notify_changes(&n, &res)) {
+                Ok(true) => debug!("Sent webhook notification"),
+                Ok(false) => (),
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
 
     if res.successes.is_empty() && res.failures.is_empty() {
         info!("No changes made");
-        return Ok(());
+        return Ok(res);
     }
 
+    let applied: Plan = res.successes.iter().cloned().collect();
+    debug!("Applied changes: {:?}", res.successes);
     match (res.successes.len(), res.failures.len()) {
         (0, 0) => info!("No changes made"),
         (1.., 0) => {
-            info!(
-                "Successfully applied the following changes: {:?}",
-                res.successes
-            );
+            info!("Successfully applied {}", applied.summary());
             info!("No errors were encountered");
         }
         (0, 1..) => {
@@ -190,10 +851,7 @@ fn run_job(cli: Cli) -> Result<(), ()> {
             );
         }
         (1.., 1..) => {
-            info!(
-                "Successfully applied the following changes: {:?}",
-                res.successes
-            );
+            info!("Successfully applied {}", applied.summary());
             info!(
                 "Encountered Errors while applying the following changes: {:?}",
                 res.failures
@@ -201,5 +859,5 @@ fn run_job(cli: Cli) -> Result<(), ()> {
         }
     }
 
-    Ok(())
+    Ok(res)
 }
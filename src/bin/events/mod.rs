@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    io::{self, Write},
+    net::Ipv4Addr,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use clouddns_nat_helper::plan::Action;
+use log::{debug, warn};
+use serde_json::{json, Value};
+
+use crate::executor::ExecutorError;
+
+/// How long to wait for a client to accept an event before dropping it
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Streams newline-delimited JSON events to all clients connected to a Unix domain socket.
+///
+/// Clients may connect at any time and will receive all events emitted after they connected.
+/// Clients that disconnect, or that do not read their events in time, are dropped silently.
+pub struct EventSocket {
+    path: PathBuf,
+    clients: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl EventSocket {
+    /// Create a new socket at the given path and start accepting clients in the background.
+    /// A stale socket left behind at that path (e.g. after a crash) is replaced.
+    /// Returns an error if the path is taken by any other file, or by a socket that is still in use
+    pub fn bind(path: &Path) -> Result<EventSocket, io::Error> {
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        let clients: Arc<Mutex<Vec<UnixStream>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let accept_clients = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(s) => {
+                        debug!("Event socket client connected");
+                        // A client that stops reading must not block the daemon while emitting
+                        if let Err(e) = s.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)) {
+                            warn!("Could not set up event socket client: {}", e);
+                            continue;
+                        }
+                        accept_clients.lock().unwrap().push(s);
+                    }
+                    Err(e) => warn!("Could not accept event socket client: {}", e),
+                }
+            }
+        });
+
+        Ok(EventSocket {
+            path: path.to_owned(),
+            clients,
+        })
+    }
+
+    /// Send an event to all connected clients
+    pub fn emit(&self, event: &Value) {
+        let line = format!("{}\n", event);
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|c| c.write_all(line.as_bytes()).is_ok());
+    }
+
+    /// Emit an event for a change of the source address(es)
+    pub fn emit_address_change(&self, previous: Option<&[Ipv4Addr]>, current: &[Ipv4Addr]) {
+        self.emit(&json!({
+            "event": "address_changed",
+            "previous": previous,
+            "current": current,
+        }));
+    }
+
+    /// Emit an event for an applied action and its outcome
+    pub fn emit_action(&self, action: &Action, result: Result<(), &ExecutorError>) {
+        let mut event = action_json(action);
+        event["event"] = json!("action");
+        event["success"] = json!(result.is_ok());
+        event["error"] = json!(result.err().map(|e| e.to_string()));
+        self.emit(&event);
+    }
+
+    #[cfg(test)]
+    fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Remove a socket file at `path` that no process is listening on anymore
+fn remove_stale_socket(path: &Path) -> Result<(), io::Error> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!(
+                        "Event socket {} is in use by another process",
+                        path.display()
+                    ),
+                ));
+            }
+            fs::remove_file(path)
+        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn action_json(action: &Action) -> Value {
+    serde_json::to_value(action).unwrap_or_else(|_| json!({"action": action.to_string()}))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        net::Ipv4Addr,
+        os::unix::net::{UnixListener, UnixStream},
+        path::PathBuf,
+        thread,
+        time::Duration,
+    };
+
+    use clouddns_nat_helper::plan::Action;
+    use serde_json::{json, Value};
+
+    use super::EventSocket;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "clouddns-nat-helper-events-{}-{}.sock",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn wait_for_clients(socket: &EventSocket, count: usize) {
+        for _ in 0..100 {
+            if socket.client_count() == count {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(socket.client_count(), count);
+    }
+
+    #[test]
+    fn should_stream_events_to_clients() {
+        let path = socket_path("stream");
+        let socket = EventSocket::bind(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        wait_for_clients(&socket, 1);
+
+        socket.emit_address_change(None, &[Ipv4Addr::new(203, 0, 113, 1)]);
+        socket.emit_action(
+            &Action::Update(
+                "my.example.com".to_string(),
+                vec![Ipv4Addr::new(203, 0, 113, 1)],
+            ),
+            Ok(()),
+        );
+
+        let mut lines = BufReader::new(client).lines();
+        let change: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(
+            change,
+            json!({"event": "address_changed", "previous": null, "current": ["203.0.113.1"]})
+        );
+        let action: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(
+            action,
+            json!({
+                "event": "action",
+                "action": "update",
+                "domain": "my.example.com",
+                "addresses": ["203.0.113.1"],
+                "success": true,
+                "error": null,
+            })
+        );
+    }
+
+    #[test]
+    fn should_drop_clients_that_do_not_read() {
+        let path = socket_path("slow");
+        let socket = EventSocket::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        wait_for_clients(&socket, 1);
+
+        // Fill the socket buffer of the client until writing blocks
+        let event = json!({ "padding": "x".repeat(64 * 1024) });
+        for _ in 0..1000 {
+            if socket.client_count() == 0 {
+                break;
+            }
+            socket.emit(&event);
+        }
+        assert_eq!(socket.client_count(), 0);
+    }
+
+    #[test]
+    fn should_only_replace_stale_sockets() {
+        let path = socket_path("stale");
+        // Binding a listener and dropping it leaves the socket file behind
+        drop(UnixListener::bind(&path).unwrap());
+        let socket = EventSocket::bind(&path).unwrap();
+
+        // The socket is in use now
+        EventSocket::bind(&path).unwrap_err();
+        drop(socket);
+
+        let file = socket_path("regular");
+        fs::write(&file, "not a socket").unwrap();
+        EventSocket::bind(&file).unwrap_err();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "not a socket");
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -1,20 +1,63 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt::Display,
+    hash::{BuildHasher, Hasher},
+    net::Ipv4Addr,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
 use clouddns_nat_helper::{
     ipv4source::{Ipv4Source, SourceError},
-    plan::{Action, Plan},
+    plan::{Action, Plan, PlanOptions},
     provider::{Provider, ProviderError},
     registry::{ARegistry, RegistryError},
 };
-use log::{debug, info};
+use itertools::Itertools;
+use log::{debug, info, warn};
 use thiserror::Error;
 
-use crate::cli::Policy;
+use crate::{
+    cli::{OutputFormat, Policy},
+    metrics::Metrics,
+};
 
 /// An executor performs the complete set of actions needed to bring our records up-to-date
 pub struct Executor<'a> {
     source: &'a dyn Ipv4Source,
-    provider: &'a mut dyn Provider,
+    provider: &'a (dyn Provider + Sync),
     registry: &'a mut dyn ARegistry,
     policy: Policy,
+    plan_options: PlanOptions,
+    dry_run: bool,
+    output: OutputFormat,
+    show_diff: bool,
+    max_destructive: Option<usize>,
+    renew_ownership: bool,
+    max_concurrency: NonZeroUsize,
+    retry: RetryPolicy,
+    max_changes: Option<usize>,
+    verify: bool,
+    write_probe: bool,
+    metrics: Option<Arc<Metrics>>,
+    on_action: Option<ActionCallback<'a>>,
+}
+
+/// A callback that is invoked with the outcome of each action, see [`Executor::set_on_action()`]
+pub type ActionCallback<'a> = Box<dyn Fn(&Action, &Result<(), ExecutorError>) + 'a>;
+
+/// How to retry actions that failed with a transient error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// How often to retry an action before marking it as failed
+    pub max_retries: u32,
+    /// Delay before the first retry. Doubles with each subsequent retry
+    pub base_delay: Duration,
 }
 
 #[derive(Error, Debug, Eq, PartialEq, Clone)]
@@ -25,6 +68,10 @@ pub enum ExecutorError {
     Registry(RegistryError),
     #[error("`{0}`")]
     Source(SourceError),
+    #[error("Plan contains {count} destructive action(s), more than the allowed {max}")]
+    Destructive { count: usize, max: usize },
+    #[error("Plan contains {count} action(s), more than the allowed {max}. Refusing to apply it")]
+    TooManyChanges { count: usize, max: usize },
 }
 impl From<ProviderError> for ExecutorError {
     fn from(p: ProviderError) -> Self {
@@ -42,23 +89,371 @@ impl From<SourceError> for ExecutorError {
     }
 }
 
+/// Process exit code if a run could not be executed at all, e.g. because the provider is unreachable
+pub const EXIT_ERROR: u8 = 1;
+/// Process exit code if a run completed, but some actions failed
+pub const EXIT_ACTIONS_FAILED: u8 = 2;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RunResult {
+    /// The addresses retrieved from the source that this run targeted
+    pub target_addrs: Vec<Ipv4Addr>,
     pub successes: Vec<Action>,
     pub failures: Vec<(Action, ExecutorError)>,
+    /// The plan that was generated for this run. Actions that are neither in `successes` nor in `failures` were not attempted
+    pub plan: Plan,
+}
+/// The outcome of a run for a single domain, see [`RunResult::by_domain()`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DomainOutcome {
+    /// The action that was attempted for this domain
+    pub action: Action,
+    /// The addresses that the domain's A records were set to. Empty for [`Action::DeleteAndRelease`]
+    pub addresses: Vec<Ipv4Addr>,
+    /// The first error encountered while performing the action, if any
+    pub error: Option<ExecutorError>,
+}
+impl DomainOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl RunResult {
+    /// Group the outcome of this run by domain.
+    ///
+    /// An action may be reported as both successful and failed, e.g. if the records of a domain were deleted, but releasing it failed.
+    /// Such domains are considered failed.
+    pub fn by_domain(&self) -> HashMap<String, DomainOutcome> {
+        let mut outcomes: HashMap<String, DomainOutcome> = HashMap::new();
+        let all = self
+            .successes
+            .iter()
+            .map(|a| (a, None))
+            .chain(self.failures.iter().map(|(a, e)| (a, Some(e))));
+        for (action, error) in all {
+            let (domain, addresses) = match action {
+                Action::ClaimAndUpdate(d, ips) | Action::Update(d, ips) => (d, ips.to_owned()),
+                Action::DeleteAndRelease(d) => (d, vec![]),
+                _ => continue,
+            };
+            let outcome = outcomes
+                .entry(domain.to_owned())
+                .or_insert_with(|| DomainOutcome {
+                    action: action.clone(),
+                    addresses,
+                    error: None,
+                });
+            if outcome.error.is_none() {
+                outcome.error = error.cloned();
+            }
+        }
+        outcomes
+    }
+
+    /// All planned actions that were neither applied successfully nor failed
+    pub fn skipped(&self) -> Vec<&Action> {
+        self.plan
+            .actions()
+            .filter(|a| !self.successes.contains(a) && !self.failures.iter().any(|(f, _)| f == *a))
+            .collect()
+    }
+
+    /// The process exit code for this run: 0 if all actions succeeded, [`EXIT_ACTIONS_FAILED`] otherwise
+    pub fn exit_code(&self) -> u8 {
+        if self.failures.is_empty() {
+            0
+        } else {
+            EXIT_ACTIONS_FAILED
+        }
+    }
+}
+
+/// The process exit code for the outcome of a run: [`EXIT_ERROR`] if the run was aborted, see [`RunResult::exit_code()`] otherwise
+pub fn run_exit_code<E>(result: &Result<RunResult, E>) -> u8 {
+    match result {
+        Ok(res) => res.exit_code(),
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+/// Compare the addresses targeted by the previous run against the current ones and
+/// return a message describing the change, if there was any.
+///
+/// On the first run (no previous addresses), the initial address is reported.
+pub fn address_change_message(
+    previous: Option<&[Ipv4Addr]>,
+    current: &[Ipv4Addr],
+    domains: usize,
+) -> Option<String> {
+    match previous {
+        None => Some(format!(
+            "Initial WAN address: {}, updating {} domains.",
+            current.iter().join(", "),
+            domains
+        )),
+        Some(previous) if previous.iter().sorted().ne(current.iter().sorted()) => Some(format!(
+            "WAN address changed {} -> {}, updating {} domains.",
+            previous.iter().join(", "),
+            current.iter().join(", "),
+            domains
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Add a random delay between zero and `jitter` to `interval`, so that instances started at the same time spread out their runs
+pub fn jittered_interval(interval: Duration, jitter: Duration) -> Duration {
+    jittered_interval_with(interval, jitter, RandomState::new().build_hasher().finish())
+}
+
+fn jittered_interval_with(interval: Duration, jitter: Duration, random: u64) -> Duration {
+    match jitter.as_millis() as u64 {
+        0 => interval,
+        jitter_ms => interval + Duration::from_millis(random % (jitter_ms + 1)),
+    }
+}
+
+/// Double `interval` for each consecutive failed run, but never wait longer than `max` (or `interval`, if that is longer)
+pub fn backoff_interval(interval: Duration, consecutive_failures: u32, max: Duration) -> Duration {
+    interval
+        .saturating_mul(2u32.saturating_pow(consecutive_failures))
+        .min(max)
+        .max(interval)
+}
+
+/// Returns an error if the number of destructive actions in a plan exceeds the allowed maximum (if any)
+fn check_destructive(count: usize, max: Option<usize>) -> Result<(), ExecutorError> {
+    match max {
+        Some(max) if count > max => Err(ExecutorError::Destructive { count, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a provider error might go away on its own, e.g. a network or API error
+fn is_transient_provider_error(e: &ProviderError) -> bool {
+    matches!(e, ProviderError::Internal(_))
+}
+
+/// Whether a registry error might go away on its own.
+/// Claim and release errors indicate a conflict with another owner and are never retried
+fn is_transient_registry_error(e: &RegistryError) -> bool {
+    matches!(e, RegistryError::Internal(_))
+}
+
+fn with_retry<T, E: Display>(
+    policy: &RetryPolicy,
+    sleep: impl Fn(Duration),
+    is_transient: impl Fn(&E) -> bool,
+    mut request: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 0;
+    loop {
+        match request() {
+            Err(e) if attempt < policy.max_retries && is_transient(&e) => {
+                let delay = policy
+                    .base_delay
+                    .saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                warn!(
+                    "{}. Retrying in {}s ({}/{})",
+                    e,
+                    delay.as_secs_f32(),
+                    attempt,
+                    policy.max_retries
+                );
+                sleep(delay);
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Retry an action that was already applied once with the given result, if it failed with a transient error
+fn retry_action(
+    provider: &(dyn Provider + Sync),
+    action: &Action,
+    result: Result<(), ProviderError>,
+    policy: &RetryPolicy,
+    sleep: impl Fn(Duration),
+) -> Result<(), ProviderError> {
+    let mut first = Some(result);
+    with_retry(policy, sleep, is_transient_provider_error, || {
+        first.take().unwrap_or_else(|| provider.apply(action))
+    })
+}
+
+/// Check that the provider persisted an action that it applied successfully
+fn verify_action(provider: &(dyn Provider + Sync), action: &Action) -> Result<(), ProviderError> {
+    match provider.verify(action)? {
+        true => Ok(()),
+        false => Err(ProviderError::Internal(format!(
+            "Provider accepted {}, but its records do not match the action",
+            action
+        ))),
+    }
+}
+
+/// Confirm that the provider may write records, using the first domain that is not a wildcard.
+/// Does nothing if there is no such domain
+fn probe_write_access<'d>(
+    provider: &(dyn Provider + Sync),
+    mut domains: impl Iterator<Item = &'d str>,
+) -> Result<(), ExecutorError> {
+    match domains.find(|d| !d.starts_with("*.")) {
+        Some(domain) => {
+            info!(
+                "Probing write access with a throwaway TXT record for {}",
+                domain
+            );
+            provider.check_write_access(domain).map_err(|e| {
+                ProviderError::Internal(format!(
+                    "Write probe failed, the provider credentials may not allow writing records: {}",
+                    e
+                ))
+                .into()
+            })
+        }
+        None => {
+            warn!("No domains found to probe write access with, skipping the write probe");
+            Ok(())
+        }
+    }
+}
+
+/// Pass the outcome of an action to the callback, if one is set
+fn report_action(
+    callback: &Option<ActionCallback>,
+    action: &Action,
+    result: &Result<(), ExecutorError>,
+) {
+    if let Some(callback) = callback {
+        callback(action, result);
+    }
+}
+
+/// Apply all actions in a plan, grouped by the zone of their domain (see [`clouddns_nat_helper::provider::DnsProvider::zone_of()`]).
+///
+/// The actions are split into batches that never mix zones, so that a slow or failing zone does not hold up the others.
+/// The batches are applied with [`Provider::apply_plan()`] by up to `max_concurrency` worker threads in total,
+/// or one after another on the current thread if `max_concurrency` is 1.
+/// Returns one result per action, in the same order as the plan.
+fn apply_by_zone(
+    provider: &(dyn Provider + Sync),
+    plan: &Plan,
+    max_concurrency: NonZeroUsize,
+) -> Vec<Result<(), ProviderError>> {
+    let zones = plan
+        .actions()
+        .enumerate()
+        .into_group_map_by(|(_, action)| provider.zone_of(action.domain()));
+    // Split zones into several batches only if there are more workers than zones
+    let batches_per_zone = (max_concurrency.get() / zones.len().max(1)).max(1);
+    let batches = zones
+        .into_iter()
+        // Keep the order of the plan when applying zones one after another
+        .sorted_by_key(|(_, actions)| actions[0].0)
+        .flat_map(|(zone, actions)| {
+            debug!(
+                "Applying {} action(s) in zone {}",
+                actions.len(),
+                zone.as_deref().unwrap_or("<unknown>")
+            );
+            let batch_size = actions.len().div_ceil(batches_per_zone);
+            actions
+                .chunks(batch_size)
+                .map(|chunk| {
+                    let indices = chunk.iter().map(|(i, _)| *i).collect_vec();
+                    let batch: Plan = chunk.iter().map(|(_, a)| (*a).clone()).collect();
+                    (indices, batch)
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let workers = max_concurrency.get().min(batches.len());
+    let batch_results = if workers <= 1 {
+        batches
+            .iter()
+            .enumerate()
+            .map(|(i, (_, batch))| (i, provider.apply_plan(batch)))
+            .collect_vec()
+    } else {
+        // Workers take the next batch until none are left, so that at most `workers` batches are applied at the same time
+        let next = AtomicUsize::new(0);
+        thread::scope(|s| {
+            let handles = (0..workers)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match batches.get(i) {
+                                Some((_, batch)) => done.push((i, provider.apply_plan(batch))),
+                                None => break done,
+                            }
+                        }
+                    })
+                })
+                .collect_vec();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().ok())
+                .flatten()
+                .collect_vec()
+        })
+    };
+
+    let mut results: Vec<Option<Result<(), ProviderError>>> = vec![None; plan.actions().count()];
+    for (i, batch_result) in batch_results {
+        for (index, result) in batches[i].0.iter().zip(batch_result) {
+            results[*index] = Some(result);
+        }
+    }
+    // Actions of a batch whose worker thread panicked have no result
+    results
+        .into_iter()
+        .map(|r| {
+            r.unwrap_or_else(|| {
+                Err(ProviderError::Internal(
+                    "Action was not applied".to_string(),
+                ))
+            })
+        })
+        .collect()
 }
 
 impl<'a> Executor<'a> {
-    /// Create a new basic executor
+    /// Create a new basic executor.
+    /// The provider may be shared with the registry. In dry-run mode, it must already be in dry-run mode (see [`clouddns_nat_helper::provider::DnsProvider::enable_dry_run()`]).
+    /// In dry-run mode, the plan is printed in the given `output` format, followed by a diff of the affected records if `show_diff` is set.
+    /// If `renew_ownership` is set, all owned domains are claimed again after each run to renew their ownership.
+    /// Up to `max_concurrency` actions are applied in parallel, actions that fail with a transient error are retried according to `retry`
+    #[allow(clippy::too_many_arguments)]
     pub fn try_new(
         source: &'a dyn Ipv4Source,
-        provider: &'a mut dyn Provider,
+        provider: &'a (dyn Provider + Sync),
         registry: &'a mut dyn ARegistry,
         policy: Policy,
+        plan_options: PlanOptions,
         dry_run: bool,
+        output: OutputFormat,
+        show_diff: bool,
+        max_destructive: Option<usize>,
+        renew_ownership: bool,
+        max_concurrency: NonZeroUsize,
+        retry: RetryPolicy,
     ) -> Result<Executor<'a>, ExecutorError> {
         if dry_run {
-            provider.enable_dry_run()?;
+            if !provider.capabilities().dry_run {
+                return Err(ProviderError::DryRunNotSupported.into());
+            }
+            if !provider.dry_run() {
+                return Err(ProviderError::Internal(
+                    "Provider must be in dry-run mode for a dry-run".to_string(),
+                )
+                .into());
+            }
             registry.enable_dry_run()?;
         }
         Ok(Self {
@@ -66,66 +461,1171 @@ impl<'a> Executor<'a> {
             provider,
             registry,
             policy,
+            plan_options,
+            dry_run,
+            output,
+            show_diff,
+            max_destructive,
+            renew_ownership,
+            max_concurrency,
+            retry,
+            max_changes: None,
+            verify: false,
+            write_probe: false,
+            metrics: None,
+            on_action: None,
         })
     }
 
+    /// Refuse to apply plans with more than `max` actions, as a safety guard against faulty sources rewriting all records at once.
+    /// Runs exceeding the limit fail with [`ExecutorError::TooManyChanges`] before any domain is claimed or record is changed
+    pub fn set_max_changes(&mut self, max: usize) {
+        self.max_changes = Some(max);
+    }
+
+    /// Read the records of each domain again after applying an action, and mark the action as failed if the provider did not persist it.
+    /// See [`clouddns_nat_helper::provider::DnsProvider::verify()`]. Has no effect in dry-run mode
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// In dry-run mode, create and delete a throwaway TXT record before each run to confirm that the provider credentials allow writing records.
+    /// See [`clouddns_nat_helper::provider::Provider::check_write_access()`]. A records are never touched
+    pub fn set_write_probe(&mut self, write_probe: bool) {
+        self.write_probe = write_probe;
+    }
+
+    /// Record the outcome of every run in the given [`Metrics`]
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Invoke `callback` once for each action in a plan after it has been attempted, e.g. to report progress.
+    /// The callback receives the first error encountered while claiming, applying or releasing the domain, if any.
+    /// Actions that are not attempted (e.g. because the run was aborted) are not reported
+    pub fn set_on_action(&mut self, callback: ActionCallback<'a>) {
+        self.on_action = Some(callback);
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "run", skip_all, fields(policy = ?self.policy, dry_run = self.dry_run))
+    )]
     pub fn run(&mut self) -> Result<RunResult, ExecutorError> {
-        let target_addr = match self.source.addr() {
+        let result = self.run_inner();
+        if let Some(metrics) = &self.metrics {
+            metrics.record(&result);
+        }
+        result
+    }
+
+    fn run_inner(&mut self) -> Result<RunResult, ExecutorError> {
+        let target_addrs = match self.source.addrs() {
             Ok(a) => a,
             Err(e) => return Err(e.into()),
         };
-        info!("Target Ipv4 address: {}", target_addr);
+        info!(
+            "Target Ipv4 address(es): {}",
+            target_addrs.iter().join(", ")
+        );
 
         info!("Generating plan and registering domains...");
-        let plan = Plan::generate(self.registry, target_addr, self.policy.into());
+        let domains = self.registry.all_domains();
+        let plan = Plan::generate_with_options(
+            self.registry,
+            &target_addrs,
+            self.policy.into(),
+            &self.plan_options,
+        );
         debug!("Generated plan: {:?}", plan);
+        if self.dry_run {
+            match self.output {
+                OutputFormat::Text => (),
+                OutputFormat::Table => print!("{}", plan.table(&domains)),
+                OutputFormat::Json => match plan.to_json() {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => warn!("Could not serialize plan: {}", e),
+                },
+            }
+            if self.show_diff {
+                print!("{}", plan.diff(&domains));
+            }
+        }
+        if self.dry_run {
+            check_destructive(plan.destructive_actions().count(), self.max_destructive)?;
+            if self.write_probe {
+                probe_write_access(self.provider, domains.iter().map(|d| d.name.as_str()))?;
+            }
+        }
+        if let Some(max) = self.max_changes {
+            let count = plan.actions().count();
+            if count > max {
+                return Err(ExecutorError::TooManyChanges { count, max });
+            }
+        }
 
         let mut successes: Vec<Action> = vec![];
         let mut failures: Vec<(Action, ExecutorError)> = vec![];
 
-        for action in plan.actions() {
-            match action {
-                Action::ClaimAndUpdate(domain, _) => {
-                    match self.registry.claim(domain.as_str()) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            failures.push((action.clone(), e.into()));
-                            continue;
-                        }
-                    };
-                    match self.provider.apply(action) {
-                        Ok(_) => {
-                            successes.push(action.clone());
-                        }
-                        Err(e) => failures.push((action.clone(), e.into())),
-                    };
-                }
-                Action::Update(_, _) => {
-                    match self.provider.apply(action) {
-                        Ok(_) => {
-                            successes.push(action.clone());
-                        }
-                        Err(e) => failures.push((action.clone(), e.into())),
+        // Claim new domains first, so that we never touch records of domains we don't own
+        let claimed: Plan = plan
+            .actions()
+            .filter(|action| match action {
+                Action::ClaimAndUpdate(domain, _) => match with_retry(
+                    &self.retry,
+                    thread::sleep,
+                    is_transient_registry_error,
+                    || self.registry.claim(domain.as_str()),
+                ) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        let e: ExecutorError = e.into();
+                        report_action(&self.on_action, action, &Err(e.clone()));
+                        failures.push(((*action).clone(), e));
+                        false
+                    }
+                },
+                _ => true,
+            })
+            .cloned()
+            .collect();
+
+        // Apply all remaining actions in one go per zone, allowing the provider to batch them.
+        // Registry changes are only made on this thread, before and after applying
+        let results = apply_by_zone(self.provider, &claimed, self.max_concurrency);
+        for (action, result) in claimed.actions().zip(results) {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("domain", domain = %action.domain()).entered();
+            let result = retry_action(self.provider, action, result, &self.retry, thread::sleep)
+                .and_then(|_| match self.verify && !self.dry_run {
+                    true => verify_action(self.provider, action),
+                    false => Ok(()),
+                })
+                .map_err(ExecutorError::from);
+            let outcome = match action {
+                Action::ClaimAndUpdate(_, _) | Action::Update(_, _) => {
+                    match &result {
+                        Ok(_) => successes.push(action.clone()),
+                        Err(e) => failures.push((action.clone(), e.clone())),
                     };
+                    result
                 }
                 Action::DeleteAndRelease(domain) => {
-                    match self.provider.apply(action) {
-                        Ok(_) => {}
-                        Err(e) => failures.push((action.clone(), e.into())),
+                    if let Err(e) = &result {
+                        failures.push((action.clone(), e.clone()));
                     };
-                    match self.registry.release(domain) {
+                    let released = self.registry.release(domain).map_err(ExecutorError::from);
+                    match &released {
                         Ok(_) => {
                             successes.push(action.clone());
                         }
-                        Err(e) => failures.push((action.clone(), e.into())),
+                        Err(e) => failures.push((action.clone(), e.clone())),
                     };
+                    result.and(released)
                 }
                 _ => todo!(),
+            };
+            report_action(&self.on_action, action, &outcome);
+        }
+
+        if self.renew_ownership {
+            for domain in self.registry.owned_domains() {
+                if let Err(e) = self.registry.claim(&domain.name) {
+                    warn!("Could not renew ownership of {}: {}", domain.name, e);
+                }
             }
         }
         Ok(RunResult {
+            target_addrs,
             successes,
             failures,
+            plan,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        net::{Ipv4Addr, Ipv6Addr},
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicU32, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use clouddns_nat_helper::{
+        ipv4source::{FixedSource, Ipv4Source},
+        plan::PlanOptions,
+        plan::{Action, Plan},
+        provider::{
+            DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+            TxTRegistryProvider, TTL,
+        },
+        registry::{ARegistry, InMemoryRegistry, RegistryError, TxtRegistry, DEFAULT_TXT_PREFIX},
+    };
+
+    use crate::{
+        cli::{OutputFormat, Policy},
+        metrics::Metrics,
+    };
+
+    use super::{
+        address_change_message, apply_by_zone, backoff_interval, check_destructive,
+        jittered_interval_with, probe_write_access, retry_action, run_exit_code, verify_action,
+        with_retry, Executor, ExecutorError, RetryPolicy, RunResult, EXIT_ACTIONS_FAILED,
+        EXIT_ERROR,
+    };
+
+    /// Provider that fails all actions for domains starting with "fail"
+    struct FlakyProvider;
+    impl DnsProvider for FlakyProvider {
+        fn capabilities(&self) -> ProviderCapabilities {
+            ProviderCapabilities {
+                dry_run: true,
+                ..Default::default()
+            }
+        }
+        fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn dry_run(&self) -> bool {
+            false
+        }
+        fn ttl(&self) -> Option<TTL> {
+            None
+        }
+        fn set_ttl(&mut self, _ttl: TTL) {}
+        fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+            Ok(vec![DnsRecord::new(
+                "new.example.com",
+                RecordContent::Aaaa(Ipv6Addr::LOCALHOST),
+            )])
+        }
+        fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+            match action {
+                Action::DeleteAndRelease(d) if d.starts_with("fail") => {
+                    Err(ProviderError::Internal(d.to_owned()))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+    impl TxTRegistryProvider for FlakyProvider {
+        fn create_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn delete_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+    }
+    impl Provider for FlakyProvider {
+        fn check_write_access(&self, domain: &str) -> Result<(), ProviderError> {
+            match domain.starts_with("fail") {
+                true => Err(ProviderError::Internal("forbidden".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    /// Provider in dry-run mode with a new domain and a domain that lost its AAAA records, counting applied actions
+    #[derive(Default)]
+    struct DestructiveProvider {
+        applied: AtomicU32,
+    }
+    impl DnsProvider for DestructiveProvider {
+        fn capabilities(&self) -> ProviderCapabilities {
+            ProviderCapabilities {
+                dry_run: true,
+                ..Default::default()
+            }
+        }
+        fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn dry_run(&self) -> bool {
+            true
+        }
+        fn ttl(&self) -> Option<TTL> {
+            None
+        }
+        fn set_ttl(&mut self, _ttl: TTL) {}
+        fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+            Ok(vec![
+                DnsRecord::new("new.example.com", RecordContent::Aaaa(Ipv6Addr::LOCALHOST)),
+                DnsRecord::new(
+                    "gone.example.com",
+                    RecordContent::A(Ipv4Addr::new(203, 0, 113, 1)),
+                ),
+                DnsRecord::new(
+                    "gone.example.com",
+                    RecordContent::Txt(TxtRegistry::ownership_marker(DEFAULT_TXT_PREFIX, "tenant")),
+                ),
+            ])
+        }
+        fn apply(&self, _action: &Action) -> Result<(), ProviderError> {
+            self.applied.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+    impl TxTRegistryProvider for DestructiveProvider {
+        fn create_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn delete_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+    }
+    impl Provider for DestructiveProvider {}
+
+    /// Provider that fails the first `failures` calls to apply with the given error
+    struct UnreliableProvider {
+        failures: u32,
+        error: ProviderError,
+        calls: AtomicU32,
+    }
+    impl DnsProvider for UnreliableProvider {
+        fn capabilities(&self) -> ProviderCapabilities {
+            ProviderCapabilities::default()
+        }
+        fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn dry_run(&self) -> bool {
+            false
+        }
+        fn ttl(&self) -> Option<TTL> {
+            None
+        }
+        fn set_ttl(&mut self, _ttl: TTL) {}
+        fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+            Ok(vec![DnsRecord::new(
+                "my.example.com",
+                RecordContent::Aaaa(Ipv6Addr::LOCALHOST),
+            )])
+        }
+        fn apply(&self, _action: &Action) -> Result<(), ProviderError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err(self.error.clone())
+            } else {
+                Ok(())
+            }
+        }
+    }
+    impl TxTRegistryProvider for UnreliableProvider {
+        fn create_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn delete_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+    }
+    impl Provider for UnreliableProvider {}
+
+    /// Provider with one zone per parent domain, that fails entire batches containing domains of the `broken.example` zone.
+    /// Keeps track of how many batches are applied at the same time, and of the zone of each applied batch
+    #[derive(Default)]
+    struct ZonedProvider {
+        in_flight: AtomicUsize,
+        peak_in_flight: AtomicUsize,
+        applied_zones: Mutex<Vec<String>>,
+    }
+    impl DnsProvider for ZonedProvider {
+        fn capabilities(&self) -> ProviderCapabilities {
+            ProviderCapabilities {
+                batch_apply: true,
+                ..Default::default()
+            }
+        }
+        fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn dry_run(&self) -> bool {
+            false
+        }
+        fn ttl(&self) -> Option<TTL> {
+            None
+        }
+        fn set_ttl(&mut self, _ttl: TTL) {}
+        fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+            Ok(["www.broken.example", "www.healthy.example"]
+                .into_iter()
+                .map(|domain| {
+                    DnsRecord::new(domain.to_string(), RecordContent::Aaaa(Ipv6Addr::LOCALHOST))
+                })
+                .collect())
+        }
+        fn zone_of(&self, domain: &str) -> Option<String> {
+            domain.split_once('.').map(|(_, zone)| zone.to_string())
+        }
+        fn apply(&self, _action: &Action) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn apply_plan(&self, plan: &Plan) -> Vec<Result<(), ProviderError>> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            if let Some(zone) = plan.actions().next().and_then(|a| self.zone_of(a.domain())) {
+                self.applied_zones.lock().unwrap().push(zone);
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let broken = plan
+                .actions()
+                .any(|a| self.zone_of(a.domain()).as_deref() == Some("broken.example"));
+            plan.actions()
+                .map(|_| {
+                    if broken {
+                        Err(ProviderError::Internal("zone unavailable".to_string()))
+                    } else {
+                        Ok(())
+                    }
+                })
+                .collect()
+        }
+    }
+    impl TxTRegistryProvider for ZonedProvider {
+        fn create_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+        fn delete_txt_record(
+            &self,
+            _domain: String,
+            _content: String,
+        ) -> Result<(), ProviderError> {
+            Ok(())
+        }
+    }
+    impl Provider for ZonedProvider {}
+
+    const RETRY: RetryPolicy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_secs(1),
+    };
+
+    #[test]
+    fn should_report_initial_address() {
+        assert_eq!(
+            address_change_message(None, &[Ipv4Addr::new(203, 0, 113, 1)], 3),
+            Some("Initial WAN address: 203.0.113.1, updating 3 domains.".to_string())
+        );
+    }
+
+    #[test]
+    fn should_report_changed_address() {
+        assert_eq!(
+            address_change_message(
+                Some(&[Ipv4Addr::new(203, 0, 113, 1)][..]),
+                &[Ipv4Addr::new(203, 0, 113, 9)],
+                2
+            ),
+            Some("WAN address changed 203.0.113.1 -> 203.0.113.9, updating 2 domains.".to_string())
+        );
+    }
+
+    #[test]
+    fn should_ignore_unchanged_address() {
+        assert_eq!(
+            address_change_message(
+                Some(&[Ipv4Addr::new(203, 0, 113, 1), Ipv4Addr::new(203, 0, 113, 2)][..]),
+                &[Ipv4Addr::new(203, 0, 113, 2), Ipv4Addr::new(203, 0, 113, 1)],
+                0
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn should_fail_on_destructive_actions() {
+        assert_eq!(
+            check_destructive(1, Some(0)),
+            Err(ExecutorError::Destructive { count: 1, max: 0 })
+        );
+        assert_eq!(
+            check_destructive(3, Some(2)),
+            Err(ExecutorError::Destructive { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn should_pass_without_destructive_actions() {
+        check_destructive(0, Some(0)).unwrap();
+        check_destructive(2, Some(2)).unwrap();
+        check_destructive(5, None).unwrap();
+    }
+
+    #[test]
+    fn should_apply_concurrently_in_order() {
+        let plan: Plan = (0..7)
+            .map(|i| match i % 3 {
+                0 => Action::DeleteAndRelease(format!("fail{}.example.com", i)),
+                _ => Action::DeleteAndRelease(format!("ok{}.example.com", i)),
+            })
+            .collect();
+
+        for concurrency in [1, 2, 3, 16] {
+            let results = apply_by_zone(
+                &FlakyProvider,
+                &plan,
+                NonZeroUsize::new(concurrency).unwrap(),
+            );
+            assert_eq!(results.len(), 7);
+            for (i, result) in results.iter().enumerate() {
+                match i % 3 {
+                    0 => assert_eq!(
+                        result,
+                        &Err(ProviderError::Internal(format!("fail{}.example.com", i)))
+                    ),
+                    _ => assert_eq!(result, &Ok(())),
+                }
+            }
+        }
+    }
+
+    fn zoned_plan() -> Plan {
+        (0..12)
+            .map(|i| Action::DeleteAndRelease(format!("www{}.zone{}.example", i, i % 4)))
+            .collect()
+    }
+
+    #[test]
+    fn should_limit_concurrency_across_zones() {
+        for concurrency in [1, 2, 3, 16] {
+            let provider = ZonedProvider::default();
+            let results = apply_by_zone(
+                &provider,
+                &zoned_plan(),
+                NonZeroUsize::new(concurrency).unwrap(),
+            );
+
+            assert_eq!(results.len(), 12);
+            assert!(results.iter().all(Result::is_ok));
+            assert!(provider.peak_in_flight.load(Ordering::SeqCst) <= concurrency);
+        }
+    }
+
+    #[test]
+    fn should_apply_zones_one_after_another_without_concurrency() {
+        let provider = ZonedProvider::default();
+        apply_by_zone(&provider, &zoned_plan(), NonZeroUsize::MIN);
+
+        assert_eq!(provider.peak_in_flight.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *provider.applied_zones.lock().unwrap(),
+            vec![
+                "zone0.example",
+                "zone1.example",
+                "zone2.example",
+                "zone3.example"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_isolate_failing_zones() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = ZonedProvider::default();
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        let res = Executor::try_new(
+            source.as_ref(),
+            &provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            false,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .unwrap()
+        .run()
+        .unwrap();
+
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        assert_eq!(
+            res.successes,
+            vec![Action::ClaimAndUpdate(
+                "www.healthy.example".to_string(),
+                vec![ip]
+            )]
+        );
+        assert_eq!(res.failures.len(), 1);
+        assert_eq!(
+            res.failures[0].0,
+            Action::ClaimAndUpdate("www.broken.example".to_string(), vec![ip])
+        );
+        assert_eq!(res.exit_code(), EXIT_ACTIONS_FAILED);
+    }
+
+    #[test]
+    fn should_retry_flaky_apply() {
+        let provider = UnreliableProvider {
+            failures: 3,
+            error: ProviderError::Internal("timeout".to_string()),
+            calls: AtomicU32::new(0),
+        };
+        let action = Action::DeleteAndRelease("my.example.com".to_string());
+        let delays = std::sync::Mutex::new(vec![]);
+
+        // The initial attempt already happened outside of retry_action
+        let first = provider.apply(&action);
+        let result = retry_action(&provider, &action, first, &RETRY, |d| {
+            delays.lock().unwrap().push(d)
+        });
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            *delays.lock().unwrap(),
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_give_up_after_max_retries() {
+        let provider = UnreliableProvider {
+            failures: 10,
+            error: ProviderError::Internal("timeout".to_string()),
+            calls: AtomicU32::new(0),
+        };
+        let action = Action::DeleteAndRelease("my.example.com".to_string());
+
+        let first = provider.apply(&action);
+        retry_action(&provider, &action, first, &RETRY, |_| ()).unwrap_err();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn should_not_retry_permanent_errors() {
+        let provider = UnreliableProvider {
+            failures: 1,
+            error: ProviderError::DryRunNotSupported,
+            calls: AtomicU32::new(0),
+        };
+        let action = Action::DeleteAndRelease("my.example.com".to_string());
+
+        let first = provider.apply(&action);
+        retry_action(&provider, &action, first, &RETRY, |_| ()).unwrap_err();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+        // Successful actions are not applied again
+        retry_action(&provider, &action, Ok(()), &RETRY, |_| ()).unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn should_not_retry_registry_conflicts() {
+        let mut calls = 0;
+        with_retry(
+            &RETRY,
+            |_| (),
+            super::is_transient_registry_error,
+            || {
+                calls += 1;
+                Err::<(), _>(RegistryError::ClaimError {
+                    domain: "my.example.com".to_string(),
+                    reason: "Owned by someone else".to_string(),
+                })
+            },
+        )
+        .unwrap_err();
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        with_retry(
+            &RETRY,
+            |_| (),
+            super::is_transient_registry_error,
+            || {
+                calls += 1;
+                match calls {
+                    1 => Err(RegistryError::Internal("timeout".to_string())),
+                    _ => Ok(()),
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn should_exit_non_zero_on_failures() {
+        let mut res = RunResult {
+            target_addrs: vec![Ipv4Addr::new(203, 0, 113, 1)],
+            successes: vec![Action::DeleteAndRelease("ok.example.com".to_string())],
+            failures: vec![],
+            plan: Plan::default(),
+        };
+        assert_eq!(res.exit_code(), 0);
+
+        res.failures.push((
+            Action::DeleteAndRelease("fail.example.com".to_string()),
+            ExecutorError::Provider(ProviderError::Internal("timeout".to_string())),
+        ));
+        assert_eq!(res.exit_code(), EXIT_ACTIONS_FAILED);
+    }
+
+    #[test]
+    fn should_record_metrics_after_run() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = FlakyProvider;
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+        let metrics = Arc::new(Metrics::default());
+
+        let mut exec = Executor::try_new(
+            source.as_ref(),
+            &provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            false,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .unwrap();
+        exec.set_metrics(metrics.clone());
+        assert_eq!(metrics.snapshot().runs, 0);
+
+        let res = exec.run().unwrap();
+        assert_eq!(res.successes.len(), 1);
+        assert_eq!(res.plan.actions().count(), 1);
+        assert!(res.skipped().is_empty());
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.runs, 1);
+        assert_eq!(snapshot.successes.creates, 1);
+        assert_eq!(snapshot.last_run_failures, 0);
+    }
+
+    fn run_with_callback(provider: &(dyn Provider + Sync)) -> Vec<(Action, bool)> {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let mut registry = InMemoryRegistry::from_provider("tenant".to_string(), provider).unwrap();
+        let outcomes = RefCell::new(vec![]);
+
+        let mut exec = Executor::try_new(
+            source.as_ref(),
+            provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            false,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .unwrap();
+        exec.set_on_action(Box::new(|action, result| {
+            outcomes.borrow_mut().push((action.clone(), result.is_ok()))
+        }));
+        exec.run().unwrap();
+        drop(exec);
+        outcomes.into_inner()
+    }
+
+    #[test]
+    fn should_report_each_action_to_callback() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        assert_eq!(
+            run_with_callback(&FlakyProvider),
+            vec![(
+                Action::ClaimAndUpdate("new.example.com".to_string(), vec![ip]),
+                true
+            )]
+        );
+
+        let provider = UnreliableProvider {
+            failures: 1,
+            error: ProviderError::Internal("timeout".to_string()),
+            calls: AtomicU32::new(0),
+        };
+        assert_eq!(
+            run_with_callback(&provider),
+            vec![(
+                Action::ClaimAndUpdate("my.example.com".to_string(), vec![ip]),
+                false
+            )]
+        );
+    }
+
+    fn limited_executor<'a>(
+        source: &'a dyn Ipv4Source,
+        provider: &'a FlakyProvider,
+        registry: &'a mut dyn ARegistry,
+        max_changes: usize,
+    ) -> Executor<'a> {
+        let mut exec = Executor::try_new(
+            source,
+            provider,
+            registry,
+            Policy::Sync,
+            PlanOptions::default(),
+            false,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .unwrap();
+        exec.set_max_changes(max_changes);
+        exec
+    }
+
+    #[test]
+    fn should_fail_unverified_actions() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = FlakyProvider;
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        // FlakyProvider accepts all creates, but never actually stores any A records
+        let mut exec = limited_executor(source.as_ref(), &provider, registry.as_mut(), 1);
+        exec.set_verify(true);
+        let res = exec.run().unwrap();
+        assert!(res.successes.is_empty());
+        assert_eq!(res.failures.len(), 1);
+        assert_eq!(
+            res.failures[0].0,
+            Action::ClaimAndUpdate(
+                "new.example.com".to_string(),
+                vec![Ipv4Addr::new(203, 0, 113, 1)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_verify_applied_actions() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+
+        verify_action(
+            &FlakyProvider,
+            &Action::DeleteAndRelease("new.example.com".to_string()),
+        )
+        .unwrap();
+        verify_action(
+            &FlakyProvider,
+            &Action::Update("new.example.com".to_string(), vec![ip]),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_probe_write_access_with_first_regular_domain() {
+        probe_write_access(
+            &FlakyProvider,
+            ["*.fail.example.com", "ok.example.com", "fail.example.com"].into_iter(),
+        )
+        .unwrap();
+        probe_write_access(&FlakyProvider, std::iter::empty()).unwrap();
+    }
+
+    #[test]
+    fn should_fail_write_probe_without_write_access() {
+        let err = probe_write_access(&FlakyProvider, ["fail.example.com"].into_iter()).unwrap_err();
+        assert!(err.to_string().contains("forbidden"));
+    }
+
+    #[test]
+    fn should_abort_dry_run_with_too_many_destructive_actions() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = DestructiveProvider::default();
+        let mut registry = TxtRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        let result = Executor::try_new(
+            source.as_ref(),
+            &provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            true,
+            OutputFormat::Text,
+            false,
+            Some(0),
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .unwrap()
+        .run();
+
+        assert_eq!(
+            result.clone().unwrap_err(),
+            ExecutorError::Destructive { count: 1, max: 0 }
+        );
+        assert_eq!(run_exit_code(&result), EXIT_ERROR);
+        // Aborted before claiming the new domain or applying anything
+        assert_eq!(provider.applied.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            registry
+                .owned_domains()
+                .into_iter()
+                .map(|d| d.name)
+                .collect::<Vec<_>>(),
+            vec!["gone.example.com"]
+        );
+    }
+
+    #[test]
+    fn should_abort_if_plan_exceeds_max_changes() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = FlakyProvider;
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        let err = limited_executor(source.as_ref(), &provider, registry.as_mut(), 0)
+            .run()
+            .unwrap_err();
+        assert_eq!(err, ExecutorError::TooManyChanges { count: 1, max: 0 });
+        // Nothing was claimed
+        assert!(registry.owned_domains().is_empty());
+    }
+
+    #[test]
+    fn should_apply_plan_within_max_changes() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = FlakyProvider;
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        let res = limited_executor(source.as_ref(), &provider, registry.as_mut(), 1)
+            .run()
+            .unwrap();
+        assert_eq!(res.successes.len(), 1);
+    }
+
+    #[test]
+    fn should_report_skipped_actions() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        let res = RunResult {
+            target_addrs: vec![ip],
+            successes: vec![Action::ClaimAndUpdate(
+                "new.example.com".to_string(),
+                vec![ip],
+            )],
+            failures: vec![(
+                Action::Update("old.example.com".to_string(), vec![ip]),
+                ExecutorError::Provider(ProviderError::Internal("timeout".to_string())),
+            )],
+            plan: vec![
+                Action::DeleteAndRelease("gone.example.com".to_string()),
+                Action::Update("old.example.com".to_string(), vec![ip]),
+                Action::ClaimAndUpdate("new.example.com".to_string(), vec![ip]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        assert_eq!(
+            res.skipped(),
+            vec![&Action::DeleteAndRelease("gone.example.com".to_string())]
+        );
+    }
+
+    #[test]
+    fn should_add_jitter_to_interval() {
+        let interval = Duration::from_secs(60);
+
+        assert_eq!(
+            jittered_interval_with(interval, Duration::ZERO, 12345),
+            interval
+        );
+        for random in [0, 1, 9_999, 10_000, 10_001, u64::MAX] {
+            let jittered = jittered_interval_with(interval, Duration::from_secs(10), random);
+            assert!(jittered >= interval);
+            assert!(jittered <= interval + Duration::from_secs(10));
+        }
+        assert_eq!(
+            jittered_interval_with(interval, Duration::from_secs(10), 10_000),
+            interval + Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn should_back_off_after_failures() {
+        let interval = Duration::from_secs(60);
+        let max = Duration::from_secs(300);
+
+        assert_eq!(backoff_interval(interval, 0, max), interval);
+        assert_eq!(backoff_interval(interval, 1, max), Duration::from_secs(120));
+        assert_eq!(backoff_interval(interval, 2, max), Duration::from_secs(240));
+        assert_eq!(backoff_interval(interval, 3, max), max);
+        assert_eq!(backoff_interval(interval, u32::MAX, max), max);
+        // The cap never shortens the regular interval
+        assert_eq!(
+            backoff_interval(interval, 2, Duration::from_secs(10)),
+            interval
+        );
+    }
+
+    #[test]
+    fn should_require_provider_dry_run() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = FlakyProvider;
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        // FlakyProvider never reports dry-run mode, so a dry-run could modify records
+        Executor::try_new(
+            source.as_ref(),
+            &provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            true,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .err()
+        .unwrap();
+    }
+
+    #[test]
+    fn should_reject_dry_run_if_unsupported() {
+        let source = FixedSource::from_addrs(vec![Ipv4Addr::new(203, 0, 113, 1)]);
+        let provider = UnreliableProvider {
+            failures: 0,
+            error: ProviderError::Internal("unused".to_string()),
+            calls: AtomicU32::new(0),
+        };
+        let mut registry =
+            InMemoryRegistry::from_provider("tenant".to_string(), &provider).unwrap();
+
+        let err = Executor::try_new(
+            source.as_ref(),
+            &provider,
+            registry.as_mut(),
+            Policy::Sync,
+            PlanOptions::default(),
+            true,
+            OutputFormat::Text,
+            false,
+            None,
+            false,
+            NonZeroUsize::MIN,
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            ExecutorError::Provider(ProviderError::DryRunNotSupported)
+        ));
+    }
+
+    #[test]
+    fn should_group_results_by_domain() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        let timeout = ExecutorError::Provider(ProviderError::Internal("timeout".to_string()));
+        let res = RunResult {
+            target_addrs: vec![ip],
+            successes: vec![
+                Action::ClaimAndUpdate("new.example.com".to_string(), vec![ip]),
+                Action::DeleteAndRelease("gone.example.com".to_string()),
+                // Records were deleted, but releasing the domain failed
+                Action::DeleteAndRelease("half.example.com".to_string()),
+            ],
+            failures: vec![
+                (
+                    Action::Update("old.example.com".to_string(), vec![ip]),
+                    timeout.clone(),
+                ),
+                (
+                    Action::DeleteAndRelease("half.example.com".to_string()),
+                    timeout.clone(),
+                ),
+            ],
+            plan: Plan::default(),
+        };
+
+        let outcomes = res.by_domain();
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(
+            outcomes["new.example.com"],
+            DomainOutcome {
+                action: Action::ClaimAndUpdate("new.example.com".to_string(), vec![ip]),
+                addresses: vec![ip],
+                error: None,
+            }
+        );
+        assert!(outcomes["gone.example.com"].is_success());
+        assert!(outcomes["gone.example.com"].addresses.is_empty());
+        assert_eq!(outcomes["old.example.com"].addresses, vec![ip]);
+        assert_eq!(outcomes["old.example.com"].error, Some(timeout.clone()));
+        assert_eq!(outcomes["half.example.com"].error, Some(timeout));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::http;
+
+/// Tracks whether the main loop is healthy, for use as a liveness/readiness probe.
+///
+/// The daemon is healthy if the last run succeeded and completed no longer than `max_age` ago.
+/// Until the first run completes, the daemon is considered healthy for `max_age` after startup.
+#[derive(Debug)]
+pub struct Health {
+    max_age: Duration,
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HealthState {
+    /// When the last successful run completed (or the daemon started)
+    last_success: Instant,
+    /// Whether the last run failed
+    failed: bool,
+}
+
+impl Health {
+    pub fn new(max_age: Duration) -> Health {
+        Health {
+            max_age,
+            state: Mutex::new(HealthState {
+                last_success: Instant::now(),
+                failed: false,
+            }),
+        }
+    }
+
+    /// Record the outcome of a run
+    pub fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.failed = !success;
+        if success {
+            state.last_success = Instant::now();
+        }
+    }
+
+    /// Returns `Ok` if the daemon is healthy, otherwise a description of the problem
+    pub fn check(&self) -> Result<(), String> {
+        let state = *self.state.lock().unwrap();
+        if state.failed {
+            return Err("the last run failed".to_string());
+        }
+        let age = state.last_success.elapsed();
+        if age > self.max_age {
+            return Err(format!(
+                "no successful run within the last {}s",
+                age.as_secs()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Serve the health status over HTTP on the given address in the background.
+    /// Requests are answered with 200 if the daemon is healthy and 503 otherwise, regardless of their path.
+    /// Returns the address that the server is listening on.
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> Result<SocketAddr, std::io::Error> {
+        let health = self.clone();
+        http::serve(addr, http::PLAIN_TEXT, move || match health.check() {
+            Ok(_) => (200, "OK", "ok\n".to_string()),
+            Err(e) => (503, "Service Unavailable", format!("{}\n", e)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use super::Health;
+    use crate::http;
+
+    #[test]
+    fn should_be_healthy_after_startup() {
+        Health::new(Duration::from_secs(60)).check().unwrap();
+    }
+
+    #[test]
+    fn should_be_unhealthy_after_failed_run() {
+        let health = Health::new(Duration::from_secs(60));
+
+        health.record(false);
+        health.check().unwrap_err();
+        health.record(true);
+        health.check().unwrap();
+    }
+
+    #[test]
+    fn should_be_unhealthy_without_recent_success() {
+        let health = Health::new(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(20));
+
+        health.check().unwrap_err();
+        health.record(true);
+        health.check().unwrap();
+    }
+
+    #[test]
+    fn should_serve_health_over_http() {
+        let health = Arc::new(Health::new(Duration::from_secs(60)));
+        let addr = health
+            .serve(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))
+            .unwrap();
+        let response = http::get(addr);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+
+        health.record(false);
+        assert!(http::get(addr).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+}
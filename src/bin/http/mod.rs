@@ -0,0 +1,120 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use log::{debug, warn};
+
+/// A response of a [`serve()`] handler: status code, reason phrase and plain-text body
+pub type Response = (u16, &'static str, String);
+
+/// Content type of plain-text responses
+pub const PLAIN_TEXT: &str = "text/plain; charset=utf-8";
+
+/// How long to wait for a client to send its request or to accept the response before giving up on it
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer HTTP requests on the given address in the background.
+/// All requests are answered with the response of `handler` and the given content type, regardless of their method or path.
+/// Each connection is handled on its own thread, so that idle clients cannot hold up others.
+/// Returns the address that the server is listening on.
+pub fn serve(
+    addr: SocketAddr,
+    content_type: &'static str,
+    handler: impl Fn() -> Response + Send + Sync + 'static,
+) -> Result<SocketAddr, std::io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let handler = Arc::new(handler);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Could not accept HTTP connection on {}: {}", local_addr, e);
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            thread::spawn(move || match respond(stream, content_type, &*handler) {
+                Ok(_) => debug!("Served HTTP request on {}", local_addr),
+                Err(e) => warn!("Could not serve HTTP request on {}: {}", local_addr, e),
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+/// Send a GET request to the given address and return the full response
+#[cfg(test)]
+pub fn get(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn respond(
+    mut stream: TcpStream,
+    content_type: &str,
+    handler: &dyn Fn() -> Response,
+) -> Result<(), std::io::Error> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    // We don't care about the request itself, but need to read it before responding
+    let mut buf = [0u8; 1024];
+    let _ = stream.read(&mut buf)?;
+    let (status, reason, body) = handler();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+
+    use super::{get, serve, PLAIN_TEXT};
+
+    #[test]
+    fn should_answer_with_handler_response() {
+        let addr = serve(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+            PLAIN_TEXT,
+            || (503, "Service Unavailable", "not ready\n".to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            get(addr),
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 10\r\nConnection: close\r\n\r\nnot ready\n"
+        );
+    }
+
+    #[test]
+    fn should_not_be_blocked_by_idle_connections() {
+        let addr = serve(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+            PLAIN_TEXT,
+            || (200, "OK", "ok\n".to_string()),
+        )
+        .unwrap();
+
+        // Connects, but never sends a request
+        let _idle = TcpStream::connect(addr).unwrap();
+        assert!(get(addr).ends_with("\r\n\r\nok\n"));
+    }
+}
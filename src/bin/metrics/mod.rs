@@ -0,0 +1,225 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clouddns_nat_helper::plan::{Plan, PlanSummary};
+
+use crate::{
+    executor::{ExecutorError, RunResult},
+    http,
+};
+
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+
+/// Totals collected across all runs, see [`Metrics::snapshot()`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MetricsSnapshot {
+    /// Number of completed runs
+    pub runs: u64,
+    /// Number of runs that could not be completed
+    pub run_errors: u64,
+    /// Successfully applied actions by type
+    pub successes: ActionCounts,
+    /// Failed actions by type
+    pub failures: ActionCounts,
+    /// Number of failed actions in the last completed run
+    pub last_run_failures: u64,
+    /// Unix timestamp of the last run, whether it completed or not
+    pub last_run_timestamp: u64,
+}
+
+/// Number of actions of each type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ActionCounts {
+    pub creates: u64,
+    pub updates: u64,
+    pub deletes: u64,
+}
+impl ActionCounts {
+    fn add(&mut self, summary: PlanSummary) {
+        self.creates += summary.creates as u64;
+        self.updates += summary.updates as u64;
+        self.deletes += summary.deletes as u64;
+    }
+}
+
+/// Collects metrics about executor runs and renders them in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    snapshot: Mutex<MetricsSnapshot>,
+}
+
+impl Metrics {
+    /// Update the metrics with the outcome of a run
+    pub fn record(&self, result: &Result<RunResult, ExecutorError>) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.last_run_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match result {
+            Ok(res) => {
+                snapshot.runs += 1;
+                snapshot
+                    .successes
+                    .add(res.successes.iter().cloned().collect::<Plan>().summary());
+                snapshot.failures.add(
+                    res.failures
+                        .iter()
+                        .map(|(a, _)| a.clone())
+                        .collect::<Plan>()
+                        .summary(),
+                );
+                snapshot.last_run_failures = res.failures.len() as u64;
+            }
+            Err(_) => snapshot.run_errors += 1,
+        }
+    }
+
+    /// The current values of all metrics
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        *self.snapshot.lock().unwrap()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let s = self.snapshot();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(&str, u64)]| {
+            let _ = writeln!(out, "# HELP clouddns_nat_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE clouddns_nat_{} {}", name, kind);
+            for (labels, value) in values {
+                let _ = writeln!(out, "clouddns_nat_{}{} {}", name, labels, value);
+            }
+        };
+        metric(
+            "runs_total",
+            "counter",
+            "Number of completed runs",
+            &[("", s.runs)],
+        );
+        metric(
+            "run_errors_total",
+            "counter",
+            "Number of runs that could not be completed",
+            &[("", s.run_errors)],
+        );
+        metric(
+            "actions_total",
+            "counter",
+            "Number of applied actions by type and result",
+            &[
+                (r#"{action="create",result="success"}"#, s.successes.creates),
+                (r#"{action="update",result="success"}"#, s.successes.updates),
+                (r#"{action="delete",result="success"}"#, s.successes.deletes),
+                (r#"{action="create",result="failure"}"#, s.failures.creates),
+                (r#"{action="update",result="failure"}"#, s.failures.updates),
+                (r#"{action="delete",result="failure"}"#, s.failures.deletes),
+            ],
+        );
+        metric(
+            "last_run_failures",
+            "gauge",
+            "Number of failed actions in the last completed run",
+            &[("", s.last_run_failures)],
+        );
+        metric(
+            "last_run_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last run",
+            &[("", s.last_run_timestamp)],
+        );
+        out
+    }
+
+    /// Serve the metrics over HTTP on the given address in the background.
+    /// All requests are answered with the rendered metrics, regardless of their path.
+    /// Returns the address that the exporter is listening on.
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> Result<SocketAddr, std::io::Error> {
+        let metrics = self.clone();
+        http::serve(addr, PROMETHEUS_TEXT, move || (200, "OK", metrics.render()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use clouddns_nat_helper::{
+        plan::{Action, Plan},
+        provider::ProviderError,
+    };
+
+    use super::Metrics;
+    use crate::{
+        executor::{ExecutorError, RunResult},
+        http,
+    };
+
+    fn result() -> RunResult {
+        RunResult {
+            target_addrs: vec![Ipv4Addr::new(203, 0, 113, 1)],
+            successes: vec![
+                Action::ClaimAndUpdate(
+                    "new.example.com".to_string(),
+                    vec![Ipv4Addr::new(203, 0, 113, 1)],
+                ),
+                Action::DeleteAndRelease("gone.example.com".to_string()),
+            ],
+            failures: vec![(
+                Action::Update(
+                    "old.example.com".to_string(),
+                    vec![Ipv4Addr::new(203, 0, 113, 1)],
+                ),
+                ExecutorError::Provider(ProviderError::Internal("timeout".to_string())),
+            )],
+            plan: Plan::default(),
+        }
+    }
+
+    #[test]
+    fn should_count_runs_and_actions() {
+        let metrics = Metrics::default();
+        metrics.record(&Ok(result()));
+        metrics.record(&Ok(result()));
+        metrics.record(&Err(ExecutorError::Provider(ProviderError::Internal(
+            "unreachable".to_string(),
+        ))));
+
+        let s = metrics.snapshot();
+        assert_eq!(s.runs, 2);
+        assert_eq!(s.run_errors, 1);
+        assert_eq!(s.successes.creates, 2);
+        assert_eq!(s.successes.deletes, 2);
+        assert_eq!(s.failures.updates, 2);
+        assert_eq!(s.last_run_failures, 1);
+        assert!(s.last_run_timestamp > 0);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("clouddns_nat_runs_total 2\n"));
+        assert!(rendered
+            .contains("clouddns_nat_actions_total{action=\"update\",result=\"failure\"} 2\n"));
+        assert!(rendered.contains("# TYPE clouddns_nat_last_run_failures gauge\n"));
+    }
+
+    #[test]
+    fn should_serve_metrics_over_http() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.record(&Ok(result()));
+        let addr = metrics
+            .serve(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))
+            .unwrap();
+
+        let response = http::get(addr);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response.contains("clouddns_nat_runs_total 1\n"));
+    }
+}
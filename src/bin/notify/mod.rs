@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::executor::RunResult;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A notifier is informed about runs that changed (or tried to change) records
+pub trait Notifier {
+    /// Send a notification about the given run
+    fn notify(&self, result: &RunResult) -> Result<(), String>;
+}
+
+/// Notify `notifier` about a run, but only if it contained any actions.
+/// Returns whether a notification was sent
+pub fn notify_changes(notifier: &dyn Notifier, result: &RunResult) -> Result<bool, String> {
+    if result.successes.is_empty() && result.failures.is_empty() {
+        return Ok(false);
+    }
+    notifier.notify(result).map(|_| true)
+}
+
+/// Posts a JSON summary of each run to a webhook URL, see [`result_json()`] for the format
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Result<WebhookNotifier, String> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| format!("Could not create webhook client: {}", e))?;
+        Ok(WebhookNotifier {
+            url: url.to_owned(),
+            client,
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, result: &RunResult) -> Result<(), String> {
+        self.client
+            .post(&self.url)
+            .json(&result_json(result))
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format!("Could not send webhook to {}: {}", self.url, e))
+    }
+}
+
+/// Serialize a [`RunResult`], e.g.
+/// `{"target_addrs": ["203.0.113.1"], "successes": [{"action": "delete", "domain": "my.example.com"}], "failures": [], "domains": {...}}`.
+/// Failures contain an additional `error` field. `domains` contains the outcome for each domain, see [`RunResult::by_domain()`]
+pub fn result_json(result: &RunResult) -> Value {
+    let failures = result
+        .failures
+        .iter()
+        .map(|(action, e)| {
+            let mut failure = serde_json::to_value(action).unwrap_or_else(|_| json!({}));
+            failure["error"] = json!(e.to_string());
+            failure
+        })
+        .collect::<Vec<_>>();
+    let domains = result
+        .by_domain()
+        .into_iter()
+        .map(|(domain, outcome)| {
+            (
+                domain,
+                json!({
+                    "action": serde_json::to_value(&outcome.action).unwrap_or_default()["action"],
+                    "addresses": outcome.addresses,
+                    "success": outcome.is_success(),
+                    "error": outcome.error.map(|e| e.to_string()),
+                }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "target_addrs": result.target_addrs,
+        "successes": result.successes,
+        "failures": failures,
+        "domains": domains,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, net::Ipv4Addr};
+
+    use clouddns_nat_helper::{
+        plan::{Action, Plan},
+        provider::ProviderError,
+    };
+    use serde_json::json;
+
+    use super::{notify_changes, result_json, Notifier};
+    use crate::executor::{ExecutorError, RunResult};
+
+    #[derive(Default)]
+    struct CountingNotifier {
+        calls: Cell<u32>,
+    }
+    impl Notifier for CountingNotifier {
+        fn notify(&self, _result: &RunResult) -> Result<(), String> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(())
+        }
+    }
+
+    fn result() -> RunResult {
+        RunResult {
+            target_addrs: vec![Ipv4Addr::new(203, 0, 113, 1)],
+            successes: vec![],
+            failures: vec![],
+            plan: Plan::default(),
+        }
+    }
+
+    #[test]
+    fn should_only_notify_on_changes() {
+        let notifier = CountingNotifier::default();
+
+        assert!(!notify_changes(&notifier, &result()).unwrap());
+        assert_eq!(notifier.calls.get(), 0);
+
+        let mut res = result();
+        res.successes
+            .push(Action::DeleteAndRelease("gone.example.com".to_string()));
+        assert!(notify_changes(&notifier, &res).unwrap());
+
+        let mut res = result();
+        res.failures.push((
+            Action::DeleteAndRelease("gone.example.com".to_string()),
+            ExecutorError::Provider(ProviderError::Internal("timeout".to_string())),
+        ));
+        assert!(notify_changes(&notifier, &res).unwrap());
+        assert_eq!(notifier.calls.get(), 2);
+    }
+
+    #[test]
+    fn should_serialize_result() {
+        let mut res = result();
+        res.successes.push(Action::Update(
+            "my.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ));
+        res.failures.push((
+            Action::DeleteAndRelease("gone.example.com".to_string()),
+            ExecutorError::Provider(ProviderError::Internal("timeout".to_string())),
+        ));
+
+        assert_eq!(
+            result_json(&res),
+            json!({
+                "target_addrs": ["203.0.113.1"],
+                "successes": [
+                    {"action": "update", "domain": "my.example.com", "addresses": ["203.0.113.1"]},
+                ],
+                "failures": [
+                    {"action": "delete", "domain": "gone.example.com", "error": "`Internal provider Error: `timeout``"},
+                ],
+                "domains": {
+                    "my.example.com": {"action": "update", "addresses": ["203.0.113.1"], "success": true, "error": null},
+                    "gone.example.com": {"action": "delete", "addresses": [], "success": false, "error": "`Internal provider Error: `timeout``"},
+                },
+            })
+        );
+    }
+}
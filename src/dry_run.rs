@@ -0,0 +1,74 @@
+//! Shared dry-run handling for providers and registries.
+//!
+//! Implementers store a [`DryRun`] instead of a plain `bool` and wrap every write in [`DryRun::guard()`],
+//! so that no write can accidentally be performed while dry-run mode is enabled.
+
+/// Tracks whether dry-run mode is enabled and skips guarded writes while it is.
+///
+/// ```
+/// use clouddns_nat_helper::dry_run::DryRun;
+///
+/// let mut dry_run = DryRun::default();
+/// dry_run.enable();
+/// let written = dry_run.guard(|| Ok::<_, String>("written")).unwrap();
+/// assert_eq!(written, None);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DryRun {
+    enabled: bool,
+}
+
+impl DryRun {
+    /// Enable dry-run mode. All subsequent guarded writes are skipped
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Whether dry-run mode is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Perform a write, unless dry-run mode is enabled.
+    /// Returns the result of `write`, or `Ok(None)` if it was skipped
+    pub fn guard<T, E>(&self, write: impl FnOnce() -> Result<T, E>) -> Result<Option<T>, E> {
+        if self.enabled {
+            return Ok(None);
+        }
+        write().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::DryRun;
+
+    #[test]
+    fn should_skip_guarded_writes_when_enabled() {
+        let writes = Cell::new(0);
+        let write = || {
+            writes.set(writes.get() + 1);
+            Ok::<_, String>(writes.get())
+        };
+
+        let mut dry_run = DryRun::default();
+        assert!(!dry_run.is_enabled());
+        assert_eq!(dry_run.guard(write), Ok(Some(1)));
+
+        dry_run.enable();
+        assert!(dry_run.is_enabled());
+        assert_eq!(dry_run.guard(write), Ok(None));
+        assert_eq!(writes.get(), 1);
+    }
+
+    #[test]
+    fn should_return_write_errors() {
+        let dry_run = DryRun::default();
+        assert_eq!(
+            dry_run.guard(|| Err::<(), _>("failed".to_string())),
+            Err("failed".to_string())
+        );
+    }
+}
@@ -2,21 +2,53 @@
 //! Each source implements the [`Ipv4Source`] trait.
 //!
 //! The following sources are currently available:
-//! - [`FixedSource`]: Returns a static Ipv4 address
+//! - [`FixedSource`]: Returns one or more static Ipv4 addresses
 //! - [`HostnameSource`]: Resolves a hostname to an IPv4 address and returns it
+//! - [`StunSource`]: Discovers the public (NAT) IPv4 address through a STUN server
+//! - [`ExecSource`]: Runs a custom command that prints an IPv4 address
+//! - [`EnvSource`]: Reads an IPv4 address from an environment variable
+//!
+//! Additionally, the following sources wrap other sources:
+//! - [`AaaaMirrorSource`]: Only returns addresses while a domain has an AAAA record
+//! - [`FallbackSource`]: Tries multiple sources in order and returns the first successful result
+//! - [`QuorumSource`]: Queries multiple sources and only returns an address that enough of them agree on
+//! - [`CachingSource`]: Caches the addresses of another source for a configurable duration
+//! - [`ValidatingSource`]: Rejects private and otherwise reserved addresses returned by another source
 
+mod aaaa_mirror;
+mod caching;
+mod env;
+mod exec;
+mod fallback;
 mod fixed;
 mod hostname;
+mod quorum;
+mod stun;
+mod validating;
 
 // Export our concrete sources
+pub use aaaa_mirror::AaaaMirrorSource;
+pub use caching::CachingSource;
+pub use env::{EnvSource, EnvSourceConfig};
+pub use exec::{ExecSource, ExecSourceConfig};
+pub use fallback::FallbackSource;
 pub use fixed::FixedSource;
-pub use hostname::{HostnameSource, HostnameSourceConfig};
+pub use hostname::{DnsTransport, HostnameSource, HostnameSourceConfig};
+pub use quorum::QuorumSource;
+pub use stun::{StunSource, StunSourceConfig};
+pub use validating::{ReservedRange, ValidatingSource};
 
 use std::{fmt::Display, net::Ipv4Addr};
 
 /// An `Ipv4Source` can be used to retrieve a single IPv4 address for use in DNS records.
 pub trait Ipv4Source {
     fn addr(&self) -> Result<Ipv4Addr, SourceError>;
+
+    /// Retrieve all IPv4 addresses provided by this source, for example for round-robin setups.
+    /// By default, this simply wraps the address returned by [`Ipv4Source::addr()`].
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        Ok(vec![self.addr()?])
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -34,3 +66,55 @@ impl From<String> for SourceError {
         SourceError { msg: s }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{Ipv4Source, SourceError};
+
+    struct SingleSource;
+    impl Ipv4Source for SingleSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            Ok(Ipv4Addr::new(10, 1, 1, 1))
+        }
+    }
+
+    struct MultiSource;
+    impl Ipv4Source for MultiSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            Ok(Ipv4Addr::new(10, 1, 1, 1))
+        }
+        fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+            Ok(vec![Ipv4Addr::new(10, 1, 1, 1), Ipv4Addr::new(10, 1, 1, 2)])
+        }
+    }
+
+    struct FailingSource;
+    impl Ipv4Source for FailingSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            Err("no address".to_string().into())
+        }
+    }
+
+    #[test]
+    fn addrs_should_default_to_addr() {
+        assert_eq!(
+            SingleSource.addrs().unwrap(),
+            vec![Ipv4Addr::new(10, 1, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn addrs_should_propagate_addr_error() {
+        FailingSource.addrs().unwrap_err();
+    }
+
+    #[test]
+    fn addrs_should_be_overridable() {
+        assert_eq!(
+            MultiSource.addrs().unwrap(),
+            vec![Ipv4Addr::new(10, 1, 1, 1), Ipv4Addr::new(10, 1, 1, 2)]
+        );
+    }
+}
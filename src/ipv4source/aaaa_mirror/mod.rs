@@ -0,0 +1,137 @@
+use std::net::Ipv4Addr;
+
+use super::{Ipv4Source, SourceError};
+use crate::provider::{normalize_domain, DnsProvider, RecordContent};
+
+/// An [`Ipv4Source`] that wraps another source and only returns its addresses while a domain has an AAAA record.
+///
+/// Before each lookup, the records of the domain are retrieved from a [`DnsProvider`].
+/// If the domain has no AAAA record, an error is returned instead of the address(es) of the inner source.
+/// This ties the validity of the IPv4 address to the presence of the IPv6 address it is mirroring,
+/// for example to confirm that a host is still online before publishing its NAT address.
+///
+/// To create a new source, use the [`AaaaMirrorSource::new()`] function
+#[non_exhaustive]
+pub struct AaaaMirrorSource<'a> {
+    inner: Box<dyn Ipv4Source + 'a>,
+    provider: &'a dyn DnsProvider,
+    domain: String,
+}
+
+impl Ipv4Source for AaaaMirrorSource<'_> {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        self.check_aaaa()?;
+        self.inner.addr()
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        self.check_aaaa()?;
+        self.inner.addrs()
+    }
+}
+
+impl<'a> AaaaMirrorSource<'a> {
+    /// Create a new [`AaaaMirrorSource`] that returns the addresses of `inner` as long as `domain` has an AAAA record in `provider`
+    pub fn new(
+        inner: Box<dyn Ipv4Source + 'a>,
+        provider: &'a dyn DnsProvider,
+        domain: &str,
+    ) -> Box<dyn Ipv4Source + 'a> {
+        Box::new(AaaaMirrorSource {
+            inner,
+            provider,
+            domain: normalize_domain(domain.trim_end_matches('.')),
+        })
+    }
+
+    fn check_aaaa(&self) -> Result<(), SourceError> {
+        let records = self
+            .provider
+            .records()
+            .map_err(|e| format!("could not retrieve records for {}: {}", self.domain, e))?;
+        if records.iter().any(|r| {
+            normalize_domain(r.domain_name.trim_end_matches('.')) == self.domain
+                && matches!(r.content, RecordContent::Aaaa(_))
+        }) {
+            Ok(())
+        } else {
+            Err(format!("domain {} does not have an AAAA record", self.domain).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::{
+        ipv4source::FixedSource,
+        provider::{DnsRecord, MockDnsProvider, ProviderError, RecordContent},
+    };
+
+    use super::AaaaMirrorSource;
+
+    fn record(domain: &str, content: RecordContent) -> DnsRecord {
+        DnsRecord::new(domain.to_string(), content)
+    }
+
+    fn provider() -> MockDnsProvider {
+        let mut mock = MockDnsProvider::new();
+        mock.expect_records().returning(|| {
+            Ok(vec![
+                record(
+                    "host.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                ),
+                record(
+                    "v4only.example.com",
+                    RecordContent::A(Ipv4Addr::new(203, 0, 113, 2)),
+                ),
+            ])
+        });
+        mock
+    }
+
+    #[test]
+    fn should_return_address_if_aaaa_exists() {
+        let provider = provider();
+        let src = AaaaMirrorSource::new(
+            FixedSource::from_addr(Ipv4Addr::new(203, 0, 113, 1)),
+            &provider,
+            "host.example.com.",
+        );
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(src.addrs().unwrap(), vec![Ipv4Addr::new(203, 0, 113, 1)]);
+    }
+
+    #[test]
+    fn should_fail_without_aaaa() {
+        let provider = provider();
+        for domain in ["v4only.example.com", "unknown.example.com"] {
+            let src = AaaaMirrorSource::new(
+                FixedSource::from_addr(Ipv4Addr::new(203, 0, 113, 1)),
+                &provider,
+                domain,
+            );
+
+            src.addr().unwrap_err();
+            src.addrs().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn should_fail_if_provider_fails() {
+        let mut provider = MockDnsProvider::new();
+        provider
+            .expect_records()
+            .returning(|| Err(ProviderError::Internal("unreachable".to_string())));
+        let src = AaaaMirrorSource::new(
+            FixedSource::from_addr(Ipv4Addr::new(203, 0, 113, 1)),
+            &provider,
+            "host.example.com",
+        );
+
+        src.addr().unwrap_err();
+    }
+}
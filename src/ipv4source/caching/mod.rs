@@ -0,0 +1,169 @@
+use std::{
+    net::Ipv4Addr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use super::{Ipv4Source, SourceError};
+
+/// An [`Ipv4Source`] that wraps another source and caches its addresses for a given duration.
+///
+/// The inner source is only queried again once the cached addresses are older than `cache_ttl`.
+/// If refreshing fails, the last known-good addresses are returned instead and a warning is logged.
+/// An error is only returned if the inner source has never returned any addresses.
+///
+/// Note that the cache stores the result of [`Ipv4Source::addrs()`], [`Ipv4Source::addr()`] returns the first cached address.
+///
+/// To create a new source, use the [`CachingSource::new()`] function
+#[non_exhaustive]
+pub struct CachingSource {
+    inner: Box<dyn Ipv4Source>,
+    cache_ttl: Duration,
+    cache: Mutex<Option<CacheEntry>>,
+}
+
+struct CacheEntry {
+    fetched: Instant,
+    addrs: Vec<Ipv4Addr>,
+    invalidated: bool,
+}
+
+impl Ipv4Source for CachingSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        self.addrs()?.first().copied().ok_or_else(|| {
+            "CachingSource did not receive any addresses"
+                .to_string()
+                .into()
+        })
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(entry) = cache.as_ref() {
+            if !entry.invalidated && entry.fetched.elapsed() < self.cache_ttl {
+                return Ok(entry.addrs.clone());
+            }
+        }
+        match self.inner.addrs() {
+            Ok(addrs) => {
+                *cache = Some(CacheEntry {
+                    fetched: Instant::now(),
+                    addrs: addrs.clone(),
+                    invalidated: false,
+                });
+                Ok(addrs)
+            }
+            Err(e) => match cache.as_ref() {
+                Some(entry) => {
+                    warn!(
+                        "Could not refresh IPv4 address(es), using stale value from {}s ago: {}",
+                        entry.fetched.elapsed().as_secs(),
+                        e
+                    );
+                    Ok(entry.addrs.clone())
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+impl CachingSource {
+    /// Create a new [`CachingSource`] that caches the addresses of `inner` for `cache_ttl`.
+    pub fn new(inner: Box<dyn Ipv4Source>, cache_ttl: Duration) -> CachingSource {
+        CachingSource {
+            inner,
+            cache_ttl,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Force the next lookup to query the inner source, regardless of the cache age.
+    /// The current addresses are still used as a fallback if that lookup fails
+    pub fn invalidate(&self) {
+        if let Some(entry) = self.cache.lock().unwrap().as_mut() {
+            entry.invalidated = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, net::Ipv4Addr, rc::Rc, time::Duration};
+
+    use crate::ipv4source::{Ipv4Source, SourceError};
+
+    use super::CachingSource;
+
+    /// Returns 10.0.0.x, where x is the number of calls so far, or an error once `fail_after` calls have been made
+    struct CountingSource {
+        calls: Rc<Cell<u8>>,
+        fail_after: u8,
+    }
+    impl Ipv4Source for CountingSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            self.calls.set(self.calls.get() + 1);
+            if self.calls.get() > self.fail_after {
+                return Err("lookup failed".to_string().into());
+            }
+            Ok(Ipv4Addr::new(10, 0, 0, self.calls.get()))
+        }
+    }
+
+    fn source(calls: &Rc<Cell<u8>>, fail_after: u8, ttl: Duration) -> CachingSource {
+        let inner = CountingSource {
+            calls: calls.clone(),
+            fail_after,
+        };
+        CachingSource::new(Box::new(inner), ttl)
+    }
+
+    #[test]
+    fn should_cache_within_ttl() {
+        let calls = Rc::new(Cell::new(0));
+        let src = source(&calls, u8::MAX, Duration::from_secs(3600));
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn should_refresh_after_ttl() {
+        let calls = Rc::new(Cell::new(0));
+        let src = source(&calls, u8::MAX, Duration::ZERO);
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn should_refresh_after_invalidate() {
+        let calls = Rc::new(Cell::new(0));
+        let src = source(&calls, u8::MAX, Duration::from_secs(3600));
+
+        src.addr().unwrap();
+        src.invalidate();
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+    }
+
+    #[test]
+    fn should_serve_stale_address_on_error() {
+        let calls = Rc::new(Cell::new(0));
+        let src = source(&calls, 1, Duration::ZERO);
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn should_fail_without_cached_address() {
+        let src = source(&Rc::new(Cell::new(0)), 0, Duration::from_secs(3600));
+
+        src.addr().unwrap_err();
+    }
+}
@@ -0,0 +1,119 @@
+use std::{env, net::Ipv4Addr};
+
+use super::{Ipv4Source, SourceError};
+
+/// An Ipv4 address source that reads an IPv4 address from an environment variable.
+///
+/// This is useful when the address is determined by an external tool that launches this application,
+/// for example a router hook or a container orchestrator. Surrounding whitespace is ignored.
+///
+/// The variable is read again on each call to [`Ipv4Source::addr()`].
+///
+/// To create a new source, use the [`EnvSource::from_config()`] function
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct EnvSource {
+    config: EnvSourceConfig,
+}
+
+/// Configuration for [`EnvSource`]. Must be supplied when creating an [`EnvSource`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnvSourceConfig {
+    /// Name of the environment variable that contains the address
+    pub var: String,
+}
+
+impl Ipv4Source for EnvSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        let value = env::var(&self.config.var).map_err(|e| {
+            format!(
+                "could not read environment variable {}: {}",
+                self.config.var, e
+            )
+        })?;
+        let value = value.trim();
+        value.parse().map_err(|e| {
+            format!(
+                "environment variable {} contains '{}', which is not a valid IPv4 address: {}",
+                self.config.var, value, e
+            )
+            .into()
+        })
+    }
+}
+
+impl EnvSource {
+    /// Create a new [`EnvSource`] with the supplied configuration.
+    /// Returns an error if the variable is not set or does not contain a valid address
+    pub fn from_config(config: &EnvSourceConfig) -> Result<Box<dyn Ipv4Source>, SourceError> {
+        let source = EnvSource {
+            config: config.to_owned(),
+        };
+        match source.addr() {
+            Ok(_) => Ok(Box::new(source)),
+            Err(e) => Err(format!("could not initialize EnvSource: {}", e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, net::Ipv4Addr};
+
+    use crate::ipv4source::Ipv4Source;
+
+    use super::{EnvSource, EnvSourceConfig};
+
+    // Each test uses its own variable, as tests run in parallel within the same process
+    fn source(var: &str) -> EnvSource {
+        EnvSource {
+            config: EnvSourceConfig {
+                var: var.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn should_return_ip_address() {
+        let var = "CLOUDDNS_NAT_HELPER_TEST_ENV_VALID";
+        env::set_var(var, " 203.0.113.1\n");
+
+        assert_eq!(source(var).addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+        env::remove_var(var);
+    }
+
+    #[test]
+    fn should_follow_changes() {
+        let var = "CLOUDDNS_NAT_HELPER_TEST_ENV_CHANGES";
+        env::set_var(var, "203.0.113.1");
+        let src = source(var);
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+
+        env::set_var(var, "203.0.113.2");
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 2));
+
+        env::remove_var(var);
+        src.addr().unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_unset_variable() {
+        let var = "CLOUDDNS_NAT_HELPER_TEST_ENV_UNSET";
+        env::remove_var(var);
+
+        source(var).addr().unwrap_err();
+        EnvSource::from_config(&EnvSourceConfig {
+            var: var.to_string(),
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_invalid_value() {
+        let var = "CLOUDDNS_NAT_HELPER_TEST_ENV_INVALID";
+        env::set_var(var, "2001:db8::1");
+
+        source(var).addr().unwrap_err();
+        env::remove_var(var);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{net::Ipv4Addr, process::Command};
+
+use super::{Ipv4Source, SourceError};
+
+/// An Ipv4 address source that runs a user-provided command and parses its output as an IPv4 address.
+///
+/// The command must print a single IPv4 address to stdout and exit successfully, surrounding whitespace is ignored.
+/// Note that the command is run with the same privileges as this application, so only use trusted commands.
+///
+/// This source does not perform any sort of caching, each call to [`Ipv4Source::addr()`] will run the command again.
+///
+/// To create a new source, use the [`ExecSource::from_config()`] function
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ExecSource {
+    config: ExecSourceConfig,
+}
+
+/// Configuration for [`ExecSource`]. Must be supplied when creating an [`ExecSource`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExecSourceConfig {
+    /// The command to run
+    pub command: String,
+    /// Arguments to pass to the command
+    pub args: Vec<String>,
+}
+
+impl Ipv4Source for ExecSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        let output = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .output()
+            .map_err(|e| format!("could not run command {}: {}", self.config.command, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "command {} failed with {}: {}",
+                self.config.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stdout = stdout.trim();
+        if stdout.is_empty() {
+            return Err(format!("command {} did not print an address", self.config.command).into());
+        }
+        stdout.parse().map_err(|e| {
+            format!(
+                "command {} printed '{}', which is not a valid IPv4 address: {}",
+                self.config.command, stdout, e
+            )
+            .into()
+        })
+    }
+}
+
+impl ExecSource {
+    /// Create a new [`ExecSource`] with the supplied configuration.
+    /// Returns an error if the initialization of the source fails
+    pub fn from_config(config: &ExecSourceConfig) -> Result<Box<dyn Ipv4Source>, SourceError> {
+        let source = ExecSource {
+            config: config.to_owned(),
+        };
+        match source.addr() {
+            Ok(_) => Ok(Box::new(source)),
+            Err(e) => Err(format!("could not initialize ExecSource: {}", e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::ipv4source::Ipv4Source;
+
+    use super::{ExecSource, ExecSourceConfig};
+
+    fn source(script: &str) -> ExecSource {
+        ExecSource {
+            config: ExecSourceConfig {
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+            },
+        }
+    }
+
+    #[test]
+    fn should_return_ip_address() {
+        let src = source("echo '  203.0.113.1 '");
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+    }
+
+    #[test]
+    fn should_fail_on_nonzero_exit() {
+        source("echo 203.0.113.1; exit 1").addr().unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_empty_output() {
+        source("true").addr().unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_invalid_output() {
+        source("echo not-an-address").addr().unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_missing_command() {
+        ExecSource::from_config(&ExecSourceConfig {
+            command: "/nonexistent/command".to_string(),
+            args: vec![],
+        })
+        .unwrap_err();
+    }
+}
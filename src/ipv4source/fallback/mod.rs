@@ -0,0 +1,109 @@
+use std::net::Ipv4Addr;
+
+use log::debug;
+
+use super::{Ipv4Source, SourceError};
+
+/// An [`Ipv4Source`] that wraps several other sources and tries them in order.
+///
+/// The result of the first source that succeeds is returned. Only if all sources fail is an error returned,
+/// containing the error messages of every source.
+/// This can be used to combine a primary source with one or more backup sources.
+///
+/// To create a new source, use the [`FallbackSource::from_sources()`] function
+#[non_exhaustive]
+pub struct FallbackSource {
+    sources: Vec<Box<dyn Ipv4Source>>,
+}
+
+impl Ipv4Source for FallbackSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        self.first_ok(|s| s.addr())
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        self.first_ok(|s| s.addrs())
+    }
+}
+
+impl FallbackSource {
+    /// Create a new [`FallbackSource`] that tries the supplied sources in order.
+    /// Returns an error if no sources are supplied
+    pub fn from_sources(
+        sources: Vec<Box<dyn Ipv4Source>>,
+    ) -> Result<Box<dyn Ipv4Source>, SourceError> {
+        if sources.is_empty() {
+            return Err("FallbackSource requires at least one source"
+                .to_string()
+                .into());
+        }
+        Ok(Box::new(FallbackSource { sources }))
+    }
+
+    fn first_ok<T>(
+        &self,
+        f: impl Fn(&dyn Ipv4Source) -> Result<T, SourceError>,
+    ) -> Result<T, SourceError> {
+        let mut errors = Vec::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            match f(source.as_ref()) {
+                Ok(res) => {
+                    debug!("Fallback source #{} returned an address", i + 1);
+                    return Ok(res);
+                }
+                Err(e) => {
+                    debug!("Fallback source #{} failed: {}", i + 1, e);
+                    errors.push(format!("source #{}: {}", i + 1, e));
+                }
+            }
+        }
+        Err(format!("all sources failed: {}", errors.join("; ")).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::ipv4source::{FixedSource, Ipv4Source, SourceError};
+
+    use super::FallbackSource;
+
+    struct FailingSource(&'static str);
+    impl Ipv4Source for FailingSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            Err(self.0.to_string().into())
+        }
+    }
+
+    #[test]
+    fn should_reject_empty_sources() {
+        assert!(FallbackSource::from_sources(vec![]).is_err());
+    }
+
+    #[test]
+    fn should_return_first_successful_source() {
+        let src = FallbackSource::from_sources(vec![
+            Box::new(FailingSource("lookup failed")),
+            FixedSource::from_addr(Ipv4Addr::new(10, 1, 1, 1)),
+            FixedSource::from_addr(Ipv4Addr::new(10, 1, 1, 2)),
+        ])
+        .unwrap();
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 1, 1, 1));
+        assert_eq!(src.addrs().unwrap(), vec![Ipv4Addr::new(10, 1, 1, 1)]);
+    }
+
+    #[test]
+    fn should_aggregate_errors_if_all_fail() {
+        let src = FallbackSource::from_sources(vec![
+            Box::new(FailingSource("first failed")),
+            Box::new(FailingSource("second failed")),
+        ])
+        .unwrap();
+
+        let err = src.addr().unwrap_err().to_string();
+        assert!(err.contains("first failed"));
+        assert!(err.contains("second failed"));
+    }
+}
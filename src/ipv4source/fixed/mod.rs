@@ -2,21 +2,76 @@ use std::net::Ipv4Addr;
 
 use super::{Ipv4Source, SourceError};
 
-/// A simple [`Ipv4Source`] that always returns the same static IP address.
+/// A simple [`Ipv4Source`] that always returns the same static IP address(es).
 ///
-/// Create such a source with the [`FixedSource::from_addr()`] function.
+/// Create such a source with the [`FixedSource::from_addr()`] or [`FixedSource::from_addrs()`] functions.
+/// If multiple addresses are supplied, [`Ipv4Source::addr()`] returns the first one, while
+/// [`Ipv4Source::addrs()`] returns all of them (e.g. for a static round-robin setup).
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct FixedSource {
-    addr: Ipv4Addr,
+    addrs: Vec<Ipv4Addr>,
 }
 impl Ipv4Source for FixedSource {
     fn addr(&self) -> Result<Ipv4Addr, SourceError> {
-        Ok(self.addr)
+        self.addrs.first().copied().ok_or_else(|| {
+            "FixedSource does not contain any addresses"
+                .to_string()
+                .into()
+        })
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        if self.addrs.is_empty() {
+            return Err("FixedSource does not contain any addresses"
+                .to_string()
+                .into());
+        }
+        Ok(self.addrs.clone())
     }
 }
 impl FixedSource {
     pub fn from_addr(address: Ipv4Addr) -> Box<dyn Ipv4Source> {
-        Box::new(FixedSource { addr: address })
+        Box::new(FixedSource {
+            addrs: vec![address],
+        })
+    }
+
+    /// Create a source that returns all of the given addresses.
+    /// At least one address must be supplied, otherwise the source always returns an error
+    pub fn from_addrs(addresses: Vec<Ipv4Addr>) -> Box<dyn Ipv4Source> {
+        Box::new(FixedSource { addrs: addresses })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::FixedSource;
+
+    #[test]
+    fn should_return_single_address() {
+        let src = FixedSource::from_addr(Ipv4Addr::new(10, 1, 1, 1));
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 1, 1, 1));
+        assert_eq!(src.addrs().unwrap(), vec![Ipv4Addr::new(10, 1, 1, 1)]);
+    }
+
+    #[test]
+    fn should_return_multiple_addresses() {
+        let addrs = vec![Ipv4Addr::new(10, 1, 1, 1), Ipv4Addr::new(10, 1, 1, 2)];
+        let src = FixedSource::from_addrs(addrs.clone());
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(10, 1, 1, 1));
+        assert_eq!(src.addrs().unwrap(), addrs);
+    }
+
+    #[test]
+    fn should_fail_without_addresses() {
+        let src = FixedSource::from_addrs(vec![]);
+
+        src.addr().unwrap_err();
+        src.addrs().unwrap_err();
     }
 }
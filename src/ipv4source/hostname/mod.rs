@@ -1,23 +1,150 @@
-use std::net::{Ipv4Addr, SocketAddr};
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    thread,
+    time::Duration,
+};
 
 /* the domain crate does have DNS resolving builtin, we could switch to that in the future */
 use dnsclient::{sync::DNSClient, UpstreamServer};
+use hickory_resolver::{
+    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::error::ProtoErrorKind,
+};
+use log::warn;
 
 use super::{Ipv4Source, SourceError};
 
+/// Location of the system resolver configuration, used if no DNS servers are configured
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+/// Base delay between two lookup attempts. Grows with each attempt and has up to the same amount of random jitter added
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
 /// A simple Ipv4 address source that looks up the A record for a given hostname and returns it.
 ///
-/// Note that this source will simply return the first A record that it finds, round-robin DNS and similar
-/// setups are therefore not supported.
+/// [`Ipv4Source::addr()`] returns the first A record that it finds, while [`Ipv4Source::addrs()`] returns all of them
+/// (e.g. for round-robin DNS setups).
 ///
 /// This source does not perform any sort of caching, each call to [`Ipv4Source::addr()`] will lookup the hostname again.
+/// Failed lookups are retried with a short, jittered backoff, up to the configured number of attempts.
 ///
 /// To create a new source, use the [`HostnameSource::from_config()`] function
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct HostnameSource {
     hostname: String,
-    client: DNSClient,
+    client: Box<dyn Resolver>,
+    attempts: u32,
+}
+
+/// Performs the actual DNS lookups for a [`HostnameSource`]
+trait Resolver: std::fmt::Debug {
+    fn query_a(&self, hostname: &str) -> Result<Vec<Ipv4Addr>, SourceError>;
+}
+impl Resolver for DNSClient {
+    fn query_a(&self, hostname: &str) -> Result<Vec<Ipv4Addr>, SourceError> {
+        DNSClient::query_a(self, hostname).map_err(|e| e.to_string().into())
+    }
+}
+
+/// Resolver for lookups over TCP or DNS-over-TLS, which the plain DNS client does not support
+struct StreamResolver {
+    resolver: hickory_resolver::Resolver,
+    tls: bool,
+}
+impl std::fmt::Debug for StreamResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamResolver")
+            .field("tls", &self.tls)
+            .finish_non_exhaustive()
+    }
+}
+impl StreamResolver {
+    fn new(
+        servers: &[SocketAddr],
+        transport: &DnsTransport,
+        timeout: Option<Duration>,
+    ) -> Result<StreamResolver, SourceError> {
+        let (protocol, tls_name) = match transport {
+            DnsTransport::Tls { hostname } => (Protocol::Tls, Some(hostname.to_owned())),
+            _ => (Protocol::Tcp, None),
+        };
+        let name_servers = servers
+            .iter()
+            .map(|addr| {
+                let mut ns = NameServerConfig::new(*addr, protocol);
+                ns.tls_dns_name = tls_name.to_owned();
+                ns
+            })
+            .collect::<Vec<_>>();
+        let mut opts = ResolverOpts::default();
+        if let Some(timeout) = timeout {
+            opts.timeout = timeout;
+        }
+        let resolver = hickory_resolver::Resolver::new(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            opts,
+        )
+        .map_err(|e| format!("Could not create DNS resolver: {}", e))?;
+        Ok(StreamResolver {
+            resolver,
+            tls: protocol == Protocol::Tls,
+        })
+    }
+}
+impl Resolver for StreamResolver {
+    fn query_a(&self, hostname: &str) -> Result<Vec<Ipv4Addr>, SourceError> {
+        match self.resolver.ipv4_lookup(hostname) {
+            Ok(lookup) => Ok(lookup.iter().map(|a| a.0).collect()),
+            Err(e) => Err(lookup_error(&e, self.tls)),
+        }
+    }
+}
+
+/// Describe a failed lookup, distinguishing failed TLS connections to the server from failures to resolve the hostname
+fn lookup_error(e: &ResolveError, tls: bool) -> SourceError {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => format!("Could not resolve hostname: {}", e),
+        ResolveErrorKind::Timeout => format!("DNS lookup timed out: {}", e),
+        ResolveErrorKind::Proto(p) if matches!(p.kind(), ProtoErrorKind::Timeout) => {
+            format!("DNS lookup timed out: {}", e)
+        }
+        ResolveErrorKind::Io(_) | ResolveErrorKind::Proto(_) | ResolveErrorKind::NoConnections
+            if tls =>
+        {
+            format!(
+                "TLS connection to DNS server failed (handshake or certificate validation): {}",
+                e
+            )
+        }
+        _ => format!("DNS lookup failed: {}", e),
+    }
+    .into()
+}
+
+/// How to send DNS queries to the servers of a [`HostnameSource`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum DnsTransport {
+    /// Plain DNS over UDP
+    #[default]
+    Udp,
+    /// Plain DNS over TCP
+    Tcp,
+    /// DNS-over-TLS. The certificate of each server must be valid for `hostname`
+    Tls { hostname: String },
+}
+impl DnsTransport {
+    /// The port that DNS servers usually listen on for this transport
+    pub fn default_port(&self) -> u16 {
+        match self {
+            DnsTransport::Udp | DnsTransport::Tcp => 53,
+            DnsTransport::Tls { .. } => 853,
+        }
+    }
 }
 
 /// Configuration for [`HostnameSource`]. Must be supplied when creating a [`HostnameSource`].
@@ -26,41 +153,86 @@ pub struct HostnameSourceConfig {
     /// The hostname to look up
     pub hostname: String,
     /// A list of DNS server addresses (IP address + Port number) to use for looking up the hostname.
-    pub servers: Vec<SocketAddr>,
+    /// Uses the nameservers of the system (from `/etc/resolv.conf`) if not set
+    pub servers: Option<Vec<SocketAddr>>,
+    /// How long to wait for a DNS server to respond before failing the lookup.
+    /// Uses the default timeout of the DNS client if not set
+    pub timeout: Option<Duration>,
+    /// How to send queries to the DNS servers
+    pub transport: DnsTransport,
+    /// How often to attempt the lookup before giving up. Each attempt queries the configured servers again.
+    /// Values below 1 are treated as 1
+    pub attempts: u32,
 }
 
 impl Ipv4Source for HostnameSource {
     fn addr(&self) -> Result<Ipv4Addr, SourceError> {
-        match self.client.query_a(self.hostname.as_str()) {
-            Ok(addrs) => match addrs.first() {
-                Some(a) => Ok(a.to_owned()),
-                None => Err(SourceError {
-                    msg: format!(
-                        "query for host {} did not return an IPv4 address",
-                        self.hostname
-                    ),
-                }),
-            },
-            Err(e) => Err(e.to_string().into()),
+        Ok(self.addrs()?[0])
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        match self.query_with_retry() {
+            Ok(addrs) if addrs.is_empty() => Err(SourceError {
+                msg: format!(
+                    "query for host {} did not return an IPv4 address",
+                    self.hostname
+                ),
+            }),
+            Ok(addrs) => Ok(addrs),
+            Err(e) => Err(e),
         }
     }
 }
 
 impl HostnameSource {
+    /// Look up the hostname, retrying failed lookups. Returns the error of the last attempt if all of them fail
+    fn query_with_retry(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        let attempts = self.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match self.client.query_a(self.hostname.as_str()) {
+                Ok(addrs) => return Ok(addrs),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(e) => {
+                    let delay = retry_delay(attempt, RandomState::new().build_hasher().finish());
+                    warn!(
+                        "Lookup of {} failed (attempt {}/{}), retrying in {}ms: {}",
+                        self.hostname,
+                        attempt,
+                        attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
     /// Create a new [`HostnameSource`] with the supplied configuration.
     /// Returns an error if the initialization of the source fails
     pub fn from_config(config: &HostnameSourceConfig) -> Result<Box<dyn Ipv4Source>, SourceError> {
-        let client = DNSClient::new(
-            config
-                .servers
-                .iter()
-                .copied()
-                .map(UpstreamServer::new)
-                .collect(),
-        );
+        let servers = servers(config, Path::new(RESOLV_CONF))?;
+        let client: Box<dyn Resolver> = match config.transport {
+            DnsTransport::Udp => {
+                let mut client =
+                    DNSClient::new(servers.into_iter().map(UpstreamServer::new).collect());
+                if let Some(timeout) = config.timeout {
+                    client.set_timeout(timeout);
+                }
+                Box::new(client)
+            }
+            DnsTransport::Tcp | DnsTransport::Tls { .. } => Box::new(StreamResolver::new(
+                &servers,
+                &config.transport,
+                config.timeout,
+            )?),
+        };
         let source = HostnameSource {
             hostname: config.hostname.to_owned(),
             client,
+            attempts: config.attempts,
         };
         match source.addr() {
             Ok(_) => Ok(Box::new(source)),
@@ -73,19 +245,313 @@ impl HostnameSource {
     }
 }
 
+/// Delay before the next lookup after `attempt` failed: a linear backoff plus random jitter, so that
+/// instances started at the same time do not all hit the servers again at once
+fn retry_delay(attempt: u32, random: u64) -> Duration {
+    let backoff_ms = RETRY_BACKOFF.as_millis() as u64;
+    Duration::from_millis(backoff_ms * attempt as u64 + random % (backoff_ms + 1))
+}
+
+/// The DNS servers to query: either the configured ones, or the nameservers listed in the given resolv.conf file
+fn servers(
+    config: &HostnameSourceConfig,
+    resolv_conf: &Path,
+) -> Result<Vec<SocketAddr>, SourceError> {
+    if let Some(servers) = &config.servers {
+        return Ok(servers.to_owned());
+    }
+    let content = fs::read(resolv_conf)
+        .map_err(|e| format!("Could not read {}: {}", resolv_conf.display(), e))?;
+    let resolv = resolv_conf::Config::parse(content)
+        .map_err(|e| format!("Could not parse {}: {}", resolv_conf.display(), e))?;
+    let servers = resolv
+        .nameservers
+        .into_iter()
+        .map(|ns| SocketAddr::new(ns.into(), config.transport.default_port()))
+        .collect::<Vec<_>>();
+    if servers.is_empty() {
+        return Err(format!("No nameservers found in {}", resolv_conf.display()).into());
+    }
+    Ok(servers)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::net::{Ipv4Addr, SocketAddr};
+    use std::{
+        cell::Cell,
+        fs,
+        net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+        time::{Duration, Instant},
+    };
+
+    use hickory_resolver::{
+        error::{ResolveError, ResolveErrorKind},
+        proto::error::ProtoError,
+    };
+
+    use crate::ipv4source::{Ipv4Source, SourceError};
+
+    use super::{
+        lookup_error, retry_delay, servers, DnsTransport, HostnameSource, HostnameSourceConfig,
+        Resolver, RETRY_BACKOFF,
+    };
+
+    /// Returns the configured addresses, or a lookup error if `None`
+    #[derive(Debug)]
+    struct MockResolver(Option<Vec<Ipv4Addr>>);
+    impl Resolver for MockResolver {
+        fn query_a(&self, hostname: &str) -> Result<Vec<Ipv4Addr>, SourceError> {
+            assert_eq!(hostname, "my.example.com");
+            self.0
+                .clone()
+                .ok_or_else(|| "lookup timed out".to_string().into())
+        }
+    }
 
-    use super::{HostnameSource, HostnameSourceConfig};
+    /// Fails the first `failures` lookups, then returns the configured addresses
+    #[derive(Debug)]
+    struct FlakyResolver {
+        failures: Cell<u32>,
+        addrs: Vec<Ipv4Addr>,
+    }
+    impl Resolver for FlakyResolver {
+        fn query_a(&self, _hostname: &str) -> Result<Vec<Ipv4Addr>, SourceError> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err("server failure".to_string().into());
+            }
+            Ok(self.addrs.clone())
+        }
+    }
+
+    fn source(addrs: Option<Vec<Ipv4Addr>>) -> HostnameSource {
+        HostnameSource {
+            hostname: "my.example.com".to_string(),
+            client: Box::new(MockResolver(addrs)),
+            attempts: 1,
+        }
+    }
+
+    fn flaky_source(failures: u32, attempts: u32) -> HostnameSource {
+        HostnameSource {
+            hostname: "my.example.com".to_string(),
+            client: Box::new(FlakyResolver {
+                failures: Cell::new(failures),
+                addrs: vec![Ipv4Addr::new(203, 0, 113, 1)],
+            }),
+            attempts,
+        }
+    }
 
     #[test]
     fn should_return_ip_address() {
-        let src = HostnameSource::from_config(&HostnameSourceConfig {
-            hostname: "google.com".to_string(),
-            servers: vec![SocketAddr::new(Ipv4Addr::new(8, 8, 8, 8).into(), 53)],
+        let src = source(Some(vec![Ipv4Addr::new(203, 0, 113, 1)]));
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+    }
+
+    #[test]
+    fn should_return_all_addresses() {
+        let addrs = vec![Ipv4Addr::new(203, 0, 113, 1), Ipv4Addr::new(203, 0, 113, 2)];
+        let src = source(Some(addrs.clone()));
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(src.addrs().unwrap(), addrs);
+    }
+
+    #[test]
+    fn should_fail_on_empty_answer() {
+        let src = source(Some(vec![]));
+
+        src.addr().unwrap_err();
+        src.addrs().unwrap_err();
+    }
+
+    #[test]
+    fn should_fail_on_resolver_error() {
+        let src = source(None);
+
+        let err = src.addr().unwrap_err();
+        assert_eq!(err.to_string(), "lookup timed out");
+    }
+
+    #[test]
+    fn should_retry_failed_lookup() {
+        let src = flaky_source(1, 3);
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+    }
+
+    #[test]
+    fn should_fail_after_all_attempts() {
+        let src = flaky_source(2, 2);
+
+        let err = src.addr().unwrap_err();
+        assert_eq!(err.to_string(), "server failure");
+    }
+
+    #[test]
+    fn should_attempt_lookup_at_least_once() {
+        let src = flaky_source(0, 0);
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+    }
+
+    #[test]
+    fn should_increase_jittered_retry_delay() {
+        assert_eq!(retry_delay(1, 0), RETRY_BACKOFF);
+        assert_eq!(retry_delay(2, 0), RETRY_BACKOFF * 2);
+        for random in [1, 42, 12345, u64::MAX] {
+            let delay = retry_delay(1, random);
+            assert!(delay >= RETRY_BACKOFF);
+            assert!(delay <= RETRY_BACKOFF * 2);
+        }
+    }
+
+    #[test]
+    fn should_time_out_on_unresponsive_server() {
+        // a server that never answers our queries
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let start = Instant::now();
+
+        let res = HostnameSource::from_config(&HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: Some(vec![server.local_addr().unwrap()]),
+            timeout: Some(Duration::from_millis(200)),
+            transport: DnsTransport::Udp,
+            attempts: 1,
+        });
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    fn resolv_conf(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "clouddns-nat-helper-resolv-{}-{}.conf",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn should_use_system_nameservers_if_unset() {
+        let path = resolv_conf(
+            "system",
+            "# Generated by NetworkManager\nsearch example.com\nnameserver 192.0.2.53\nnameserver 2001:db8::53\n",
+        );
+        let config = HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: None,
+            timeout: None,
+            transport: DnsTransport::Udp,
+            attempts: 1,
+        };
+
+        assert_eq!(
+            servers(&config, &path).unwrap(),
+            vec![
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)), 53),
+                SocketAddr::new("2001:db8::53".parse().unwrap(), 53),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_prefer_configured_servers() {
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 53)), 53);
+        let config = HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: Some(vec![server]),
+            timeout: None,
+            transport: DnsTransport::Udp,
+            attempts: 1,
+        };
+
+        assert_eq!(
+            servers(&config, std::path::Path::new("/nonexistent")).unwrap(),
+            vec![server]
+        );
+    }
+
+    #[test]
+    fn should_fail_without_system_nameservers() {
+        let path = resolv_conf("empty", "search example.com\n");
+        let config = HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: None,
+            timeout: None,
+            transport: DnsTransport::Udp,
+            attempts: 1,
+        };
+
+        servers(&config, &path).unwrap_err();
+    }
+
+    #[test]
+    fn should_use_dot_port_for_system_nameservers() {
+        let path = resolv_conf("dot", "nameserver 192.0.2.53\n");
+        let config = HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: None,
+            timeout: None,
+            transport: DnsTransport::Tls {
+                hostname: "dns.example.com".to_string(),
+            },
+            attempts: 1,
+        };
+
+        assert_eq!(
+            servers(&config, &path).unwrap(),
+            vec![SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)),
+                853
+            )]
+        );
+    }
+
+    #[test]
+    fn should_distinguish_tls_failures_from_resolution_failures() {
+        let connection = ResolveError::from(ProtoError::from("connection reset"));
+        assert!(lookup_error(&connection, true)
+            .to_string()
+            .starts_with("TLS connection to DNS server failed"));
+        assert!(lookup_error(&connection, false)
+            .to_string()
+            .starts_with("DNS lookup failed"));
+
+        let timeout = ResolveError::from(ResolveErrorKind::Timeout);
+        assert!(lookup_error(&timeout, true)
+            .to_string()
+            .starts_with("DNS lookup timed out"));
+    }
+
+    #[test]
+    fn should_fail_tls_handshake_with_plaintext_server() {
+        // a server that accepts connections, but immediately closes them without speaking TLS
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in server.incoming() {
+                drop(stream);
+            }
+        });
+
+        let err = HostnameSource::from_config(&HostnameSourceConfig {
+            hostname: "my.example.com".to_string(),
+            servers: Some(vec![addr]),
+            timeout: Some(Duration::from_millis(500)),
+            transport: DnsTransport::Tls {
+                hostname: "dns.example.com".to_string(),
+            },
+            attempts: 1,
         })
-        .unwrap();
-        src.addr().unwrap();
+        .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("TLS connection to DNS server failed"));
     }
 }
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, net::Ipv4Addr};
+
+use itertools::Itertools;
+use log::debug;
+
+use super::{Ipv4Source, SourceError};
+
+/// An [`Ipv4Source`] that wraps several other sources and only returns an address that enough of them agree on.
+///
+/// All sources are queried on each call to [`Ipv4Source::addr()`]. The address reported by at least `min_agreement` sources is returned,
+/// sources that fail are counted as not agreeing with any address.
+/// This guards against a single misbehaving or compromised source (such as a public echo service) returning a wrong address.
+///
+/// To create a new source, use the [`QuorumSource::from_sources()`] function
+#[non_exhaustive]
+pub struct QuorumSource {
+    sources: Vec<Box<dyn Ipv4Source>>,
+    min_agreement: usize,
+}
+
+impl Ipv4Source for QuorumSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        let mut votes: HashMap<Ipv4Addr, usize> = HashMap::new();
+        let mut errors = Vec::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            match source.addr() {
+                Ok(addr) => {
+                    debug!("Quorum source #{} returned {}", i + 1, addr);
+                    *votes.entry(addr).or_default() += 1;
+                }
+                Err(e) => {
+                    debug!("Quorum source #{} failed: {}", i + 1, e);
+                    errors.push(format!("source #{}: {}", i + 1, e));
+                }
+            }
+        }
+
+        let agreed = votes
+            .iter()
+            .filter(|(_, count)| **count >= self.min_agreement)
+            .collect_vec();
+        match agreed.as_slice() {
+            [(addr, _)] => Ok(**addr),
+            [] => {
+                let mut msg = format!(
+                    "no address was returned by at least {} of {} sources (got {})",
+                    self.min_agreement,
+                    self.sources.len(),
+                    votes
+                        .iter()
+                        .sorted()
+                        .map(|(addr, count)| format!("{} from {} source(s)", addr, count))
+                        .join(", ")
+                );
+                if !errors.is_empty() {
+                    msg.push_str(&format!("; {}", errors.join("; ")));
+                }
+                Err(msg.into())
+            }
+            _ => Err(format!(
+                "sources agree on multiple addresses ({}), refusing to pick one",
+                agreed.iter().map(|(addr, _)| addr).sorted().join(", ")
+            )
+            .into()),
+        }
+    }
+}
+
+impl QuorumSource {
+    /// Create a new [`QuorumSource`] that requires at least `min_agreement` of the supplied sources to return the same address.
+    /// Returns an error if no sources are supplied or `min_agreement` is zero or larger than the number of sources
+    pub fn from_sources(
+        sources: Vec<Box<dyn Ipv4Source>>,
+        min_agreement: usize,
+    ) -> Result<Box<dyn Ipv4Source>, SourceError> {
+        if sources.is_empty() {
+            return Err("QuorumSource requires at least one source"
+                .to_string()
+                .into());
+        }
+        if min_agreement == 0 || min_agreement > sources.len() {
+            return Err(format!(
+                "QuorumSource requires between 1 and {} agreeing sources, got {}",
+                sources.len(),
+                min_agreement
+            )
+            .into());
+        }
+        Ok(Box::new(QuorumSource {
+            sources,
+            min_agreement,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::ipv4source::{FixedSource, Ipv4Source, SourceError};
+
+    use super::QuorumSource;
+
+    struct FailingSource;
+    impl Ipv4Source for FailingSource {
+        fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+            Err("lookup failed".to_string().into())
+        }
+    }
+
+    fn fixed(last: u8) -> Box<dyn Ipv4Source> {
+        FixedSource::from_addr(Ipv4Addr::new(203, 0, 113, last))
+    }
+
+    #[test]
+    fn should_reject_invalid_quorum() {
+        assert!(QuorumSource::from_sources(vec![], 1).is_err());
+        assert!(QuorumSource::from_sources(vec![fixed(1)], 0).is_err());
+        assert!(QuorumSource::from_sources(vec![fixed(1), fixed(1)], 3).is_err());
+    }
+
+    #[test]
+    fn should_return_address_with_quorum() {
+        let src = QuorumSource::from_sources(vec![fixed(1), fixed(2), fixed(1)], 2).unwrap();
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(src.addrs().unwrap(), vec![Ipv4Addr::new(203, 0, 113, 1)]);
+    }
+
+    #[test]
+    fn should_fail_without_quorum() {
+        let src = QuorumSource::from_sources(vec![fixed(1), fixed(2), fixed(3)], 2).unwrap();
+
+        let err = src.addr().unwrap_err().to_string();
+        assert!(err.contains("at least 2 of 3 sources"));
+    }
+
+    #[test]
+    fn should_not_count_failed_sources() {
+        let src = QuorumSource::from_sources(vec![fixed(1), Box::new(FailingSource), fixed(2)], 2)
+            .unwrap();
+
+        let err = src.addr().unwrap_err().to_string();
+        assert!(err.contains("lookup failed"));
+
+        let src = QuorumSource::from_sources(vec![fixed(1), Box::new(FailingSource), fixed(1)], 2)
+            .unwrap();
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+    }
+
+    #[test]
+    fn should_refuse_ambiguous_quorum() {
+        let src =
+            QuorumSource::from_sources(vec![fixed(1), fixed(2), fixed(1), fixed(2)], 2).unwrap();
+
+        src.addr().unwrap_err();
+    }
+}
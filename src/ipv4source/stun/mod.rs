@@ -0,0 +1,302 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use super::{Ipv4Source, SourceError};
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV4: u8 = 0x01;
+const HEADER_LEN: usize = 20;
+/// Time to wait for a response before sending the request again. Doubles with each retransmission
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+/// How often to send the request before giving up, as UDP packets may get lost
+const MAX_TRANSMISSIONS: u32 = 4;
+
+/// An Ipv4 address source that discovers the public (NAT) address of this host by sending a STUN binding request
+/// ([RFC 5389](https://datatracker.ietf.org/doc/html/rfc5389)) to a STUN server.
+///
+/// This is useful if the public address can't be resolved through a hostname, for example behind carrier-grade NAT.
+/// Only IPv4 mapped addresses are accepted, a server reporting an IPv6 address results in an error.
+///
+/// This source does not perform any sort of caching, each call to [`Ipv4Source::addr()`] will send a new request.
+///
+/// To create a new source, use the [`StunSource::from_config()`] function
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StunSource {
+    server: SocketAddr,
+}
+
+/// Configuration for [`StunSource`]. Must be supplied when creating a [`StunSource`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StunSourceConfig {
+    /// The STUN server to query (IP address + Port number)
+    pub server: SocketAddr,
+}
+
+impl Ipv4Source for StunSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        let bind_addr: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .map_err(|e| format!("could not open socket for STUN request: {}", e))?;
+
+        // Retransmissions reuse the transaction ID, so that a late response to an earlier transmission is accepted as well
+        let transaction_id = transaction_id();
+        let request = binding_request(&transaction_id);
+        let mut buf = [0u8; 512];
+        let mut rto = INITIAL_RTO;
+        let mut transmissions = 0;
+        loop {
+            socket
+                .set_read_timeout(Some(rto))
+                .map_err(|e| format!("could not configure socket for STUN request: {}", e))?;
+            socket
+                .send_to(&request, self.server)
+                .map_err(|e| format!("could not send STUN request to {}: {}", self.server, e))?;
+            transmissions += 1;
+
+            match socket.recv(&mut buf) {
+                Ok(len) => return parse_binding_response(&buf[..len], &transaction_id),
+                Err(e)
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                        && transmissions < MAX_TRANSMISSIONS =>
+                {
+                    rto *= 2;
+                }
+                Err(e) => {
+                    return Err(format!(
+                    "did not receive a STUN response from {} after {} request(s) (timed out?): {}",
+                    self.server, transmissions, e
+                )
+                    .into())
+                }
+            }
+        }
+    }
+}
+
+impl StunSource {
+    /// Create a new [`StunSource`] with the supplied configuration.
+    /// Returns an error if the initialization of the source fails
+    pub fn from_config(config: &StunSourceConfig) -> Result<Box<dyn Ipv4Source>, SourceError> {
+        let source = StunSource {
+            server: config.server,
+        };
+        match source.addr() {
+            Ok(_) => Ok(Box::new(source)),
+            Err(e) => Err(format!(
+                "could not initialize StunSource (is the server reachable?): {}",
+                e
+            )
+            .into()),
+        }
+    }
+}
+
+/// Generate a random 96-bit STUN transaction ID
+fn transaction_id() -> [u8; 12] {
+    let mut id = [0u8; 12];
+    let high = RandomState::new().build_hasher().finish().to_be_bytes();
+    let low = RandomState::new().build_hasher().finish().to_be_bytes();
+    id[..8].copy_from_slice(&high);
+    id[8..].copy_from_slice(&low[..4]);
+    id
+}
+
+/// Build a STUN binding request without any attributes
+fn binding_request(transaction_id: &[u8; 12]) -> [u8; HEADER_LEN] {
+    let mut req = [0u8; HEADER_LEN];
+    req[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // message length (0) is already set
+    req[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    req[8..20].copy_from_slice(transaction_id);
+    req
+}
+
+/// Parse a STUN binding response and return the mapped IPv4 address.
+/// XOR-MAPPED-ADDRESS is preferred, MAPPED-ADDRESS is accepted as a fallback for older servers.
+fn parse_binding_response(buf: &[u8], transaction_id: &[u8; 12]) -> Result<Ipv4Addr, SourceError> {
+    if buf.len() < HEADER_LEN {
+        return Err("STUN response is too short".to_string().into());
+    }
+    let msg_type = u16::from_be_bytes([buf[0], buf[1]]);
+    if msg_type != BINDING_SUCCESS_RESPONSE {
+        return Err(format!(
+            "STUN server did not return a binding success response (type {:#06x})",
+            msg_type
+        )
+        .into());
+    }
+    if buf[4..8] != MAGIC_COOKIE.to_be_bytes() || buf[8..20] != transaction_id[..] {
+        return Err("STUN response does not match our request"
+            .to_string()
+            .into());
+    }
+    let msg_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    let attrs = buf
+        .get(HEADER_LEN..HEADER_LEN + msg_len)
+        .ok_or_else(|| "STUN response is truncated".to_string())?;
+
+    let mut mapped = None;
+    let mut xor_mapped = None;
+    let mut pos = 0;
+    while pos + 4 <= attrs.len() {
+        let attr_type = u16::from_be_bytes([attrs[pos], attrs[pos + 1]]);
+        let attr_len = u16::from_be_bytes([attrs[pos + 2], attrs[pos + 3]]) as usize;
+        let value = attrs
+            .get(pos + 4..pos + 4 + attr_len)
+            .ok_or_else(|| "STUN response contains a truncated attribute".to_string())?;
+        match attr_type {
+            ATTR_MAPPED_ADDRESS => mapped = Some(value),
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = Some(value),
+            _ => (),
+        }
+        // attributes are padded to a multiple of 4 bytes
+        pos += 4 + attr_len.div_ceil(4) * 4;
+    }
+
+    if let Some(value) = xor_mapped {
+        let addr = parse_address(value)?;
+        Ok(Ipv4Addr::from(u32::from(addr) ^ MAGIC_COOKIE))
+    } else if let Some(value) = mapped {
+        parse_address(value)
+    } else {
+        Err("STUN response does not contain a mapped address"
+            .to_string()
+            .into())
+    }
+}
+
+/// Parse the value of a (XOR-)MAPPED-ADDRESS attribute, rejecting anything but IPv4
+fn parse_address(value: &[u8]) -> Result<Ipv4Addr, SourceError> {
+    match value {
+        [_, FAMILY_IPV4, _, _, a, b, c, d] => Ok(Ipv4Addr::new(*a, *b, *c, *d)),
+        [_, FAMILY_IPV4, ..] => Err("STUN response contains a malformed IPv4 address"
+            .to_string()
+            .into()),
+        _ => Err("STUN server did not report an IPv4 address"
+            .to_string()
+            .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, UdpSocket},
+        thread,
+    };
+
+    use super::{binding_request, parse_binding_response, StunSource, MAGIC_COOKIE};
+    use crate::ipv4source::Ipv4Source;
+
+    const TID: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    fn response(attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (t, v) in attrs {
+            body.extend_from_slice(&t.to_be_bytes());
+            body.extend_from_slice(&(v.len() as u16).to_be_bytes());
+            body.extend_from_slice(v);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&0x0101u16.to_be_bytes());
+        msg.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(&TID);
+        msg.extend(body);
+        msg
+    }
+
+    fn xor_mapped(addr: Ipv4Addr) -> Vec<u8> {
+        let mut v = vec![0, 0x01, 0x11, 0x22];
+        v.extend_from_slice(&(u32::from(addr) ^ MAGIC_COOKIE).to_be_bytes());
+        v
+    }
+
+    #[test]
+    fn should_build_binding_request() {
+        let req = binding_request(&TID);
+        assert_eq!(req[0..4], [0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(req[4..8], MAGIC_COOKIE.to_be_bytes());
+        assert_eq!(req[8..20], TID);
+    }
+
+    #[test]
+    fn should_parse_xor_mapped_address() {
+        let resp = response(&[
+            (0x8022, b"test".to_vec()),
+            (0x0020, xor_mapped(Ipv4Addr::new(203, 0, 113, 7))),
+        ]);
+
+        assert_eq!(
+            parse_binding_response(&resp, &TID).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+    }
+
+    #[test]
+    fn should_fall_back_to_mapped_address() {
+        let resp = response(&[(0x0001, vec![0, 0x01, 0x11, 0x22, 203, 0, 113, 8])]);
+
+        assert_eq!(
+            parse_binding_response(&resp, &TID).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 8)
+        );
+    }
+
+    #[test]
+    fn should_reject_ipv6_address() {
+        let mut v6 = vec![0, 0x02, 0x11, 0x22];
+        v6.extend_from_slice(&[0u8; 16]);
+        let resp = response(&[(0x0020, v6)]);
+
+        parse_binding_response(&resp, &TID).unwrap_err();
+    }
+
+    #[test]
+    fn should_reject_mismatched_transaction() {
+        let resp = response(&[(0x0020, xor_mapped(Ipv4Addr::new(203, 0, 113, 7)))]);
+
+        parse_binding_response(&resp, &[0u8; 12]).unwrap_err();
+    }
+
+    #[test]
+    fn should_reject_response_without_address() {
+        let resp = response(&[]);
+
+        parse_binding_response(&resp, &TID).unwrap_err();
+    }
+
+    #[test]
+    fn should_retransmit_lost_requests() {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let source = StunSource {
+            server: server.local_addr().unwrap(),
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            // Ignore the first request, as if it had been lost
+            server.recv_from(&mut buf).unwrap();
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let mut resp = response(&[(0x0020, xor_mapped(Ipv4Addr::new(203, 0, 113, 7)))]);
+            resp[8..20].copy_from_slice(&buf[8..20]);
+            server.send_to(&resp, client).unwrap();
+        });
+
+        assert_eq!(source.addr().unwrap(), Ipv4Addr::new(203, 0, 113, 7));
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use std::net::Ipv4Addr;
+
+use super::{Ipv4Source, SourceError};
+
+/// A range of non-global IPv4 addresses that [`ValidatingSource`] rejects unless explicitly allowed.
+/// Addresses in [`ReservedRange::UNUSABLE`] ranges are always rejected
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReservedRange {
+    /// Private networks (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16)
+    Private,
+    /// Shared address space used for carrier-grade NAT (100.64.0.0/10)
+    Shared,
+    /// Loopback addresses (127.0.0.0/8)
+    Loopback,
+    /// Link-local addresses (169.254.0.0/16)
+    LinkLocal,
+    /// Documentation networks (192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24)
+    Documentation,
+    /// The unspecified address (0.0.0.0)
+    Unspecified,
+    /// The broadcast address (255.255.255.255)
+    Broadcast,
+    /// Multicast addresses (224.0.0.0/4)
+    Multicast,
+}
+
+impl ReservedRange {
+    const ALL: [ReservedRange; 8] = [
+        ReservedRange::Private,
+        ReservedRange::Shared,
+        ReservedRange::Loopback,
+        ReservedRange::LinkLocal,
+        ReservedRange::Documentation,
+        ReservedRange::Unspecified,
+        ReservedRange::Broadcast,
+        ReservedRange::Multicast,
+    ];
+
+    /// Ranges whose addresses can never be the address of a host, so they are rejected even if they are allowed
+    pub const UNUSABLE: [ReservedRange; 3] = [
+        ReservedRange::Unspecified,
+        ReservedRange::Broadcast,
+        ReservedRange::Multicast,
+    ];
+
+    /// Whether the address is part of this range
+    pub fn contains(&self, addr: &Ipv4Addr) -> bool {
+        match self {
+            ReservedRange::Private => addr.is_private(),
+            // 100.64.0.0/10
+            ReservedRange::Shared => addr.octets()[0] == 100 && (addr.octets()[1] & 0xc0) == 64,
+            ReservedRange::Loopback => addr.is_loopback(),
+            ReservedRange::LinkLocal => addr.is_link_local(),
+            ReservedRange::Documentation => addr.is_documentation(),
+            ReservedRange::Unspecified => addr.is_unspecified(),
+            ReservedRange::Broadcast => addr.is_broadcast(),
+            ReservedRange::Multicast => addr.is_multicast(),
+        }
+    }
+}
+
+/// An [`Ipv4Source`] that wraps another source and rejects addresses that are not globally routable.
+///
+/// If the inner source returns an address in any [`ReservedRange`], an error is returned instead.
+/// Ranges can be explicitly allowed, for example for intentional deployments in private networks.
+/// Addresses in [`ReservedRange::UNUSABLE`] ranges are rejected even if allowed.
+///
+/// To create a new source, use the [`ValidatingSource::new()`] or [`ValidatingSource::rejecting_unusable()`] functions
+#[non_exhaustive]
+pub struct ValidatingSource {
+    inner: Box<dyn Ipv4Source>,
+    allowed: Vec<ReservedRange>,
+}
+
+impl Ipv4Source for ValidatingSource {
+    fn addr(&self) -> Result<Ipv4Addr, SourceError> {
+        let addr = self.inner.addr()?;
+        self.validate(&addr)?;
+        Ok(addr)
+    }
+
+    fn addrs(&self) -> Result<Vec<Ipv4Addr>, SourceError> {
+        let addrs = self.inner.addrs()?;
+        for addr in &addrs {
+            self.validate(addr)?;
+        }
+        Ok(addrs)
+    }
+}
+
+impl ValidatingSource {
+    /// Create a new [`ValidatingSource`] that validates the addresses of `inner`.
+    /// Addresses in any of the `allowed` ranges are accepted
+    pub fn new(inner: Box<dyn Ipv4Source>, allowed: Vec<ReservedRange>) -> Box<dyn Ipv4Source> {
+        Box::new(ValidatingSource { inner, allowed })
+    }
+
+    /// Create a new [`ValidatingSource`] that only rejects addresses in [`ReservedRange::UNUSABLE`] ranges, such as `0.0.0.0`.
+    /// All other reserved ranges are accepted
+    pub fn rejecting_unusable(inner: Box<dyn Ipv4Source>) -> Box<dyn Ipv4Source> {
+        ValidatingSource::new(inner, ReservedRange::ALL.to_vec())
+    }
+
+    fn validate(&self, addr: &Ipv4Addr) -> Result<(), SourceError> {
+        if let Some(range) = ReservedRange::UNUSABLE.iter().find(|r| r.contains(addr)) {
+            return Err(format!(
+                "source returned {} ({:?} address), which can never be used in an A record",
+                addr, range
+            )
+            .into());
+        }
+        match ReservedRange::ALL
+            .iter()
+            .find(|r| r.contains(addr) && !self.allowed.contains(r))
+        {
+            Some(range) => Err(format!(
+                "source returned the non-global address {} ({:?} range), refusing to use it",
+                addr, range
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::ipv4source::FixedSource;
+
+    use super::{ReservedRange, ValidatingSource};
+
+    #[test]
+    fn should_accept_global_address() {
+        let src = ValidatingSource::new(FixedSource::from_addr(Ipv4Addr::new(1, 1, 1, 1)), vec![]);
+
+        assert_eq!(src.addr().unwrap(), Ipv4Addr::new(1, 1, 1, 1));
+    }
+
+    #[test]
+    fn should_reject_reserved_addresses() {
+        for addr in [
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(100, 64, 0, 1),
+            Ipv4Addr::new(100, 127, 255, 254),
+            Ipv4Addr::new(127, 0, 0, 1),
+            Ipv4Addr::new(169, 254, 1, 1),
+        ] {
+            let src = ValidatingSource::new(FixedSource::from_addr(addr), vec![]);
+            src.addr().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn should_not_treat_neighbours_of_shared_range_as_reserved() {
+        for addr in [Ipv4Addr::new(100, 63, 0, 1), Ipv4Addr::new(100, 128, 0, 1)] {
+            let src = ValidatingSource::new(FixedSource::from_addr(addr), vec![]);
+            src.addr().unwrap();
+        }
+    }
+
+    #[test]
+    fn should_accept_allowed_ranges() {
+        let src = ValidatingSource::new(
+            FixedSource::from_addr(Ipv4Addr::new(192, 168, 1, 1)),
+            vec![ReservedRange::Private],
+        );
+
+        src.addr().unwrap();
+    }
+
+    #[test]
+    fn should_reject_multicast_addresses() {
+        for addr in [
+            Ipv4Addr::new(224, 0, 0, 1),
+            Ipv4Addr::new(239, 255, 255, 255),
+        ] {
+            let src = ValidatingSource::new(FixedSource::from_addr(addr), vec![]);
+            src.addr().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn should_always_reject_unusable_addresses() {
+        for addr in [
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+            Ipv4Addr::new(224, 0, 0, 1),
+            Ipv4Addr::new(239, 255, 255, 255),
+        ] {
+            let src = ValidatingSource::rejecting_unusable(FixedSource::from_addr(addr));
+            src.addr().unwrap_err();
+
+            let src = ValidatingSource::new(
+                FixedSource::from_addr(addr),
+                vec![
+                    ReservedRange::Unspecified,
+                    ReservedRange::Broadcast,
+                    ReservedRange::Multicast,
+                ],
+            );
+            src.addr().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn should_only_reject_unusable_addresses_if_requested() {
+        for addr in [
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(100, 64, 0, 1),
+            Ipv4Addr::new(223, 255, 255, 255),
+            Ipv4Addr::new(240, 0, 0, 1),
+        ] {
+            let src = ValidatingSource::rejecting_unusable(FixedSource::from_addr(addr));
+            assert_eq!(src.addr().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn should_validate_all_addresses() {
+        let src = ValidatingSource::new(
+            FixedSource::from_addrs(vec![
+                Ipv4Addr::new(1, 1, 1, 1),
+                Ipv4Addr::new(192, 168, 1, 1),
+            ]),
+            vec![],
+        );
+
+        src.addr().unwrap();
+        src.addrs().unwrap_err();
+    }
+}
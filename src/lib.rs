@@ -7,9 +7,11 @@
 //! - [`ipv4source`]s are used to retrieve a valid Ipv4 address for any managed A records
 //! - [`provider`]s are DNS providers such as Cloudflare that ultimately server DNS records to clients
 //! - [`registry`] is used to implement ownership over DNS A records, preventing conflicts with other instances of this application
+//! - [`dry_run`] helps providers and registries skip all writes in dry-run mode
 
 #![allow(clippy::uninlined_format_args)]
 
+pub mod dry_run;
 pub mod ipv4source;
 pub mod plan;
 pub mod provider;
@@ -1,10 +1,19 @@
 //! Plan the actions required to bring domains up-to-date.
 
-use std::{fmt::Display, net::Ipv4Addr};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
 
-use log::info;
+use itertools::Itertools;
+use log::{debug, info, warn};
 
-use crate::registry::ARegistry;
+use crate::{
+    provider::normalize_domain,
+    registry::{self, ARegistry},
+};
 
 pub type Domain = String;
 
@@ -12,7 +21,8 @@ pub type Domain = String;
 /// Plans contain the changes required to bring a provider from their current to their desired state.
 ///
 /// To create a new plan, use [`Plan::generate()`].
-#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+#[derive(Debug, Default, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Plan(Vec<Action>);
 
 /// Represents an action to be performed on a domain by a provider.
@@ -24,21 +34,80 @@ pub struct Plan(Vec<Action>);
 pub enum Action {
     /// Indicates that this domain is new and needs to be added.
     /// This means claiming ownership with a [`crate::registry::ARegistry`] and then applying the Action to a [`crate::provider::Provider`].
-    ClaimAndUpdate(Domain, Ipv4Addr),
+    /// Contains the full set of addresses that the domain should have A records for.
+    ClaimAndUpdate(Domain, Vec<Ipv4Addr>),
     /// Indicates that this domain is already owned but is out-of-date.
-    Update(Domain, Ipv4Addr),
+    /// Contains the full set of addresses that the domain should have A records for.
+    Update(Domain, Vec<Ipv4Addr>),
     /// Indicates that the entry for this domain should be deleted and the domain released
     DeleteAndRelease(Domain),
 }
+#[cfg(feature = "serde")]
+impl serde::Serialize for Action {
+    /// Actions are serialized as a map, e.g. `{"action": "update", "domain": "my.example.com", "addresses": ["203.0.113.1"]}`
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let (action, domain, addresses) = match self {
+            Action::ClaimAndUpdate(d, ips) => ("create", d, Some(ips)),
+            Action::Update(d, ips) => ("update", d, Some(ips)),
+            Action::DeleteAndRelease(d) => ("delete", d, None),
+        };
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("action", action)?;
+        map.serialize_entry("domain", domain)?;
+        if let Some(addresses) = addresses {
+            map.serialize_entry("addresses", addresses)?;
+        }
+        map.end()
+    }
+}
+
 impl Display for Action {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Action::ClaimAndUpdate(d, ip) => write!(f, "CREATE {} => {}", d, ip),
-            Action::Update(d, ip) => write!(f, "UPDATE {} => {}", d, ip),
+            Action::ClaimAndUpdate(d, ips) => {
+                write!(f, "CREATE {} => {}", d, ips.iter().join(", "))
+            }
+            Action::Update(d, ips) => write!(f, "UPDATE {} => {}", d, ips.iter().join(", ")),
             Action::DeleteAndRelease(d) => write!(f, "DELETE {}", d),
         }
     }
 }
+impl Action {
+    /// The domain this action applies to
+    pub fn domain(&self) -> &Domain {
+        match self {
+            Action::ClaimAndUpdate(d, _) | Action::Update(d, _) | Action::DeleteAndRelease(d) => d,
+        }
+    }
+}
+
+/// Number of actions of each type in a [`Plan`], see [`Plan::summary()`]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PlanSummary {
+    /// Number of [`Action::ClaimAndUpdate`]s
+    pub creates: usize,
+    /// Number of [`Action::Update`]s
+    pub updates: usize,
+    /// Number of [`Action::DeleteAndRelease`]s
+    pub deletes: usize,
+}
+impl PlanSummary {
+    /// Whether the summarized plan does not contain any actions
+    pub fn is_empty(&self) -> bool {
+        self.creates == 0 && self.updates == 0 && self.deletes == 0
+    }
+}
+impl Display for PlanSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} creates, {} updates, {} deletes",
+            self.creates, self.updates, self.deletes
+        )
+    }
+}
 
 /// Policies limit the types of [`Action`] that will be added when generating a [`Plan`]:
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -50,6 +119,114 @@ pub enum Policy {
     Upsert,
     /// Will perform all types of actions, including [`Action::ClaimAndUpdate`],[`Action::Update`] and [`Action::DeleteAndRelease`].
     Sync,
+    /// Will only delete records of owned domains that no longer have an AAAA record, never creating or updating anything.
+    /// Records are always deleted with [`Action::DeleteAndRelease`], regardless of [`PlanOptions::delete_mode`].
+    DeleteOnly,
+}
+
+/// Additional options that fine-tune how a [`Plan`] is generated.
+/// The [`Default`] options match the behavior of [`Plan::generate()`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct PlanOptions {
+    /// Do not update A records that were modified within their TTL, deferring the update to a later run instead.
+    /// This reduces churn and resolver inconsistency when the address source is flapping.
+    /// Only has an effect if the provider reports record TTLs and modification times.
+    pub defer_recent_updates: bool,
+    /// With [`Policy::CreateOnly`], do not recreate missing A records for domains that are already owned.
+    /// By default, owned domains without an A record always get one, regardless of policy.
+    pub create_only_skip_owned: bool,
+    /// What to do with the A records of owned domains that no longer have AAAA records (with [`Policy::Sync`])
+    pub delete_mode: DeleteMode,
+    /// Only consider domains matching this filter. Other domains are never claimed, updated or deleted
+    pub domain_filter: DomainFilter,
+    /// Consider wildcard domains such as `*.example.com`. By default, they are never claimed, updated or deleted
+    pub manage_wildcards: bool,
+    /// If set, only domains in this list are claimed or updated. Unlike [`PlanOptions::domain_filter`], domains must match exactly.
+    /// Owned domains that are not in the list may still be deleted once they lose their AAAA records. See [`parse_domain_list()`]
+    pub domain_allowlist: Option<BTreeSet<String>>,
+    /// Consider owned domains up-to-date if their A records contain all desired addresses, even if they contain other addresses as well.
+    /// By default, the other addresses are pruned with [`Policy::Upsert`] and [`Policy::Sync`]
+    pub keep_extra_addresses: bool,
+    /// Ignore domains whose AAAA records are all non-global (unique local, link-local, loopback or unspecified addresses).
+    /// Publishing an A record for such a domain is almost always a misconfiguration, unless the zone is only used internally
+    pub skip_non_global_aaaa: bool,
+}
+
+/// Parse a newline-delimited list of domains, e.g. for [`PlanOptions::domain_allowlist`].
+/// Empty lines and lines starting with `#` are ignored
+pub fn parse_domain_list(content: &str) -> BTreeSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| normalize_domain(l.trim_end_matches('.')))
+        .collect()
+}
+
+/// Limits the domains that a [`Plan`] may contain actions for.
+///
+/// Patterns starting with `*.` match all subdomains of the given domain, other patterns must match a domain exactly.
+/// The default filter matches all domains.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct DomainFilter {
+    /// If not empty, only domains matching one of these patterns are considered
+    pub include: Vec<String>,
+    /// Domains matching any of these patterns are never considered, even if they are included
+    pub exclude: Vec<String>,
+}
+impl DomainFilter {
+    /// Whether the given domain passes the filter
+    pub fn matches(&self, domain: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| pattern_matches(p, domain)))
+            && !self.exclude.iter().any(|p| pattern_matches(p, domain))
+    }
+}
+
+/// Whether an IPv6 address may be reachable from the internet, i.e. is not unique local (`fc00::/7`), link-local (`fe80::/10`), loopback or unspecified
+fn is_global_ipv6(addr: &Ipv6Addr) -> bool {
+    let first = addr.segments()[0];
+    !(addr.is_loopback()
+        || addr.is_unspecified()
+        || first & 0xfe00 == 0xfc00
+        || first & 0xffc0 == 0xfe80)
+}
+
+fn pattern_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = normalize_domain(pattern.trim_end_matches('.'));
+    let domain = normalize_domain(domain.trim_end_matches('.'));
+    match pattern.strip_prefix("*.") {
+        Some(parent) => domain.ends_with(&format!(".{}", parent)),
+        None => pattern == domain,
+    }
+}
+
+/// Determines how records of owned domains without AAAA records are removed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeleteMode {
+    /// Delete the A records and release ownership of the domain
+    #[default]
+    Delete,
+    /// Keep the domain and point its A record at the given "parking" address instead, retaining ownership.
+    /// This makes teardown easily reversible
+    Park(Ipv4Addr),
+}
+
+/// Whether the A records of a domain were modified within their TTL
+fn recently_modified(domain: &registry::Domain) -> bool {
+    match (domain.a_modified, domain.a_ttl) {
+        (Some(modified), Some(ttl)) => match modified.elapsed() {
+            Ok(elapsed) => elapsed < Duration::from_secs(ttl.into()),
+            // Modification time is in the future, so it was certainly modified recently
+            Err(_) => true,
+        },
+        _ => false,
+    }
+}
+
+impl FromIterator<Action> for Plan {
+    fn from_iter<T: IntoIterator<Item = Action>>(iter: T) -> Self {
+        Plan(iter.into_iter().collect())
+    }
 }
 
 impl Plan {
@@ -57,12 +234,202 @@ impl Plan {
         self.0.iter()
     }
 
-    fn add_create(&mut self, name: String, addr: Ipv4Addr) {
-        self.0.push(Action::ClaimAndUpdate(name, addr));
+    /// Serialize the plan as a JSON array of actions, e.g. for auditing dry-runs.
+    /// See the [`serde::Serialize`] implementation of [`Action`] for the format of each action
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Return the plan with its actions in a deterministic order: all [`Action::DeleteAndRelease`]s first,
+    /// then all [`Action::Update`]s and finally all [`Action::ClaimAndUpdate`]s, each sorted by domain.
+    ///
+    /// Applying deletes first avoids a window in which both old and new A records exist at the same time.
+    /// Plans returned by [`Plan::generate()`] are already sorted.
+    pub fn sorted(mut self) -> Plan {
+        self.0.sort_by_cached_key(|action| match action {
+            Action::DeleteAndRelease(d) => (0, d.to_owned()),
+            Action::Update(d, _) => (1, d.to_owned()),
+            Action::ClaimAndUpdate(d, _) => (2, d.to_owned()),
+        });
+        self
+    }
+
+    /// Count the actions in this plan by type
+    pub fn summary(&self) -> PlanSummary {
+        self.0
+            .iter()
+            .fold(PlanSummary::default(), |mut summary, action| {
+                match action {
+                    Action::ClaimAndUpdate(_, _) => summary.creates += 1,
+                    Action::Update(_, _) => summary.updates += 1,
+                    Action::DeleteAndRelease(_) => summary.deletes += 1,
+                }
+                summary
+            })
+    }
+
+    /// Whether a domain passes the domain filter of the given options
+    fn filter_domain(domain: &registry::Domain, options: &PlanOptions) -> bool {
+        let matches = options.domain_filter.matches(&domain.name);
+        if !matches {
+            debug!(
+                "Domain {} is excluded by the domain filter, ignoring",
+                domain.name
+            );
+        }
+        matches
+    }
+
+    /// Whether A records may be created or updated for a domain according to the allowlist of the given options
+    fn filter_allowlist(domain: &registry::Domain, options: &PlanOptions) -> bool {
+        match &options.domain_allowlist {
+            Some(allowlist) if !allowlist.contains(&domain.name) => {
+                debug!(
+                    "Domain {} is not in the domain allowlist, ignoring",
+                    domain.name
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether a wildcard domain may be managed according to the given options
+    fn filter_wildcard(domain: &registry::Domain, options: &PlanOptions) -> bool {
+        if domain.is_wildcard() && !options.manage_wildcards {
+            debug!("Domain {} is a wildcard, ignoring", domain.name);
+            return false;
+        }
+        true
+    }
+
+    /// Whether a domain can hold A records at all. Domains with a CNAME can't have any other records, so they are skipped
+    fn filter_cname(domain: &registry::Domain) -> bool {
+        match &domain.cname {
+            Some(cname) => {
+                warn!(
+                    "Domain {} has a CNAME record pointing to {}, A records can't be added to it. Ignoring",
+                    domain.name, cname
+                );
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Whether a domain has at least one globally reachable AAAA record, if required by the given options.
+    /// Domains without any AAAA records pass this filter, as they are handled by the deletion logic instead
+    fn filter_global_aaaa(domain: &registry::Domain, options: &PlanOptions) -> bool {
+        if options.skip_non_global_aaaa
+            && !domain.aaaa.is_empty()
+            && !domain.aaaa.iter().any(is_global_ipv6)
+        {
+            warn!(
+                "Domain {} only has non-global AAAA records ({}), ignoring",
+                domain.name,
+                domain.aaaa.iter().join(", ")
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Returns all actions that remove records, i.e. [`Action::DeleteAndRelease`]
+    pub fn destructive_actions(&self) -> impl Iterator<Item = &Action> + '_ {
+        self.0
+            .iter()
+            .filter(|a| matches!(a, Action::DeleteAndRelease(_)))
+    }
+
+    /// Render the plan as a human-readable table with one row per [`Action`].
+    ///
+    /// The current state of each affected domain (existing A records and ownership) is looked up in `domains`,
+    /// which should be the set of domains the plan was generated from (see [`ARegistry::all_domains()`]).
+    pub fn table(&self, domains: &[registry::Domain]) -> String {
+        let header = ["ACTION", "DOMAIN", "OLD A", "NEW A", "OWNERSHIP"];
+        let rows = self
+            .actions()
+            .map(|action| {
+                let (kind, name, new) = match action {
+                    Action::ClaimAndUpdate(d, ips) => ("CREATE", d, ips.iter().join(", ")),
+                    Action::Update(d, ips) => ("UPDATE", d, ips.iter().join(", ")),
+                    Action::DeleteAndRelease(d) => ("DELETE", d, "-".to_string()),
+                };
+                let current = domains.iter().find(|d| &d.name == name);
+                let old = current
+                    .map(|d| d.a.iter().join(", "))
+                    .filter(|a| !a.is_empty())
+                    .unwrap_or_else(|| "-".to_string());
+                let ownership = current
+                    .map(|d| format!("{:?}", d.ownership()))
+                    .unwrap_or_else(|| "-".to_string());
+                [kind.to_string(), name.to_owned(), old, new, ownership]
+            })
+            .collect_vec();
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (i, col) in row.iter().enumerate() {
+                widths[i] = widths[i].max(col.len());
+            }
+        }
+
+        let mut table = String::new();
+        for row in std::iter::once(header.map(String::from)).chain(rows) {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(col, width)| format!("{:<width$}", col, width = width))
+                .join("  ");
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table
+    }
+
+    /// Render the changes of the plan as a unified-style diff of the A records of each affected domain, e.g.:
+    ///
+    /// ```text
+    /// update my.example.com
+    ///   A 203.0.113.1
+    /// - A 203.0.113.2
+    /// + A 203.0.113.3
+    /// ```
+    ///
+    /// Unchanged records are prefixed with two spaces. Like [`Plan::table()`], the current A records are looked up in `domains`.
+    pub fn diff(&self, domains: &[registry::Domain]) -> String {
+        let mut diff = String::new();
+        for action in self.actions() {
+            let (kind, name, new) = match action {
+                Action::ClaimAndUpdate(d, ips) => ("create", d, ips.as_slice()),
+                Action::Update(d, ips) => ("update", d, ips.as_slice()),
+                Action::DeleteAndRelease(d) => ("delete", d, [].as_slice()),
+            };
+            let old = domains
+                .iter()
+                .find(|d| &d.name == name)
+                .map(|d| d.a.as_slice())
+                .unwrap_or_default();
+
+            diff.push_str(&format!("{} {}\n", kind, name));
+            for ip in old {
+                let marker = if new.contains(ip) { ' ' } else { '-' };
+                diff.push_str(&format!("{} A {}\n", marker, ip));
+            }
+            for ip in new.iter().filter(|ip| !old.contains(ip)) {
+                diff.push_str(&format!("+ A {}\n", ip));
+            }
+        }
+        diff
+    }
+
+    fn add_create(&mut self, name: String, addrs: &[Ipv4Addr]) {
+        self.0.push(Action::ClaimAndUpdate(name, addrs.to_vec()));
     }
 
-    fn add_update(&mut self, name: String, addr: Ipv4Addr) {
-        self.0.push(Action::Update(name, addr));
+    fn add_update(&mut self, name: String, addrs: &[Ipv4Addr]) {
+        self.0.push(Action::Update(name, addrs.to_vec()));
     }
 
     fn add_delete(&mut self, name: String) {
@@ -73,63 +440,174 @@ impl Plan {
     ///
     /// # Inputs
     /// - registry: [`ARegistry`] that serves as the source of domains to evaluate
-    /// - desired_address: The [`Ipv4Addr`] to insert into newly created A records
+    /// - desired_addresses: The [`Ipv4Addr`]s to insert into newly created A records. A domain is only considered up-to-date
+    ///   if its A records match this set exactly
     /// - policy: [`Policy`]. Determines whether to overwrite or delete existing records.
     pub fn generate(
         registry: &mut dyn ARegistry,
-        desired_address: Ipv4Addr,
+        desired_addresses: &[Ipv4Addr],
+        policy: Policy,
+    ) -> Plan {
+        Plan::generate_with_options(registry, desired_addresses, policy, &PlanOptions::default())
+    }
+
+    /// Generate a new plan using additional [`PlanOptions`] and return it.
+    /// See [`Plan::generate()`] for a description of the other inputs.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "plan", skip_all, fields(policy = ?policy))
+    )]
+    pub fn generate_with_options(
+        registry: &mut dyn ARegistry,
+        desired_addresses: &[Ipv4Addr],
         policy: Policy,
+        options: &PlanOptions,
     ) -> Plan {
         let mut plan = Plan(vec![]);
+        let desired_set: HashSet<&Ipv4Addr> = desired_addresses.iter().collect();
 
-        for domain in &registry.owned_domains() {
+        let owned_domains = registry
+            .owned_domains()
+            .into_iter()
+            .filter(|d| {
+                Plan::filter_domain(d, options)
+                    && Plan::filter_wildcard(d, options)
+                    && Plan::filter_cname(d)
+                    && Plan::filter_global_aaaa(d, options)
+            })
+            .collect_vec();
+        let available_domains = registry
+            .available_domains()
+            .into_iter()
+            .filter(|d| {
+                Plan::filter_domain(d, options)
+                    && Plan::filter_wildcard(d, options)
+                    && Plan::filter_cname(d)
+                    && Plan::filter_global_aaaa(d, options)
+            })
+            .collect_vec();
+
+        for domain in &owned_domains {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("domain", domain = %domain.name).entered();
             if !domain.aaaa.is_empty() {
+                if policy == Policy::DeleteOnly {
+                    debug!(
+                        "Domain {} still has AAAA records, but policy is {:?}, not modifying",
+                        domain.name, policy
+                    );
+                    continue;
+                }
+                if !Plan::filter_allowlist(domain, options) {
+                    continue;
+                }
                 if domain.a.is_empty() {
+                    if policy == Policy::CreateOnly && options.create_only_skip_owned {
+                        info!("No A record found for owned domain {}, but policy is {:?} and owned domains are skipped, not creating", domain.name, policy);
+                        continue;
+                    }
                     info!(
                         "No A record found for owned domain {}, creating",
                         domain.name
                     );
-                    plan.add_update(domain.name.clone(), desired_address);
-                } else if domain.a.len() == 1 && domain.a[0] == desired_address {
+                    plan.add_update(domain.name.clone(), desired_addresses);
+                } else if domain.a.iter().collect::<HashSet<_>>() == desired_set {
                     info!("Domain is already up-to-date: {}", domain.name);
                     continue;
+                } else if desired_set.is_subset(&domain.a.iter().collect()) {
+                    // All desired addresses are present, only the others need to be removed
+                    match policy {
+                        Policy::CreateOnly | Policy::DeleteOnly => {
+                            info!("Found extra A record(s) for domain {}, but policy is {:?}, not pruning. Records: {:?}", domain.name, policy, domain.a);
+                        }
+                        Policy::Upsert | Policy::Sync if options.keep_extra_addresses => {
+                            info!("Domain {} contains all desired A records, keeping extra records: {:?}", domain.name, domain.a);
+                        }
+                        Policy::Upsert | Policy::Sync
+                            if options.defer_recent_updates && recently_modified(domain) =>
+                        {
+                            info!(
+                                "Found extra A record(s) for domain {}, but they were modified within their TTL. Deferring pruning",
+                                domain.name
+                            );
+                        }
+                        Policy::Upsert | Policy::Sync => {
+                            info!(
+                                "Found extra A record(s) for domain {}, pruning. Records: {:?}",
+                                domain.name, domain.a
+                            );
+                            plan.add_update(domain.name.clone(), desired_addresses);
+                        }
+                    }
                 } else {
                     match policy {
-                        Policy::CreateOnly => {
+                        Policy::CreateOnly | Policy::DeleteOnly => {
                             info!("Found outdated A record(s) for domain {}, but policy is {:?}, not modifying. Records: {:?}", domain.name, policy, domain.a);
                         }
+                        Policy::Upsert | Policy::Sync
+                            if options.defer_recent_updates && recently_modified(domain) =>
+                        {
+                            info!(
+                                "Found outdated A record(s) for domain {}, but they were modified within their TTL. Deferring update",
+                                domain.name
+                            );
+                        }
                         Policy::Upsert | Policy::Sync => {
                             info!(
                                 "Found outdated A record(s) for domain {}, updating",
                                 domain.name
                             );
-                            plan.add_update(domain.name.clone(), desired_address);
+                            plan.add_update(domain.name.clone(), desired_addresses);
                         }
                     }
                 }
             } else {
-                match policy {
-                    Policy::Sync => {
+                match (policy, options.delete_mode) {
+                    (Policy::Sync, DeleteMode::Delete) | (Policy::DeleteOnly, _) => {
                         info!(
                             "No more AAAA records associated with owned domain {}, deleting",
                             domain.name
                         );
                         plan.add_delete(domain.name.clone());
                     }
-                    Policy::Upsert | Policy::CreateOnly => {
+                    (Policy::Sync, DeleteMode::Park(park_addr)) => {
+                        if domain.a == [park_addr] {
+                            info!("Domain is already parked: {}", domain.name);
+                            continue;
+                        }
+                        info!(
+                            "No more AAAA records associated with owned domain {}, parking at {}",
+                            domain.name, park_addr
+                        );
+                        plan.add_update(domain.name.clone(), &[park_addr]);
+                    }
+                    (Policy::Upsert | Policy::CreateOnly, _) => {
                         info!("No more AAAA records associated with owned domain {}, but policy is {:?}, not modifying", domain.name, policy);
                     }
                 }
             }
         }
 
-        for domain in &registry.available_domains() {
-            if !domain.aaaa.is_empty() && domain.a.is_empty() {
-                // Domain not owned and matches our criteria (at least one AAAA record and no A records), try to create our A record
-                plan.add_create(domain.name.clone(), desired_address);
+        if policy == Policy::DeleteOnly {
+            // Never claim new domains
+            return plan.sorted();
+        }
+        for domain in &available_domains {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("domain", domain = %domain.name).entered();
+            if !domain.aaaa.is_empty() && Plan::filter_allowlist(domain, options) {
+                // Domain not owned and matches our criteria (at least one AAAA record), try to create our A record.
+                // Available domains usually don't have any A records, unless the registry allows adopting them
+                if !domain.a.is_empty() {
+                    info!(
+                        "Adopting existing A record(s) for available domain {}: {:?}",
+                        domain.name, domain.a
+                    );
+                }
+                plan.add_create(domain.name.clone(), desired_addresses);
             }
         }
-        plan
+        plan.sorted()
     }
 }
 
@@ -138,11 +616,14 @@ mod tests {
     use std::{
         collections::HashSet,
         net::{Ipv4Addr, Ipv6Addr},
+        time::{Duration, SystemTime},
         vec,
     };
 
     use crate::{
-        plan::{Action, Policy},
+        plan::{
+            parse_domain_list, Action, DeleteMode, DomainFilter, PlanOptions, PlanSummary, Policy,
+        },
         registry::{ARegistry, Domain, MockARegistry},
     };
 
@@ -152,102 +633,146 @@ mod tests {
     fn owned_correct_d() -> Domain {
         Domain {
             name: "owned-ok.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![DESIRED_IP],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 3)],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_insert_d() -> Domain {
         Domain {
             name: "owned-but-no-a.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 3)],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_update_d() -> Domain {
         Domain {
             name: "owned-but-old-a.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 10, 10, 111)],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 3)],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_multiple_a_with_correct_d() -> Domain {
         Domain {
             name: "owned-but-multiple-a-with-correct.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![DESIRED_IP, Ipv4Addr::new(10, 10, 10, 111)],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 3)],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_multiple_a_without_correct_d() -> Domain {
         Domain {
             name: "owned-but-multiple-a-without-correct.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![
                 Ipv4Addr::new(10, 10, 10, 111),
                 Ipv4Addr::new(10, 10, 10, 123),
             ],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 3)],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_delete_incorrect_a_d() -> Domain {
         Domain {
             name: "owned-but-to-delete-and-old-a.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 1)],
             aaaa: vec![],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_delete_correct_a_d() -> Domain {
         Domain {
             name: "owned-but-to-delete.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![DESIRED_IP],
             aaaa: vec![],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_delete_multiple_a_with_correct_d() -> Domain {
         Domain {
             name: "owned-but-to-delete-multiple-a-with-correct.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![DESIRED_IP, Ipv4Addr::new(10, 1, 1, 1)],
             aaaa: vec![],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn owned_to_delete_multiple_a_without_correct_d() -> Domain {
         Domain {
             name: "owned-but-to-delete-multiple-a-without-correct.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![DESIRED_IP, Ipv4Addr::new(10, 1, 1, 1)],
             aaaa: vec![],
             txt: vec!["i_am_tenant".to_string()],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn available_d() -> Domain {
         Domain {
             name: "available.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)],
             a: vec![],
             txt: vec![],
+            cname: None,
             a_ownership: crate::registry::Ownership::Available,
         }
     }
     fn taken_d() -> Domain {
         Domain {
             name: "taken.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 2)],
             aaaa: vec![],
             txt: vec![],
+            cname: None,
             a_ownership: crate::registry::Ownership::Taken,
         }
     }
@@ -306,12 +831,12 @@ mod tests {
 
     #[test]
     fn should_generate_valid_plan_sync() {
-        let create_expected = [Action::ClaimAndUpdate(available_d().name, DESIRED_IP)];
+        let create_expected = [Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP])];
         let update_expected = [
-            Action::Update(owned_multiple_a_without_correct_d().name, DESIRED_IP),
-            Action::Update(owned_to_insert_d().name, DESIRED_IP),
-            Action::Update(owned_to_update_d().name, DESIRED_IP),
-            Action::Update(owned_multiple_a_with_correct_d().name, DESIRED_IP),
+            Action::Update(owned_multiple_a_without_correct_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_to_insert_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_to_update_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_multiple_a_with_correct_d().name, vec![DESIRED_IP]),
         ];
         let delete_expected = [
             Action::DeleteAndRelease(owned_to_delete_correct_a_d().name),
@@ -320,7 +845,7 @@ mod tests {
             Action::DeleteAndRelease(owned_to_delete_multiple_a_without_correct_d().name),
         ];
 
-        let plan = Plan::generate(mock().as_mut(), DESIRED_IP, Policy::Sync);
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Sync);
 
         assert_eq!(
             HashSet::from_iter(create_expected.iter().cloned()),
@@ -347,11 +872,11 @@ mod tests {
 
     #[test]
     fn should_generate_valid_plan_create_only() {
-        let create_expected = [Action::ClaimAndUpdate(available_d().name, DESIRED_IP)];
-        let update_expected = [Action::Update(owned_to_insert_d().name, DESIRED_IP)];
+        let create_expected = [Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP])];
+        let update_expected = [Action::Update(owned_to_insert_d().name, vec![DESIRED_IP])];
         let delete_expected = [];
 
-        let plan = Plan::generate(mock().as_mut(), DESIRED_IP, Policy::CreateOnly);
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::CreateOnly);
 
         assert_eq!(
             HashSet::from_iter(create_expected.iter().cloned()),
@@ -376,18 +901,95 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_generate_valid_plan_delete_only() {
+        let delete_expected = [
+            Action::DeleteAndRelease(owned_to_delete_correct_a_d().name),
+            Action::DeleteAndRelease(owned_to_delete_incorrect_a_d().name),
+            Action::DeleteAndRelease(owned_to_delete_multiple_a_with_correct_d().name),
+            Action::DeleteAndRelease(owned_to_delete_multiple_a_without_correct_d().name),
+        ];
+
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::DeleteOnly);
+
+        assert!(plan
+            .actions()
+            .all(|a| matches!(a, crate::plan::Action::DeleteAndRelease(_))));
+        assert_eq!(
+            HashSet::from_iter(delete_expected.iter().cloned()),
+            plan.actions().cloned().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn delete_only_should_ignore_park_mode() {
+        let plan = Plan::generate_with_options(
+            mock().as_mut(),
+            &[DESIRED_IP],
+            Policy::DeleteOnly,
+            &PlanOptions {
+                delete_mode: DeleteMode::Park(Ipv4Addr::new(192, 0, 2, 1)),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(plan.summary().deletes, 4);
+        assert_eq!(plan.actions().count(), 4);
+    }
+
+    #[test]
+    fn should_order_actions_by_type() {
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Sync);
+
+        let kinds = plan
+            .actions()
+            .map(|a| match a {
+                Action::DeleteAndRelease(_) => "delete",
+                Action::Update(_, _) => "update",
+                Action::ClaimAndUpdate(_, _) => "create",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "delete", "delete", "delete", "delete", "update", "update", "update", "update",
+                "create"
+            ]
+        );
+
+        let mixed: Plan = [
+            Action::ClaimAndUpdate("b.example.com".to_string(), vec![DESIRED_IP]),
+            Action::Update("c.example.com".to_string(), vec![DESIRED_IP]),
+            Action::DeleteAndRelease("z.example.com".to_string()),
+            Action::ClaimAndUpdate("a.example.com".to_string(), vec![DESIRED_IP]),
+            Action::DeleteAndRelease("y.example.com".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            mixed.sorted().actions().cloned().collect::<Vec<_>>(),
+            vec![
+                Action::DeleteAndRelease("y.example.com".to_string()),
+                Action::DeleteAndRelease("z.example.com".to_string()),
+                Action::Update("c.example.com".to_string(), vec![DESIRED_IP]),
+                Action::ClaimAndUpdate("a.example.com".to_string(), vec![DESIRED_IP]),
+                Action::ClaimAndUpdate("b.example.com".to_string(), vec![DESIRED_IP]),
+            ]
+        );
+    }
+
     #[test]
     fn should_generate_valid_plan_upsert() {
-        let create_expected = [Action::ClaimAndUpdate(available_d().name, DESIRED_IP)];
+        let create_expected = [Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP])];
         let update_expected = [
-            Action::Update(owned_multiple_a_without_correct_d().name, DESIRED_IP),
-            Action::Update(owned_to_insert_d().name, DESIRED_IP),
-            Action::Update(owned_to_update_d().name, DESIRED_IP),
-            Action::Update(owned_multiple_a_with_correct_d().name, DESIRED_IP),
+            Action::Update(owned_multiple_a_without_correct_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_to_insert_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_to_update_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_multiple_a_with_correct_d().name, vec![DESIRED_IP]),
         ];
         let delete_expected = [];
 
-        let plan = Plan::generate(mock().as_mut(), DESIRED_IP, Policy::Upsert);
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Upsert);
 
         assert_eq!(
             HashSet::from_iter(create_expected.iter().cloned()),
@@ -411,4 +1013,585 @@ mod tests {
                 .collect::<HashSet<_>>()
         );
     }
+
+    #[test]
+    fn should_render_table_row_per_action() {
+        let mut registry = mock();
+        let domains = registry.all_domains();
+        let plan = Plan::generate(registry.as_mut(), &[DESIRED_IP], Policy::Sync);
+
+        let table = plan.table(&domains);
+        let mut lines = table.lines();
+
+        let header = lines.next().unwrap();
+        for col in ["ACTION", "DOMAIN", "OLD A", "NEW A", "OWNERSHIP"] {
+            assert!(header.contains(col));
+        }
+        assert_eq!(lines.clone().count(), plan.actions().count());
+
+        let update_row = lines
+            .clone()
+            .find(|l| l.contains(&owned_to_update_d().name))
+            .unwrap();
+        assert!(update_row.starts_with("UPDATE"));
+        assert!(update_row.contains("10.10.10.111"));
+        assert!(update_row.contains(&DESIRED_IP.to_string()));
+        assert!(update_row.contains("Owned"));
+
+        let create_row = lines
+            .clone()
+            .find(|l| l.contains(&available_d().name))
+            .unwrap();
+        assert!(create_row.starts_with("CREATE"));
+        assert!(create_row.contains("Available"));
+
+        let delete_row = lines
+            .find(|l| l.contains(&owned_to_delete_incorrect_a_d().name))
+            .unwrap();
+        assert!(delete_row.starts_with("DELETE"));
+        assert!(delete_row.contains("10.1.1.1"));
+    }
+
+    #[test]
+    fn should_render_diff_per_domain() {
+        let domains = vec![
+            owned_to_update_d(),
+            owned_multiple_a_with_correct_d(),
+            owned_to_delete_incorrect_a_d(),
+            available_d(),
+        ];
+        let plan = Plan(vec![
+            Action::DeleteAndRelease(owned_to_delete_incorrect_a_d().name),
+            Action::Update(owned_to_update_d().name, vec![DESIRED_IP]),
+            Action::Update(owned_multiple_a_with_correct_d().name, vec![DESIRED_IP]),
+            Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP]),
+        ]);
+
+        assert_eq!(
+            plan.diff(&domains),
+            "\
+delete owned-but-to-delete-and-old-a.example.com
+- A 10.1.1.1
+update owned-but-old-a.example.com
+- A 10.10.10.111
++ A 10.10.10.10
+update owned-but-multiple-a-with-correct.example.com
+  A 10.10.10.10
+- A 10.10.10.111
+create available.example.com
++ A 10.10.10.10
+"
+        );
+    }
+
+    #[test]
+    fn should_defer_recently_modified_updates() {
+        let mut recent = owned_to_update_d();
+        recent.a_modified = Some(SystemTime::now());
+        recent.a_ttl = Some(300);
+        let mut old = owned_multiple_a_without_correct_d();
+        old.a_modified = Some(SystemTime::now() - Duration::from_secs(600));
+        old.a_ttl = Some(300);
+
+        let mut mock = MockARegistry::new();
+        let domains = vec![recent, old.clone()];
+        mock.expect_owned_domains()
+            .returning(move || domains.clone());
+        mock.expect_available_domains().returning(Vec::new);
+
+        let plan = Plan::generate_with_options(
+            &mut mock,
+            &[DESIRED_IP],
+            Policy::Sync,
+            &PlanOptions {
+                defer_recent_updates: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            plan.actions().cloned().collect::<Vec<_>>(),
+            vec![Action::Update(old.name, vec![DESIRED_IP])]
+        );
+
+        // Without the option, both domains get updated
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::Sync);
+        assert_eq!(plan.actions().count(), 2);
+    }
+
+    #[test]
+    fn should_adopt_available_domains_with_a_records() {
+        let mut adoptable = available_d();
+        adoptable.a = vec![DESIRED_IP];
+
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains().returning(Vec::new);
+        mock.expect_available_domains()
+            .returning(move || vec![adoptable.clone()]);
+
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::CreateOnly);
+        assert_eq!(
+            plan.actions().cloned().collect::<Vec<_>>(),
+            vec![Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP])]
+        );
+    }
+
+    #[test]
+    fn should_skip_owned_without_a_in_create_only() {
+        let plan = Plan::generate_with_options(
+            mock().as_mut(),
+            &[DESIRED_IP],
+            Policy::CreateOnly,
+            &PlanOptions {
+                create_only_skip_owned: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            plan.actions().cloned().collect::<Vec<_>>(),
+            vec![Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP])]
+        );
+
+        // Other policies are unaffected
+        let plan = Plan::generate_with_options(
+            mock().as_mut(),
+            &[DESIRED_IP],
+            Policy::Upsert,
+            &PlanOptions {
+                create_only_skip_owned: true,
+                ..Default::default()
+            },
+        );
+        assert!(plan
+            .actions()
+            .any(|a| a == &Action::Update(owned_to_insert_d().name, vec![DESIRED_IP])));
+    }
+
+    #[test]
+    fn should_park_instead_of_delete() {
+        let park_ip = Ipv4Addr::new(192, 0, 2, 1);
+        let mut parked = owned_to_delete_correct_a_d();
+        parked.name = "already-parked.example.com".to_string();
+        parked.a = vec![park_ip];
+
+        let mut mock = MockARegistry::new();
+        let domains = vec![
+            owned_to_delete_correct_a_d(),
+            owned_to_delete_multiple_a_without_correct_d(),
+            parked,
+        ];
+        mock.expect_owned_domains()
+            .returning(move || domains.clone());
+        mock.expect_available_domains().returning(Vec::new);
+
+        let plan = Plan::generate_with_options(
+            &mut mock,
+            &[DESIRED_IP],
+            Policy::Sync,
+            &PlanOptions {
+                delete_mode: DeleteMode::Park(park_ip),
+                ..Default::default()
+            },
+        );
+        assert_eq!(plan.destructive_actions().count(), 0);
+        assert_eq!(
+            HashSet::from_iter([
+                Action::Update(owned_to_delete_correct_a_d().name, vec![park_ip]),
+                Action::Update(
+                    owned_to_delete_multiple_a_without_correct_d().name,
+                    vec![park_ip]
+                ),
+            ]),
+            plan.actions().cloned().collect::<HashSet<_>>()
+        );
+
+        // Delete mode behaves as before
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::Sync);
+        assert_eq!(plan.destructive_actions().count(), 3);
+    }
+
+    #[test]
+    fn should_count_destructive_actions() {
+        let sync = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Sync);
+        let upsert = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Upsert);
+
+        assert_eq!(sync.destructive_actions().count(), 4);
+        assert_eq!(upsert.destructive_actions().count(), 0);
+    }
+
+    #[test]
+    fn should_compare_a_records_as_set_with_multiple_addresses() {
+        let second_ip = Ipv4Addr::new(10, 10, 10, 20);
+        let mut same_set = owned_correct_d();
+        same_set.name = "same-set.example.com".to_string();
+        same_set.a = vec![second_ip, DESIRED_IP];
+        let mut subset = owned_correct_d();
+        subset.name = "subset.example.com".to_string();
+        subset.a = vec![DESIRED_IP];
+        let mut superset = owned_correct_d();
+        superset.name = "superset.example.com".to_string();
+        superset.a = vec![DESIRED_IP, second_ip, Ipv4Addr::new(10, 10, 10, 30)];
+
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains()
+            .return_const(vec![same_set, subset.clone(), superset.clone()]);
+        mock.expect_available_domains()
+            .return_const(vec![available_d()]);
+
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP, second_ip], Policy::Sync);
+
+        assert_eq!(
+            plan.actions().cloned().collect::<HashSet<_>>(),
+            HashSet::from([
+                Action::Update(subset.name, vec![DESIRED_IP, second_ip]),
+                Action::Update(superset.name, vec![DESIRED_IP, second_ip]),
+                Action::ClaimAndUpdate(available_d().name, vec![DESIRED_IP, second_ip]),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_not_update_for_ttl_only() {
+        // Domains are compared by address only, a differing TTL never triggers an update that would reset it
+        let mut long_ttl = owned_correct_d();
+        long_ttl.a_ttl = Some(86400);
+
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains().return_const(vec![long_ttl]);
+        mock.expect_available_domains().return_const(vec![]);
+
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::Sync);
+
+        assert_eq!(plan.actions().count(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_serialize_actions_to_json() {
+        let plan: Plan = [
+            Action::ClaimAndUpdate("new.example.com".to_string(), vec![DESIRED_IP]),
+            Action::Update(
+                "old.example.com".to_string(),
+                vec![DESIRED_IP, Ipv4Addr::new(10, 10, 10, 20)],
+            ),
+            Action::DeleteAndRelease("gone.example.com".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        let json: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"action": "create", "domain": "new.example.com", "addresses": ["10.10.10.10"]},
+                {"action": "update", "domain": "old.example.com", "addresses": ["10.10.10.10", "10.10.10.20"]},
+                {"action": "delete", "domain": "gone.example.com"},
+            ])
+        );
+    }
+
+    #[test]
+    fn should_summarize_actions() {
+        let plan = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Sync);
+        let summary = plan.summary();
+
+        assert_eq!(
+            summary,
+            PlanSummary {
+                creates: 1,
+                updates: 4,
+                deletes: 4,
+            }
+        );
+        assert!(!summary.is_empty());
+        assert_eq!(summary.to_string(), "1 creates, 4 updates, 4 deletes");
+
+        let empty: Plan = std::iter::empty().collect();
+        assert!(empty.summary().is_empty());
+    }
+
+    #[test]
+    fn domain_filter_should_include_and_exclude_domains() {
+        let all = DomainFilter::default();
+        assert!(all.matches("anything.example.com"));
+
+        let filter = DomainFilter {
+            include: vec!["*.example.com".to_string(), "example.org".to_string()],
+            exclude: vec!["Secret.example.com.".to_string()],
+        };
+        assert!(filter.matches("www.example.com"));
+        assert!(filter.matches("example.org"));
+        assert!(!filter.matches("example.com"));
+        assert!(!filter.matches("www.example.org"));
+        assert!(!filter.matches("secret.example.com"));
+    }
+
+    #[test]
+    fn domain_filter_should_match_idn_domains() {
+        let filter = DomainFilter {
+            include: vec!["*.münchen.example.com".to_string()],
+            exclude: vec![],
+        };
+
+        assert!(filter.matches("www.xn--mnchen-3ya.example.com"));
+        assert!(filter.matches("www.münchen.example.com"));
+        assert!(!filter.matches("www.example.com"));
+    }
+
+    #[test]
+    fn should_skip_filtered_domains() {
+        let options = PlanOptions {
+            domain_filter: DomainFilter {
+                include: vec![],
+                exclude: vec![
+                    available_d().name,
+                    owned_to_update_d().name,
+                    owned_to_delete_correct_a_d().name,
+                ],
+            },
+            ..Default::default()
+        };
+
+        let plan =
+            Plan::generate_with_options(mock().as_mut(), &[DESIRED_IP], Policy::Sync, &options);
+
+        assert!(!plan
+            .actions()
+            .any(|a| matches!(a, Action::ClaimAndUpdate(_, _))));
+        assert!(!plan.actions().any(|a| match a {
+            Action::Update(d, _) | Action::DeleteAndRelease(d) =>
+                *d == owned_to_update_d().name || *d == owned_to_delete_correct_a_d().name,
+            _ => false,
+        }));
+        assert_eq!(plan.summary().updates, 3);
+        assert_eq!(plan.summary().deletes, 3);
+    }
+
+    #[test]
+    fn should_parse_domain_list() {
+        let domains = parse_domain_list(
+            "# Managed domains\nwww.example.com\n\n  Mail.Example.com.  \nmünchen.example.com\n",
+        );
+
+        assert_eq!(
+            domains.into_iter().collect::<Vec<_>>(),
+            vec![
+                "mail.example.com",
+                "www.example.com",
+                "xn--mnchen-3ya.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_only_create_and_update_allowlisted_domains() {
+        let unfiltered = Plan::generate(mock().as_mut(), &[DESIRED_IP], Policy::Sync);
+        let options = PlanOptions {
+            domain_allowlist: Some([owned_to_update_d().name].into_iter().collect()),
+            ..Default::default()
+        };
+
+        let plan =
+            Plan::generate_with_options(mock().as_mut(), &[DESIRED_IP], Policy::Sync, &options);
+
+        assert!(plan.actions().all(|a| match a {
+            Action::ClaimAndUpdate(d, _) | Action::Update(d, _) => *d == owned_to_update_d().name,
+            _ => true,
+        }));
+        assert!(plan
+            .actions()
+            .any(|a| matches!(a, Action::Update(d, _) if *d == owned_to_update_d().name)));
+        // Deletes are not affected by the allowlist
+        assert_eq!(plan.summary().deletes, unfiltered.summary().deletes);
+        assert!(unfiltered.summary().creates > 0);
+    }
+
+    fn wildcard_mock() -> MockARegistry {
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains().returning(|| {
+            vec![Domain {
+                name: "*.owned.example.com".to_string(),
+                aaaa: vec![],
+                ..owned_correct_d()
+            }]
+        });
+        mock.expect_available_domains().returning(|| {
+            vec![Domain {
+                name: "*.example.com".to_string(),
+                ..available_d()
+            }]
+        });
+        mock
+    }
+
+    fn multiple_a_mock() -> MockARegistry {
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains()
+            .returning(|| vec![owned_multiple_a_with_correct_d()]);
+        mock.expect_available_domains().returning(Vec::new);
+        mock
+    }
+
+    #[test]
+    fn should_prune_extra_addresses_by_policy() {
+        let prune = vec![Action::Update(
+            owned_multiple_a_with_correct_d().name,
+            vec![DESIRED_IP],
+        )];
+        for (policy, expected) in [
+            (Policy::CreateOnly, vec![]),
+            (Policy::Upsert, prune.clone()),
+            (Policy::Sync, prune.clone()),
+            (Policy::DeleteOnly, vec![]),
+        ] {
+            let plan = Plan::generate(&mut multiple_a_mock(), &[DESIRED_IP], policy);
+            assert_eq!(plan.actions().cloned().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn should_keep_extra_addresses_if_enabled() {
+        let options = PlanOptions {
+            keep_extra_addresses: true,
+            ..Default::default()
+        };
+        for policy in [
+            Policy::CreateOnly,
+            Policy::Upsert,
+            Policy::Sync,
+            Policy::DeleteOnly,
+        ] {
+            let plan = Plan::generate_with_options(
+                &mut multiple_a_mock(),
+                &[DESIRED_IP],
+                policy,
+                &options,
+            );
+            assert_eq!(plan.actions().count(), 0);
+        }
+    }
+
+    #[test]
+    fn should_replace_addresses_without_desired_address_even_if_keeping_extras() {
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains()
+            .returning(|| vec![owned_multiple_a_without_correct_d()]);
+        mock.expect_available_domains().returning(Vec::new);
+        let options = PlanOptions {
+            keep_extra_addresses: true,
+            ..Default::default()
+        };
+
+        let plan = Plan::generate_with_options(&mut mock, &[DESIRED_IP], Policy::Upsert, &options);
+        assert_eq!(plan.summary().updates, 1);
+    }
+
+    #[test]
+    fn should_skip_wildcard_domains() {
+        let plan = Plan::generate(&mut wildcard_mock(), &[DESIRED_IP], Policy::Sync);
+
+        assert_eq!(plan.actions().count(), 0);
+    }
+
+    #[test]
+    fn should_manage_wildcard_domains_if_enabled() {
+        let options = PlanOptions {
+            manage_wildcards: true,
+            ..Default::default()
+        };
+
+        let plan = Plan::generate_with_options(
+            &mut wildcard_mock(),
+            &[DESIRED_IP],
+            Policy::Sync,
+            &options,
+        );
+
+        assert_eq!(
+            plan.actions().collect::<Vec<_>>(),
+            vec![
+                &Action::DeleteAndRelease("*.owned.example.com".to_string()),
+                &Action::ClaimAndUpdate("*.example.com".to_string(), vec![DESIRED_IP]),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_skip_domains_with_only_non_global_aaaa() {
+        let ula_d = Domain {
+            name: "ula.example.com".to_string(),
+            aaaa: vec![
+                Ipv6Addr::new(0xfd00, 1, 1, 1, 1, 1, 1, 1),
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 1, 1, 1),
+            ],
+            ..available_d()
+        };
+        let mixed_d = Domain {
+            name: "mixed.example.com".to_string(),
+            aaaa: vec![
+                Ipv6Addr::new(0xfd00, 1, 1, 1, 1, 1, 1, 1),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            ],
+            ..available_d()
+        };
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains().returning(Vec::new);
+        mock.expect_available_domains()
+            .returning(move || vec![ula_d.clone(), mixed_d.clone()]);
+        let options = PlanOptions {
+            skip_non_global_aaaa: true,
+            ..Default::default()
+        };
+
+        let plan = Plan::generate_with_options(&mut mock, &[DESIRED_IP], Policy::Sync, &options);
+        assert_eq!(
+            plan.actions().collect::<Vec<_>>(),
+            vec![&Action::ClaimAndUpdate(
+                "mixed.example.com".to_string(),
+                vec![DESIRED_IP]
+            )]
+        );
+
+        // Without the option, all domains are eligible
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::Sync);
+        assert_eq!(plan.summary().creates, 2);
+    }
+
+    #[test]
+    fn should_leave_owned_domains_with_only_non_global_aaaa_untouched() {
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains()
+            .returning(|| vec![owned_to_update_d()]);
+        mock.expect_available_domains().returning(Vec::new);
+        let options = PlanOptions {
+            skip_non_global_aaaa: true,
+            ..Default::default()
+        };
+
+        let plan = Plan::generate_with_options(&mut mock, &[DESIRED_IP], Policy::Sync, &options);
+        assert_eq!(plan.actions().count(), 0);
+    }
+
+    #[test]
+    fn should_skip_domains_with_cname() {
+        let cname_d = Domain {
+            name: "alias.example.com".to_string(),
+            cname: Some("target.example.net".to_string()),
+            ..available_d()
+        };
+        let mut mock = MockARegistry::new();
+        mock.expect_owned_domains().returning(Vec::new);
+        mock.expect_available_domains()
+            .returning(move || vec![available_d(), cname_d.clone()]);
+
+        let plan = Plan::generate(&mut mock, &[DESIRED_IP], Policy::Sync);
+
+        assert_eq!(
+            plan.actions().collect::<Vec<_>>(),
+            vec![&Action::ClaimAndUpdate(
+                available_d().name,
+                vec![DESIRED_IP]
+            )]
+        );
+    }
 }
@@ -3,17 +3,36 @@
 //! Providers are DNS server providers such as Cloudflare that can be accessed through an API.
 //! All providers must implement the [`Provider`] trait. Currently, the following providers are available:
 //! - [`CloudflareProvider`]: Interfaces with the Cloudflare dns and zone API
+//! - [`DigitalOceanProvider`]: Interfaces with the DigitalOcean domains API
+//! - [`NjallaProvider`]: Interfaces with the Njalla JSON-RPC API
+//! - [`Rfc2136Provider`]: Sends dynamic updates (RFC2136) to traditional DNS servers such as BIND or Knot
+//! - [`ZoneFileProvider`]: Reads and rewrites a BIND-style zone file, for testing and offline use
+//!
+//! Providers built on async clients may implement [`AsyncProvider`] instead and be wrapped in a [`BlockingProvider`].
+mod asynchronous;
 mod cloudflare;
+mod digitalocean;
+mod njalla;
+mod rfc2136;
+mod zonefile;
 
 // Re-exports for convenience
+pub use self::asynchronous::{AsyncProvider, BlockingProvider};
 pub use self::cloudflare::{CloudflareProvider, CloudflareProviderConfig};
+pub use self::digitalocean::{DigitalOceanProvider, DigitalOceanProviderConfig};
+pub use self::njalla::{NjallaProvider, NjallaProviderConfig};
+pub use self::rfc2136::{Rfc2136Provider, Rfc2136ProviderConfig, TsigAlgorithm};
+pub use self::zonefile::{ZoneFileProvider, ZoneFileProviderConfig};
 
-use crate::plan::Action;
+use crate::plan::{Action, Plan};
 #[cfg(test)]
 use mockall::{automock, mock};
 use std::{
+    collections::HashSet,
     fmt::Display,
     net::{Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+    time::SystemTime,
 };
 use thiserror::Error;
 
@@ -21,6 +40,9 @@ use thiserror::Error;
 /// Used to interface with DNS providers such as Cloudflare, PowerDNS, etc.
 #[cfg_attr(test, automock)]
 pub trait DnsProvider {
+    /// Optional features supported by this provider. Callers should check these before relying on a feature
+    fn capabilities(&self) -> ProviderCapabilities;
+
     /// Tell the provider to not apply any changes, only to pretend doing so. Returns an Error if the provider does not support dry-run mode.
     fn enable_dry_run(&mut self) -> Result<(), ProviderError>;
     /// Whether the provider is currently running in dry-run mode
@@ -28,16 +50,77 @@ pub trait DnsProvider {
 
     /// Returns the default ttl that will be applied to all new records
     fn ttl(&self) -> Option<TTL>;
-    /// Set a TTL that the provider should apply to all created records
+    /// Set a TTL that the provider should apply to all created records.
+    /// Providers clamp TTLs outside of [`DnsProvider::ttl_bounds()`] to the nearest supported value
     fn set_ttl(&mut self, ttl: TTL);
+    /// Set the TTL that the provider should apply to all created records, optionally letting the provider choose it.
+    ///
+    /// By default, [`RecordTtl::Fixed`] is passed to [`DnsProvider::set_ttl()`] and [`RecordTtl::Auto`] returns an error.
+    /// Providers with a notion of automatic TTLs (such as Cloudflare) override this
+    fn set_record_ttl(&mut self, ttl: RecordTtl) -> Result<(), ProviderError> {
+        match ttl {
+            RecordTtl::Fixed(ttl) => {
+                self.set_ttl(ttl);
+                Ok(())
+            }
+            RecordTtl::Auto => Err("The selected provider does not support automatic TTLs"
+                .to_string()
+                .into()),
+        }
+    }
+    /// The range of TTLs supported by the provider, so that callers can validate a TTL before setting it.
+    /// By default, all TTLs are supported
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        TTL::MIN..=TTL::MAX
+    }
 
     /// Get all relevant records currently registered with the provider.
-    /// Note that we only care about A and AAAA records, as well as TXT records (for the [`crate::registry::TxtRegistry`]).
+    /// Note that we only care about A and AAAA records, TXT records (for the [`crate::registry::TxtRegistry`])
+    /// and CNAME records (to avoid creating A records next to them).
     /// Returns a result of [`DnsRecord`]s
     fn records(&self) -> Result<Vec<DnsRecord>, ProviderError>;
 
+    /// Get all relevant records of a zone or domain, including those of its subdomains (e.g. `example.com` returns the records of `www.example.com` as well).
+    ///
+    /// By default, this filters the output of [`DnsProvider::records()`].
+    /// Providers should override this if they can retrieve the records of a single zone without listing all others.
+    fn records_for(&self, zone_or_domain: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|r| in_domain(&r.domain_name, zone_or_domain))
+            .collect())
+    }
+
+    /// The zone that a domain belongs to (e.g. `example.com` for `www.example.com`), if the provider manages multiple zones.
+    ///
+    /// Callers may use this to process the domains of each zone independently, so that a failing zone does not hold up the others.
+    /// By default, all domains are considered part of a single zone
+    fn zone_of(&self, _domain: &str) -> Option<String> {
+        None
+    }
+
     /// Perform a single Action such as Create, Update or Delete.
+    /// Updates should never leave a domain without any A records if they fail halfway through,
+    /// e.g. by creating the new records before deleting the old ones.
     fn apply(&self, action: &Action) -> Result<(), ProviderError>;
+
+    /// Perform all actions in a plan, returning one result per action (in the same order).
+    ///
+    /// By default, this calls [`DnsProvider::apply()`] for each action.
+    /// Providers should override this if they can share work between actions, such as retrieving the current records only once.
+    fn apply_plan(&self, plan: &Plan) -> Vec<Result<(), ProviderError>> {
+        plan.actions().map(|a| self.apply(a)).collect()
+    }
+
+    /// Check that an action was actually persisted by the provider, by reading the records of its domain again.
+    /// Returns `false` if the A records of the domain do not match the action, e.g. because a created record is missing.
+    ///
+    /// By default, this compares the output of [`DnsProvider::records_for()`] against the action.
+    /// Note that in dry-run mode, actions are never persisted
+    fn verify(&self, action: &Action) -> Result<bool, ProviderError> {
+        Ok(action_applied(action, &self.records_for(action.domain())?))
+    }
 }
 
 /// Trait to be implemented by DNS providers that provides methods for managing TXT records.
@@ -57,11 +140,50 @@ pub trait TxTRegistryProvider {
 /// A provider represents a DNS service provider such as Cloudflare.
 /// They must be able to read and write DNS records, both for updating the actual A records and for managing ownership via TXT records when using the
 /// [`crate::registry::TxtRegistry`]
-pub trait Provider: DnsProvider + TxTRegistryProvider {}
+pub trait Provider: DnsProvider + TxTRegistryProvider {
+    /// Whether the provider can store ownership metadata natively on the records it manages (e.g. in record comments or tags),
+    /// making separate ownership TXT records unnecessary.
+    ///
+    /// Providers that return `true` must attach the comment set with [`Provider::set_record_comment()`] to all A records they create
+    /// and report it in [`DnsRecord::comment`].
+    fn supports_native_ownership(&self) -> bool {
+        false
+    }
+    /// Set a comment that the provider should attach to all created records.
+    /// Providers without support for record comments ignore this setting.
+    fn set_record_comment(&mut self, _comment: Option<String>) {}
+
+    /// Confirm that the provider is allowed to write records in the zone of `domain`, by creating and immediately deleting a throwaway TXT record
+    /// (see [`write_probe_record()`]). A records are never touched.
+    ///
+    /// Unlike all other writes, the probe is performed even in dry-run mode, so that missing write permissions are detected before a real run.
+    /// By default, this returns an error as the provider does not support write probes
+    fn check_write_access(&self, _domain: &str) -> Result<(), ProviderError> {
+        Err("The selected provider does not support write probes"
+            .to_string()
+            .into())
+    }
+
+    /// Get all records relevant to a TXT-based registry: all A and AAAA records, but only those TXT records whose content starts with `txt_prefix`.
+    ///
+    /// By default, this filters the output of [`DnsProvider::records()`].
+    /// Providers that can filter TXT records server-side should override this to avoid retrieving unrelated TXT records in large zones.
+    fn registry_records(&self, txt_prefix: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|r| match &r.content {
+                RecordContent::Txt(txt) => txt.starts_with(txt_prefix),
+                _ => true,
+            })
+            .collect())
+    }
+}
 #[cfg(test)]
 mock! {
     pub Provider {}
     impl DnsProvider for Provider {
+        fn capabilities(&self) -> ProviderCapabilities;
         fn enable_dry_run(&mut self) -> Result<(), ProviderError>;
         fn dry_run(&self) -> bool;
         fn ttl(&self) -> Option<TTL>;
@@ -73,7 +195,22 @@ mock! {
         fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError>;
         fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError>;
     }
-    impl Provider for Provider {}
+    impl Provider for Provider {
+        fn supports_native_ownership(&self) -> bool;
+    }
+}
+
+/// Optional features supported by a provider, see [`DnsProvider::capabilities()`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProviderCapabilities {
+    /// The provider supports dry-run mode (see [`DnsProvider::enable_dry_run()`])
+    pub dry_run: bool,
+    /// The provider applies the TTL set with [`DnsProvider::set_ttl()`] to created records
+    pub ttl: bool,
+    /// The provider shares work between the actions of a plan in [`DnsProvider::apply_plan()`], e.g. by retrieving the current records only once
+    pub batch_apply: bool,
+    /// The provider can proxy traffic to created records through its own network
+    pub proxied: bool,
 }
 
 /// Generic error returned by providers.
@@ -95,9 +232,29 @@ impl From<String> for ProviderError {
 pub struct DnsRecord {
     /// The fully-qualified domain name of the record (e.g. `my.example.com`)
     pub domain_name: String,
+    /// The TTL of the record, if known
+    pub ttl: Option<TTL>,
+    /// When the record was last modified, if the provider exposes this information
+    pub modified_on: Option<SystemTime>,
+    /// An optional comment/note attached to the record, for providers that support it.
+    /// Providers without support for record comments always set this to [`None`]
+    pub comment: Option<String>,
     /// A variant of [`RecordContent`], representing the data stored in the record
     pub content: RecordContent,
 }
+impl DnsRecord {
+    /// Create a new record without any of the optional metadata (TTL, modification time, comment).
+    /// Use struct update syntax to set those, e.g. `DnsRecord { ttl: Some(60), ..DnsRecord::new(name, content) }`
+    pub fn new(domain_name: impl Into<String>, content: RecordContent) -> DnsRecord {
+        DnsRecord {
+            domain_name: domain_name.into(),
+            ttl: None,
+            modified_on: None,
+            comment: None,
+            content,
+        }
+    }
+}
 impl Display for DnsRecord {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}: {}", self.domain_name, self.content)
@@ -115,6 +272,8 @@ pub enum RecordContent {
     A(Ipv4Addr),
     Aaaa(Ipv6Addr),
     Txt(String),
+    /// Canonical name (target) of an alias
+    Cname(String),
 }
 impl Display for RecordContent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -125,10 +284,178 @@ impl Display for RecordContent {
                 RecordContent::A(a) => format!("A {}", a),
                 RecordContent::Aaaa(aaaa) => format!("AAAA {}", aaaa),
                 RecordContent::Txt(txt) => format!("TXT {}", txt),
+                RecordContent::Cname(cname) => format!("CNAME {}", cname),
             }
         )
     }
 }
 
+/// Convert a domain name to its ASCII (punycode) form, e.g. `münchen.example.com` to `xn--mnchen-3ya.example.com`.
+///
+/// Providers usually return internationalized domain names in this form, while users may configure them in their Unicode form.
+/// Names should be normalized with this function before comparing them. Names that are not valid IDNs are only lowercased.
+pub fn normalize_domain(name: &str) -> String {
+    idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_lowercase())
+}
+
+/// Strip a single trailing dot from a fully-qualified domain name, e.g. `www.example.com.` to `www.example.com`.
+///
+/// Providers are inconsistent about returning names with or without the trailing dot of the root zone,
+/// so names should be passed through this function before comparing them.
+pub fn strip_root_dot(name: &str) -> &str {
+    name.strip_suffix('.').unwrap_or(name)
+}
+
+/// Whether `name` is `domain` itself or one of its subdomains
+pub(crate) fn in_domain(name: &str, domain: &str) -> bool {
+    let name = normalize_domain(name.trim_end_matches('.'));
+    let domain = normalize_domain(domain.trim_end_matches('.'));
+    name == domain || name.ends_with(&format!(".{}", domain))
+}
+
+/// Whether the A records of an action's domain match the intent of the action:
+/// exactly the desired addresses for creates and updates, no A records at all for deletes
+pub(crate) fn action_applied(action: &Action, records: &[DnsRecord]) -> bool {
+    let domain = normalize_domain(strip_root_dot(action.domain()));
+    let current = records
+        .iter()
+        .filter(|r| normalize_domain(strip_root_dot(&r.domain_name)) == domain)
+        .filter_map(|r| match r.content {
+            RecordContent::A(addr) => Some(addr),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    match action {
+        Action::ClaimAndUpdate(_, addrs) | Action::Update(_, addrs) => {
+            current == addrs.iter().copied().collect::<HashSet<_>>()
+        }
+        Action::DeleteAndRelease(_) => current.is_empty(),
+    }
+}
+
+/// Label of the throwaway TXT record created by [`Provider::check_write_access()`], below the probed domain
+pub const WRITE_PROBE_LABEL: &str = "_clouddns-nat-probe";
+
+/// The throwaway TXT record that [`Provider::check_write_access()`] creates and deletes for `domain`,
+/// e.g. `_clouddns-nat-probe.www.example.com` for `www.example.com`
+pub fn write_probe_record(domain: &str) -> DnsRecord {
+    DnsRecord::new(
+        format!("{}.{}", WRITE_PROBE_LABEL, strip_root_dot(domain)),
+        RecordContent::Txt("clouddns-nat-helper write probe".to_string()),
+    )
+}
+
 // Desired TTL of managed records
 pub type TTL = u32;
+
+/// TTL to apply to created records, see [`DnsProvider::set_record_ttl()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordTtl {
+    /// Let the provider choose a suitable TTL
+    Auto,
+    /// Use this TTL in seconds
+    Fixed(TTL),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::plan::Action;
+
+    use super::{DnsProvider, DnsRecord, MockProvider, Provider, RecordContent, RecordTtl};
+
+    fn a_record(name: &str) -> DnsRecord {
+        DnsRecord::new(
+            name.to_string(),
+            RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+        )
+    }
+
+    #[test]
+    fn records_for_should_only_return_records_of_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().returning(|| {
+            Ok(vec![
+                a_record("example.com"),
+                a_record("www.Example.com"),
+                a_record("example.org"),
+                a_record("notexample.com"),
+            ])
+        });
+
+        assert_eq!(
+            mock.records_for("example.com").unwrap(),
+            vec![a_record("example.com"), a_record("www.Example.com")]
+        );
+        assert_eq!(
+            mock.records_for("www.example.com.").unwrap(),
+            vec![a_record("www.Example.com")]
+        );
+    }
+
+    #[test]
+    fn verify_should_compare_a_records_of_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records()
+            .returning(|| Ok(vec![a_record("www.example.com."), a_record("example.com")]));
+
+        let addr = Ipv4Addr::new(10, 1, 1, 1);
+        assert!(mock
+            .verify(&Action::ClaimAndUpdate(
+                "www.example.com".to_string(),
+                vec![addr]
+            ))
+            .unwrap());
+        assert!(!mock
+            .verify(&Action::Update(
+                "www.example.com".to_string(),
+                vec![addr, Ipv4Addr::new(10, 1, 1, 2)]
+            ))
+            .unwrap());
+        assert!(!mock
+            .verify(&Action::DeleteAndRelease("www.example.com".to_string()))
+            .unwrap());
+        assert!(mock
+            .verify(&Action::DeleteAndRelease("other.example.com".to_string()))
+            .unwrap());
+    }
+
+    #[test]
+    fn set_record_ttl_should_only_accept_fixed_ttls_by_default() {
+        let mut mock = MockProvider::new();
+        mock.expect_set_ttl()
+            .withf(|ttl| *ttl == 300)
+            .times(1)
+            .return_const(());
+
+        mock.set_record_ttl(RecordTtl::Fixed(300)).unwrap();
+        mock.set_record_ttl(RecordTtl::Auto).unwrap_err();
+    }
+
+    #[test]
+    fn registry_records_should_only_return_prefixed_txt() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new("example.com", RecordContent::A(Ipv4Addr::new(10, 1, 1, 1))),
+                DnsRecord::new("example.com", RecordContent::Txt("v=spf1 -all".to_string())),
+                DnsRecord::new(
+                    "example.com",
+                    RecordContent::Txt("clouddns_nat_tenant;rec: A".to_string()),
+                ),
+            ])
+        });
+
+        assert_eq!(
+            mock.registry_records("clouddns_nat").unwrap(),
+            vec![
+                DnsRecord::new("example.com", RecordContent::A(Ipv4Addr::new(10, 1, 1, 1))),
+                DnsRecord::new(
+                    "example.com",
+                    RecordContent::Txt("clouddns_nat_tenant;rec: A".to_string())
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use std::{future::Future, ops::RangeInclusive};
+
+use tokio::runtime::{Builder, Runtime};
+
+use super::{
+    action_applied, in_domain, DnsProvider, DnsRecord, Provider, ProviderCapabilities,
+    ProviderError, RecordContent, RecordTtl, TxTRegistryProvider, TTL,
+};
+use crate::plan::{Action, Plan};
+
+/// Asynchronous counterpart of [`Provider`], for providers built on async clients (e.g. async `reqwest`).
+///
+/// Methods that talk to the provider return futures, configuration methods are synchronous just like in [`Provider`].
+/// See the documentation of [`DnsProvider`], [`TxTRegistryProvider`] and [`Provider`] for the expected behavior of each method.
+///
+/// To use an async provider wherever a [`Provider`] is expected (e.g. with a registry), wrap it in a [`BlockingProvider`].
+pub trait AsyncProvider: Send + Sync {
+    /// See [`DnsProvider::capabilities()`]
+    fn capabilities(&self) -> ProviderCapabilities;
+    /// See [`DnsProvider::enable_dry_run()`]
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError>;
+    /// See [`DnsProvider::dry_run()`]
+    fn dry_run(&self) -> bool;
+    /// See [`DnsProvider::ttl()`]
+    fn ttl(&self) -> Option<TTL>;
+    /// See [`DnsProvider::set_ttl()`]
+    fn set_ttl(&mut self, ttl: TTL);
+    /// See [`DnsProvider::set_record_ttl()`]
+    fn set_record_ttl(&mut self, ttl: RecordTtl) -> Result<(), ProviderError> {
+        match ttl {
+            RecordTtl::Fixed(ttl) => {
+                self.set_ttl(ttl);
+                Ok(())
+            }
+            RecordTtl::Auto => Err("The selected provider does not support automatic TTLs"
+                .to_string()
+                .into()),
+        }
+    }
+    /// See [`DnsProvider::ttl_bounds()`]
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        TTL::MIN..=TTL::MAX
+    }
+
+    /// See [`DnsProvider::records()`]
+    fn records(&self) -> impl Future<Output = Result<Vec<DnsRecord>, ProviderError>> + Send;
+    /// See [`DnsProvider::records_for()`].
+    ///
+    /// By default, this filters the output of [`AsyncProvider::records()`]
+    fn records_for(
+        &self,
+        zone_or_domain: &str,
+    ) -> impl Future<Output = Result<Vec<DnsRecord>, ProviderError>> + Send {
+        async move {
+            Ok(self
+                .records()
+                .await?
+                .into_iter()
+                .filter(|r| in_domain(&r.domain_name, zone_or_domain))
+                .collect())
+        }
+    }
+    /// See [`DnsProvider::zone_of()`]
+    fn zone_of(&self, _domain: &str) -> Option<String> {
+        None
+    }
+    /// See [`DnsProvider::apply()`]
+    fn apply(&self, action: &Action) -> impl Future<Output = Result<(), ProviderError>> + Send;
+    /// See [`DnsProvider::apply_plan()`].
+    ///
+    /// By default, this awaits [`AsyncProvider::apply()`] for each action in turn.
+    /// Providers may override this to apply the actions of a plan concurrently.
+    fn apply_plan(
+        &self,
+        plan: &Plan,
+    ) -> impl Future<Output = Vec<Result<(), ProviderError>>> + Send {
+        async move {
+            let mut results = Vec::new();
+            for action in plan.actions() {
+                results.push(self.apply(action).await);
+            }
+            results
+        }
+    }
+    /// See [`DnsProvider::verify()`].
+    ///
+    /// By default, this compares the output of [`AsyncProvider::records_for()`] against the action
+    fn verify(&self, action: &Action) -> impl Future<Output = Result<bool, ProviderError>> + Send {
+        async move {
+            Ok(action_applied(
+                action,
+                &self.records_for(action.domain()).await?,
+            ))
+        }
+    }
+
+    /// See [`TxTRegistryProvider::create_txt_record()`]
+    fn create_txt_record(
+        &self,
+        domain: String,
+        content: String,
+    ) -> impl Future<Output = Result<(), ProviderError>> + Send;
+    /// See [`TxTRegistryProvider::delete_txt_record()`]
+    fn delete_txt_record(
+        &self,
+        domain: String,
+        content: String,
+    ) -> impl Future<Output = Result<(), ProviderError>> + Send;
+
+    /// See [`Provider::supports_native_ownership()`]
+    fn supports_native_ownership(&self) -> bool {
+        false
+    }
+    /// See [`Provider::set_record_comment()`]
+    fn set_record_comment(&mut self, _comment: Option<String>) {}
+    /// See [`Provider::check_write_access()`]
+    fn check_write_access(
+        &self,
+        _domain: &str,
+    ) -> impl Future<Output = Result<(), ProviderError>> + Send {
+        async {
+            Err("The selected provider does not support write probes"
+                .to_string()
+                .into())
+        }
+    }
+    /// See [`Provider::registry_records()`].
+    ///
+    /// By default, this filters the output of [`AsyncProvider::records()`]
+    fn registry_records(
+        &self,
+        txt_prefix: &str,
+    ) -> impl Future<Output = Result<Vec<DnsRecord>, ProviderError>> + Send {
+        async move {
+            Ok(self
+                .records()
+                .await?
+                .into_iter()
+                .filter(|r| match &r.content {
+                    RecordContent::Txt(txt) => txt.starts_with(txt_prefix),
+                    _ => true,
+                })
+                .collect())
+        }
+    }
+}
+
+/// Bridges an [`AsyncProvider`] into a blocking [`Provider`], so that it can be used by registries and the executor.
+///
+/// Each call blocks on the returned future using a runtime owned by the bridge.
+/// Like any other blocking [`Provider`], it must not be called from within an async context. Use a blocking thread instead
+/// (e.g. [`tokio::task::spawn_blocking()`]).
+///
+/// To create a bridge, use the [`BlockingProvider::new()`] function.
+#[non_exhaustive]
+pub struct BlockingProvider<P: AsyncProvider> {
+    provider: P,
+    runtime: Runtime,
+}
+
+impl<P: AsyncProvider> BlockingProvider<P> {
+    /// Wrap an [`AsyncProvider`] in a new [`BlockingProvider`].
+    /// Returns an error if the runtime for driving the provider could not be created
+    pub fn new(provider: P) -> Result<BlockingProvider<P>, ProviderError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Could not create runtime for async provider: {}", e))?;
+        Ok(BlockingProvider { provider, runtime })
+    }
+
+    /// The wrapped [`AsyncProvider`]
+    pub fn inner(&self) -> &P {
+        &self.provider
+    }
+}
+
+impl<P: AsyncProvider> DnsProvider for BlockingProvider<P> {
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.provider.capabilities()
+    }
+
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+        self.provider.enable_dry_run()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.provider.dry_run()
+    }
+
+    fn ttl(&self) -> Option<TTL> {
+        self.provider.ttl()
+    }
+
+    fn set_ttl(&mut self, ttl: TTL) {
+        self.provider.set_ttl(ttl)
+    }
+
+    fn set_record_ttl(&mut self, ttl: RecordTtl) -> Result<(), ProviderError> {
+        self.provider.set_record_ttl(ttl)
+    }
+
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        self.provider.ttl_bounds()
+    }
+
+    fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+        self.runtime.block_on(self.provider.records())
+    }
+
+    fn records_for(&self, zone_or_domain: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        self.runtime
+            .block_on(self.provider.records_for(zone_or_domain))
+    }
+
+    fn zone_of(&self, domain: &str) -> Option<String> {
+        self.provider.zone_of(domain)
+    }
+
+    fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+        self.runtime.block_on(self.provider.apply(action))
+    }
+
+    fn apply_plan(&self, plan: &Plan) -> Vec<Result<(), ProviderError>> {
+        self.runtime.block_on(self.provider.apply_plan(plan))
+    }
+
+    fn verify(&self, action: &Action) -> Result<bool, ProviderError> {
+        self.runtime.block_on(self.provider.verify(action))
+    }
+}
+
+impl<P: AsyncProvider> TxTRegistryProvider for BlockingProvider<P> {
+    fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        self.runtime
+            .block_on(self.provider.create_txt_record(domain, content))
+    }
+
+    fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        self.runtime
+            .block_on(self.provider.delete_txt_record(domain, content))
+    }
+}
+
+impl<P: AsyncProvider> Provider for BlockingProvider<P> {
+    fn supports_native_ownership(&self) -> bool {
+        self.provider.supports_native_ownership()
+    }
+
+    fn set_record_comment(&mut self, comment: Option<String>) {
+        self.provider.set_record_comment(comment)
+    }
+
+    fn check_write_access(&self, domain: &str) -> Result<(), ProviderError> {
+        self.runtime
+            .block_on(self.provider.check_write_access(domain))
+    }
+
+    fn registry_records(&self, txt_prefix: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        self.runtime
+            .block_on(self.provider.registry_records(txt_prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Mutex, thread};
+
+    use tokio::task;
+
+    use super::{AsyncProvider, BlockingProvider};
+    use crate::{
+        plan::{Action, Plan},
+        provider::{
+            DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+            RecordTtl, TxTRegistryProvider, TTL,
+        },
+        registry::TxtRegistry,
+    };
+
+    /// Minimal async provider that keeps its records in memory and yields to the runtime on every request
+    #[derive(Default)]
+    struct MockAsyncProvider {
+        records: Mutex<Vec<DnsRecord>>,
+        ttl: Option<TTL>,
+    }
+    impl MockAsyncProvider {
+        fn with_records(records: Vec<DnsRecord>) -> MockAsyncProvider {
+            MockAsyncProvider {
+                records: Mutex::new(records),
+                ttl: None,
+            }
+        }
+    }
+    impl AsyncProvider for MockAsyncProvider {
+        fn capabilities(&self) -> ProviderCapabilities {
+            ProviderCapabilities {
+                ttl: true,
+                ..Default::default()
+            }
+        }
+        fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+            Err(ProviderError::DryRunNotSupported)
+        }
+        fn dry_run(&self) -> bool {
+            false
+        }
+        fn ttl(&self) -> Option<TTL> {
+            self.ttl
+        }
+        fn set_ttl(&mut self, ttl: TTL) {
+            self.ttl = Some(ttl);
+        }
+        async fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+            task::yield_now().await;
+            Ok(self.records.lock().unwrap().clone())
+        }
+        async fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+            task::yield_now().await;
+            let mut records = self.records.lock().unwrap();
+            match action {
+                Action::ClaimAndUpdate(domain, ips) | Action::Update(domain, ips) => {
+                    records.retain(|r| {
+                        !(r.domain_name == *domain && matches!(r.content, RecordContent::A(_)))
+                    });
+                    records.extend(ips.iter().map(|ip| a_record(domain, *ip)));
+                    Ok(())
+                }
+                Action::DeleteAndRelease(domain) if domain.starts_with("fail") => {
+                    Err(ProviderError::Internal(domain.to_owned()))
+                }
+                Action::DeleteAndRelease(domain) => {
+                    records.retain(|r| {
+                        !(r.domain_name == *domain && matches!(r.content, RecordContent::A(_)))
+                    });
+                    Ok(())
+                }
+            }
+        }
+        async fn create_txt_record(
+            &self,
+            domain: String,
+            content: String,
+        ) -> Result<(), ProviderError> {
+            task::yield_now().await;
+            self.records.lock().unwrap().push(DnsRecord {
+                ttl: self.ttl,
+                ..DnsRecord::new(domain, RecordContent::Txt(content))
+            });
+            Ok(())
+        }
+        async fn delete_txt_record(
+            &self,
+            domain: String,
+            content: String,
+        ) -> Result<(), ProviderError> {
+            task::yield_now().await;
+            self.records.lock().unwrap().retain(|r| {
+                r.domain_name != domain || r.content != RecordContent::Txt(content.clone())
+            });
+            Ok(())
+        }
+        fn zone_of(&self, domain: &str) -> Option<String> {
+            domain.split_once('.').map(|(_, zone)| zone.to_string())
+        }
+        async fn check_write_access(&self, domain: &str) -> Result<(), ProviderError> {
+            task::yield_now().await;
+            match domain.starts_with("readonly") {
+                true => Err(ProviderError::Internal(domain.to_owned())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn a_record(domain: &str, ip: Ipv4Addr) -> DnsRecord {
+        DnsRecord::new(domain.to_string(), RecordContent::A(ip))
+    }
+
+    #[test]
+    fn should_forward_requests_to_async_provider() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        let mut p = BlockingProvider::new(MockAsyncProvider::default()).unwrap();
+        p.set_ttl(300);
+        assert_eq!(p.ttl(), Some(300));
+        assert!(p.capabilities().ttl);
+        p.enable_dry_run().unwrap_err();
+
+        p.apply(&Action::ClaimAndUpdate(
+            "my.example.com".to_string(),
+            vec![ip],
+        ))
+        .unwrap();
+        assert_eq!(p.records().unwrap(), vec![a_record("my.example.com", ip)]);
+
+        p.create_txt_record("my.example.com".to_string(), "owner".to_string())
+            .unwrap();
+        assert_eq!(p.records().unwrap().len(), 2);
+        p.delete_txt_record("my.example.com".to_string(), "owner".to_string())
+            .unwrap();
+        assert_eq!(p.inner().records.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn should_apply_plans_in_order() {
+        let p = BlockingProvider::new(MockAsyncProvider::with_records(vec![a_record(
+            "old.example.com",
+            Ipv4Addr::new(10, 1, 1, 1),
+        )]))
+        .unwrap();
+        let plan: Plan = vec![
+            Action::DeleteAndRelease("old.example.com".to_string()),
+            Action::DeleteAndRelease("fail.example.com".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            p.apply_plan(&plan),
+            vec![
+                Ok(()),
+                Err(ProviderError::Internal("fail.example.com".to_string()))
+            ]
+        );
+        assert!(p.records().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_be_usable_from_multiple_threads() {
+        let p = BlockingProvider::new(MockAsyncProvider::default()).unwrap();
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let p = &p;
+                s.spawn(move || {
+                    p.apply(&Action::Update(
+                        format!("{}.example.com", i),
+                        vec![Ipv4Addr::new(203, 0, 113, i)],
+                    ))
+                    .unwrap()
+                });
+            }
+        });
+        assert_eq!(p.records().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn should_back_a_registry() {
+        let p = BlockingProvider::new(MockAsyncProvider::with_records(vec![DnsRecord::new(
+            "my.example.com",
+            RecordContent::Aaaa("2001:db8::1".parse().unwrap()),
+        )]))
+        .unwrap();
+
+        let mut registry = TxtRegistry::from_provider("tenant".to_string(), &p).unwrap();
+        registry.claim("my.example.com").unwrap();
+        assert_eq!(registry.owned_domains().len(), 1);
+        assert!(p
+            .records()
+            .unwrap()
+            .iter()
+            .any(|r| matches!(r.content, RecordContent::Txt(_))));
+    }
+
+    #[test]
+    fn should_forward_record_ttl() {
+        let mut p = BlockingProvider::new(MockAsyncProvider::default()).unwrap();
+
+        p.set_record_ttl(RecordTtl::Fixed(120)).unwrap();
+        assert_eq!(p.ttl(), Some(120));
+        p.set_record_ttl(RecordTtl::Auto).unwrap_err();
+    }
+
+    #[test]
+    fn should_forward_records_for() {
+        let p = BlockingProvider::new(MockAsyncProvider::with_records(vec![
+            a_record("www.example.com", Ipv4Addr::new(10, 1, 1, 1)),
+            a_record("www.example.org", Ipv4Addr::new(10, 1, 1, 2)),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            p.records_for("example.com").unwrap(),
+            vec![a_record("www.example.com", Ipv4Addr::new(10, 1, 1, 1))]
+        );
+    }
+
+    #[test]
+    fn should_forward_zone_of() {
+        let p = BlockingProvider::new(MockAsyncProvider::default()).unwrap();
+
+        assert_eq!(
+            p.zone_of("www.example.com"),
+            Some("example.com".to_string())
+        );
+        assert_eq!(p.zone_of("localhost"), None);
+    }
+
+    #[test]
+    fn should_forward_verify() {
+        let ip = Ipv4Addr::new(203, 0, 113, 1);
+        let p = BlockingProvider::new(MockAsyncProvider::with_records(vec![a_record(
+            "my.example.com",
+            ip,
+        )]))
+        .unwrap();
+
+        assert!(p
+            .verify(&Action::Update("my.example.com".to_string(), vec![ip]))
+            .unwrap());
+        assert!(!p
+            .verify(&Action::DeleteAndRelease("my.example.com".to_string()))
+            .unwrap());
+    }
+
+    #[test]
+    fn should_forward_check_write_access() {
+        let p = BlockingProvider::new(MockAsyncProvider::default()).unwrap();
+
+        p.check_write_access("my.example.com").unwrap();
+        assert_eq!(
+            p.check_write_access("readonly.example.com"),
+            Err(ProviderError::Internal("readonly.example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_forward_registry_records() {
+        let p = BlockingProvider::new(MockAsyncProvider::with_records(vec![
+            a_record("my.example.com", Ipv4Addr::new(10, 1, 1, 1)),
+            DnsRecord::new(
+                "my.example.com",
+                RecordContent::Txt("v=spf1 -all".to_string()),
+            ),
+            DnsRecord::new(
+                "my.example.com",
+                RecordContent::Txt("clouddns_nat_tenant".to_string()),
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            p.registry_records("clouddns_nat").unwrap(),
+            vec![
+                a_record("my.example.com", Ipv4Addr::new(10, 1, 1, 1)),
+                DnsRecord::new(
+                    "my.example.com",
+                    RecordContent::Txt("clouddns_nat_tenant".to_string()),
+                ),
+            ]
+        );
+    }
+}
@@ -1,24 +1,41 @@
+mod records;
 mod traits;
 mod wrapper;
 
-use log::{debug, trace};
+use std::{collections::HashMap, num::NonZeroUsize, ops::RangeInclusive, time::Duration};
+
+use itertools::Itertools;
+use log::{debug, trace, warn};
 use mockall_double::double;
 
-use super::{DnsProvider, DnsRecord, Provider, ProviderError, TxTRegistryProvider};
-use crate::{provider::RecordContent, provider::TTL};
+use super::{
+    DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordTtl,
+    TxTRegistryProvider,
+};
+use crate::{dry_run::DryRun, provider::normalize_domain, provider::RecordContent, provider::TTL};
 
 #[double]
 use wrapper::CloudflareWrapper;
 
+/// Cloudflare uses a TTL of 1 to indicate that the TTL should be chosen automatically
+const CLOUDFLARE_AUTO_TTL: TTL = 1;
+/// Smallest and largest TTL accepted by Cloudflare (except for [`CLOUDFLARE_AUTO_TTL`])
+const CLOUDFLARE_TTL_BOUNDS: RangeInclusive<TTL> = 60..=86400;
+
 /// A [`Provider`] connecting to the Cloudflare API for creating, retrieving and deleting DNS records.
 ///
 /// To create a provider, use the [`CloudflareProvider::from_config()`] function.
 #[non_exhaustive]
 pub struct CloudflareProvider {
     api: CloudflareWrapper,
+    zones: ZoneFilter,
     ttl: Option<TTL>,
     proxied: Option<bool>,
-    dry_run: bool,
+    proxied_domains: Vec<String>,
+    unproxied_domains: Vec<String>,
+    max_zone_fetches: NonZeroUsize,
+    comment: Option<String>,
+    dry_run: DryRun,
 }
 
 /// Configuration object for a [`CloudflareProvider`]. Must be supplied when creating a provider.
@@ -28,6 +45,100 @@ pub struct CloudflareProviderConfig<'a> {
     pub api_token: &'a str,
     /// Whether newly created records should be proxied through Cloudflares protective network
     pub proxied: Option<bool>,
+    /// Domains whose records should always be proxied, regardless of `proxied`.
+    /// Patterns may start with `*.` to match all subdomains (e.g. `*.example.com`)
+    pub proxied_domains: Vec<String>,
+    /// Domains whose records should never be proxied, regardless of `proxied`. Takes precedence over `proxied_domains`.
+    /// Patterns may start with `*.` to match all subdomains (e.g. `*.example.com`)
+    pub unproxied_domains: Vec<String>,
+    /// How often to retry a request that was rejected due to rate-limiting before failing
+    pub max_retries: u32,
+    /// Delay before retrying a rate-limited request. Doubles with each subsequent retry
+    pub retry_base_delay: Duration,
+    /// Only manage records in these zones (e.g. `example.com`). Manages all zones of the account if empty
+    pub include_zones: Vec<String>,
+    /// Never manage records in these zones, even if they are included
+    pub exclude_zones: Vec<String>,
+    /// Whether to verify that the API token is valid when creating the provider.
+    /// Disable this for offline testing
+    pub verify_token: bool,
+    /// Timeout for each request to the API. Uses the default of the API client (30 seconds) if not set
+    pub http_timeout: Option<Duration>,
+    /// Base URL of the API (e.g. `http://localhost:8080/client/v4/`), to send requests through a proxy or to a mock server.
+    /// Uses the production API if not set
+    pub api_url: Option<String>,
+    /// How many zones to list records for in parallel. Speeds up reading records for accounts with many zones
+    pub max_zone_fetches: NonZeroUsize,
+}
+
+/// Whether a domain matches a pattern. Patterns starting with `*.` match all subdomains, other patterns must match exactly
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = normalize_domain(pattern.trim_end_matches('.'));
+    let domain = normalize_domain(domain.trim_end_matches('.'));
+    match pattern.strip_prefix("*.") {
+        Some(parent) => domain.ends_with(&format!(".{}", parent)),
+        None => pattern == domain,
+    }
+}
+
+/// Decides which zones of the account the provider may manage
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+struct ZoneFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+impl ZoneFilter {
+    fn from_config(config: &CloudflareProviderConfig) -> ZoneFilter {
+        let normalize = |zones: &[String]| {
+            zones
+                .iter()
+                .map(|z| normalize_domain(z.trim_end_matches('.')))
+                .collect()
+        };
+        ZoneFilter {
+            include: normalize(&config.include_zones),
+            exclude: normalize(&config.exclude_zones),
+        }
+    }
+
+    fn matches(&self, zone_name: &str) -> bool {
+        let zone_name = normalize_domain(zone_name);
+        (self.include.is_empty() || self.include.contains(&zone_name))
+            && !self.exclude.contains(&zone_name)
+    }
+}
+
+/// The record queries needed by a TXT registry: all records of the types we manage, but only TXT records with the registry prefix
+fn registry_filters(txt_prefix: &str) -> Vec<records::RecordFilter> {
+    ["A", "AAAA", "CNAME"]
+        .into_iter()
+        .map(|t| records::RecordFilter {
+            record_type: Some(t.to_string()),
+            content_prefix: None,
+        })
+        .chain([records::RecordFilter {
+            record_type: Some("TXT".to_string()),
+            content_prefix: Some(txt_prefix.to_string()),
+        }])
+        .collect()
+}
+
+/// Make sure that the API token is valid and active, so that a bad token fails early instead of on the first request
+fn verify_token(api: &CloudflareWrapper) -> Result<(), ProviderError> {
+    match api.verify_token() {
+        Ok(r) if r.result.status == "active" => {
+            debug!("Verified Cloudflare API token {}", r.result.id);
+            Ok(())
+        }
+        Ok(r) => Err(ProviderError::Internal(format!(
+            "Invalid or expired API token: token status is {}",
+            r.result.status
+        ))),
+        Err(e) => Err(ProviderError::Internal(format!(
+            "Invalid or expired API token: {}",
+            e
+        ))),
+    }
 }
 
 impl CloudflareProvider {
@@ -35,13 +146,35 @@ impl CloudflareProvider {
     pub fn from_config(
         config: &CloudflareProviderConfig,
     ) -> Result<CloudflareProvider, ProviderError> {
-        let api = CloudflareWrapper::try_new(config.api_token)?;
+        let zones = ZoneFilter::from_config(config);
+        let api = CloudflareWrapper::try_new(
+            config.api_token,
+            wrapper::RetryPolicy {
+                max_retries: config.max_retries,
+                base_delay: config.retry_base_delay,
+            },
+            zones.clone(),
+            &wrapper::ClientSettings {
+                http_timeout: config.http_timeout,
+                api_url: config.api_url.to_owned(),
+                max_zone_fetches: config.max_zone_fetches,
+            },
+        )?;
+        if config.verify_token {
+            verify_token(&api)?;
+        }
+        api.init_cache()?;
 
         Ok(CloudflareProvider {
             api,
+            zones,
             ttl: None,
             proxied: config.proxied,
-            dry_run: false,
+            proxied_domains: config.proxied_domains.to_owned(),
+            unproxied_domains: config.unproxied_domains.to_owned(),
+            max_zone_fetches: config.max_zone_fetches,
+            comment: None,
+            dry_run: DryRun::default(),
         })
     }
 
@@ -53,151 +186,392 @@ impl CloudflareProvider {
     ) -> CloudflareProvider {
         CloudflareProvider {
             api: wrapper,
+            zones: ZoneFilter::from_config(config),
             ttl: None,
             proxied: config.proxied,
-            dry_run: false,
+            proxied_domains: config.proxied_domains.to_owned(),
+            unproxied_domains: config.unproxied_domains.to_owned(),
+            max_zone_fetches: config.max_zone_fetches,
+            comment: None,
+            dry_run: DryRun::default(),
         }
     }
 
     fn create_record(&self, rec: &DnsRecord) -> Result<(), ProviderError> {
         let zone_id = &self
             .api
-            .find_record_zone(rec)
-            .ok_or(format!("Could not find suitable zone for record {}", rec))?
-            .id;
+            .find_record_zone_id(rec)?
+            .ok_or(format!("Could not find suitable zone for record {}", rec))?;
 
-        if !self.dry_run {
+        self.dry_run.guard(|| {
             self.api.create_record(
                 zone_id,
                 &rec.domain_name,
-                &self.ttl,
-                &self.proxied,
-                rec.content.to_owned().into(),
-            )?;
-        }
+                // An explicitly configured TTL takes precedence over the one of the record
+                &self.ttl.or(rec.ttl),
+                &self.proxied_for(&rec.domain_name),
+                &rec.content,
+                &rec.comment,
+            )
+        })?;
         debug!("Created record {} in zone {}", rec, zone_id);
         Ok(())
     }
 
-    fn delete_record(&self, rec: &DnsRecord) -> Result<(), ProviderError> {
+    /// Attach a comment to an existing record, replacing any previous comment
+    fn comment_record(&self, rec: &DnsRecord, comment: &str) -> Result<(), ProviderError> {
         let zone_id = &self
             .api
-            .find_record_zone(rec)
-            .ok_or(format!("Could not find suitable zone for record {}", rec))?
-            .id;
-        let record_id = &self
+            .find_record_zone_id(rec)?
+            .ok_or(format!("Could not find suitable zone for record {}", rec))?;
+        let record_id = &self.api.find_record_id(rec).ok_or(format!(
+            "Could not find matching record id for record {}",
+            rec
+        ))?;
+
+        self.dry_run
+            .guard(|| self.api.update_record_comment(zone_id, record_id, comment))?;
+        debug!("Set comment of record {} to \"{}\"", rec, comment);
+        Ok(())
+    }
+
+    /// ID of the managed zone that a domain belongs to
+    fn zone_id_of(&self, domain: &str) -> Result<String, ProviderError> {
+        let domain = normalize_domain(domain.trim_end_matches('.'));
+        Ok(self.api.find_zone_id(&domain)?.ok_or(format!(
+            "Domain {} is not part of any zone managed by this provider (check the included/excluded zones)",
+            domain
+        ))?)
+    }
+
+    /// All records of a single zone
+    fn zone_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        debug!("Reading records of zone {} from Cloudflare API", zone_id);
+        Ok(self
             .api
-            .find_record_endpoint(rec)
-            .ok_or(format!(
-                "Could not find matching record id for record {}",
-                rec
-            ))?
-            .id;
+            .list_records(zone_id)?
+            .result
+            .iter()
+            .filter_map(|r| DnsRecord::try_from(r).ok())
+            .collect())
+    }
+
+    /// IDs of all zones of the account that the provider may manage
+    fn managed_zone_ids(&self) -> Result<Vec<String>, ProviderError> {
+        debug!("Reading zones from Cloudflare API");
+        let zone_ids = self
+            .api
+            .list_zones()?
+            .result
+            .into_iter()
+            .filter(|z| self.zones.matches(&z.name))
+            .map(|z| z.id)
+            .collect::<Vec<_>>();
+        trace!("Collected zones {:?}", zone_ids);
+        Ok(zone_ids)
+    }
 
-        if !self.dry_run {
-            self.api.delete_record(zone_id, record_id)?;
+    /// Whether records of a domain should be proxied, taking per-domain overrides into account
+    fn proxied_for(&self, domain: &str) -> Option<bool> {
+        if self
+            .unproxied_domains
+            .iter()
+            .any(|p| domain_matches(p, domain))
+        {
+            Some(false)
+        } else if self
+            .proxied_domains
+            .iter()
+            .any(|p| domain_matches(p, domain))
+        {
+            Some(true)
+        } else {
+            self.proxied
         }
+    }
+
+    fn delete_record(&self, rec: &DnsRecord) -> Result<(), ProviderError> {
+        let zone_id = &self
+            .api
+            .find_record_zone_id(rec)?
+            .ok_or(format!("Could not find suitable zone for record {}", rec))?;
+        let record_id = &self.api.find_record_id(rec).ok_or(format!(
+            "Could not find matching record id for record {}",
+            rec
+        ))?;
+
+        self.dry_run
+            .guard(|| self.api.delete_record(zone_id, record_id))?;
         debug!(
             "Deleted record {} with id {} from zone {}",
             rec, record_id, zone_id
         );
         Ok(())
     }
+
+    /// Apply an action, using the supplied list of current records instead of retrieving them from the API
+    fn apply_with_records(
+        &self,
+        action: &crate::plan::Action,
+        current_records: &[DnsRecord],
+    ) -> Result<(), ProviderError> {
+        let domain = match action {
+            crate::plan::Action::ClaimAndUpdate(domain, _)
+            | crate::plan::Action::Update(domain, _)
+            | crate::plan::Action::DeleteAndRelease(domain) => domain,
+        };
+        if self
+            .api
+            .find_record_zone_id(&DnsRecord::new(
+                domain.to_owned(),
+                RecordContent::A(std::net::Ipv4Addr::UNSPECIFIED),
+            ))?
+            .is_none()
+        {
+            return Err(format!(
+                "Domain {} is not part of any zone managed by this provider (check the included/excluded zones)",
+                domain
+            )
+            .into());
+        }
+
+        match action {
+            // Newly claimed domains usually have no A records, but the registry may have allowed adopting existing ones.
+            // Either way, they are replaced just like for an update
+            crate::plan::Action::ClaimAndUpdate(domain, ips)
+            | crate::plan::Action::Update(domain, ips) => {
+                let old_records = current_records
+                    .iter()
+                    .filter(|r| match r.content {
+                        RecordContent::A(_) => r.domain_name == *domain,
+                        _ => false,
+                    })
+                    .collect_vec();
+                // Keep the TTL of the existing records, unless a TTL has been set explicitly
+                let ttl = old_records.iter().filter_map(|r| r.ttl).max();
+                // Records for addresses that are still desired are kept as-is, Cloudflare rejects identical records anyway
+                let (kept, obsolete): (Vec<&DnsRecord>, Vec<&DnsRecord>) = old_records
+                    .into_iter()
+                    .partition(|r| matches!(r.content, RecordContent::A(a) if ips.contains(&a)));
+                // Create new records before deleting the old ones, so that a failure in between never leaves the domain without any A record
+                for ip in ips
+                    .iter()
+                    .filter(|ip| !kept.iter().any(|r| r.content == RecordContent::A(**ip)))
+                {
+                    self.create_record(&DnsRecord {
+                        ttl,
+                        comment: self.comment.clone(),
+                        ..DnsRecord::new(domain.clone(), RecordContent::A(*ip))
+                    })?;
+                }
+                // Kept records must carry the comment as well, e.g. when adopting records that were created by someone else
+                if let Some(comment) = &self.comment {
+                    for r in kept.iter().filter(|r| r.comment.as_ref() != Some(comment)) {
+                        self.comment_record(r, comment)?;
+                    }
+                }
+                for r in obsolete {
+                    self.delete_record(r)?;
+                }
+                Ok(())
+            }
+            crate::plan::Action::DeleteAndRelease(domain) => {
+                for r in current_records.iter().filter(|r| match r.content {
+                    RecordContent::A(_) => r.domain_name == *domain,
+                    _ => false,
+                }) {
+                    self.delete_record(r)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl DnsProvider for CloudflareProvider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            dry_run: true,
+            ttl: true,
+            batch_apply: true,
+            proxied: true,
+        }
+    }
+
     fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
-        debug!("Reading zones from Cloudflare API");
-        let zones = self.api.list_zones()?.result;
-        trace!("Collected zones {:?}", zones);
+        let zone_ids = self.managed_zone_ids()?;
+        let records = wrapper::map_concurrently(&zone_ids, self.max_zone_fetches, |id| {
+            self.api.list_records(id)
+        })?
+        .into_iter()
+        .flat_map(|f| f.result)
+        .filter_map(|r| DnsRecord::try_from(&r).ok())
+        .collect::<Vec<DnsRecord>>();
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
 
-        let records = zones
-            .iter()
-            .map(|z| self.api.list_records(&z.id))
-            .collect::<Result<Vec<_>, _>>()?
+    fn records_for(&self, zone_or_domain: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let domain = normalize_domain(zone_or_domain.trim_end_matches('.'));
+        let records = self
+            .zone_records(&self.zone_id_of(&domain)?)?
             .into_iter()
-            .flat_map(|f| f.result)
-            .filter_map(|r| DnsRecord::try_from(&r).ok())
+            .filter(|r| super::in_domain(&r.domain_name, &domain))
             .collect::<Vec<DnsRecord>>();
         trace!("Collected Records: {:?}", records);
         Ok(records)
     }
 
+    /// Returns the ID of the zone, domains outside of the managed zones are not assigned to any
+    fn zone_of(&self, domain: &str) -> Option<String> {
+        self.api
+            .find_zone_id(&normalize_domain(domain.trim_end_matches('.')))
+            .ok()
+            .flatten()
+    }
+
     fn ttl(&self) -> Option<TTL> {
         self.ttl
     }
 
     fn set_ttl(&mut self, ttl: TTL) {
-        self.ttl = Some(ttl);
+        let bounds = self.ttl_bounds();
+        let clamped = match ttl {
+            CLOUDFLARE_AUTO_TTL => ttl,
+            ttl => ttl.clamp(*bounds.start(), *bounds.end()),
+        };
+        if clamped != ttl {
+            warn!(
+                "TTL {} is not supported by Cloudflare (must be between {} and {}, or {} for automatic), using {} instead",
+                ttl,
+                bounds.start(),
+                bounds.end(),
+                CLOUDFLARE_AUTO_TTL,
+                clamped
+            );
+        }
+        self.ttl = Some(clamped);
+    }
+
+    /// [`RecordTtl::Auto`] maps to Cloudflare's automatic TTL, which is sent as a TTL of 1
+    fn set_record_ttl(&mut self, ttl: RecordTtl) -> Result<(), ProviderError> {
+        match ttl {
+            RecordTtl::Auto => self.ttl = Some(CLOUDFLARE_AUTO_TTL),
+            RecordTtl::Fixed(ttl) => self.set_ttl(ttl),
+        }
+        Ok(())
+    }
+
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        CLOUDFLARE_TTL_BOUNDS
     }
 
     fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
-        self.dry_run = true;
+        self.dry_run.enable();
         Ok(())
     }
 
     fn dry_run(&self) -> bool {
-        self.dry_run
+        self.dry_run.is_enabled()
     }
 
     fn apply(&self, action: &crate::plan::Action) -> Result<(), ProviderError> {
-        let current_records = self.records()?;
+        let zone_id = self.zone_id_of(action.domain())?;
+        self.apply_with_records(action, &self.zone_records(&zone_id)?)
+    }
 
-        match action {
-            crate::plan::Action::ClaimAndUpdate(domain, ip) => self.create_record(&DnsRecord {
-                domain_name: domain.clone(),
-                content: RecordContent::A(*ip),
-            }),
-            crate::plan::Action::Update(domain, ip) => {
-                // Delete old A records first
-                for r in current_records.iter().filter(|r| match r.content {
-                    RecordContent::A(_) => r.domain_name == *domain,
-                    _ => false,
-                }) {
-                    self.delete_record(r)?;
-                }
-                self.create_record(&DnsRecord {
-                    domain_name: domain.clone(),
-                    content: RecordContent::A(*ip),
-                })
-            }
-            crate::plan::Action::DeleteAndRelease(domain) => {
-                for r in current_records.iter().filter(|r| match r.content {
-                    RecordContent::A(_) => r.domain_name == *domain,
-                    _ => false,
-                }) {
-                    self.delete_record(r)?;
+    fn apply_plan(&self, plan: &crate::plan::Plan) -> Vec<Result<(), ProviderError>> {
+        // Only list the records of each zone once instead of for every action, and never those of zones the plan does not touch
+        let mut zone_records: HashMap<String, Result<Vec<DnsRecord>, ProviderError>> =
+            HashMap::new();
+        plan.actions()
+            .map(|a| {
+                let zone_id = self.zone_id_of(a.domain())?;
+                match zone_records
+                    .entry(zone_id)
+                    .or_insert_with_key(|id| self.zone_records(id))
+                {
+                    Ok(current_records) => self.apply_with_records(a, current_records),
+                    Err(e) => Err(e.clone()),
                 }
-                Ok(())
-            }
-        }
+            })
+            .collect()
     }
 }
 
 impl TxTRegistryProvider for CloudflareProvider {
     fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
-        self.create_record(&DnsRecord {
-            domain_name: domain,
-            content: super::RecordContent::Txt(content),
-        })
+        self.create_record(&DnsRecord::new(domain, super::RecordContent::Txt(content)))
     }
 
     fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
-        self.delete_record(&DnsRecord {
-            domain_name: domain,
-            content: super::RecordContent::Txt(content),
-        })
+        self.delete_record(&DnsRecord::new(domain, super::RecordContent::Txt(content)))
+    }
+}
+impl Provider for CloudflareProvider {
+    /// Ownership is stored in the comments of the A records
+    fn supports_native_ownership(&self) -> bool {
+        true
+    }
+
+    fn set_record_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    /// Lets the API filter TXT records by their prefix, so that unrelated TXT records are never transferred
+    fn registry_records(&self, txt_prefix: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let filters = registry_filters(txt_prefix);
+        let queries = self
+            .managed_zone_ids()?
+            .into_iter()
+            .cartesian_product(&filters)
+            .collect_vec();
+        let records =
+            wrapper::map_concurrently(&queries, self.max_zone_fetches, |(id, filter)| {
+                self.api.list_filtered_records(id, filter)
+            })?
+            .into_iter()
+            .flat_map(|f| f.result)
+            .filter_map(|r| DnsRecord::try_from(&r).ok())
+            .collect::<Vec<DnsRecord>>();
+        trace!("Collected registry records: {:?}", records);
+        Ok(records)
+    }
+
+    fn check_write_access(&self, domain: &str) -> Result<(), ProviderError> {
+        let probe = super::write_probe_record(domain);
+        let zone_id = &self
+            .api
+            .find_record_zone_id(&probe)?
+            .ok_or(format!("Could not find suitable zone for record {}", probe))?;
+
+        // Deliberately not guarded by dry-run mode, this is the one write that a dry-run performs
+        let created = self.api.create_record(
+            zone_id,
+            &probe.domain_name,
+            &None,
+            &None,
+            &probe.content,
+            &None,
+        )?;
+        self.api
+            .delete_record(zone_id, &created.result.record.id)
+            .map_err(|e| format!("Could not delete probe record {}: {}", probe, e))?;
+        debug!(
+            "Created and deleted probe record {} in zone {}",
+            probe, zone_id
+        );
+        Ok(())
     }
 }
-impl Provider for CloudflareProvider {}
 
 #[cfg(test)]
 mod tests {
-    use std::{net::Ipv4Addr, vec};
+    use std::{collections::HashMap, net::Ipv4Addr, vec};
 
     use cloudflare::{
         endpoints::{self, account::AccountDetails},
-        framework::response::ApiSuccess,
+        framework::response::{ApiErrors, ApiFailure, ApiResponse, ApiSuccess},
     };
 
     use super::*;
@@ -244,7 +618,7 @@ mod tests {
             name: "domain2.example.org".to_string(),
             ttl: 60,
             zone_id: "123456".to_string(),
-            modified_on: chrono::offset::Utc::now(),
+            modified_on: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
             created_on: chrono::offset::Utc::now(),
             proxiable: true,
             content: endpoints::dns::DnsContent::A {
@@ -256,18 +630,90 @@ mod tests {
         }
     }
 
+    fn token_status(status: &str) -> ApiResponse<endpoints::user::UserTokenStatus> {
+        Ok(ApiSuccess {
+            result: endpoints::user::UserTokenStatus {
+                id: "token123".to_string(),
+                status: status.to_string(),
+            },
+            result_info: None,
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        })
+    }
+
+    #[test]
+    fn should_accept_active_token() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_verify_token()
+            .times(1)
+            .return_once(|| token_status("active"));
+
+        verify_token(&mock).unwrap();
+    }
+
+    #[test]
+    fn should_reject_invalid_token() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_verify_token()
+            .times(1)
+            .return_once(|| token_status("expired"));
+        let err = verify_token(&mock).unwrap_err();
+        assert!(err.to_string().contains("Invalid or expired API token"));
+
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_verify_token().times(1).return_once(|| {
+            Err(ApiFailure::Error(
+                http::StatusCode::UNAUTHORIZED,
+                ApiErrors {
+                    errors: vec![],
+                    other: HashMap::new(),
+                },
+            ))
+        });
+        let err = verify_token(&mock).unwrap_err();
+        assert!(err.to_string().contains("Invalid or expired API token"));
+    }
+
+    #[test]
+    fn should_report_capabilities() {
+        let p =
+            CloudflareProvider::from_mock_wrapper(&config(&[], &[]), CloudflareWrapper::default());
+
+        assert_eq!(
+            p.capabilities(),
+            ProviderCapabilities {
+                dry_run: true,
+                ttl: true,
+                batch_apply: true,
+                proxied: true,
+            }
+        );
+    }
+
     #[test]
     fn should_support_dry_run() {
         // We intentionally do not expect create/delete_record to be called. If those are called in dry_run mode we fucked up
         let mut mock = CloudflareWrapper::default();
-        mock.expect_find_record_zone().returning(|_| Some(zone()));
-        mock.expect_find_record_endpoint()
-            .returning(|_| Some(endpoint()));
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some(zone().id)));
+        mock.expect_find_record_id()
+            .returning(|_| Some(endpoint().id));
 
         let mut p = CloudflareProvider::from_mock_wrapper(
             &super::CloudflareProviderConfig {
                 api_token: "abc",
                 proxied: Some(false),
+                proxied_domains: vec![],
+                unproxied_domains: vec![],
+                max_retries: 0,
+                retry_base_delay: Duration::ZERO,
+                include_zones: vec![],
+                exclude_zones: vec![],
+                verify_token: false,
+                http_timeout: None,
+                api_url: None,
+                max_zone_fetches: NonZeroUsize::MIN,
             },
             mock,
         );
@@ -278,6 +724,96 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn should_list_records_once_per_plan() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_list_zones().never();
+        mock.expect_find_zone_id()
+            .returning(|_| Ok(Some(zone().id)));
+        mock.expect_list_records().times(1).return_once(|_| {
+            Ok(ApiSuccess {
+                result: vec![endpoint().into()],
+                result_info: None,
+                messages: serde_json::Value::Null,
+                errors: vec![],
+            })
+        });
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some(zone().id)));
+        mock.expect_find_record_id()
+            .returning(|_| Some(endpoint().id));
+
+        let mut p = CloudflareProvider::from_mock_wrapper(
+            &super::CloudflareProviderConfig {
+                api_token: "abc",
+                proxied: Some(false),
+                proxied_domains: vec![],
+                unproxied_domains: vec![],
+                max_retries: 0,
+                retry_base_delay: Duration::ZERO,
+                include_zones: vec![],
+                exclude_zones: vec![],
+                verify_token: false,
+                http_timeout: None,
+                api_url: None,
+                max_zone_fetches: NonZeroUsize::MIN,
+            },
+            mock,
+        );
+        p.enable_dry_run().unwrap();
+        let plan = crate::plan::Plan::from_iter([
+            crate::plan::Action::Update(
+                "domain2.example.org".to_string(),
+                vec![Ipv4Addr::new(10, 1, 1, 3)],
+            ),
+            crate::plan::Action::ClaimAndUpdate(
+                "domain3.example.org".to_string(),
+                vec![Ipv4Addr::new(10, 1, 1, 3)],
+            ),
+            crate::plan::Action::DeleteAndRelease("domain2.example.org".to_string()),
+        ]);
+
+        assert_eq!(p.apply_plan(&plan), vec![Ok(()), Ok(()), Ok(())]);
+    }
+
+    #[test]
+    fn should_only_list_records_of_zones_in_plan() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_list_zones().never();
+        mock.expect_find_zone_id().returning(|domain| {
+            Ok(Some(
+                match domain.ends_with("example.com") {
+                    true => "1",
+                    false => "2",
+                }
+                .to_string(),
+            ))
+        });
+        for id in ["1", "2"] {
+            mock.expect_list_records()
+                .withf(move |zone_id| zone_id == id)
+                .times(1)
+                .returning(|_| {
+                    Ok(ApiSuccess {
+                        result: vec![],
+                        result_info: None,
+                        messages: serde_json::Value::Null,
+                        errors: vec![],
+                    })
+                });
+        }
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+        let plan = crate::plan::Plan::from_iter([
+            crate::plan::Action::DeleteAndRelease("a.example.com".to_string()),
+            crate::plan::Action::DeleteAndRelease("b.example.org".to_string()),
+            crate::plan::Action::DeleteAndRelease("c.example.com".to_string()),
+        ]);
+
+        assert_eq!(p.apply_plan(&plan), vec![Ok(()), Ok(()), Ok(())]);
+    }
+
     #[test]
     fn should_return_records() {
         let mut mock = CloudflareWrapper::default();
@@ -293,7 +829,7 @@ mod tests {
             .withf(|id| id == zone().id)
             .return_once(|_| {
                 Ok(ApiSuccess {
-                    result: vec![endpoint()],
+                    result: vec![endpoint().into()],
                     result_info: None,
                     messages: serde_json::Value::Null,
                     errors: vec![],
@@ -303,6 +839,16 @@ mod tests {
             &super::CloudflareProviderConfig {
                 api_token: "abc",
                 proxied: Some(false),
+                proxied_domains: vec![],
+                unproxied_domains: vec![],
+                max_retries: 0,
+                retry_base_delay: Duration::ZERO,
+                include_zones: vec![],
+                exclude_zones: vec![],
+                verify_token: false,
+                http_timeout: None,
+                api_url: None,
+                max_zone_fetches: NonZeroUsize::MIN,
             },
             mock,
         );
@@ -310,9 +856,806 @@ mod tests {
         assert_eq!(
             p.records(),
             Ok(vec![DnsRecord {
-                domain_name: endpoint().name,
-                content: crate::provider::RecordContent::A(Ipv4Addr::new(10, 1, 1, 2))
+                ttl: Some(endpoint().ttl),
+                modified_on: Some(endpoint().modified_on.into()),
+                ..DnsRecord::new(
+                    endpoint().name,
+                    crate::provider::RecordContent::A(Ipv4Addr::new(10, 1, 1, 2))
+                )
             }])
         );
     }
+
+    #[test]
+    fn should_only_list_records_of_target_zone() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_zone_id()
+            .withf(|domain| domain == "domain2.example.org")
+            .returning(|_| Ok(Some("2".to_string())));
+        mock.expect_list_zones().never();
+        mock.expect_list_records()
+            .withf(|id| id == "2")
+            .times(1)
+            .returning(|_| {
+                Ok(ApiSuccess {
+                    result: vec![
+                        endpoint().into(),
+                        endpoints::dns::DnsRecord {
+                            name: "other.example.org".to_string(),
+                            ..endpoint()
+                        }
+                        .into(),
+                    ],
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        let records = p.records_for("Domain2.example.org.").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].domain_name, endpoint().name);
+    }
+
+    #[test]
+    fn should_strip_trailing_dot_when_converting_records() {
+        let dotted = endpoints::dns::DnsRecord {
+            name: "domain2.example.org.".to_string(),
+            ..endpoint()
+        };
+
+        let converted = DnsRecord::try_from(&dotted).unwrap();
+        assert_eq!(converted.domain_name, "domain2.example.org");
+        assert_eq!(converted, DnsRecord::try_from(&endpoint()).unwrap());
+    }
+
+    #[test]
+    fn should_preserve_comment_when_converting_records() {
+        let commented = records::CommentedRecord {
+            record: endpoint(),
+            comment: Some("heritage=clouddns-nat".to_string()),
+        };
+
+        let converted = DnsRecord::try_from(&commented).unwrap();
+        assert_eq!(converted.comment, Some("heritage=clouddns-nat".to_string()));
+        assert_eq!(converted.domain_name, endpoint().name);
+        assert_eq!(
+            DnsRecord::try_from(&records::CommentedRecord::from(endpoint()))
+                .unwrap()
+                .comment,
+            None
+        );
+    }
+
+    #[test]
+    fn should_resolve_zone_of_domain() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_zone_id().returning(|domain| match domain {
+            "www.example.org" => Ok(Some("2".to_string())),
+            _ => Ok(None),
+        });
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        assert_eq!(p.zone_of("WWW.example.org."), Some("2".to_string()));
+        assert_eq!(p.zone_of("www.example.net"), None);
+    }
+
+    #[test]
+    fn should_fail_to_list_records_outside_managed_zones() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_zone_id().returning(|_| Ok(None));
+        mock.expect_list_records().never();
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        p.records_for("example.net").unwrap_err();
+    }
+
+    fn a_endpoint(name: &str, ip: Ipv4Addr) -> endpoints::dns::DnsRecord {
+        endpoints::dns::DnsRecord {
+            name: name.to_string(),
+            content: endpoints::dns::DnsContent::A { content: ip },
+            ..endpoint()
+        }
+    }
+
+    /// Returns a mock for a single zone that stores created records if `persist` is set, or silently drops them otherwise
+    fn verify_mock(persist: bool) -> CloudflareWrapper {
+        let stored = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        let created = stored.clone();
+        mock.expect_create_record()
+            .returning(move |_, name, _, _, content, _| {
+                let ip = match content {
+                    RecordContent::A(ip) => *ip,
+                    _ => unreachable!(),
+                };
+                if persist {
+                    created.lock().unwrap().push((name.to_string(), ip));
+                }
+                Ok(ApiSuccess {
+                    result: a_endpoint(name, ip).into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock.expect_list_records()
+            .withf(|id| id == "1")
+            .returning(move |_| {
+                Ok(ApiSuccess {
+                    result: stored
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(|(name, ip)| a_endpoint(name, *ip).into())
+                        .collect(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock
+    }
+
+    #[test]
+    fn should_verify_created_records() {
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), verify_mock(true));
+        let action = crate::plan::Action::ClaimAndUpdate(
+            "web.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        );
+
+        p.apply_with_records(&action, &[]).unwrap();
+        assert!(p.verify(&action).unwrap());
+    }
+
+    #[test]
+    fn should_fail_verification_of_missing_records() {
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), verify_mock(false));
+        let action = crate::plan::Action::ClaimAndUpdate(
+            "web.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        );
+
+        p.apply_with_records(&action, &[]).unwrap();
+        assert!(!p.verify(&action).unwrap());
+    }
+
+    #[test]
+    fn should_probe_write_access_in_dry_run() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_create_record()
+            .withf(|zone_id, name, _, _, content, _| {
+                zone_id == "1"
+                    && name == "_clouddns-nat-probe.example.com"
+                    && matches!(content, RecordContent::Txt(_))
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoints::dns::DnsRecord {
+                        id: "probe".to_string(),
+                        ..endpoint()
+                    }
+                    .into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock.expect_delete_record()
+            .withf(|zone_id, id| zone_id == "1" && id == "probe")
+            .times(1)
+            .returning(|_, _| {
+                Ok(ApiSuccess {
+                    result: endpoints::dns::DeleteDnsRecordResponse {
+                        id: "probe".to_string(),
+                    },
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let mut p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+        p.enable_dry_run().unwrap();
+
+        p.check_write_access("example.com.").unwrap();
+    }
+
+    #[test]
+    fn should_fail_write_probe_without_write_permission() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_create_record()
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Err(ApiFailure::Error(
+                    http::StatusCode::FORBIDDEN,
+                    ApiErrors {
+                        errors: vec![],
+                        other: HashMap::new(),
+                    },
+                ))
+            });
+        mock.expect_delete_record().never();
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        p.check_write_access("example.com").unwrap_err();
+    }
+
+    #[test]
+    fn should_return_records_of_all_zones_when_fetching_concurrently() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_list_zones().return_once(|| {
+            Ok(ApiSuccess {
+                result: vec![
+                    named_zone("1", "example.com"),
+                    named_zone("2", "example.org"),
+                    named_zone("3", "example.net"),
+                ],
+                result_info: None,
+                messages: serde_json::Value::Null,
+                errors: vec![],
+            })
+        });
+        mock.expect_list_records().times(3).returning(|id| {
+            Ok(ApiSuccess {
+                result: vec![endpoints::dns::DnsRecord {
+                    name: format!("domain{}.example.org", id),
+                    zone_id: id.to_string(),
+                    ..endpoint()
+                }
+                .into()],
+                result_info: None,
+                messages: serde_json::Value::Null,
+                errors: vec![],
+            })
+        });
+        let p = CloudflareProvider::from_mock_wrapper(
+            &CloudflareProviderConfig {
+                max_zone_fetches: NonZeroUsize::new(2).unwrap(),
+                ..config(&[], &[])
+            },
+            mock,
+        );
+
+        assert_eq!(
+            p.records()
+                .unwrap()
+                .into_iter()
+                .map(|r| r.domain_name)
+                .collect::<Vec<_>>(),
+            vec![
+                "domain1.example.org",
+                "domain2.example.org",
+                "domain3.example.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_filter_registry_records_by_api() {
+        let mut mock = multi_zone_mock(&[]);
+        let expected = registry_filters("clouddns_nat");
+        assert!(expected.contains(&records::RecordFilter {
+            record_type: Some("TXT".to_string()),
+            content_prefix: Some("clouddns_nat".to_string()),
+        }));
+        // Every filter is queried once per zone
+        mock.expect_list_filtered_records()
+            .times(3 * expected.len())
+            .withf(move |_, filter| expected.contains(filter))
+            .returning(|id, filter| {
+                let content = match filter.record_type.as_deref() {
+                    Some("TXT") => endpoints::dns::DnsContent::TXT {
+                        content: "clouddns_nat_tenant;rec: A".to_string(),
+                    },
+                    _ => endpoints::dns::DnsContent::A {
+                        content: Ipv4Addr::new(10, 1, 1, 2),
+                    },
+                };
+                Ok(ApiSuccess {
+                    result: vec![endpoints::dns::DnsRecord {
+                        name: format!("domain{}.example.org", id),
+                        content,
+                        ..endpoint()
+                    }
+                    .into()],
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        let records = p.registry_records("clouddns_nat").unwrap();
+        assert_eq!(records.len(), 3 * registry_filters("clouddns_nat").len());
+        assert!(records.contains(&DnsRecord {
+            ttl: Some(60),
+            modified_on: Some(endpoint().modified_on.into()),
+            ..DnsRecord::new(
+                "domain1.example.org",
+                RecordContent::Txt("clouddns_nat_tenant;rec: A".to_string())
+            )
+        }));
+    }
+
+    fn named_zone(id: &str, name: &str) -> endpoints::zone::Zone {
+        endpoints::zone::Zone {
+            id: id.to_string(),
+            name: name.to_string(),
+            ..zone()
+        }
+    }
+
+    fn config<'a>(include: &[&str], exclude: &[&str]) -> CloudflareProviderConfig<'a> {
+        CloudflareProviderConfig {
+            api_token: "abc",
+            proxied: Some(false),
+            proxied_domains: vec![],
+            unproxied_domains: vec![],
+            max_retries: 0,
+            retry_base_delay: Duration::ZERO,
+            include_zones: include.iter().map(|z| z.to_string()).collect(),
+            exclude_zones: exclude.iter().map(|z| z.to_string()).collect(),
+            verify_token: false,
+            http_timeout: None,
+            api_url: None,
+            max_zone_fetches: NonZeroUsize::MIN,
+        }
+    }
+
+    /// Returns a mock with three zones (example.com, example.org and example.net) that expects records to be listed for the given zone ids only
+    fn multi_zone_mock(listed_ids: &'static [&'static str]) -> CloudflareWrapper {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_list_zones().return_once(|| {
+            Ok(ApiSuccess {
+                result: vec![
+                    named_zone("1", "example.com"),
+                    named_zone("2", "example.org"),
+                    named_zone("3", "example.net"),
+                ],
+                result_info: None,
+                messages: serde_json::Value::Null,
+                errors: vec![],
+            })
+        });
+        mock.expect_list_records()
+            .times(listed_ids.len())
+            .withf(move |id| listed_ids.iter().any(|l| *l == id))
+            .returning(|_| {
+                Ok(ApiSuccess {
+                    result: vec![],
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock
+    }
+
+    #[test]
+    fn zone_filter_should_include_and_exclude_zones() {
+        let all = ZoneFilter::from_config(&config(&[], &[]));
+        assert!(all.matches("example.com"));
+
+        let included = ZoneFilter::from_config(&config(&["Example.com."], &[]));
+        assert!(included.matches("example.com"));
+        assert!(!included.matches("example.org"));
+        assert!(!included.matches("sub.example.com"));
+
+        let excluded = ZoneFilter::from_config(&config(&["example.com"], &["example.com"]));
+        assert!(!excluded.matches("example.com"));
+    }
+
+    #[test]
+    fn should_only_list_included_zones() {
+        let p = CloudflareProvider::from_mock_wrapper(
+            &config(&["example.com", "example.net"], &[]),
+            multi_zone_mock(&["1", "3"]),
+        );
+
+        p.records().unwrap();
+    }
+
+    #[test]
+    fn should_not_list_excluded_zones() {
+        let p = CloudflareProvider::from_mock_wrapper(
+            &config(&[], &["example.org"]),
+            multi_zone_mock(&["1", "3"]),
+        );
+
+        p.records().unwrap();
+    }
+
+    #[test]
+    fn should_reject_actions_outside_managed_zones() {
+        let mut mock = multi_zone_mock(&[]);
+        mock.expect_find_zone_id().returning(|_| Ok(None));
+        mock.expect_find_record_zone_id().returning(|_| Ok(None));
+        let p = CloudflareProvider::from_mock_wrapper(&config(&["example.com"], &[]), mock);
+
+        p.apply(&crate::plan::Action::DeleteAndRelease(
+            "my.example.org".to_string(),
+        ))
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_match_domain_patterns() {
+        assert!(domain_matches("my.example.com", "My.Example.com."));
+        assert!(!domain_matches("my.example.com", "sub.my.example.com"));
+        assert!(domain_matches("*.example.com", "my.example.com"));
+        assert!(domain_matches("*.example.com", "sub.my.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "myexample.com"));
+    }
+
+    #[test]
+    fn should_override_proxied_per_domain() {
+        let p = CloudflareProvider::from_mock_wrapper(
+            &CloudflareProviderConfig {
+                proxied_domains: vec!["*.example.com".to_string()],
+                unproxied_domains: vec!["ssh.example.com".to_string()],
+                ..config(&[], &[])
+            },
+            CloudflareWrapper::default(),
+        );
+
+        assert_eq!(p.proxied_for("web.example.com"), Some(true));
+        assert_eq!(p.proxied_for("ssh.example.com"), Some(false));
+        assert_eq!(p.proxied_for("web.example.org"), Some(false));
+    }
+
+    #[test]
+    fn should_create_records_with_domain_proxied_setting() {
+        let mut mock = multi_zone_mock(&["1"]);
+        mock.expect_find_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_create_record()
+            .withf(|_, name, _, proxied, _, _| name == "web.example.com" && *proxied == Some(true))
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoint().into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let p = CloudflareProvider::from_mock_wrapper(
+            &CloudflareProviderConfig {
+                proxied_domains: vec!["web.example.com".to_string()],
+                ..config(&["example.com"], &[])
+            },
+            mock,
+        );
+
+        p.apply(&crate::plan::Action::ClaimAndUpdate(
+            "web.example.com".to_string(),
+            vec![Ipv4Addr::new(10, 1, 1, 3)],
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_replace_a_records_with_all_desired_addresses() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_id()
+            .returning(|_| Some("old".to_string()));
+        mock.expect_delete_record()
+            .withf(|zone_id, id| zone_id == "1" && id == "old")
+            .times(1)
+            .returning(|_, _| {
+                Ok(ApiSuccess {
+                    result: endpoints::dns::DeleteDnsRecordResponse {
+                        id: "old".to_string(),
+                    },
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        for ip in [
+            Ipv4Addr::new(203, 0, 113, 1),
+            Ipv4Addr::new(198, 51, 100, 1),
+        ] {
+            mock.expect_create_record()
+                .withf(move |_, name, _, _, content, _| {
+                    name == "multi.example.com" && *content == RecordContent::A(ip)
+                })
+                .times(1)
+                .returning(|_, _, _, _, _, _| {
+                    Ok(ApiSuccess {
+                        result: endpoint().into(),
+                        result_info: None,
+                        messages: serde_json::Value::Null,
+                        errors: vec![],
+                    })
+                });
+        }
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        p.apply_with_records(
+            &crate::plan::Action::Update(
+                "multi.example.com".to_string(),
+                vec![
+                    Ipv4Addr::new(203, 0, 113, 1),
+                    Ipv4Addr::new(198, 51, 100, 1),
+                ],
+            ),
+            &[DnsRecord::new(
+                "multi.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+            )],
+        )
+        .unwrap();
+    }
+
+    fn multi_a_record(ip: Ipv4Addr) -> DnsRecord {
+        DnsRecord::new("multi.example.com", RecordContent::A(ip))
+    }
+
+    #[test]
+    fn should_support_native_ownership() {
+        let p =
+            CloudflareProvider::from_mock_wrapper(&config(&[], &[]), CloudflareWrapper::default());
+        assert!(p.supports_native_ownership());
+    }
+
+    #[test]
+    fn should_attach_comment_to_created_and_kept_records() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_id()
+            .returning(|_| Some("kept".to_string()));
+        mock.expect_create_record()
+            .withf(|_, _, _, _, content, comment| {
+                *content == RecordContent::A(Ipv4Addr::new(203, 0, 113, 1))
+                    && *comment == Some("owner".to_string())
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoint().into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        // Only the adopted record without the comment is updated
+        mock.expect_update_record_comment()
+            .withf(|zone_id, id, comment| zone_id == "1" && id == "kept" && comment == "owner")
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoint().into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let mut p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+        p.set_record_comment(Some("owner".to_string()));
+
+        p.apply_with_records(
+            &crate::plan::Action::ClaimAndUpdate(
+                "multi.example.com".to_string(),
+                vec![
+                    Ipv4Addr::new(203, 0, 113, 1),
+                    Ipv4Addr::new(198, 51, 100, 1),
+                    Ipv4Addr::new(198, 51, 100, 2),
+                ],
+            ),
+            &[
+                multi_a_record(Ipv4Addr::new(198, 51, 100, 1)),
+                DnsRecord {
+                    comment: Some("owner".to_string()),
+                    ..multi_a_record(Ipv4Addr::new(198, 51, 100, 2))
+                },
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn should_keep_old_records_if_creating_new_ones_fails() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_id()
+            .returning(|_| Some("old".to_string()));
+        mock.expect_create_record()
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Err(ApiFailure::Error(
+                    http::StatusCode::BAD_GATEWAY,
+                    ApiErrors {
+                        errors: vec![],
+                        other: HashMap::new(),
+                    },
+                ))
+            });
+        mock.expect_delete_record().never();
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        p.apply_with_records(
+            &crate::plan::Action::Update(
+                "multi.example.com".to_string(),
+                vec![Ipv4Addr::new(203, 0, 113, 1)],
+            ),
+            &[multi_a_record(Ipv4Addr::new(10, 1, 1, 1))],
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_only_replace_changed_addresses() {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_id()
+            .withf(|r| r.content == RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)))
+            .returning(|_| Some("old".to_string()));
+        mock.expect_create_record()
+            .withf(|_, _, _, _, content, _| {
+                *content == RecordContent::A(Ipv4Addr::new(198, 51, 100, 1))
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoint().into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock.expect_delete_record()
+            .withf(|_, id| id == "old")
+            .times(1)
+            .returning(|_, _| {
+                Ok(ApiSuccess {
+                    result: endpoints::dns::DeleteDnsRecordResponse {
+                        id: "old".to_string(),
+                    },
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), mock);
+
+        p.apply_with_records(
+            &crate::plan::Action::Update(
+                "multi.example.com".to_string(),
+                vec![
+                    Ipv4Addr::new(203, 0, 113, 1),
+                    Ipv4Addr::new(198, 51, 100, 1),
+                ],
+            ),
+            &[
+                multi_a_record(Ipv4Addr::new(10, 1, 1, 1)),
+                multi_a_record(Ipv4Addr::new(203, 0, 113, 1)),
+            ],
+        )
+        .unwrap();
+    }
+
+    fn ttl_mock(expected_ttl: Option<TTL>) -> CloudflareWrapper {
+        let mut mock = CloudflareWrapper::default();
+        mock.expect_find_record_zone_id()
+            .returning(|_| Ok(Some("1".to_string())));
+        mock.expect_find_record_id()
+            .returning(|_| Some("old".to_string()));
+        mock.expect_delete_record().returning(|_, _| {
+            Ok(ApiSuccess {
+                result: endpoints::dns::DeleteDnsRecordResponse {
+                    id: "old".to_string(),
+                },
+                result_info: None,
+                messages: serde_json::Value::Null,
+                errors: vec![],
+            })
+        });
+        mock.expect_create_record()
+            .withf(move |_, _, ttl, _, _, _| *ttl == expected_ttl)
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(ApiSuccess {
+                    result: endpoint().into(),
+                    result_info: None,
+                    messages: serde_json::Value::Null,
+                    errors: vec![],
+                })
+            });
+        mock
+    }
+
+    fn ttl_record(ttl: Option<TTL>) -> DnsRecord {
+        DnsRecord {
+            ttl,
+            ..DnsRecord::new(
+                "ttl.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+            )
+        }
+    }
+
+    fn ttl_update() -> crate::plan::Action {
+        crate::plan::Action::Update(
+            "ttl.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        )
+    }
+
+    #[test]
+    fn should_preserve_existing_ttl_on_update() {
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), ttl_mock(Some(3600)));
+
+        p.apply_with_records(&ttl_update(), &[ttl_record(Some(3600))])
+            .unwrap();
+    }
+
+    #[test]
+    fn should_prefer_configured_ttl_on_update() {
+        let mut p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), ttl_mock(Some(120)));
+        p.set_ttl(120);
+
+        p.apply_with_records(&ttl_update(), &[ttl_record(Some(3600))])
+            .unwrap();
+    }
+
+    #[test]
+    fn should_send_automatic_ttl_as_one() {
+        let mut p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), ttl_mock(Some(1)));
+        p.set_record_ttl(RecordTtl::Auto).unwrap();
+        assert_eq!(p.ttl(), Some(1));
+
+        p.apply_with_records(&ttl_update(), &[ttl_record(Some(3600))])
+            .unwrap();
+    }
+
+    #[test]
+    fn should_clamp_unsupported_ttls() {
+        let mut p =
+            CloudflareProvider::from_mock_wrapper(&config(&[], &[]), CloudflareWrapper::default());
+
+        p.set_ttl(30);
+        assert_eq!(p.ttl(), Some(60));
+        p.set_ttl(100_000);
+        assert_eq!(p.ttl(), Some(86400));
+    }
+
+    #[test]
+    fn should_keep_supported_ttls() {
+        let mut p =
+            CloudflareProvider::from_mock_wrapper(&config(&[], &[]), CloudflareWrapper::default());
+
+        p.set_ttl(300);
+        assert_eq!(p.ttl(), Some(300));
+        // Automatic TTL
+        p.set_ttl(1);
+        assert_eq!(p.ttl(), Some(1));
+        assert!(p.ttl_bounds().contains(&300));
+    }
+
+    #[test]
+    fn should_use_default_ttl_without_existing_records() {
+        let p = CloudflareProvider::from_mock_wrapper(&config(&[], &[]), ttl_mock(None));
+
+        p.apply_with_records(&ttl_update(), &[]).unwrap();
+    }
 }
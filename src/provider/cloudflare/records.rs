@@ -0,0 +1,359 @@
+//! Record endpoints that expose more than our API client supports, such as record comments.
+//!
+//! Responses are parsed from raw JSON, reusing the record type of the API client for all fields it knows about.
+
+use cloudflare::{
+    endpoints::dns::{DnsContent, DnsRecord},
+    framework::{
+        endpoint::{Endpoint, Method},
+        response::{ApiError, ApiErrors, ApiFailure, ApiResponse, ApiSuccess},
+    },
+};
+use serde_json::{json, Value};
+
+use crate::provider::{RecordContent, TTL};
+
+/// A record as returned by the API, together with its comment
+#[derive(Debug)]
+pub struct CommentedRecord {
+    pub record: DnsRecord,
+    pub comment: Option<String>,
+}
+impl From<DnsRecord> for CommentedRecord {
+    fn from(record: DnsRecord) -> Self {
+        CommentedRecord {
+            record,
+            comment: None,
+        }
+    }
+}
+impl TryFrom<Value> for CommentedRecord {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        // The API returns either null or an empty string for records without a comment
+        let comment = value["comment"]
+            .as_str()
+            .filter(|c| !c.is_empty())
+            .map(str::to_owned);
+        Ok(CommentedRecord {
+            record: serde_json::from_value(value)?,
+            comment,
+        })
+    }
+}
+
+/// Restricts which records are returned by a [`ListRecords`] request. Returns all records by default
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RecordFilter {
+    /// Only return records of this type (e.g. `TXT`)
+    pub record_type: Option<String>,
+    /// Only return records whose content starts with this prefix
+    pub content_prefix: Option<String>,
+}
+
+/// Lists one page of the records of a zone
+pub struct ListRecords<'a> {
+    pub zone_identifier: &'a str,
+    pub filter: &'a RecordFilter,
+    pub page: u32,
+    pub per_page: u32,
+}
+impl Endpoint<Value, Value> for ListRecords<'_> {
+    fn method(&self) -> Method {
+        Method::Get
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    fn query(&self) -> Option<Value> {
+        let mut query = json!({ "page": self.page, "per_page": self.per_page });
+        if let Some(record_type) = &self.filter.record_type {
+            query["type"] = json!(record_type);
+        }
+        if let Some(prefix) = &self.filter.content_prefix {
+            query["content.startswith"] = json!(prefix);
+        }
+        Some(query)
+    }
+}
+
+/// Creates a record, attaching a comment if one is given
+/// Creates a new record. The body is built by [`CreateRecord::new()`], as serializing the record content may fail
+pub struct CreateRecord<'a> {
+    zone_identifier: &'a str,
+    body: Value,
+}
+impl<'a> CreateRecord<'a> {
+    pub fn new(
+        zone_identifier: &'a str,
+        name: &str,
+        ttl: Option<TTL>,
+        proxied: Option<bool>,
+        content: &RecordContent,
+        comment: Option<&str>,
+    ) -> Result<Self, serde_json::Error> {
+        // Serializing the content of the API client yields the "type" and "content" fields
+        let mut body = serde_json::to_value(DnsContent::from(content.to_owned()))?;
+        body["name"] = json!(name);
+        if let Some(ttl) = ttl {
+            body["ttl"] = json!(ttl);
+        }
+        if let Some(proxied) = proxied {
+            body["proxied"] = json!(proxied);
+        }
+        if let Some(comment) = comment {
+            body["comment"] = json!(comment);
+        }
+        Ok(CreateRecord {
+            zone_identifier,
+            body,
+        })
+    }
+}
+impl Endpoint<Value, (), Value> for CreateRecord<'_> {
+    fn method(&self) -> Method {
+        Method::Post
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    fn body(&self) -> Option<Value> {
+        Some(self.body.to_owned())
+    }
+}
+
+/// Replaces the comment of an existing record, leaving all other fields untouched
+pub struct UpdateRecordComment<'a> {
+    pub zone_identifier: &'a str,
+    pub identifier: &'a str,
+    pub comment: &'a str,
+}
+impl Endpoint<Value, (), Value> for UpdateRecordComment<'_> {
+    fn method(&self) -> Method {
+        Method::Patch
+    }
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/dns_records/{}",
+            self.zone_identifier, self.identifier
+        )
+    }
+    fn body(&self) -> Option<Value> {
+        Some(json!({ "comment": self.comment }))
+    }
+}
+
+/// Parse the response to a [`ListRecords`] request
+pub fn parse_records(response: ApiSuccess<Value>) -> ApiResponse<Vec<CommentedRecord>> {
+    map_result(response, |result| match result {
+        Value::Array(records) => records
+            .into_iter()
+            .map(CommentedRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid record in response: {}", e)),
+        other => Err(format!("Expected a list of records, got {}", other)),
+    })
+}
+
+/// Parse the response to a [`CreateRecord`] or [`UpdateRecordComment`] request
+pub fn parse_record(response: ApiSuccess<Value>) -> ApiResponse<CommentedRecord> {
+    map_result(response, |result| {
+        CommentedRecord::try_from(result).map_err(|e| format!("Invalid record in response: {}", e))
+    })
+}
+
+fn map_result<T>(
+    response: ApiSuccess<Value>,
+    parse: impl FnOnce(Value) -> Result<T, String>,
+) -> ApiResponse<T> {
+    Ok(ApiSuccess {
+        result: parse(response.result).map_err(invalid_response)?,
+        result_info: response.result_info,
+        messages: response.messages,
+        errors: response.errors,
+    })
+}
+
+/// The request itself succeeded, but we could not make sense of the response
+fn invalid_response(message: String) -> ApiFailure {
+    api_failure(http::StatusCode::OK, message)
+}
+
+/// We could not build a valid request, so it was never sent
+pub fn invalid_request(message: String) -> ApiFailure {
+    api_failure(http::StatusCode::BAD_REQUEST, message)
+}
+
+fn api_failure(status: http::StatusCode, message: String) -> ApiFailure {
+    ApiFailure::Error(
+        status,
+        ApiErrors {
+            errors: vec![ApiError {
+                code: 0,
+                message,
+                other: Default::default(),
+            }],
+            other: Default::default(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use std::net::Ipv4Addr;
+
+    use cloudflare::framework::{endpoint::Endpoint, response::ApiSuccess};
+
+    use super::{
+        parse_record, parse_records, CommentedRecord, CreateRecord, ListRecords, RecordFilter,
+    };
+    use crate::provider::RecordContent;
+
+    fn record_json(comment: serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": "372e67954025e0ba6aaa6d586b9e0b59",
+            "zone_id": "023e105f4ecef8ad9ca31a8372d0c353",
+            "zone_name": "example.com",
+            "name": "www.example.com",
+            "type": "A",
+            "content": "198.51.100.4",
+            "proxiable": true,
+            "proxied": false,
+            "ttl": 3600,
+            "meta": { "auto_added": false },
+            "comment": comment,
+            "created_on": "2014-01-01T05:20:00.12345Z",
+            "modified_on": "2014-01-01T05:20:00.12345Z"
+        })
+    }
+
+    #[test]
+    fn should_parse_record_comments() {
+        let record =
+            CommentedRecord::try_from(record_json(json!("managed by nat-helper"))).unwrap();
+        assert_eq!(record.comment, Some("managed by nat-helper".to_string()));
+        assert_eq!(record.record.name, "www.example.com");
+
+        let record = CommentedRecord::try_from(record_json(json!(null))).unwrap();
+        assert_eq!(record.comment, None);
+        let record = CommentedRecord::try_from(record_json(json!(""))).unwrap();
+        assert_eq!(record.comment, None);
+    }
+
+    #[test]
+    fn should_parse_record_lists() {
+        let response = parse_records(ApiSuccess {
+            result: json!([record_json(json!("first")), record_json(json!(null))]),
+            result_info: Some(json!({"total_pages": 1})),
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        })
+        .unwrap();
+
+        assert_eq!(response.result.len(), 2);
+        assert_eq!(response.result[0].comment, Some("first".to_string()));
+        assert_eq!(response.result_info, Some(json!({"total_pages": 1})));
+    }
+
+    #[test]
+    fn should_reject_invalid_record_lists() {
+        parse_records(ApiSuccess {
+            result: json!({"not": "a list"}),
+            result_info: None,
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        })
+        .unwrap_err();
+        parse_records(ApiSuccess {
+            result: json!([{"id": "incomplete"}]),
+            result_info: None,
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_send_comment_when_creating_records() {
+        let content = RecordContent::A(Ipv4Addr::new(198, 51, 100, 4));
+        let request = CreateRecord::new(
+            "023e105f4ecef8ad9ca31a8372d0c353",
+            "www.example.com",
+            Some(60),
+            None,
+            &content,
+            Some("managed by nat-helper"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.body(),
+            Some(json!({
+                "type": "A",
+                "content": "198.51.100.4",
+                "name": "www.example.com",
+                "ttl": 60,
+                "comment": "managed by nat-helper"
+            }))
+        );
+        assert_eq!(
+            request.path(),
+            "zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records"
+        );
+    }
+
+    #[test]
+    fn should_parse_single_records() {
+        let response = parse_record(ApiSuccess {
+            result: record_json(json!("managed by nat-helper")),
+            result_info: None,
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        })
+        .unwrap();
+
+        assert_eq!(
+            response.result.comment,
+            Some("managed by nat-helper".to_string())
+        );
+        assert_eq!(
+            response.result.record.id,
+            "372e67954025e0ba6aaa6d586b9e0b59"
+        );
+    }
+
+    #[test]
+    fn should_filter_listed_records() {
+        let filter = RecordFilter {
+            record_type: Some("TXT".to_string()),
+            content_prefix: Some("clouddns_nat".to_string()),
+        };
+        let request = ListRecords {
+            zone_identifier: "023e105f4ecef8ad9ca31a8372d0c353",
+            filter: &filter,
+            page: 2,
+            per_page: 100,
+        };
+        assert_eq!(
+            request.query(),
+            Some(json!({
+                "page": 2,
+                "per_page": 100,
+                "type": "TXT",
+                "content.startswith": "clouddns_nat"
+            }))
+        );
+
+        let unfiltered = ListRecords {
+            filter: &RecordFilter::default(),
+            ..request
+        };
+        assert_eq!(
+            unfiltered.query(),
+            Some(json!({ "page": 2, "per_page": 100 }))
+        );
+    }
+}
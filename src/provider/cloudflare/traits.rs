@@ -1,6 +1,7 @@
 use cloudflare::{endpoints, framework::response::ApiFailure};
 
-use crate::provider::{DnsRecord, ProviderError, RecordContent};
+use super::records::CommentedRecord;
+use crate::provider::{strip_root_dot, DnsRecord, ProviderError, RecordContent};
 
 impl From<ApiFailure> for ProviderError {
     fn from(e: ApiFailure) -> Self {
@@ -16,15 +17,33 @@ impl TryFrom<&endpoints::dns::DnsRecord> for DnsRecord {
             endpoints::dns::DnsContent::A { content } => RecordContent::A(*content),
             endpoints::dns::DnsContent::AAAA { content } => RecordContent::Aaaa(*content),
             endpoints::dns::DnsContent::TXT { content } => RecordContent::Txt(content.to_owned()),
+            endpoints::dns::DnsContent::CNAME { content } => {
+                RecordContent::Cname(content.to_owned())
+            }
             _ => return Err(format!("Invalid record type: {:?}", r.content)),
         };
         Ok(DnsRecord {
-            domain_name: r.name.to_owned(),
+            domain_name: strip_root_dot(&r.name).to_owned(),
+            ttl: Some(r.ttl),
+            modified_on: Some(r.modified_on.into()),
+            // The record type of our API client does not include comments, see CommentedRecord
+            comment: None,
             content: converted_content,
         })
     }
 }
 
+impl TryFrom<&CommentedRecord> for DnsRecord {
+    type Error = String;
+
+    fn try_from(r: &CommentedRecord) -> Result<Self, Self::Error> {
+        Ok(DnsRecord {
+            comment: r.comment.to_owned(),
+            ..DnsRecord::try_from(&r.record)?
+        })
+    }
+}
+
 impl From<RecordContent> for endpoints::dns::DnsContent {
     fn from(c: RecordContent) -> Self {
         match &c {
@@ -33,6 +52,9 @@ impl From<RecordContent> for endpoints::dns::DnsContent {
             RecordContent::Txt(txt) => endpoints::dns::DnsContent::TXT {
                 content: txt.to_owned(),
             },
+            RecordContent::Cname(cname) => endpoints::dns::DnsContent::CNAME {
+                content: cname.to_owned(),
+            },
         }
     }
 }
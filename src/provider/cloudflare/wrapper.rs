@@ -1,5 +1,7 @@
 #![cfg_attr(test, allow(dead_code))]
 
+use std::{num::NonZeroUsize, sync::Mutex, thread, time::Duration};
+
 use cloudflare::{
     endpoints::{self},
     framework::{
@@ -9,7 +11,14 @@ use cloudflare::{
     },
 };
 
-use crate::provider::{DnsRecord, ProviderError, RecordContent, TTL};
+use itertools::Itertools;
+use log::warn;
+
+use super::{
+    records::{self, CommentedRecord, RecordFilter},
+    ZoneFilter,
+};
+use crate::provider::{in_domain, DnsRecord, ProviderError, RecordContent, TTL};
 
 const CLOUDFLARE_ZONE_PAGE_SIZE: u8 = 50;
 const CLOUDFLARE_RECORD_PAGE_SIZE: u16 = 5000;
@@ -17,43 +26,139 @@ const CLOUDFLARE_RECORD_PAGE_SIZE: u16 = 5000;
 /// Internal wrapper around the Cloudflare API. Provides some convenience features such as paged requests
 pub struct CloudflareWrapper {
     client: HttpApiClient,
-    cache: FinderCache,
+    cache: Mutex<FinderCache>,
+    retry: RetryPolicy,
+    zones: ZoneFilter,
+    max_zone_fetches: NonZeroUsize,
+}
+
+/// How to retry requests that were rejected due to rate-limiting (HTTP 429)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// How often to retry a request before giving up
+    pub max_retries: u32,
+    /// Delay before the first retry. Doubles with each subsequent retry
+    pub base_delay: Duration,
+}
+
+/// Settings for the HTTP client used to access the API
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientSettings {
+    /// Timeout for each request. Uses the default of the API client if not set
+    pub http_timeout: Option<Duration>,
+    /// Base URL of the API. Uses the production API if not set
+    pub api_url: Option<String>,
+    /// How many zones to list records for in parallel
+    pub max_zone_fetches: NonZeroUsize,
+}
+impl ClientSettings {
+    fn client_config(&self) -> HttpApiClientConfig {
+        let mut config = HttpApiClientConfig::default();
+        if let Some(timeout) = self.http_timeout {
+            config.http_timeout = timeout;
+        }
+        config
+    }
+
+    fn environment(&self) -> Result<Environment, ProviderError> {
+        match &self.api_url {
+            Some(url) => url::Url::parse(url)
+                .map(Environment::Custom)
+                .map_err(|e| ProviderError::Internal(format!("Invalid API URL {}: {}", url, e))),
+            None => Ok(Environment::Production),
+        }
+    }
+}
+
+// Our API client does not expose response headers, so we can't honor Retry-After and use exponential backoff instead.
+fn with_retry<T>(
+    policy: &RetryPolicy,
+    sleep: impl Fn(Duration),
+    mut request: impl FnMut() -> Result<T, ApiFailure>,
+) -> Result<T, ApiFailure> {
+    let mut attempt = 0;
+    loop {
+        match request() {
+            Err(ApiFailure::Error(http::StatusCode::TOO_MANY_REQUESTS, _))
+                if attempt < policy.max_retries =>
+            {
+                let delay = policy
+                    .base_delay
+                    .saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                warn!(
+                    "Rate-limited by Cloudflare API, retrying in {}s ({}/{})",
+                    delay.as_secs_f32(),
+                    attempt,
+                    policy.max_retries
+                );
+                sleep(delay);
+            }
+            res => return res,
+        }
+    }
+}
+
+// Perform a paged request by repeatedly calling the provided request fun with the current page number (starting at 1).
+// page_size must match the page_size in the request. The caller is responsible for ensuring that these match.
+// Results are returned in page order.
+//
+// The total number of pages is taken from the result_info of the first response.
+// If the API does not return that information, we keep requesting pages until we get a partial page (or a 404 for an empty one)
+fn collect_pages<R>(
+    page_size: usize,
+    request: &mut dyn FnMut(u32) -> ApiResponse<Vec<R>>,
+) -> ApiResponse<Vec<R>> {
+    let mut page_counter = 1;
+
+    // Initial failures are never good, return quickly
+    let mut response = request(page_counter)?;
+    let total_pages = response
+        .result_info
+        .as_ref()
+        .and_then(|i| i.get("total_pages"))
+        .and_then(|p| p.as_u64());
+    let mut current_size = response.result.len();
+
+    loop {
+        let more_pages = match total_pages {
+            Some(total) => u64::from(page_counter) < total,
+            None => current_size >= page_size,
+        };
+        if !more_pages {
+            return Ok(response);
+        }
+
+        page_counter += 1;
+        match request(page_counter) {
+            Ok(mut r) => {
+                current_size = r.result.len();
+                response.result.append(&mut r.result);
+            }
+            Err(e) => match e {
+                ApiFailure::Error(code, _) => match code {
+                    http::StatusCode::NOT_FOUND => return Ok(response),
+                    _ => return Err(e),
+                },
+                ApiFailure::Invalid(e) => return Err(e.into()),
+            },
+        };
+    }
 }
 
 impl CloudflareWrapper {
-    // Perform a paged request by repeatedly calling the provided request fun.
-    // The request callback needs to accept a CloudflareProvider (handled by this method) and the current page_counter
-    // page_size must match the page_size in the request. The caller is responsible for ensuring that these match
+    // Perform a request, retrying it with backoff if we are being rate-limited
+    fn retrying<T>(&self, request: impl FnMut() -> Result<T, ApiFailure>) -> Result<T, ApiFailure> {
+        with_retry(&self.retry, thread::sleep, request)
+    }
+
+    // Perform a paged request, retrying individual pages if we are being rate-limited. See collect_pages() for details
     fn paged_request<R>(
         &self,
         page_size: usize,
         request: &mut dyn FnMut(u32) -> ApiResponse<Vec<R>>,
     ) -> ApiResponse<Vec<R>> {
-        let mut page_counter = 1;
-
-        // Initial failures are never good, return quickly
-        let mut response = request(page_counter)?;
-        let mut current_size = response.result.len();
-
-        while current_size >= page_size {
-            page_counter += 1;
-            match request(page_counter) {
-                Ok(r) => {
-                    current_size = r.result.len();
-                    let mut previous_results = response.result;
-                    response = r;
-                    response.result.append(&mut previous_results);
-                }
-                Err(e) => match e {
-                    ApiFailure::Error(code, _) => match code {
-                        http::StatusCode::NOT_FOUND => return Ok(response),
-                        _ => return Err(e),
-                    },
-                    ApiFailure::Invalid(e) => return Err(e.into()),
-                },
-            };
-        }
-        Ok(response)
+        collect_pages(page_size, &mut |page| self.retrying(|| request(page)))
     }
 
     pub fn list_zones(&self) -> ApiResponse<Vec<endpoints::zone::Zone>> {
@@ -71,18 +176,25 @@ impl CloudflareWrapper {
         )
     }
 
-    pub fn list_records(&self, zone_id: &str) -> ApiResponse<Vec<endpoints::dns::DnsRecord>> {
+    pub fn list_records(&self, zone_id: &str) -> ApiResponse<Vec<CommentedRecord>> {
+        self.list_filtered_records(zone_id, &RecordFilter::default())
+    }
+
+    /// List only the records of a zone that match the filter. The filter is applied by the API, not locally
+    pub fn list_filtered_records(
+        &self,
+        zone_id: &str,
+        filter: &RecordFilter,
+    ) -> ApiResponse<Vec<CommentedRecord>> {
         self.paged_request(
             CLOUDFLARE_RECORD_PAGE_SIZE.into(),
             &mut |page_counter: u32| {
-                self.client.request(&endpoints::dns::ListDnsRecords {
+                records::parse_records(self.client.request(&records::ListRecords {
                     zone_identifier: zone_id,
-                    params: endpoints::dns::ListDnsRecordsParams {
-                        page: Some(page_counter),
-                        per_page: Some(CLOUDFLARE_RECORD_PAGE_SIZE.into()),
-                        ..Default::default()
-                    },
-                })
+                    filter,
+                    page: page_counter,
+                    per_page: CLOUDFLARE_RECORD_PAGE_SIZE.into(),
+                })?)
             },
         )
     }
@@ -93,18 +205,37 @@ impl CloudflareWrapper {
         name: &str,
         ttl: &Option<TTL>,
         proxied: &Option<bool>,
-        content: endpoints::dns::DnsContent,
-    ) -> ApiResponse<endpoints::dns::DnsRecord> {
-        self.client.request(&endpoints::dns::CreateDnsRecord {
-            zone_identifier: zone_id,
-            params: endpoints::dns::CreateDnsRecordParams {
-                priority: None,
-                ttl: *ttl,
-                proxied: *proxied,
-                name,
-                content,
-            },
-        })
+        content: &RecordContent,
+        comment: &Option<String>,
+    ) -> ApiResponse<CommentedRecord> {
+        let request =
+            records::CreateRecord::new(zone_id, name, *ttl, *proxied, content, comment.as_deref())
+                .map_err(|e| {
+                    records::invalid_request(format!("Could not serialize record {}: {}", name, e))
+                })?;
+        let response = self.retrying(|| records::parse_record(self.client.request(&request)?))?;
+        self.cache.lock().unwrap().insert_record(&response.result);
+        Ok(response)
+    }
+
+    /// Replace the comment of an existing record
+    pub fn update_record_comment(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        comment: &str,
+    ) -> ApiResponse<CommentedRecord> {
+        let response = self.retrying(|| {
+            records::parse_record(self.client.request(&records::UpdateRecordComment {
+                zone_identifier: zone_id,
+                identifier: record_id,
+                comment,
+            })?)
+        })?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.remove_record(record_id);
+        cache.insert_record(&response.result);
+        Ok(response)
     }
 
     pub fn delete_record(
@@ -112,95 +243,184 @@ impl CloudflareWrapper {
         zone_id: &str,
         record_id: &str,
     ) -> ApiResponse<endpoints::dns::DeleteDnsRecordResponse> {
-        self.client.request(&endpoints::dns::DeleteDnsRecord {
-            zone_identifier: zone_id,
-            identifier: record_id,
-        })
+        let response = self.retrying(|| {
+            self.client.request(&endpoints::dns::DeleteDnsRecord {
+                zone_identifier: zone_id,
+                identifier: record_id,
+            })
+        })?;
+        self.cache.lock().unwrap().remove_record(record_id);
+        Ok(response)
     }
 
-    pub fn try_new(api_token: &str) -> Result<CloudflareWrapper, ProviderError> {
+    /// Check whether the API token is valid
+    pub fn verify_token(&self) -> ApiResponse<endpoints::user::UserTokenStatus> {
+        self.retrying(|| self.client.request(&endpoints::user::GetUserTokenStatus {}))
+    }
+
+    pub fn try_new(
+        api_token: &str,
+        retry: RetryPolicy,
+        zones: ZoneFilter,
+        client: &ClientSettings,
+    ) -> Result<CloudflareWrapper, ProviderError> {
         let api = HttpApiClient::new(
             Credentials::UserAuthToken {
                 token: api_token.into(),
             },
-            HttpApiClientConfig::default(),
-            Environment::Production,
+            client.client_config(),
+            client.environment()?,
         );
 
         match api {
-            Ok(api) => {
-                let mut wrapper = CloudflareWrapper {
-                    client: api,
-                    cache: FinderCache {
-                        zones: Vec::new(),
-                        records: Vec::new(),
-                    },
-                };
-                let cache = FinderCache::try_new(&wrapper)?;
-                wrapper.cache = cache;
-                Ok(wrapper)
-            }
+            Ok(api) => Ok(CloudflareWrapper {
+                client: api,
+                cache: Mutex::new(FinderCache {
+                    zones: Vec::new(),
+                    records: Vec::new(),
+                }),
+                retry,
+                zones,
+                max_zone_fetches: client.max_zone_fetches,
+            }),
             Err(e) => Err(ProviderError::Internal(e.to_string())),
         }
     }
 
-    pub fn find_record_zone(&self, record: &DnsRecord) -> Option<&endpoints::zone::Zone> {
-        let mut zones = self
-            .cache
-            .zones
-            .iter()
-            .filter(|z| record.domain_name == z.name || record.domain_name.ends_with(&z.name))
-            .collect::<Vec<_>>();
+    /// Load all zones and records used to look up IDs. Must be called before using any of the find_ methods
+    pub fn init_cache(&self) -> Result<(), ProviderError> {
+        let cache = FinderCache::try_new(self)?;
+        *self.cache.lock().unwrap() = cache;
+        Ok(())
+    }
 
-        zones.sort_by(|a, b| a.name.len().cmp(&b.name.len()));
-        zones.pop()
+    /// Find the ID of the zone that the record belongs to
+    pub fn find_record_zone_id(&self, record: &DnsRecord) -> Result<Option<String>, ProviderError> {
+        self.find_zone_id(&record.domain_name)
     }
 
-    pub fn find_record_endpoint(&self, record: &DnsRecord) -> Option<&endpoints::dns::DnsRecord> {
+    /// Find the ID of the zone that a domain belongs to, see [`FinderCache::find_zone_id()`]
+    pub fn find_zone_id(&self, domain: &str) -> Result<Option<String>, ProviderError> {
+        Ok(self
+            .cache
+            .lock()
+            .unwrap()
+            .find_zone_id(domain)?
+            .map(str::to_owned))
+    }
+
+    /// Find the ID of an existing record with the same name and content
+    pub fn find_record_id(&self, record: &DnsRecord) -> Option<String> {
         self.cache
-            .records
-            .iter()
-            .filter(|r| {
-                r.name == record.domain_name
-                    && match &record.content {
-                        RecordContent::A(a) => match &r.content {
-                            endpoints::dns::DnsContent::A { content } => a == content,
-                            _ => false,
-                        },
-                        RecordContent::Aaaa(aaaa) => match &r.content {
-                            endpoints::dns::DnsContent::AAAA { content } => aaaa == content,
-                            _ => false,
-                        },
-                        RecordContent::Txt(txt) => match &r.content {
-                            endpoints::dns::DnsContent::TXT { content } => txt == content,
-                            _ => false,
-                        },
-                    }
-            })
-            .take(1)
-            .next()
+            .lock()
+            .unwrap()
+            .find_record_id(record)
+            .map(str::to_owned)
     }
 }
 
+/// Call `f` for each item, using up to `max_concurrency` threads.
+///
+/// The items are split into evenly sized chunks, each of which is processed on its own thread.
+/// Returns the results in the same order as the items, or the first error that occurred
+pub fn map_concurrently<T, R, E>(
+    items: &[T],
+    max_concurrency: NonZeroUsize,
+    f: impl Fn(&T) -> Result<R, E> + Sync,
+) -> Result<Vec<R>, E>
+where
+    T: Sync,
+    R: Send,
+    E: Send,
+{
+    if max_concurrency.get() == 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(max_concurrency.get());
+    thread::scope(|s| {
+        let handles = items
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(|| chunk.iter().map(&f).collect::<Result<Vec<_>, _>>()))
+            .collect::<Vec<_>>();
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            match handle.join() {
+                Ok(chunk) => results.extend(chunk?),
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        }
+        Ok(results)
+    })
+}
+
 // In order to look up record zones and IDs, we need to search through all records/zones provided by the API.
-// To hasten this process, we use a cache that is initialized on first run.
+// To hasten this process, we use a cache that is initialized on first run and updated whenever we create or delete a record.
 // Note that this cache is ONLY used for the find_ wrapper methods, not the regular API calls
 struct FinderCache {
-    zones: Vec<endpoints::zone::Zone>,
-    records: Vec<endpoints::dns::DnsRecord>,
+    /// ID and name of all zones
+    zones: Vec<(String, String)>,
+    /// ID and contents of all relevant records
+    records: Vec<(String, DnsRecord)>,
 }
 impl FinderCache {
     fn try_new(wrapper: &CloudflareWrapper) -> Result<FinderCache, ProviderError> {
-        let zones = wrapper.list_zones()?.result;
+        let zones = wrapper
+            .list_zones()?
+            .result
+            .into_iter()
+            .filter(|z| wrapper.zones.matches(&z.name))
+            .collect::<Vec<_>>();
+
+        let records = map_concurrently(&zones, wrapper.max_zone_fetches, |z| {
+            wrapper.list_records(&z.id)
+        })?
+        .into_iter()
+        .flat_map(|f| f.result)
+        .filter_map(|r| DnsRecord::try_from(&r).ok().map(|rec| (r.record.id, rec)))
+        .collect();
+        Ok(FinderCache {
+            zones: zones.into_iter().map(|z| (z.id, z.name)).collect(),
+            records,
+        })
+    }
 
-        let records = zones
+    /// Find the zone that a domain belongs to, preferring the most specific one (e.g. a delegated `sub.example.com` over `example.com`).
+    /// Returns an error if multiple zones are equally specific (i.e. the account contains several zones with the same name),
+    /// as there is no way to tell which one is authoritative
+    fn find_zone_id(&self, domain: &str) -> Result<Option<&str>, ProviderError> {
+        let candidates = self
+            .zones
             .iter()
-            .map(|z| wrapper.list_records(&z.id))
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flat_map(|f| f.result)
-            .collect::<Vec<endpoints::dns::DnsRecord>>();
-        Ok(FinderCache { zones, records })
+            .filter(|(_, name)| in_domain(domain, name))
+            .max_set_by_key(|(_, name)| name.len());
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [(id, _)] => Ok(Some(id.as_str())),
+            [(_, name), ..] => Err(ProviderError::Internal(format!(
+                "Domain {} matches multiple zones named {} (IDs {}), refusing to guess which one to use",
+                domain,
+                name,
+                candidates.iter().map(|(id, _)| id).join(", ")
+            ))),
+        }
+    }
+
+    fn find_record_id(&self, record: &DnsRecord) -> Option<&str> {
+        self.records
+            .iter()
+            .find(|(_, r)| r.domain_name == record.domain_name && r.content == record.content)
+            .map(|(id, _)| id.as_str())
+    }
+
+    fn insert_record(&mut self, record: &CommentedRecord) {
+        if let Ok(rec) = DnsRecord::try_from(record) {
+            self.records.push((record.record.id.to_owned(), rec));
+        }
+    }
+
+    fn remove_record(&mut self, record_id: &str) {
+        self.records.retain(|(id, _)| id != record_id);
     }
 }
 
@@ -214,22 +434,361 @@ mock! {
         pub fn list_records(
             &self,
             zone_id: &str,
-        ) -> ApiResponse<Vec<endpoints::dns::DnsRecord>>;
+        ) -> ApiResponse<Vec<CommentedRecord>>;
+        pub fn list_filtered_records(
+            &self,
+            zone_id: &str,
+            filter: &RecordFilter,
+        ) -> ApiResponse<Vec<CommentedRecord>>;
         pub fn create_record(
             &self,
             zone_id: &str,
             name: &str,
             ttl: &Option<TTL>,
             proxied: &Option<bool>,
-            content: endpoints::dns::DnsContent,
-        ) -> ApiResponse<endpoints::dns::DnsRecord>;
+            content: &RecordContent,
+            comment: &Option<String>,
+        ) -> ApiResponse<CommentedRecord>;
+        pub fn update_record_comment(
+            &self,
+            zone_id: &str,
+            record_id: &str,
+            comment: &str,
+        ) -> ApiResponse<CommentedRecord>;
         pub fn delete_record(
             &self,
             zone_id: &str,
             record_id: &str,
         ) -> ApiResponse<endpoints::dns::DeleteDnsRecordResponse>;
-        pub fn try_new(api_token: &str) -> Result<CloudflareWrapper, ProviderError>;
-        pub fn find_record_zone<'a>(&self, record: &DnsRecord) -> Option<endpoints::zone::Zone>;
-        pub fn find_record_endpoint<'a>(&self, record: &DnsRecord) -> Option<endpoints::dns::DnsRecord>;
+        pub fn verify_token(&self) -> ApiResponse<endpoints::user::UserTokenStatus>;
+        pub fn try_new(api_token: &str, retry: RetryPolicy, zones: ZoneFilter, client: &ClientSettings) -> Result<CloudflareWrapper, ProviderError>;
+        pub fn init_cache(&self) -> Result<(), ProviderError>;
+        pub fn find_record_zone_id(&self, record: &DnsRecord) -> Result<Option<String>, ProviderError>;
+        pub fn find_zone_id(&self, domain: &str) -> Result<Option<String>, ProviderError>;
+        pub fn find_record_id(&self, record: &DnsRecord) -> Option<String>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashMap, net::Ipv4Addr, num::NonZeroUsize, time::Duration};
+
+    use cloudflare::{
+        endpoints,
+        framework::response::{ApiErrors, ApiFailure},
+    };
+
+    use cloudflare::framework::response::ApiSuccess;
+    use serde_json::json;
+
+    use super::{
+        collect_pages, map_concurrently, with_retry, ClientSettings, CloudflareWrapper,
+        FinderCache, RetryPolicy,
+    };
+    use crate::provider::{DnsRecord, RecordContent};
+
+    fn endpoint(id: &str, addr: Ipv4Addr) -> endpoints::dns::DnsRecord {
+        endpoints::dns::DnsRecord {
+            meta: endpoints::dns::Meta { auto_added: false },
+            name: "my.example.org".to_string(),
+            ttl: 60,
+            zone_id: "123456".to_string(),
+            modified_on: chrono::offset::Utc::now(),
+            created_on: chrono::offset::Utc::now(),
+            proxiable: true,
+            content: endpoints::dns::DnsContent::A { content: addr },
+            id: id.to_string(),
+            proxied: false,
+            zone_name: "example.org".to_string(),
+        }
+    }
+
+    fn record(addr: Ipv4Addr) -> DnsRecord {
+        DnsRecord::new("my.example.org", RecordContent::A(addr))
+    }
+
+    fn cache() -> FinderCache {
+        FinderCache {
+            zones: vec![
+                ("1".to_string(), "example.org".to_string()),
+                ("2".to_string(), "my.example.org".to_string()),
+                ("3".to_string(), "example.com".to_string()),
+            ],
+            records: vec![],
+        }
+    }
+
+    #[test]
+    fn should_find_most_specific_zone() {
+        assert_eq!(
+            cache()
+                .find_zone_id(&record(Ipv4Addr::new(10, 1, 1, 1)).domain_name)
+                .unwrap(),
+            Some("2")
+        );
+        assert_eq!(cache().find_zone_id("www.example.org").unwrap(), Some("1"));
+        assert_eq!(cache().find_zone_id("example.com").unwrap(), Some("3"));
+    }
+
+    #[test]
+    fn should_not_match_zones_by_partial_label() {
+        assert_eq!(cache().find_zone_id("notexample.com").unwrap(), None);
+        assert_eq!(cache().find_zone_id("example.net").unwrap(), None);
+    }
+
+    #[test]
+    fn should_prefer_delegated_subdomain_zone() {
+        let cache = FinderCache {
+            zones: vec![
+                ("1".to_string(), "example.com".to_string()),
+                ("2".to_string(), "sub.example.com".to_string()),
+            ],
+            records: vec![],
+        };
+
+        assert_eq!(cache.find_zone_id("www.example.com").unwrap(), Some("1"));
+        assert_eq!(cache.find_zone_id("sub.example.com").unwrap(), Some("2"));
+        assert_eq!(
+            cache.find_zone_id("host.sub.example.com").unwrap(),
+            Some("2")
+        );
+        assert_eq!(
+            cache.find_zone_id("othersub.example.com").unwrap(),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn should_reject_ambiguous_zones() {
+        let cache = FinderCache {
+            zones: vec![
+                ("1".to_string(), "example.com".to_string()),
+                ("2".to_string(), "sub.example.com".to_string()),
+                ("3".to_string(), "sub.example.com".to_string()),
+            ],
+            records: vec![],
+        };
+
+        let err = cache.find_zone_id("host.sub.example.com").unwrap_err();
+        assert!(err.to_string().contains("2, 3"));
+        // Domains outside the duplicated zone are not affected
+        assert_eq!(cache.find_zone_id("www.example.com").unwrap(), Some("1"));
+    }
+
+    #[test]
+    fn should_find_created_record() {
+        let mut cache = cache();
+        cache.insert_record(&endpoint("abc", Ipv4Addr::new(10, 1, 1, 1)).into());
+
+        assert_eq!(
+            cache.find_record_id(&record(Ipv4Addr::new(10, 1, 1, 1))),
+            Some("abc")
+        );
+        assert_eq!(
+            cache.find_record_id(&record(Ipv4Addr::new(10, 1, 1, 2))),
+            None
+        );
+    }
+
+    #[test]
+    fn should_not_find_deleted_record() {
+        let mut cache = cache();
+        cache.insert_record(&endpoint("abc", Ipv4Addr::new(10, 1, 1, 1)).into());
+        cache.insert_record(&endpoint("def", Ipv4Addr::new(10, 1, 1, 2)).into());
+        cache.remove_record("abc");
+
+        assert_eq!(
+            cache.find_record_id(&record(Ipv4Addr::new(10, 1, 1, 1))),
+            None
+        );
+        assert_eq!(
+            cache.find_record_id(&record(Ipv4Addr::new(10, 1, 1, 2))),
+            Some("def")
+        );
+    }
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_secs(1),
+    };
+
+    fn rate_limited() -> ApiFailure {
+        ApiFailure::Error(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            ApiErrors {
+                errors: vec![],
+                other: HashMap::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn should_retry_rate_limited_requests_with_backoff() {
+        let calls = Cell::new(0);
+        let delays = Cell::new(vec![]);
+
+        let res = with_retry(
+            &POLICY,
+            |d| {
+                let mut v = delays.take();
+                v.push(d);
+                delays.set(v);
+            },
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    Err(rate_limited())
+                } else {
+                    Ok(42)
+                }
+            },
+        );
+
+        assert_eq!(res.unwrap(), 42);
+        assert_eq!(calls.get(), 3);
+        assert_eq!(
+            delays.take(),
+            vec![Duration::from_secs(1), Duration::from_secs(2)]
+        );
+    }
+
+    #[test]
+    fn should_give_up_after_max_retries() {
+        let calls = Cell::new(0);
+
+        let res: Result<u32, _> = with_retry(
+            &POLICY,
+            |_| {},
+            || {
+                calls.set(calls.get() + 1);
+                Err(rate_limited())
+            },
+        );
+
+        assert!(matches!(
+            res,
+            Err(ApiFailure::Error(http::StatusCode::TOO_MANY_REQUESTS, _))
+        ));
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn should_not_retry_other_errors() {
+        let calls = Cell::new(0);
+
+        let res: Result<u32, _> = with_retry(
+            &POLICY,
+            |_| {},
+            || {
+                calls.set(calls.get() + 1);
+                Err(ApiFailure::Error(
+                    http::StatusCode::FORBIDDEN,
+                    ApiErrors {
+                        errors: vec![],
+                        other: HashMap::new(),
+                    },
+                ))
+            },
+        );
+
+        res.unwrap_err();
+        assert_eq!(calls.get(), 1);
+    }
+
+    fn page(result: Vec<u32>, total_pages: Option<u32>) -> ApiSuccess<Vec<u32>> {
+        ApiSuccess {
+            result,
+            result_info: total_pages.map(|t| json!({"total_pages": t})),
+            messages: serde_json::Value::Null,
+            errors: vec![],
+        }
+    }
+
+    #[test]
+    fn should_return_single_page() {
+        let mut calls = vec![];
+        let res = collect_pages(3, &mut |p| {
+            calls.push(p);
+            Ok(page(vec![1, 2], Some(1)))
+        });
+
+        assert_eq!(res.unwrap().result, vec![1, 2]);
+        assert_eq!(calls, vec![1]);
+    }
+
+    #[test]
+    fn should_stop_after_last_full_page() {
+        let mut calls = vec![];
+        let res = collect_pages(2, &mut |p| {
+            calls.push(p);
+            match p {
+                1 => Ok(page(vec![1, 2], Some(2))),
+                2 => Ok(page(vec![3, 4], Some(2))),
+                _ => panic!("requested page {} after the last page", p),
+            }
+        });
+
+        assert_eq!(res.unwrap().result, vec![1, 2, 3, 4]);
+        assert_eq!(calls, vec![1, 2]);
+    }
+
+    #[test]
+    fn should_page_without_result_info() {
+        let res = collect_pages(2, &mut |p| match p {
+            1 => Ok(page(vec![1, 2], None)),
+            2 => Ok(page(vec![3, 4], None)),
+            3 => Ok(page(vec![5], None)),
+            _ => panic!("requested page {} after a partial page", p),
+        });
+
+        assert_eq!(res.unwrap().result, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn should_create_client_with_custom_settings() {
+        let settings = ClientSettings {
+            http_timeout: Some(Duration::from_secs(5)),
+            api_url: Some("http://localhost:8080/client/v4/".to_string()),
+            max_zone_fetches: NonZeroUsize::MIN,
+        };
+        assert_eq!(
+            settings.client_config().http_timeout,
+            Duration::from_secs(5)
+        );
+        assert!(matches!(
+            settings.environment().unwrap(),
+            cloudflare::framework::Environment::Custom(url) if url.as_str() == "http://localhost:8080/client/v4/"
+        ));
+
+        // Creating the wrapper does not perform any requests
+        CloudflareWrapper::try_new("abc", POLICY, Default::default(), &settings).unwrap();
+    }
+
+    #[test]
+    fn should_reject_invalid_api_url() {
+        let settings = ClientSettings {
+            http_timeout: None,
+            api_url: Some("not a url".to_string()),
+            max_zone_fetches: NonZeroUsize::MIN,
+        };
+
+        CloudflareWrapper::try_new("abc", POLICY, Default::default(), &settings).unwrap_err();
+    }
+
+    #[test]
+    fn should_map_concurrently_in_order() {
+        let items = (0..10).collect::<Vec<u32>>();
+        for max in [1, 3, 16] {
+            let res = map_concurrently(&items, NonZeroUsize::new(max).unwrap(), |i| {
+                Ok::<_, String>(i * 2)
+            });
+            assert_eq!(res.unwrap(), (0..10).map(|i| i * 2).collect::<Vec<u32>>());
+        }
+
+        let res = map_concurrently(&items, NonZeroUsize::new(3).unwrap(), |i| match i {
+            7 => Err(format!("failed on {}", i)),
+            i => Ok(*i),
+        });
+        assert_eq!(res, Err("failed on 7".to_string()));
     }
 }
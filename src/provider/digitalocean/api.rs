@@ -0,0 +1,231 @@
+#![cfg_attr(test, allow(dead_code))]
+
+use std::time::Duration;
+
+use reqwest::blocking::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use crate::provider::{ProviderError, TTL};
+
+const DIGITALOCEAN_API_URL: &str = "https://api.digitalocean.com";
+const DIGITALOCEAN_PAGE_SIZE: u32 = 200;
+
+/// A single record as returned by the DigitalOcean API.
+/// Names are relative to their domain, with `@` referring to the domain itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiRecord {
+    pub id: u64,
+    pub record_type: String,
+    pub name: String,
+    pub data: String,
+    pub ttl: Option<TTL>,
+}
+impl TryFrom<&Value> for ApiRecord {
+    type Error = ProviderError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        let field = |name: &str| {
+            value[name]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| format!("Record is missing the {} field: {}", name, value))
+        };
+        Ok(ApiRecord {
+            id: value["id"]
+                .as_u64()
+                .ok_or_else(|| format!("Record is missing the id field: {}", value))?,
+            record_type: field("type")?,
+            name: field("name")?,
+            data: field("data")?,
+            ttl: value["ttl"].as_u64().and_then(|t| TTL::try_from(t).ok()),
+        })
+    }
+}
+
+/// A record to create, see [`ApiRecord`] for the format of the fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRecord {
+    pub record_type: String,
+    pub name: String,
+    pub data: String,
+    pub ttl: Option<TTL>,
+}
+
+/// Internal client for the DigitalOcean domains API. Takes care of authentication, paging and error handling
+pub struct DigitalOceanApi {
+    client: Client,
+    api_token: String,
+    api_url: String,
+}
+
+impl DigitalOceanApi {
+    pub fn try_new(
+        api_token: &str,
+        api_url: Option<String>,
+        http_timeout: Option<Duration>,
+    ) -> Result<DigitalOceanApi, ProviderError> {
+        let mut builder = Client::builder();
+        if let Some(timeout) = http_timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(DigitalOceanApi {
+            client: builder
+                .build()
+                .map_err(|e| format!("Could not create HTTP client: {}", e))?,
+            api_token: api_token.to_owned(),
+            api_url: api_url
+                .unwrap_or(DIGITALOCEAN_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_owned(),
+        })
+    }
+
+    /// Names of all domains in the account
+    pub fn list_domains(&self) -> Result<Vec<String>, ProviderError> {
+        self.get_paged("/v2/domains", "domains")?
+            .iter()
+            .map(|d| {
+                d["name"]
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| format!("Domain is missing the name field: {}", d).into())
+            })
+            .collect()
+    }
+
+    /// All records of a domain, regardless of their type
+    pub fn list_records(&self, domain: &str) -> Result<Vec<ApiRecord>, ProviderError> {
+        self.get_paged(&format!("/v2/domains/{}/records", domain), "domain_records")?
+            .iter()
+            .map(ApiRecord::try_from)
+            .collect()
+    }
+
+    pub fn create_record(
+        &self,
+        domain: &str,
+        record: &NewRecord,
+    ) -> Result<ApiRecord, ProviderError> {
+        let mut body = json!({
+            "type": record.record_type,
+            "name": record.name,
+            "data": record.data,
+        });
+        if let Some(ttl) = record.ttl {
+            body["ttl"] = json!(ttl);
+        }
+        let response = self.send(
+            self.client
+                .post(format!("{}/v2/domains/{}/records", self.api_url, domain))
+                .json(&body),
+        )?;
+        ApiRecord::try_from(&response["domain_record"])
+    }
+
+    pub fn delete_record(&self, domain: &str, record_id: u64) -> Result<(), ProviderError> {
+        self.send(self.client.delete(format!(
+            "{}/v2/domains/{}/records/{}",
+            self.api_url, domain, record_id
+        )))?;
+        Ok(())
+    }
+
+    /// Retrieve all pages of a list endpoint and return the items stored in `key`
+    fn get_paged(&self, path: &str, key: &str) -> Result<Vec<Value>, ProviderError> {
+        let mut items = Vec::new();
+        let mut page = 1;
+        loop {
+            let response = self.send(
+                self.client
+                    .get(format!("{}{}", self.api_url, path))
+                    .query(&[("page", page), ("per_page", DIGITALOCEAN_PAGE_SIZE)]),
+            )?;
+            match response[key].as_array() {
+                Some(page_items) => items.extend(page_items.iter().cloned()),
+                None => {
+                    return Err(format!("Unexpected response from {}: {}", path, response).into())
+                }
+            }
+            if response["links"]["pages"]["next"].is_null() {
+                return Ok(items);
+            }
+            page += 1;
+        }
+    }
+
+    /// Send an authenticated request and return the response body, which is empty for some requests (e.g. deletions)
+    fn send(&self, request: RequestBuilder) -> Result<Value, ProviderError> {
+        let response = request
+            .bearer_auth(&self.api_token)
+            .send()
+            .map_err(|e| format!("DigitalOcean API request failed: {}", e))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .map_err(|e| format!("Could not read DigitalOcean API response: {}", e))?;
+        let value = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&body)
+                .map_err(|e| format!("Invalid DigitalOcean API response: {}", e))?
+        };
+        if !status.is_success() {
+            return Err(format!(
+                "DigitalOcean API returned {}: {}",
+                status,
+                value["message"].as_str().unwrap_or(&body)
+            )
+            .into());
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+use mockall::mock;
+
+#[cfg(test)]
+mock! {
+    pub DigitalOceanApi {
+        pub fn try_new(api_token: &str, api_url: Option<String>, http_timeout: Option<Duration>) -> Result<DigitalOceanApi, ProviderError>;
+        pub fn list_domains(&self) -> Result<Vec<String>, ProviderError>;
+        pub fn list_records(&self, domain: &str) -> Result<Vec<ApiRecord>, ProviderError>;
+        pub fn create_record(&self, domain: &str, record: &NewRecord) -> Result<ApiRecord, ProviderError>;
+        pub fn delete_record(&self, domain: &str, record_id: u64) -> Result<(), ProviderError>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::ApiRecord;
+
+    #[test]
+    fn should_parse_api_records() {
+        let record = ApiRecord::try_from(&json!({
+            "id": 28448433,
+            "type": "A",
+            "name": "www",
+            "data": "203.0.113.1",
+            "priority": null,
+            "ttl": 1800,
+        }))
+        .unwrap();
+        assert_eq!(
+            record,
+            ApiRecord {
+                id: 28448433,
+                record_type: "A".to_string(),
+                name: "www".to_string(),
+                data: "203.0.113.1".to_string(),
+                ttl: Some(1800),
+            }
+        );
+    }
+
+    #[test]
+    fn should_reject_incomplete_api_records() {
+        ApiRecord::try_from(&json!({"id": 1, "type": "A", "name": "www"})).unwrap_err();
+    }
+}
@@ -0,0 +1,594 @@
+mod api;
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use itertools::Itertools;
+use log::{debug, trace, warn};
+use mockall_double::double;
+
+use self::api::{ApiRecord, NewRecord};
+use super::{
+    DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+    TxTRegistryProvider,
+};
+use crate::{
+    dry_run::DryRun,
+    plan::Action,
+    provider::{normalize_domain, TTL},
+};
+
+#[double]
+use self::api::DigitalOceanApi;
+
+/// Smallest and largest TTL accepted by DigitalOcean
+const DIGITALOCEAN_TTL_BOUNDS: RangeInclusive<TTL> = 30..=TTL::MAX;
+
+/// A [`Provider`] connecting to the DigitalOcean API for creating, retrieving and deleting DNS records.
+///
+/// All domains of the account are managed. Records are assigned to the most specific domain that contains them,
+/// so that subdomains delegated to separate DigitalOcean domains (e.g. `sub.example.com` next to `example.com`) are handled correctly.
+///
+/// To create a provider, use the [`DigitalOceanProvider::from_config()`] function.
+#[non_exhaustive]
+pub struct DigitalOceanProvider {
+    api: DigitalOceanApi,
+    domains: Vec<String>,
+    ttl: Option<TTL>,
+    dry_run: DryRun,
+}
+
+/// Configuration object for a [`DigitalOceanProvider`]. Must be supplied when creating a provider.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DigitalOceanProviderConfig<'a> {
+    /// The personal access token to authenticate with. Requires read and write access to domains
+    pub api_token: &'a str,
+    /// Timeout for each API request. Uses the default of the HTTP client if not set
+    pub http_timeout: Option<Duration>,
+    /// Send API requests to this base URL instead of the official API, e.g. a proxy or mock server
+    pub api_url: Option<String>,
+}
+
+impl DigitalOceanProvider {
+    /// Create a new [`DigitalOceanProvider`] with the supplied configuration.
+    /// Returns an error if the domains of the account could not be listed
+    pub fn from_config(
+        config: &DigitalOceanProviderConfig,
+    ) -> Result<DigitalOceanProvider, ProviderError> {
+        let api = DigitalOceanApi::try_new(
+            config.api_token,
+            config.api_url.to_owned(),
+            config.http_timeout,
+        )?;
+        let domains = api
+            .list_domains()?
+            .iter()
+            .map(|d| normalize_domain(d.trim_end_matches('.')))
+            .collect_vec();
+        debug!("Managing DigitalOcean domains {:?}", domains);
+
+        Ok(DigitalOceanProvider {
+            api,
+            domains,
+            ttl: None,
+            dry_run: DryRun::default(),
+        })
+    }
+
+    #[cfg(test)]
+    // Testing-only constructor, this allows us to use a mocked API in the tests
+    fn from_mock_api(domains: &[&str], api: DigitalOceanApi) -> DigitalOceanProvider {
+        DigitalOceanProvider {
+            api,
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            ttl: None,
+            dry_run: DryRun::default(),
+        }
+    }
+
+    /// Find the DigitalOcean domain that a record belongs to, preferring the most specific match
+    fn find_record_zone(&self, name: &str) -> Option<&str> {
+        let name = normalize_domain(name.trim_end_matches('.'));
+        self.domains
+            .iter()
+            .filter(|d| super::in_domain(&name, d))
+            .max_by_key(|d| d.len())
+            .map(String::as_str)
+    }
+
+    fn zone_for(&self, name: &str) -> Result<&str, ProviderError> {
+        self.find_record_zone(name).ok_or_else(|| {
+            format!(
+                "Domain {} is not part of any domain managed by this DigitalOcean account",
+                name
+            )
+            .into()
+        })
+    }
+
+    /// Records of a zone, along with their fully qualified names
+    fn zone_records(&self, zone: &str) -> Result<Vec<(String, ApiRecord)>, ProviderError> {
+        Ok(self
+            .api
+            .list_records(zone)?
+            .into_iter()
+            .map(|r| (absolute_name(&r.name, zone), r))
+            // Records of delegated subdomains belong to their own zone
+            .filter(|(name, _)| self.find_record_zone(name) == Some(zone))
+            .collect())
+    }
+
+    fn create_record(
+        &self,
+        zone: &str,
+        name: &str,
+        record_type: &str,
+        data: String,
+        ttl: Option<TTL>,
+    ) -> Result<(), ProviderError> {
+        let record = NewRecord {
+            record_type: record_type.to_owned(),
+            name: relative_name(name, zone),
+            data,
+            ttl,
+        };
+        self.dry_run
+            .guard(|| self.api.create_record(zone, &record))?;
+        debug!(
+            "Created {} record {} => {} in domain {}",
+            record_type, name, record.data, zone
+        );
+        Ok(())
+    }
+
+    fn delete_record(
+        &self,
+        zone: &str,
+        name: &str,
+        record: &ApiRecord,
+    ) -> Result<(), ProviderError> {
+        self.dry_run
+            .guard(|| self.api.delete_record(zone, record.id))?;
+        debug!(
+            "Deleted {} record {} => {} with id {} from domain {}",
+            record.record_type, name, record.data, record.id, zone
+        );
+        Ok(())
+    }
+}
+
+impl DnsProvider for DigitalOceanProvider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            dry_run: true,
+            ttl: true,
+            batch_apply: false,
+            proxied: false,
+        }
+    }
+
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+        self.dry_run.enable();
+        Ok(())
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run.is_enabled()
+    }
+
+    fn ttl(&self) -> Option<TTL> {
+        self.ttl
+    }
+
+    fn set_ttl(&mut self, ttl: TTL) {
+        let bounds = self.ttl_bounds();
+        let clamped = ttl.clamp(*bounds.start(), *bounds.end());
+        if clamped != ttl {
+            warn!(
+                "TTL {} is not supported by DigitalOcean (must be at least {}), using {} instead",
+                ttl,
+                bounds.start(),
+                clamped
+            );
+        }
+        self.ttl = Some(clamped);
+    }
+
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        DIGITALOCEAN_TTL_BOUNDS
+    }
+
+    fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+        let mut records = Vec::new();
+        for zone in &self.domains {
+            debug!("Reading records of domain {} from DigitalOcean API", zone);
+            records.extend(
+                self.zone_records(zone)?
+                    .into_iter()
+                    .filter_map(|(name, r)| dns_record(name, &r, zone)),
+            );
+        }
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn records_for(&self, zone_or_domain: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let domain = normalize_domain(zone_or_domain.trim_end_matches('.'));
+        // Subdomains of the requested domain may be stored in separate DigitalOcean domains
+        let zones = self
+            .domains
+            .iter()
+            .filter(|z| super::in_domain(z, &domain) || super::in_domain(&domain, z))
+            .collect_vec();
+        if zones.is_empty() {
+            self.zone_for(&domain)?;
+        }
+        let mut records = Vec::new();
+        for zone in zones {
+            records.extend(
+                self.zone_records(zone)?
+                    .into_iter()
+                    .filter(|(name, _)| super::in_domain(name, &domain))
+                    .filter_map(|(name, r)| dns_record(name, &r, zone)),
+            );
+        }
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+        let domain = normalize_domain(action.domain());
+        let zone = self.zone_for(&domain)?;
+        let a_records = self
+            .zone_records(zone)?
+            .into_iter()
+            .filter(|(name, r)| *name == domain && r.record_type == "A")
+            .map(|(_, r)| r)
+            .collect_vec();
+
+        match action {
+            Action::ClaimAndUpdate(_, ips) | Action::Update(_, ips) => {
+                // Keep the TTL of the existing records, unless a TTL has been set explicitly
+                let ttl = self.ttl.or(a_records.iter().filter_map(|r| r.ttl).max());
+                let (kept, obsolete): (Vec<ApiRecord>, Vec<ApiRecord>) = a_records
+                    .into_iter()
+                    .partition(|r| ips.iter().any(|ip| ip.to_string() == r.data));
+                // Create new records before deleting the old ones, so that the domain always has an A record
+                for ip in ips
+                    .iter()
+                    .filter(|ip| !kept.iter().any(|r| r.data == ip.to_string()))
+                {
+                    self.create_record(zone, &domain, "A", ip.to_string(), ttl)?;
+                }
+                for r in &obsolete {
+                    self.delete_record(zone, &domain, r)?;
+                }
+                Ok(())
+            }
+            Action::DeleteAndRelease(_) => {
+                for r in &a_records {
+                    self.delete_record(zone, &domain, r)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TxTRegistryProvider for DigitalOceanProvider {
+    fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = normalize_domain(&domain);
+        let zone = self.zone_for(&domain)?;
+        self.create_record(zone, &domain, "TXT", content, self.ttl)
+    }
+
+    fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = normalize_domain(&domain);
+        let zone = self.zone_for(&domain)?;
+        let records = self.zone_records(zone)?;
+        let (_, record) = records
+            .iter()
+            .find(|(name, r)| *name == domain && r.record_type == "TXT" && r.data == content)
+            .ok_or(format!(
+                "Could not find TXT record {} => {} in domain {}",
+                domain, content, zone
+            ))?;
+        self.delete_record(zone, &domain, record)
+    }
+}
+impl Provider for DigitalOceanProvider {}
+
+/// Convert a record name relative to its zone into a fully qualified name
+fn absolute_name(name: &str, zone: &str) -> String {
+    match name {
+        "@" | "" => zone.to_owned(),
+        name => normalize_domain(&format!("{}.{}", name.trim_end_matches('.'), zone)),
+    }
+}
+
+/// Convert a fully qualified name into a name relative to its zone, as expected by the API
+fn relative_name(name: &str, zone: &str) -> String {
+    if name == zone {
+        return "@".to_string();
+    }
+    name.strip_suffix(&format!(".{}", zone))
+        .unwrap_or(name)
+        .to_owned()
+}
+
+/// Convert an API record into a [`DnsRecord`]. Returns `None` for irrelevant record types and invalid records
+fn dns_record(name: String, record: &ApiRecord, zone: &str) -> Option<DnsRecord> {
+    let content = match record.record_type.as_str() {
+        "A" => RecordContent::A(record.data.parse().ok()?),
+        "AAAA" => RecordContent::Aaaa(record.data.parse().ok()?),
+        "TXT" => RecordContent::Txt(record.data.to_owned()),
+        "CNAME" => RecordContent::Cname(match record.data.as_str() {
+            "@" => zone.to_owned(),
+            target => target.trim_end_matches('.').to_owned(),
+        }),
+        _ => return None,
+    };
+    Some(DnsRecord {
+        ttl: record.ttl,
+        ..DnsRecord::new(name, content)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{
+        absolute_name, relative_name, ApiRecord, DigitalOceanApi, DigitalOceanProvider, NewRecord,
+    };
+    use crate::{
+        plan::Action,
+        provider::{DnsProvider, DnsRecord, RecordContent, TxTRegistryProvider, TTL},
+    };
+
+    fn api_record(id: u64, record_type: &str, name: &str, data: &str) -> ApiRecord {
+        ApiRecord {
+            id,
+            record_type: record_type.to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            ttl: Some(1800),
+        }
+    }
+
+    fn records() -> Vec<ApiRecord> {
+        vec![
+            api_record(1, "A", "www", "10.1.1.1"),
+            api_record(2, "AAAA", "www", "fd42::1"),
+            api_record(3, "TXT", "@", "v=spf1 -all"),
+            api_record(4, "CNAME", "alias", "www.example.com."),
+            api_record(5, "MX", "@", "mail.example.com."),
+            api_record(6, "A", "host.sub", "10.1.1.2"),
+        ]
+    }
+
+    fn created(record: &NewRecord) -> Result<ApiRecord, crate::provider::ProviderError> {
+        Ok(ApiRecord {
+            id: 100,
+            record_type: record.record_type.to_owned(),
+            name: record.name.to_owned(),
+            data: record.data.to_owned(),
+            ttl: record.ttl,
+        })
+    }
+
+    #[test]
+    fn should_find_most_specific_zone() {
+        let p = DigitalOceanProvider::from_mock_api(
+            &["example.com", "sub.example.com"],
+            DigitalOceanApi::default(),
+        );
+
+        assert_eq!(p.find_record_zone("example.com"), Some("example.com"));
+        assert_eq!(p.find_record_zone("www.example.com."), Some("example.com"));
+        assert_eq!(
+            p.find_record_zone("host.sub.example.com"),
+            Some("sub.example.com")
+        );
+        assert_eq!(
+            p.find_record_zone("sub.example.com"),
+            Some("sub.example.com")
+        );
+        assert_eq!(p.find_record_zone("notexample.com"), None);
+        assert_eq!(p.find_record_zone("example.org"), None);
+    }
+
+    #[test]
+    fn should_convert_between_relative_and_absolute_names() {
+        assert_eq!(absolute_name("@", "example.com"), "example.com");
+        assert_eq!(absolute_name("www", "example.com"), "www.example.com");
+        assert_eq!(relative_name("example.com", "example.com"), "@");
+        assert_eq!(relative_name("www.example.com", "example.com"), "www");
+        assert_eq!(
+            relative_name("host.sub.example.com", "example.com"),
+            "host.sub"
+        );
+    }
+
+    #[test]
+    fn should_return_records() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records()
+            .withf(|zone| zone == "example.com")
+            .returning(|_| Ok(records()));
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+
+        let records = p.records().unwrap();
+        assert_eq!(
+            records.iter().map(|r| &r.content).collect::<Vec<_>>(),
+            vec![
+                &RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                &RecordContent::Aaaa("fd42::1".parse().unwrap()),
+                &RecordContent::Txt("v=spf1 -all".to_string()),
+                &RecordContent::Cname("www.example.com".to_string()),
+                &RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+            ]
+        );
+        assert_eq!(records[0].domain_name, "www.example.com");
+        assert_eq!(records[0].ttl, Some(1800));
+        assert_eq!(records[2].domain_name, "example.com");
+        assert_eq!(records[4].domain_name, "host.sub.example.com");
+    }
+
+    #[test]
+    fn should_assign_records_of_delegated_subdomains_to_their_zone() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records()
+            .withf(|zone| zone == "example.com")
+            .returning(|_| Ok(records()));
+        mock.expect_list_records()
+            .withf(|zone| zone == "sub.example.com")
+            .returning(|_| Ok(vec![api_record(7, "A", "host", "10.1.1.3")]));
+        let p = DigitalOceanProvider::from_mock_api(&["example.com", "sub.example.com"], mock);
+
+        let records: Vec<DnsRecord> = p.records_for("sub.example.com").unwrap();
+        assert_eq!(
+            records,
+            vec![DnsRecord {
+                ttl: Some(1800),
+                ..DnsRecord::new(
+                    "host.sub.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 3))
+                )
+            }]
+        );
+    }
+
+    #[test]
+    fn should_create_before_deleting_on_update() {
+        let mut mock = DigitalOceanApi::default();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_create_record()
+            .withf(|zone, r| {
+                zone == "example.com"
+                    && r.name == "www"
+                    && r.record_type == "A"
+                    && r.data == "203.0.113.1"
+                    && r.ttl == Some(1800)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, r| created(r));
+        mock.expect_delete_record()
+            .withf(|zone, id| zone == "example.com" && *id == 1)
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_keep_old_records_if_creating_new_ones_fails() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_create_record()
+            .returning(|_, _| Err("rate limited".to_string().into()));
+        mock.expect_delete_record().never();
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_delete_a_records() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_delete_record()
+            .withf(|zone, id| zone == "example.com" && *id == 6)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::DeleteAndRelease(
+            "host.sub.example.com".to_string(),
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_use_configured_ttl() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records().returning(|_| Ok(vec![]));
+        mock.expect_create_record()
+            .withf(|_, r| r.ttl == Some(300) && r.name == "new")
+            .times(1)
+            .returning(|_, r| created(r));
+        let mut p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+        p.set_ttl(300);
+
+        p.apply(&Action::ClaimAndUpdate(
+            "new.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_clamp_unsupported_ttls() {
+        let mut p =
+            DigitalOceanProvider::from_mock_api(&["example.com"], DigitalOceanApi::default());
+        p.set_ttl(1);
+        assert_eq!(p.ttl(), Some::<TTL>(30));
+    }
+
+    #[test]
+    fn should_manage_txt_records() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_create_record()
+            .withf(|_, r| r.record_type == "TXT" && r.name == "@" && r.data == "owner")
+            .times(1)
+            .returning(|_, r| created(r));
+        mock.expect_delete_record()
+            .withf(|zone, id| zone == "example.com" && *id == 3)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+
+        p.create_txt_record("example.com".to_string(), "owner".to_string())
+            .unwrap();
+        p.delete_txt_record("example.com".to_string(), "v=spf1 -all".to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn should_reject_domains_outside_managed_domains() {
+        let p = DigitalOceanProvider::from_mock_api(&["example.com"], DigitalOceanApi::default());
+
+        p.apply(&Action::DeleteAndRelease("www.example.org".to_string()))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn should_support_dry_run() {
+        let mut mock = DigitalOceanApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_create_record().never();
+        mock.expect_delete_record().never();
+        let mut p = DigitalOceanProvider::from_mock_api(&["example.com"], mock);
+        p.enable_dry_run().unwrap();
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+        p.create_txt_record("example.com".to_string(), "owner".to_string())
+            .unwrap();
+    }
+}
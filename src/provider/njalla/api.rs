@@ -0,0 +1,224 @@
+#![cfg_attr(test, allow(dead_code))]
+
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+use crate::provider::{ProviderError, TTL};
+
+const NJALLA_API_URL: &str = "https://njal.la/api/1/";
+
+/// A single record as returned by the Njalla API.
+/// Names are relative to their domain, with `@` referring to the domain itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiRecord {
+    pub id: String,
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub ttl: Option<TTL>,
+}
+impl TryFrom<&Value> for ApiRecord {
+    type Error = ProviderError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        let field = |name: &str| {
+            value[name]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| format!("Record is missing the {} field: {}", name, value))
+        };
+        // IDs are usually numeric, but only ever passed back to the API
+        let id = match &value["id"] {
+            Value::Number(id) => id.to_string(),
+            Value::String(id) => id.to_owned(),
+            _ => return Err(format!("Record is missing the id field: {}", value).into()),
+        };
+        Ok(ApiRecord {
+            id,
+            record_type: field("type")?,
+            name: field("name")?,
+            content: field("content")?,
+            ttl: value["ttl"].as_u64().and_then(|t| TTL::try_from(t).ok()),
+        })
+    }
+}
+
+/// A record to create, see [`ApiRecord`] for the format of the fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRecord {
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub ttl: Option<TTL>,
+}
+
+/// Internal client for the Njalla JSON-RPC API. Takes care of authentication and error handling
+pub struct NjallaApi {
+    client: Client,
+    api_token: String,
+    api_url: String,
+}
+
+impl NjallaApi {
+    pub fn try_new(
+        api_token: &str,
+        api_url: Option<String>,
+        http_timeout: Option<Duration>,
+    ) -> Result<NjallaApi, ProviderError> {
+        let mut builder = Client::builder();
+        if let Some(timeout) = http_timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(NjallaApi {
+            client: builder
+                .build()
+                .map_err(|e| format!("Could not create HTTP client: {}", e))?,
+            api_token: api_token.to_owned(),
+            api_url: api_url.unwrap_or(NJALLA_API_URL.to_string()),
+        })
+    }
+
+    /// Names of all domains in the account
+    pub fn list_domains(&self) -> Result<Vec<String>, ProviderError> {
+        let result = self.call("list-domains", json!({}))?;
+        result["domains"]
+            .as_array()
+            .ok_or_else(|| format!("Unexpected response to list-domains: {}", result))?
+            .iter()
+            .map(|d| {
+                d["name"]
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| format!("Domain is missing the name field: {}", d).into())
+            })
+            .collect()
+    }
+
+    /// All records of a domain, regardless of their type
+    pub fn list_records(&self, domain: &str) -> Result<Vec<ApiRecord>, ProviderError> {
+        let result = self.call("list-records", json!({ "domain": domain }))?;
+        result["records"]
+            .as_array()
+            .ok_or_else(|| format!("Unexpected response to list-records: {}", result))?
+            .iter()
+            .map(ApiRecord::try_from)
+            .collect()
+    }
+
+    pub fn add_record(&self, domain: &str, record: &NewRecord) -> Result<ApiRecord, ProviderError> {
+        let mut params = json!({
+            "domain": domain,
+            "type": record.record_type,
+            "name": record.name,
+            "content": record.content,
+        });
+        if let Some(ttl) = record.ttl {
+            params["ttl"] = json!(ttl);
+        }
+        ApiRecord::try_from(&self.call("add-record", params)?)
+    }
+
+    pub fn remove_record(&self, domain: &str, record_id: &str) -> Result<(), ProviderError> {
+        let id = match record_id.parse::<u64>() {
+            Ok(id) => json!(id),
+            Err(_) => json!(record_id),
+        };
+        self.call("remove-record", json!({ "domain": domain, "id": id }))?;
+        Ok(())
+    }
+
+    /// Call an API method and return its result
+    fn call(&self, method: &str, params: Value) -> Result<Value, ProviderError> {
+        let response = self
+            .client
+            .post(&self.api_url)
+            .header("Authorization", format!("Njalla {}", self.api_token))
+            .json(&json!({ "method": method, "params": params }))
+            .send()
+            .map_err(|e| format!("Njalla API request failed: {}", e))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .map_err(|e| format!("Could not read Njalla API response: {}", e))?;
+        let value: Value = serde_json::from_str(&body).map_err(|e| {
+            format!(
+                "Invalid Njalla API response to {} ({}): {}",
+                method, status, e
+            )
+        })?;
+        if let Some(error) = value.get("error") {
+            return Err(format!(
+                "Njalla API returned an error for {}: {}",
+                method,
+                error["message"].as_str().unwrap_or(&error.to_string())
+            )
+            .into());
+        }
+        if !status.is_success() {
+            return Err(format!("Njalla API returned {}: {}", status, body).into());
+        }
+        Ok(value["result"].to_owned())
+    }
+}
+
+#[cfg(test)]
+use mockall::mock;
+
+#[cfg(test)]
+mock! {
+    pub NjallaApi {
+        pub fn try_new(api_token: &str, api_url: Option<String>, http_timeout: Option<Duration>) -> Result<NjallaApi, ProviderError>;
+        pub fn list_domains(&self) -> Result<Vec<String>, ProviderError>;
+        pub fn list_records(&self, domain: &str) -> Result<Vec<ApiRecord>, ProviderError>;
+        pub fn add_record(&self, domain: &str, record: &NewRecord) -> Result<ApiRecord, ProviderError>;
+        pub fn remove_record(&self, domain: &str, record_id: &str) -> Result<(), ProviderError>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::ApiRecord;
+
+    #[test]
+    fn should_parse_api_records() {
+        let record = ApiRecord::try_from(&json!({
+            "id": 1337,
+            "name": "www",
+            "type": "A",
+            "content": "203.0.113.1",
+            "ttl": 10800,
+        }))
+        .unwrap();
+        assert_eq!(
+            record,
+            ApiRecord {
+                id: "1337".to_string(),
+                record_type: "A".to_string(),
+                name: "www".to_string(),
+                content: "203.0.113.1".to_string(),
+                ttl: Some(10800),
+            }
+        );
+
+        let record = ApiRecord::try_from(&json!({
+            "id": "abc",
+            "name": "@",
+            "type": "TXT",
+            "content": "owner",
+        }))
+        .unwrap();
+        assert_eq!(record.id, "abc");
+        assert_eq!(record.ttl, None);
+    }
+
+    #[test]
+    fn should_reject_incomplete_api_records() {
+        ApiRecord::try_from(&json!({"id": 1, "type": "A", "name": "www"})).unwrap_err();
+        ApiRecord::try_from(&json!({"type": "A", "name": "www", "content": "203.0.113.1"}))
+            .unwrap_err();
+    }
+}
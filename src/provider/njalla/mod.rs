@@ -0,0 +1,625 @@
+mod api;
+
+use std::{collections::HashMap, ops::RangeInclusive, sync::Mutex, time::Duration};
+
+use itertools::Itertools;
+use log::{debug, trace, warn};
+use mockall_double::double;
+
+use self::api::{ApiRecord, NewRecord};
+use super::{
+    DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+    TxTRegistryProvider,
+};
+use crate::{
+    dry_run::DryRun,
+    plan::Action,
+    provider::{normalize_domain, TTL},
+};
+
+#[double]
+use self::api::NjallaApi;
+
+/// TTLs accepted by Njalla. Other TTLs are rounded to the closest of these
+const NJALLA_TTLS: [TTL; 7] = [60, 300, 900, 3600, 10800, 21600, 86400];
+
+/// A [`Provider`] connecting to the Njalla JSON-RPC API for creating, retrieving and deleting DNS records.
+///
+/// All domains of the account are managed. Records are assigned to the most specific domain that contains them.
+/// Njalla can only delete records by their ID, so the provider remembers the IDs of all records it has seen (see `RecordCache`).
+///
+/// To create a provider, use the [`NjallaProvider::from_config()`] function.
+#[non_exhaustive]
+pub struct NjallaProvider {
+    api: NjallaApi,
+    domains: Vec<String>,
+    cache: Mutex<RecordCache>,
+    ttl: Option<TTL>,
+    dry_run: DryRun,
+}
+
+/// Configuration object for a [`NjallaProvider`]. Must be supplied when creating a provider.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NjallaProviderConfig<'a> {
+    /// The API token to authenticate with. Must be allowed to list, add and remove records
+    pub api_token: &'a str,
+    /// Timeout for each API request. Uses the default of the HTTP client if not set
+    pub http_timeout: Option<Duration>,
+    /// Send API requests to this URL instead of the official API, e.g. a proxy or mock server
+    pub api_url: Option<String>,
+}
+
+/// Maps records to their Njalla IDs, similar to the record cache of the Cloudflare provider.
+/// Updated whenever the records of a domain are listed and when records are created or deleted
+#[derive(Debug, Default)]
+struct RecordCache {
+    /// Records of each Njalla domain, along with their IDs
+    domains: HashMap<String, Vec<(String, DnsRecord)>>,
+}
+impl RecordCache {
+    fn replace_domain(&mut self, zone: &str, records: Vec<(String, DnsRecord)>) {
+        self.domains.insert(zone.to_owned(), records);
+    }
+
+    fn insert(&mut self, zone: &str, id: String, record: DnsRecord) {
+        self.domains
+            .entry(zone.to_owned())
+            .or_default()
+            .push((id, record));
+    }
+
+    fn remove(&mut self, zone: &str, id: &str) {
+        if let Some(records) = self.domains.get_mut(zone) {
+            records.retain(|(record_id, _)| record_id != id);
+        }
+    }
+
+    /// Find the ID of an existing record with the same name and content
+    fn find_record_id(&self, zone: &str, record: &DnsRecord) -> Option<&str> {
+        self.domains
+            .get(zone)?
+            .iter()
+            .find(|(_, r)| r.domain_name == record.domain_name && r.content == record.content)
+            .map(|(id, _)| id.as_str())
+    }
+}
+
+impl NjallaProvider {
+    /// Create a new [`NjallaProvider`] with the supplied configuration.
+    /// Returns an error if the domains of the account could not be listed
+    pub fn from_config(config: &NjallaProviderConfig) -> Result<NjallaProvider, ProviderError> {
+        let api = NjallaApi::try_new(
+            config.api_token,
+            config.api_url.to_owned(),
+            config.http_timeout,
+        )?;
+        let domains = api
+            .list_domains()?
+            .iter()
+            .map(|d| normalize_domain(d.trim_end_matches('.')))
+            .collect_vec();
+        debug!("Managing Njalla domains {:?}", domains);
+
+        Ok(NjallaProvider {
+            api,
+            domains,
+            cache: Mutex::default(),
+            ttl: None,
+            dry_run: DryRun::default(),
+        })
+    }
+
+    #[cfg(test)]
+    // Testing-only constructor, this allows us to use a mocked API in the tests
+    fn from_mock_api(domains: &[&str], api: NjallaApi) -> NjallaProvider {
+        NjallaProvider {
+            api,
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            cache: Mutex::default(),
+            ttl: None,
+            dry_run: DryRun::default(),
+        }
+    }
+
+    /// Find the Njalla domain that a record belongs to, preferring the most specific match
+    fn find_record_zone(&self, name: &str) -> Option<&str> {
+        let name = normalize_domain(name.trim_end_matches('.'));
+        self.domains
+            .iter()
+            .filter(|d| super::in_domain(&name, d))
+            .max_by_key(|d| d.len())
+            .map(String::as_str)
+    }
+
+    fn zone_for(&self, name: &str) -> Result<&str, ProviderError> {
+        self.find_record_zone(name).ok_or_else(|| {
+            format!(
+                "Domain {} is not part of any domain managed by this Njalla account",
+                name
+            )
+            .into()
+        })
+    }
+
+    /// Records of a zone, along with their fully qualified names. Refreshes the cached record IDs of the zone
+    fn zone_records(&self, zone: &str) -> Result<Vec<(String, ApiRecord)>, ProviderError> {
+        let records = self
+            .api
+            .list_records(zone)?
+            .into_iter()
+            .map(|r| (absolute_name(&r.name, zone), r))
+            // Records of subdomains with their own Njalla domain belong to that domain
+            .filter(|(name, _)| self.find_record_zone(name) == Some(zone))
+            .collect_vec();
+        self.cache.lock().unwrap().replace_domain(
+            zone,
+            records
+                .iter()
+                .filter_map(|(name, r)| {
+                    Some((r.id.to_owned(), dns_record(name.to_owned(), r, zone)?))
+                })
+                .collect(),
+        );
+        Ok(records)
+    }
+
+    fn create_record(
+        &self,
+        zone: &str,
+        name: &str,
+        record_type: &str,
+        content: String,
+        ttl: Option<TTL>,
+    ) -> Result<(), ProviderError> {
+        let record = NewRecord {
+            record_type: record_type.to_owned(),
+            name: relative_name(name, zone),
+            content,
+            ttl,
+        };
+        if let Some(created) = self.dry_run.guard(|| self.api.add_record(zone, &record))? {
+            if let Some(rec) = dns_record(name.to_owned(), &created, zone) {
+                self.cache.lock().unwrap().insert(zone, created.id, rec);
+            }
+        }
+        debug!(
+            "Created {} record {} => {} in domain {}",
+            record_type, name, record.content, zone
+        );
+        Ok(())
+    }
+
+    fn delete_record(&self, zone: &str, name: &str, id: &str) -> Result<(), ProviderError> {
+        self.dry_run.guard(|| self.api.remove_record(zone, id))?;
+        self.cache.lock().unwrap().remove(zone, id);
+        debug!(
+            "Deleted record {} with id {} from domain {}",
+            name, id, zone
+        );
+        Ok(())
+    }
+}
+
+impl DnsProvider for NjallaProvider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            dry_run: true,
+            ttl: true,
+            batch_apply: false,
+            proxied: false,
+        }
+    }
+
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+        self.dry_run.enable();
+        Ok(())
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run.is_enabled()
+    }
+
+    fn ttl(&self) -> Option<TTL> {
+        self.ttl
+    }
+
+    fn set_ttl(&mut self, ttl: TTL) {
+        let supported = supported_ttl(ttl);
+        if supported != ttl {
+            warn!(
+                "TTL {} is not supported by Njalla (must be one of {}), using {} instead",
+                ttl,
+                NJALLA_TTLS.iter().join(", "),
+                supported
+            );
+        }
+        self.ttl = Some(supported);
+    }
+
+    fn ttl_bounds(&self) -> RangeInclusive<TTL> {
+        NJALLA_TTLS[0]..=NJALLA_TTLS[NJALLA_TTLS.len() - 1]
+    }
+
+    fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+        let mut records = Vec::new();
+        for zone in &self.domains {
+            debug!("Reading records of domain {} from Njalla API", zone);
+            records.extend(
+                self.zone_records(zone)?
+                    .into_iter()
+                    .filter_map(|(name, r)| dns_record(name, &r, zone)),
+            );
+        }
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn records_for(&self, zone_or_domain: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let domain = normalize_domain(zone_or_domain.trim_end_matches('.'));
+        // Subdomains of the requested domain may be stored in separate Njalla domains
+        let zones = self
+            .domains
+            .iter()
+            .filter(|z| super::in_domain(z, &domain) || super::in_domain(&domain, z))
+            .collect_vec();
+        if zones.is_empty() {
+            self.zone_for(&domain)?;
+        }
+        let mut records = Vec::new();
+        for zone in zones {
+            records.extend(
+                self.zone_records(zone)?
+                    .into_iter()
+                    .filter(|(name, _)| super::in_domain(name, &domain))
+                    .filter_map(|(name, r)| dns_record(name, &r, zone)),
+            );
+        }
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn zone_of(&self, domain: &str) -> Option<String> {
+        self.find_record_zone(domain).map(str::to_owned)
+    }
+
+    fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+        let domain = normalize_domain(action.domain());
+        let zone = self.zone_for(&domain)?;
+        let a_records = self
+            .zone_records(zone)?
+            .into_iter()
+            .filter(|(name, r)| *name == domain && r.record_type == "A")
+            .map(|(_, r)| r)
+            .collect_vec();
+
+        match action {
+            Action::ClaimAndUpdate(_, ips) | Action::Update(_, ips) => {
+                // Keep the TTL of the existing records, unless a TTL has been set explicitly
+                let ttl = self.ttl.or(a_records.iter().filter_map(|r| r.ttl).max());
+                let (kept, obsolete): (Vec<ApiRecord>, Vec<ApiRecord>) = a_records
+                    .into_iter()
+                    .partition(|r| ips.iter().any(|ip| ip.to_string() == r.content));
+                // Create new records before deleting the old ones, so that the domain always has an A record
+                for ip in ips
+                    .iter()
+                    .filter(|ip| !kept.iter().any(|r| r.content == ip.to_string()))
+                {
+                    self.create_record(zone, &domain, "A", ip.to_string(), ttl)?;
+                }
+                for r in &obsolete {
+                    self.delete_record(zone, &domain, &r.id)?;
+                }
+                Ok(())
+            }
+            Action::DeleteAndRelease(_) => {
+                for r in &a_records {
+                    self.delete_record(zone, &domain, &r.id)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TxTRegistryProvider for NjallaProvider {
+    fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = normalize_domain(&domain);
+        let zone = self.zone_for(&domain)?;
+        self.create_record(zone, &domain, "TXT", content, self.ttl)
+    }
+
+    fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = normalize_domain(&domain);
+        let zone = self.zone_for(&domain)?;
+        let record = DnsRecord::new(domain.to_owned(), RecordContent::Txt(content));
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .find_record_id(zone, &record)
+            .map(str::to_owned);
+        let id = match cached {
+            Some(id) => id,
+            // The record may have been created since the records of the domain were last listed
+            None => {
+                self.zone_records(zone)?;
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .find_record_id(zone, &record)
+                    .map(str::to_owned)
+                    .ok_or(format!(
+                        "Could not find matching record id for record {}",
+                        record
+                    ))?
+            }
+        };
+        self.delete_record(zone, &domain, &id)
+    }
+}
+impl Provider for NjallaProvider {}
+
+/// Round a TTL to the closest TTL supported by Njalla
+fn supported_ttl(ttl: TTL) -> TTL {
+    NJALLA_TTLS
+        .into_iter()
+        .min_by_key(|t| t.abs_diff(ttl))
+        .unwrap()
+}
+
+/// Convert a record name relative to its zone into a fully qualified name
+fn absolute_name(name: &str, zone: &str) -> String {
+    match name {
+        "@" | "" => zone.to_owned(),
+        name => normalize_domain(&format!("{}.{}", name.trim_end_matches('.'), zone)),
+    }
+}
+
+/// Convert a fully qualified name into a name relative to its zone, as expected by the API
+fn relative_name(name: &str, zone: &str) -> String {
+    if name == zone {
+        return "@".to_string();
+    }
+    name.strip_suffix(&format!(".{}", zone))
+        .unwrap_or(name)
+        .to_owned()
+}
+
+/// Convert an API record into a [`DnsRecord`]. Returns `None` for irrelevant record types and invalid records
+fn dns_record(name: String, record: &ApiRecord, zone: &str) -> Option<DnsRecord> {
+    let content = match record.record_type.as_str() {
+        "A" => RecordContent::A(record.content.parse().ok()?),
+        "AAAA" => RecordContent::Aaaa(record.content.parse().ok()?),
+        "TXT" => RecordContent::Txt(record.content.to_owned()),
+        "CNAME" => RecordContent::Cname(match record.content.as_str() {
+            "@" => zone.to_owned(),
+            target => target.trim_end_matches('.').to_owned(),
+        }),
+        _ => return None,
+    };
+    Some(DnsRecord {
+        ttl: record.ttl,
+        ..DnsRecord::new(name, content)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{ApiRecord, NewRecord, NjallaApi, NjallaProvider};
+    use crate::{
+        plan::Action,
+        provider::{DnsProvider, RecordContent, TxTRegistryProvider, TTL},
+    };
+
+    fn api_record(id: &str, record_type: &str, name: &str, content: &str) -> ApiRecord {
+        ApiRecord {
+            id: id.to_string(),
+            record_type: record_type.to_string(),
+            name: name.to_string(),
+            content: content.to_string(),
+            ttl: Some(10800),
+        }
+    }
+
+    fn records() -> Vec<ApiRecord> {
+        vec![
+            api_record("1", "A", "www", "10.1.1.1"),
+            api_record("2", "AAAA", "www", "fd42::1"),
+            api_record("3", "TXT", "@", "v=spf1 -all"),
+            api_record("4", "CNAME", "alias", "www.example.com."),
+            api_record("5", "MX", "@", "mail.example.com."),
+            api_record("6", "A", "host.sub", "10.1.1.2"),
+        ]
+    }
+
+    fn created(record: &NewRecord) -> Result<ApiRecord, crate::provider::ProviderError> {
+        Ok(ApiRecord {
+            id: "100".to_string(),
+            record_type: record.record_type.to_owned(),
+            name: record.name.to_owned(),
+            content: record.content.to_owned(),
+            ttl: record.ttl,
+        })
+    }
+
+    #[test]
+    fn should_return_records() {
+        let mut mock = NjallaApi::default();
+        mock.expect_list_records()
+            .withf(|zone| zone == "example.com")
+            .returning(|_| Ok(records()));
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        let records = p.records().unwrap();
+        assert_eq!(
+            records.iter().map(|r| &r.content).collect::<Vec<_>>(),
+            vec![
+                &RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                &RecordContent::Aaaa("fd42::1".parse().unwrap()),
+                &RecordContent::Txt("v=spf1 -all".to_string()),
+                &RecordContent::Cname("www.example.com".to_string()),
+                &RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+            ]
+        );
+        assert_eq!(records[0].domain_name, "www.example.com");
+        assert_eq!(records[0].ttl, Some(10800));
+        assert_eq!(records[2].domain_name, "example.com");
+        assert_eq!(records[4].domain_name, "host.sub.example.com");
+    }
+
+    #[test]
+    fn should_create_before_deleting_on_update() {
+        let mut mock = NjallaApi::default();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_add_record()
+            .withf(|zone, r| {
+                zone == "example.com"
+                    && r.name == "www"
+                    && r.record_type == "A"
+                    && r.content == "203.0.113.1"
+                    && r.ttl == Some(10800)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, r| created(r));
+        mock.expect_remove_record()
+            .withf(|zone, id| zone == "example.com" && id == "1")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_keep_old_records_if_creating_new_ones_fails() {
+        let mut mock = NjallaApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_add_record()
+            .returning(|_, _| Err("rate limited".to_string().into()));
+        mock.expect_remove_record().never();
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap_err();
+    }
+
+    #[test]
+    fn should_delete_a_records() {
+        let mut mock = NjallaApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_remove_record()
+            .withf(|zone, id| zone == "example.com" && id == "6")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        p.apply(&Action::DeleteAndRelease(
+            "host.sub.example.com".to_string(),
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn should_delete_txt_records_by_cached_id() {
+        let mut mock = NjallaApi::default();
+        // Listed only once by records(), deleting uses the cached IDs
+        mock.expect_list_records()
+            .times(1)
+            .returning(|_| Ok(records()));
+        mock.expect_add_record()
+            .withf(|_, r| r.record_type == "TXT" && r.name == "www" && r.content == "owner")
+            .times(1)
+            .returning(|_, r| created(r));
+        mock.expect_remove_record()
+            .withf(|zone, id| zone == "example.com" && id == "3")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock.expect_remove_record()
+            .withf(|zone, id| zone == "example.com" && id == "100")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        p.records().unwrap();
+        p.delete_txt_record("example.com".to_string(), "v=spf1 -all".to_string())
+            .unwrap();
+        p.create_txt_record("www.example.com".to_string(), "owner".to_string())
+            .unwrap();
+        p.delete_txt_record("www.example.com".to_string(), "owner".to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn should_list_records_to_find_uncached_ids() {
+        let mut mock = NjallaApi::default();
+        mock.expect_list_records()
+            .times(2)
+            .returning(|_| Ok(records()));
+        mock.expect_remove_record()
+            .withf(|zone, id| zone == "example.com" && id == "3")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let p = NjallaProvider::from_mock_api(&["example.com"], mock);
+
+        p.delete_txt_record("example.com".to_string(), "v=spf1 -all".to_string())
+            .unwrap();
+        // Records that don't exist can't be deleted
+        p.delete_txt_record("example.com".to_string(), "missing".to_string())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn should_round_unsupported_ttls() {
+        let mut p = NjallaProvider::from_mock_api(&["example.com"], NjallaApi::default());
+        p.set_ttl(1);
+        assert_eq!(p.ttl(), Some::<TTL>(60));
+        p.set_ttl(3000);
+        assert_eq!(p.ttl(), Some::<TTL>(3600));
+        p.set_ttl(300);
+        assert_eq!(p.ttl(), Some::<TTL>(300));
+        p.set_ttl(TTL::MAX);
+        assert_eq!(p.ttl(), Some::<TTL>(86400));
+    }
+
+    #[test]
+    fn should_reject_domains_outside_managed_domains() {
+        let p = NjallaProvider::from_mock_api(&["example.com"], NjallaApi::default());
+
+        p.apply(&Action::DeleteAndRelease("www.example.org".to_string()))
+            .unwrap_err();
+        p.create_txt_record("example.org".to_string(), "owner".to_string())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn should_support_dry_run() {
+        let mut mock = NjallaApi::default();
+        mock.expect_list_records().returning(|_| Ok(records()));
+        mock.expect_add_record().never();
+        mock.expect_remove_record().never();
+        let mut p = NjallaProvider::from_mock_api(&["example.com"], mock);
+        p.enable_dry_run().unwrap();
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+        p.create_txt_record("example.com".to_string(), "owner".to_string())
+            .unwrap();
+        p.delete_txt_record("example.com".to_string(), "v=spf1 -all".to_string())
+            .unwrap();
+    }
+}
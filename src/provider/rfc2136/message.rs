@@ -0,0 +1,476 @@
+//! Minimal DNS wire format support for RFC2136 updates, zone transfers and TSIG signatures.
+//! Only the parts required by the [`super::Rfc2136Provider`] are implemented.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use hmac::{digest::KeyInit, Hmac, Mac};
+use sha2::{Sha256, Sha384, Sha512};
+
+use crate::provider::{DnsRecord, RecordContent, TTL};
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const TYPE_AXFR: u16 = 252;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const OPCODE_UPDATE: u16 = 5;
+const HEADER_LEN: usize = 12;
+/// Allowed clock skew between us and the server for TSIG signatures, in seconds
+const TSIG_FUDGE: u16 = 300;
+
+/// A single change in the update section of an UPDATE message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// Add a record to the zone
+    Add(DnsRecord, TTL),
+    /// Delete a single record from the zone
+    Delete(DnsRecord),
+    /// Delete all A records of a domain
+    DeleteA(String),
+}
+impl Display for Update {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Update::Add(rec, ttl) => write!(f, "add {} (ttl {})", rec, ttl),
+            Update::Delete(rec) => write!(f, "delete {}", rec),
+            Update::DeleteA(domain) => write!(f, "delete all A records of {}", domain),
+        }
+    }
+}
+
+/// Supported TSIG algorithms
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum TsigAlgorithm {
+    #[default]
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+impl TsigAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn mac(&self, key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac::<Hmac<Sha256>>(key, parts),
+            TsigAlgorithm::HmacSha384 => hmac::<Hmac<Sha384>>(key, parts),
+            TsigAlgorithm::HmacSha512 => hmac::<Hmac<Sha512>>(key, parts),
+        }
+    }
+}
+
+fn hmac<M: Mac + KeyInit>(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A shared TSIG key used to authenticate messages (RFC 8945)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+impl TsigKey {
+    /// Append a TSIG record to the message, signing it with this key at the given time (seconds since the epoch)
+    pub fn sign(&self, mut msg: Vec<u8>, time_signed: u64) -> Vec<u8> {
+        let key_name = encode_name(&self.name.to_lowercase());
+        let algorithm = encode_name(self.algorithm.name());
+        let time = &time_signed.to_be_bytes()[2..];
+
+        let mut variables = Vec::new();
+        variables.extend_from_slice(&key_name);
+        variables.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        variables.extend_from_slice(&0u32.to_be_bytes());
+        variables.extend_from_slice(&algorithm);
+        variables.extend_from_slice(time);
+        variables.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        // error and other len
+        variables.extend_from_slice(&[0, 0, 0, 0]);
+        let mac = self.algorithm.mac(&self.secret, &[&msg, &variables]);
+
+        let mut rdata = algorithm;
+        rdata.extend_from_slice(time);
+        rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        // original id
+        rdata.extend_from_slice(&msg[0..2]);
+        // error and other len
+        rdata.extend_from_slice(&[0, 0, 0, 0]);
+
+        push_rr(&mut msg, &key_name, TYPE_TSIG, CLASS_ANY, 0, &rdata);
+        let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+        msg[10..12].copy_from_slice(&arcount.to_be_bytes());
+        msg
+    }
+}
+
+/// Generate a random message ID
+pub fn message_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
+/// Encode a domain name in uncompressed wire format
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf
+}
+
+fn header(id: u16, flags: u16, counts: [u16; 4]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(512);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&flags.to_be_bytes());
+    for c in counts {
+        buf.extend_from_slice(&c.to_be_bytes());
+    }
+    buf
+}
+
+fn push_rr(buf: &mut Vec<u8>, name: &[u8], rtype: u16, class: u16, ttl: u32, rdata: &[u8]) {
+    buf.extend_from_slice(name);
+    buf.extend_from_slice(&rtype.to_be_bytes());
+    buf.extend_from_slice(&class.to_be_bytes());
+    buf.extend_from_slice(&ttl.to_be_bytes());
+    buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(rdata);
+}
+
+fn encode_rdata(content: &RecordContent) -> (u16, Vec<u8>) {
+    match content {
+        RecordContent::A(a) => (TYPE_A, a.octets().to_vec()),
+        RecordContent::Aaaa(aaaa) => (TYPE_AAAA, aaaa.octets().to_vec()),
+        RecordContent::Txt(txt) => {
+            // TXT records consist of character-strings of up to 255 bytes each
+            let mut rdata = Vec::new();
+            for chunk in txt.as_bytes().chunks(255) {
+                rdata.push(chunk.len() as u8);
+                rdata.extend_from_slice(chunk);
+            }
+            if rdata.is_empty() {
+                rdata.push(0);
+            }
+            (TYPE_TXT, rdata)
+        }
+        RecordContent::Cname(cname) => (TYPE_CNAME, encode_name(cname)),
+    }
+}
+
+/// Build an UPDATE message for the given zone
+pub fn update_message(id: u16, zone: &str, updates: &[Update]) -> Vec<u8> {
+    let mut msg = header(id, OPCODE_UPDATE << 11, [1, 0, updates.len() as u16, 0]);
+    // zone section
+    msg.extend_from_slice(&encode_name(zone));
+    msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    for update in updates {
+        match update {
+            Update::Add(rec, ttl) => {
+                let (rtype, rdata) = encode_rdata(&rec.content);
+                push_rr(
+                    &mut msg,
+                    &encode_name(&rec.domain_name),
+                    rtype,
+                    CLASS_IN,
+                    *ttl,
+                    &rdata,
+                );
+            }
+            Update::Delete(rec) => {
+                let (rtype, rdata) = encode_rdata(&rec.content);
+                push_rr(
+                    &mut msg,
+                    &encode_name(&rec.domain_name),
+                    rtype,
+                    CLASS_NONE,
+                    0,
+                    &rdata,
+                );
+            }
+            Update::DeleteA(domain) => {
+                push_rr(&mut msg, &encode_name(domain), TYPE_A, CLASS_ANY, 0, &[]);
+            }
+        }
+    }
+    msg
+}
+
+/// Build a zone transfer (AXFR) query for the given zone
+pub fn axfr_query(id: u16, zone: &str) -> Vec<u8> {
+    let mut msg = header(id, 0, [1, 0, 0, 0]);
+    msg.extend_from_slice(&encode_name(zone));
+    msg.extend_from_slice(&TYPE_AXFR.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    msg
+}
+
+/// The relevant contents of a DNS response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The response code of the message, 0 means no error
+    pub rcode: u16,
+    /// All A, AAAA and TXT records in the answer section
+    pub records: Vec<DnsRecord>,
+    /// How many SOA records are in the answer section, used to detect the end of a zone transfer
+    pub soa_count: usize,
+}
+
+/// Returns a human-readable name for common response codes
+pub fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        _ => format!("RCODE {}", rcode),
+    }
+}
+
+/// Parse a DNS response message
+pub fn parse_response(msg: &[u8]) -> Result<Response, String> {
+    if msg.len() < HEADER_LEN {
+        return Err("DNS response is too short".to_string());
+    }
+    let rcode = u16::from_be_bytes([msg[2], msg[3]]) & 0x000F;
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..qdcount {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+
+    let mut records = Vec::new();
+    let mut soa_count = 0;
+    for _ in 0..ancount {
+        let (name, next) = read_name(msg, pos)?;
+        let fixed = msg
+            .get(next..next + 10)
+            .ok_or("DNS response contains a truncated record")?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata = msg
+            .get(next + 10..next + 10 + rdlen)
+            .ok_or("DNS response contains truncated record data")?;
+        pos = next + 10 + rdlen;
+
+        let content = match (rtype, rdata) {
+            (TYPE_SOA, _) => {
+                soa_count += 1;
+                continue;
+            }
+            (TYPE_A, &[a, b, c, d]) => RecordContent::A(Ipv4Addr::new(a, b, c, d)),
+            (TYPE_AAAA, _) if rdata.len() == 16 => {
+                let octets: [u8; 16] = rdata.try_into().unwrap();
+                RecordContent::Aaaa(Ipv6Addr::from(octets))
+            }
+            (TYPE_TXT, _) => RecordContent::Txt(read_txt(rdata)?),
+            // CNAME targets may be compressed, so they have to be read from the whole message
+            (TYPE_CNAME, _) => RecordContent::Cname(read_name(msg, next + 10)?.0),
+            _ => continue,
+        };
+        records.push(DnsRecord {
+            ttl: Some(ttl),
+            ..DnsRecord::new(name, content)
+        });
+    }
+    Ok(Response {
+        rcode,
+        records,
+        soa_count,
+    })
+}
+
+/// Read a (possibly compressed) domain name starting at `pos`.
+/// Returns the name without a trailing dot and the position directly after it
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize), String> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *msg
+            .get(pos)
+            .ok_or("DNS response contains a truncated name")? as usize;
+        if len & 0xC0 == 0xC0 {
+            let low = *msg
+                .get(pos + 1)
+                .ok_or("DNS response contains a truncated name")?;
+            end.get_or_insert(pos + 2);
+            jumps += 1;
+            if jumps > 64 {
+                return Err("DNS response contains a compression loop".to_string());
+            }
+            pos = ((len & 0x3F) << 8) | low as usize;
+        } else if len == 0 {
+            return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+        } else {
+            let label = msg
+                .get(pos + 1..pos + 1 + len)
+                .ok_or("DNS response contains a truncated name")?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+}
+
+/// Read the character-strings of a TXT record and concatenate them
+fn read_txt(mut rdata: &[u8]) -> Result<String, String> {
+    let mut txt = Vec::new();
+    while let Some((&len, rest)) = rdata.split_first() {
+        let chunk = rest
+            .get(..len as usize)
+            .ok_or("DNS response contains a truncated TXT record")?;
+        txt.extend_from_slice(chunk);
+        rdata = &rest[len as usize..];
+    }
+    Ok(String::from_utf8_lossy(&txt).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::provider::{DnsRecord, RecordContent};
+
+    use super::{
+        axfr_query, encode_name, parse_response, update_message, TsigAlgorithm, TsigKey, Update,
+    };
+
+    fn rec(content: RecordContent) -> DnsRecord {
+        DnsRecord::new("my.example.com", content)
+    }
+
+    #[test]
+    fn should_encode_names() {
+        assert_eq!(
+            encode_name("my.example.com."),
+            b"\x02my\x07example\x03com\x00".to_vec()
+        );
+        assert_eq!(encode_name(""), vec![0]);
+    }
+
+    #[test]
+    fn should_build_update_message() {
+        let msg = update_message(
+            0x1234,
+            "example.com",
+            &[
+                Update::DeleteA("my.example.com".to_string()),
+                Update::Add(rec(RecordContent::A(Ipv4Addr::new(203, 0, 113, 1))), 300),
+            ],
+        );
+
+        let mut expected = vec![0x12, 0x34, 0x28, 0x00, 0, 1, 0, 0, 0, 2, 0, 0];
+        expected.extend_from_slice(b"\x07example\x03com\x00\x00\x06\x00\x01");
+        expected.extend_from_slice(b"\x02my\x07example\x03com\x00\x00\x01\x00\xff");
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"\x02my\x07example\x03com\x00\x00\x01\x00\x01");
+        expected.extend_from_slice(&[0, 0, 0x01, 0x2c, 0, 4, 203, 0, 113, 1]);
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn should_append_tsig_record() {
+        let key = TsigKey {
+            name: "Update-Key".to_string(),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        };
+        let msg = axfr_query(0x1234, "example.com");
+        let signed = key.sign(msg.clone(), 1_700_000_000);
+
+        // arcount is incremented, the rest of the header is unchanged
+        assert_eq!(signed[..10], msg[..10]);
+        assert_eq!(signed[10..12], [0, 1]);
+        assert_eq!(signed[12..msg.len()], msg[12..]);
+        // key name, type TSIG, class ANY
+        let rr = &signed[msg.len()..];
+        assert!(rr.starts_with(b"\x0aupdate-key\x00\x00\xfa\x00\xff"));
+        // signing is deterministic for the same time
+        assert_eq!(signed, key.sign(msg, 1_700_000_000));
+    }
+
+    #[test]
+    fn should_parse_response() {
+        let mut msg = vec![0x12, 0x34, 0x84, 0x00, 0, 1, 0, 4, 0, 0, 0, 0];
+        // question: example.com AXFR
+        msg.extend_from_slice(b"\x07example\x03com\x00\x00\xfc\x00\x01");
+        // SOA for example.com (pointer to the question name), rdata is ignored
+        msg.extend_from_slice(&[0xc0, 12, 0, 6, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+        // A record for my.example.com
+        msg.extend_from_slice(b"\x02my\xc0\x0c");
+        msg.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 203, 0, 113, 1]);
+        // TXT record for my.example.com, split into two strings
+        msg.extend_from_slice(&[0xc0, 43, 0, 16, 0, 1, 0, 0, 1, 0, 0, 8]);
+        msg.extend_from_slice(b"\x03abc\x03def");
+        // MX records are ignored
+        msg.extend_from_slice(&[0xc0, 12, 0, 15, 0, 1, 0, 0, 0, 60, 0, 4, 0, 10, 0xc0, 12]);
+
+        let resp = parse_response(&msg).unwrap();
+        assert_eq!(resp.rcode, 0);
+        assert_eq!(resp.soa_count, 1);
+        assert_eq!(
+            resp.records,
+            vec![
+                DnsRecord {
+                    ttl: Some(60),
+                    ..rec(RecordContent::A(Ipv4Addr::new(203, 0, 113, 1)))
+                },
+                DnsRecord {
+                    ttl: Some(256),
+                    ..rec(RecordContent::Txt("abcdef".to_string()))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_error_code() {
+        let msg = vec![0x12, 0x34, 0xa8, 0x05, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        assert_eq!(parse_response(&msg).unwrap().rcode, 5);
+    }
+
+    #[test]
+    fn should_reject_truncated_response() {
+        let mut msg = vec![0x12, 0x34, 0x84, 0x00, 0, 0, 0, 1, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x02my");
+
+        parse_response(&msg).unwrap_err();
+    }
+}
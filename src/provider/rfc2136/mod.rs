@@ -0,0 +1,330 @@
+mod message;
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use itertools::Itertools;
+use log::{debug, info, trace};
+
+use self::message::{Response, TsigKey, Update};
+use super::{
+    DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+    TxTRegistryProvider,
+};
+use crate::{
+    plan::Action,
+    provider::{normalize_domain, TTL},
+};
+
+pub use self::message::TsigAlgorithm;
+
+/// TTL applied to new records if none has been set with [`DnsProvider::set_ttl()`]
+const DEFAULT_TTL: TTL = 300;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A [`Provider`] for DNS servers that support dynamic updates as defined in [RFC2136](https://datatracker.ietf.org/doc/html/rfc2136),
+/// such as BIND or Knot.
+///
+/// Records are retrieved with a zone transfer (AXFR) and changed with UPDATE messages, both of which are authenticated with a TSIG key.
+/// The server must therefore allow both zone transfers and updates for this key.
+/// Note that TSIG signatures on responses are not verified.
+///
+/// To create a provider, use the [`Rfc2136Provider::from_config()`] function.
+#[non_exhaustive]
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: String,
+    key: TsigKey,
+    ttl: Option<TTL>,
+    dry_run: bool,
+}
+
+/// Configuration object for a [`Rfc2136Provider`]. Must be supplied when creating a provider.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rfc2136ProviderConfig<'a> {
+    /// Address of the primary DNS server to send updates to (IP address + Port number)
+    pub server: SocketAddr,
+    /// The zone to manage, e.g. `example.com`
+    pub zone: &'a str,
+    /// Name of the TSIG key
+    pub tsig_key_name: &'a str,
+    /// The base64-encoded TSIG secret
+    pub tsig_secret: &'a str,
+    /// The algorithm of the TSIG key
+    pub tsig_algorithm: TsigAlgorithm,
+}
+
+impl Rfc2136Provider {
+    /// Create a new [`Rfc2136Provider`] with the supplied configuration.
+    /// Returns an error if the secret is invalid or the zone could not be transferred from the server
+    pub fn from_config(config: &Rfc2136ProviderConfig) -> Result<Rfc2136Provider, ProviderError> {
+        let secret = STANDARD
+            .decode(config.tsig_secret)
+            .map_err(|e| format!("Invalid TSIG secret, must be base64-encoded: {}", e))?;
+        let provider = Rfc2136Provider {
+            server: config.server,
+            zone: normalize_domain(config.zone.trim_end_matches('.')),
+            key: TsigKey {
+                name: config.tsig_key_name.to_owned(),
+                algorithm: config.tsig_algorithm,
+                secret,
+            },
+            ttl: None,
+            dry_run: false,
+        };
+        // Make sure that we can actually access the zone
+        provider.records()?;
+        Ok(provider)
+    }
+
+    fn connect(&self) -> Result<TcpStream, ProviderError> {
+        let stream = TcpStream::connect_timeout(&self.server, TIMEOUT)
+            .map_err(|e| format!("Could not connect to DNS server {}: {}", self.server, e))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| format!("Could not configure connection to {}: {}", self.server, e))?;
+        Ok(stream)
+    }
+
+    fn sign(&self, msg: Vec<u8>) -> Vec<u8> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.key.sign(msg, now)
+    }
+
+    fn check_domain(&self, domain: &str) -> Result<(), ProviderError> {
+        let domain = normalize_domain(domain);
+        if domain == self.zone || domain.ends_with(&format!(".{}", self.zone)) {
+            Ok(())
+        } else {
+            Err(format!("Domain {} is not part of zone {}", domain, self.zone).into())
+        }
+    }
+
+    /// Send a single UPDATE message containing all updates, so that they are applied atomically
+    fn send_update(&self, updates: &[Update]) -> Result<(), ProviderError> {
+        let description = updates.iter().join(", ");
+        if self.dry_run {
+            info!(
+                "Dry-run: not sending update for zone {}: {}",
+                self.zone, description
+            );
+            return Ok(());
+        }
+
+        let msg = self.sign(message::update_message(
+            message::message_id(),
+            &self.zone,
+            updates,
+        ));
+        let mut stream = self.connect()?;
+        write_msg(&mut stream, &msg)?;
+        let response = parse(&read_msg(&mut stream)?)?;
+        if response.rcode != 0 {
+            return Err(format!(
+                "DNS server rejected update ({}) with {}",
+                description,
+                message::rcode_name(response.rcode)
+            )
+            .into());
+        }
+        debug!("Sent update for zone {}: {}", self.zone, description);
+        Ok(())
+    }
+}
+
+impl DnsProvider for Rfc2136Provider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            dry_run: true,
+            ttl: true,
+            batch_apply: false,
+            proxied: false,
+        }
+    }
+
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+        self.dry_run = true;
+        Ok(())
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn ttl(&self) -> Option<TTL> {
+        self.ttl
+    }
+
+    fn set_ttl(&mut self, ttl: TTL) {
+        self.ttl = Some(ttl);
+    }
+
+    fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+        debug!("Transferring zone {} from {}", self.zone, self.server);
+        let query = self.sign(message::axfr_query(message::message_id(), &self.zone));
+        let mut stream = self.connect()?;
+        write_msg(&mut stream, &query)?;
+
+        // A zone transfer starts and ends with the SOA record of the zone, possibly spread across multiple messages
+        let mut records = Vec::new();
+        let mut soa_count = 0;
+        while soa_count < 2 {
+            let response = parse(&read_msg(&mut stream)?)?;
+            if response.rcode != 0 {
+                return Err(format!(
+                    "Zone transfer for {} failed with {}",
+                    self.zone,
+                    message::rcode_name(response.rcode)
+                )
+                .into());
+            }
+            soa_count += response.soa_count;
+            records.extend(response.records);
+        }
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+        match action {
+            Action::ClaimAndUpdate(domain, ips) | Action::Update(domain, ips) => {
+                self.check_domain(domain)?;
+                let mut updates = vec![Update::DeleteA(domain.to_owned())];
+                updates.extend(ips.iter().map(|ip| {
+                    Update::Add(
+                        DnsRecord::new(domain.to_owned(), RecordContent::A(*ip)),
+                        self.ttl.unwrap_or(DEFAULT_TTL),
+                    )
+                }));
+                self.send_update(&updates)
+            }
+            Action::DeleteAndRelease(domain) => {
+                self.check_domain(domain)?;
+                self.send_update(&[Update::DeleteA(domain.to_owned())])
+            }
+        }
+    }
+}
+
+impl TxTRegistryProvider for Rfc2136Provider {
+    fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        self.check_domain(&domain)?;
+        self.send_update(&[Update::Add(
+            DnsRecord::new(domain, RecordContent::Txt(content)),
+            self.ttl.unwrap_or(DEFAULT_TTL),
+        )])
+    }
+
+    fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        self.check_domain(&domain)?;
+        self.send_update(&[Update::Delete(DnsRecord::new(
+            domain,
+            RecordContent::Txt(content),
+        ))])
+    }
+}
+impl Provider for Rfc2136Provider {}
+
+fn parse(msg: &[u8]) -> Result<Response, ProviderError> {
+    message::parse_response(msg).map_err(ProviderError::Internal)
+}
+
+/// Write a message to a TCP stream, prefixed by its length
+fn write_msg(stream: &mut TcpStream, msg: &[u8]) -> Result<(), ProviderError> {
+    let mut buf = (msg.len() as u16).to_be_bytes().to_vec();
+    buf.extend_from_slice(msg);
+    stream
+        .write_all(&buf)
+        .map_err(|e| format!("Could not send message to DNS server: {}", e).into())
+}
+
+/// Read a single length-prefixed message from a TCP stream
+fn read_msg(stream: &mut TcpStream) -> Result<Vec<u8>, ProviderError> {
+    let mut len = [0u8; 2];
+    stream
+        .read_exact(&mut len)
+        .map_err(|e| format!("Could not read response from DNS server: {}", e))?;
+    let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut msg)
+        .map_err(|e| format!("Could not read response from DNS server: {}", e))?;
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::{Rfc2136Provider, TsigAlgorithm, TsigKey};
+    use crate::{
+        plan::Action,
+        provider::{DnsProvider, TxTRegistryProvider},
+    };
+
+    fn provider() -> Rfc2136Provider {
+        Rfc2136Provider {
+            // Nothing is listening here, so any attempt to actually send a message fails
+            server: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1),
+            zone: "example.com".to_string(),
+            key: TsigKey {
+                name: "update-key".to_string(),
+                algorithm: TsigAlgorithm::HmacSha256,
+                secret: b"secret".to_vec(),
+            },
+            ttl: None,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn should_support_dry_run() {
+        let mut p = provider();
+        p.enable_dry_run().unwrap();
+
+        p.apply(&Action::Update(
+            "my.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+        p.apply(&Action::DeleteAndRelease("my.example.com".to_string()))
+            .unwrap();
+        p.create_txt_record("my.example.com".to_string(), "hello".to_string())
+            .unwrap();
+        p.delete_txt_record("my.example.com".to_string(), "hello".to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn should_reject_domains_outside_zone() {
+        let mut p = provider();
+        p.enable_dry_run().unwrap();
+
+        p.apply(&Action::DeleteAndRelease("my.example.org".to_string()))
+            .unwrap_err();
+        p.apply(&Action::DeleteAndRelease("notexample.com".to_string()))
+            .unwrap_err();
+        p.apply(&Action::DeleteAndRelease("example.com".to_string()))
+            .unwrap();
+    }
+
+    #[test]
+    fn should_reject_invalid_secret() {
+        Rfc2136Provider::from_config(&super::Rfc2136ProviderConfig {
+            server: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1),
+            zone: "example.com",
+            tsig_key_name: "update-key",
+            tsig_secret: "not base64!",
+            tsig_algorithm: TsigAlgorithm::HmacSha256,
+        })
+        .err()
+        .unwrap();
+    }
+}
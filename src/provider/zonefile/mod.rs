@@ -0,0 +1,400 @@
+mod zone;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use itertools::Itertools;
+use log::{debug, trace};
+
+use self::zone::ZoneFile;
+use super::{
+    DnsProvider, DnsRecord, Provider, ProviderCapabilities, ProviderError, RecordContent,
+    TxTRegistryProvider,
+};
+use crate::{
+    dry_run::DryRun,
+    plan::Action,
+    provider::{normalize_domain, TTL},
+};
+
+/// TTL applied to new records if none has been set with [`DnsProvider::set_ttl()`] and the zone file has no `$TTL` directive
+const DEFAULT_TTL: TTL = 300;
+
+/// A [`Provider`] that reads and rewrites a BIND-style zone file on disk instead of talking to a DNS server.
+/// This is mostly useful for testing and air-gapped setups, where the zone file is distributed by other means.
+///
+/// Only A, AAAA, TXT and CNAME records are managed, all other content of the zone file is preserved.
+/// New records are appended to the end of the file. Note that the serial number of the SOA record is not changed.
+///
+/// To create a provider, use the [`ZoneFileProvider::from_config()`] function.
+#[non_exhaustive]
+pub struct ZoneFileProvider {
+    path: PathBuf,
+    origin: String,
+    ttl: Option<TTL>,
+    dry_run: DryRun,
+    /// Held while modifying the file, so that concurrent changes (e.g. with `--concurrency`) do not overwrite each other
+    write_lock: Mutex<()>,
+}
+
+/// Configuration object for a [`ZoneFileProvider`]. Must be supplied when creating a provider.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ZoneFileProviderConfig<'a> {
+    /// Path of the zone file
+    pub path: &'a Path,
+    /// The zone stored in the file, e.g. `example.com`. Used for relative names until the file sets an `$ORIGIN`
+    pub origin: &'a str,
+}
+
+impl ZoneFileProvider {
+    /// Create a new [`ZoneFileProvider`] with the supplied configuration.
+    /// Returns an error if the zone file could not be read or parsed
+    pub fn from_config(config: &ZoneFileProviderConfig) -> Result<ZoneFileProvider, ProviderError> {
+        let provider = ZoneFileProvider {
+            path: config.path.to_owned(),
+            origin: normalize_domain(config.origin.trim_end_matches('.')),
+            ttl: None,
+            dry_run: DryRun::default(),
+            write_lock: Mutex::new(()),
+        };
+        // Make sure that the file is readable and valid
+        provider.read()?;
+        Ok(provider)
+    }
+
+    fn read(&self) -> Result<ZoneFile, ProviderError> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Could not read zone file {}: {}", self.path.display(), e))?;
+        ZoneFile::parse(&content, &self.origin)
+            .map_err(|e| format!("Could not parse zone file {}: {}", self.path.display(), e).into())
+    }
+
+    /// Write the zone file by replacing it, so that readers never see a partially written file
+    fn write(&self, zone: &ZoneFile) -> Result<(), ProviderError> {
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+
+        self.dry_run.guard(|| {
+            fs::write(&tmp_path, zone.to_string())
+                .and_then(|_| fs::rename(&tmp_path, &self.path))
+                .map_err(|e| format!("Could not write zone file {}: {}", self.path.display(), e))
+        })?;
+        Ok(())
+    }
+
+    fn check_domain(&self, domain: &str) -> Result<String, ProviderError> {
+        let domain = normalize_domain(domain.trim_end_matches('.'));
+        if super::in_domain(&domain, &self.origin) {
+            Ok(domain)
+        } else {
+            Err(format!("Domain {} is not part of zone {}", domain, self.origin).into())
+        }
+    }
+
+    /// TTL for new records: the configured TTL, then the TTL of existing records, then the default TTL of the zone file
+    fn new_ttl(&self, zone: &ZoneFile, existing: &[DnsRecord]) -> TTL {
+        self.ttl
+            .or(existing.iter().filter_map(|r| r.ttl).max())
+            .or(zone.default_ttl())
+            .unwrap_or(DEFAULT_TTL)
+    }
+}
+
+impl DnsProvider for ZoneFileProvider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            dry_run: true,
+            ttl: true,
+            batch_apply: false,
+            proxied: false,
+        }
+    }
+
+    fn enable_dry_run(&mut self) -> Result<(), ProviderError> {
+        self.dry_run.enable();
+        Ok(())
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run.is_enabled()
+    }
+
+    fn ttl(&self) -> Option<TTL> {
+        self.ttl
+    }
+
+    fn set_ttl(&mut self, ttl: TTL) {
+        self.ttl = Some(ttl);
+    }
+
+    fn records(&self) -> Result<Vec<DnsRecord>, ProviderError> {
+        let records = self.read()?.records();
+        trace!("Collected Records: {:?}", records);
+        Ok(records)
+    }
+
+    fn apply(&self, action: &Action) -> Result<(), ProviderError> {
+        let domain = self.check_domain(action.domain())?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut zone = self.read()?;
+        let is_a = |r: &DnsRecord| {
+            normalize_domain(&r.domain_name) == domain && matches!(r.content, RecordContent::A(_))
+        };
+
+        match action {
+            Action::ClaimAndUpdate(_, ips) | Action::Update(_, ips) => {
+                let existing = zone.records().into_iter().filter(is_a).collect_vec();
+                let ttl = self.new_ttl(&zone, &existing);
+                zone.remove(|r| {
+                    is_a(r) && !ips.iter().any(|ip| r.content == RecordContent::A(*ip))
+                });
+                for ip in ips
+                    .iter()
+                    .filter(|ip| !existing.iter().any(|r| r.content == RecordContent::A(**ip)))
+                {
+                    zone.add(DnsRecord {
+                        ttl: Some(ttl),
+                        ..DnsRecord::new(domain.clone(), RecordContent::A(*ip))
+                    });
+                }
+            }
+            Action::DeleteAndRelease(_) => {
+                zone.remove(is_a);
+            }
+        }
+        self.write(&zone)?;
+        debug!("Applied {} to zone file {}", action, self.path.display());
+        Ok(())
+    }
+}
+
+impl TxTRegistryProvider for ZoneFileProvider {
+    fn create_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = self.check_domain(&domain)?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut zone = self.read()?;
+        let ttl = self.new_ttl(&zone, &[]);
+        zone.add(DnsRecord {
+            ttl: Some(ttl),
+            ..DnsRecord::new(domain, RecordContent::Txt(content))
+        });
+        self.write(&zone)
+    }
+
+    fn delete_txt_record(&self, domain: String, content: String) -> Result<(), ProviderError> {
+        let domain = self.check_domain(&domain)?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut zone = self.read()?;
+        let removed = zone.remove(|r| {
+            normalize_domain(&r.domain_name) == domain
+                && r.content == RecordContent::Txt(content.clone())
+        });
+        if removed == 0 {
+            return Err(format!("Could not find TXT record {} => {}", domain, content).into());
+        }
+        self.write(&zone)
+    }
+}
+impl Provider for ZoneFileProvider {}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::Ipv4Addr, path::PathBuf};
+
+    use super::{ZoneFileProvider, ZoneFileProviderConfig};
+    use crate::{
+        plan::Action,
+        provider::{DnsProvider, DnsRecord, RecordContent, TxTRegistryProvider},
+    };
+
+    const ZONE: &str = "$TTL 3600
+@\tIN\tSOA\tns1 admin 1 3600 900 604800 300
+\tIN\tNS\tns1
+www\t300\tIN\tA\t10.1.1.1
+\tIN\tAAAA\tfd42::1
+";
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "clouddns-nat-helper-{}-{}.zone",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, ZONE).unwrap();
+        path
+    }
+
+    fn provider(path: &PathBuf) -> ZoneFileProvider {
+        ZoneFileProvider::from_config(&ZoneFileProviderConfig {
+            path,
+            origin: "example.com",
+        })
+        .unwrap()
+    }
+
+    fn a_records(p: &ZoneFileProvider, domain: &str) -> Vec<(Ipv4Addr, Option<u32>)> {
+        p.records()
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.domain_name == domain)
+            .filter_map(|r| match r.content {
+                RecordContent::A(a) => Some((a, r.ttl)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_records() {
+        let path = path("read");
+        let p = provider(&path);
+
+        assert_eq!(p.records().unwrap().len(), 2);
+        assert_eq!(
+            a_records(&p, "www.example.com"),
+            vec![(Ipv4Addr::new(10, 1, 1, 1), Some(300))]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn updates_records() {
+        let path = path("update");
+        let p = provider(&path);
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1), Ipv4Addr::new(10, 1, 1, 1)],
+        ))
+        .unwrap();
+        assert_eq!(
+            a_records(&p, "www.example.com"),
+            vec![
+                (Ipv4Addr::new(10, 1, 1, 1), Some(300)),
+                (Ipv4Addr::new(203, 0, 113, 1), Some(300)),
+            ]
+        );
+
+        p.apply(&Action::Update(
+            "www.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+        assert_eq!(
+            a_records(&p, "www.example.com"),
+            vec![(Ipv4Addr::new(203, 0, 113, 1), Some(300))]
+        );
+        // The AAAA record inherited its owner from the removed A record
+        assert!(p
+            .records()
+            .unwrap()
+            .iter()
+            .any(|r| r.domain_name == "www.example.com"
+                && matches!(r.content, RecordContent::Aaaa(_))));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn creates_and_deletes_records() {
+        let path = path("create");
+        let mut p = provider(&path);
+        p.set_ttl(60);
+
+        p.apply(&Action::ClaimAndUpdate(
+            "new.example.com".to_string(),
+            vec![Ipv4Addr::new(203, 0, 113, 1)],
+        ))
+        .unwrap();
+        assert_eq!(
+            a_records(&p, "new.example.com"),
+            vec![(Ipv4Addr::new(203, 0, 113, 1), Some(60))]
+        );
+
+        p.apply(&Action::DeleteAndRelease("new.example.com".to_string()))
+            .unwrap();
+        assert!(a_records(&p, "new.example.com").is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), ZONE);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn manages_txt_records() {
+        let path = path("txt");
+        let p = provider(&path);
+
+        p.create_txt_record("www.example.com".to_string(), "owner;rec: A".to_string())
+            .unwrap();
+        assert!(p.records().unwrap().contains(&DnsRecord {
+            ttl: Some(3600),
+            ..DnsRecord::new(
+                "www.example.com",
+                RecordContent::Txt("owner;rec: A".to_string())
+            )
+        }));
+
+        p.delete_txt_record("www.example.com".to_string(), "owner;rec: A".to_string())
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), ZONE);
+        p.delete_txt_record("www.example.com".to_string(), "owner;rec: A".to_string())
+            .unwrap_err();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_domains_outside_zone() {
+        let path = path("outside");
+        let p = provider(&path);
+
+        p.apply(&Action::DeleteAndRelease("www.example.org".to_string()))
+            .unwrap_err();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn does_not_write_in_dry_run() {
+        let path = path("dry-run");
+        let mut p = provider(&path);
+        p.enable_dry_run().unwrap();
+
+        p.apply(&Action::DeleteAndRelease("www.example.com".to_string()))
+            .unwrap();
+        p.create_txt_record("www.example.com".to_string(), "owner".to_string())
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), ZONE);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn applies_concurrently_without_losing_changes() {
+        let path = path("concurrent");
+        let p = provider(&path);
+
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let p = &p;
+                s.spawn(move || {
+                    p.apply(&Action::ClaimAndUpdate(
+                        format!("host{}.example.com", i),
+                        vec![Ipv4Addr::new(203, 0, 113, i)],
+                    ))
+                    .unwrap();
+                    p.create_txt_record(format!("host{}.example.com", i), "owner".to_string())
+                        .unwrap();
+                });
+            }
+        });
+
+        for i in 0..8 {
+            assert_eq!(
+                a_records(&p, &format!("host{}.example.com", i)),
+                vec![(Ipv4Addr::new(203, 0, 113, i), Some(3600))]
+            );
+        }
+        assert_eq!(p.records().unwrap().len(), 2 + 2 * 8);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::fmt::Display;
+
+use crate::provider::{normalize_domain, DnsRecord, RecordContent, TTL};
+
+/// Maximum length of a single character-string in a TXT record
+const TXT_CHUNK_SIZE: usize = 255;
+
+/// A parsed BIND-style zone file.
+///
+/// Only A, AAAA, TXT and CNAME records are parsed, all other lines (comments, directives, SOA, NS, MX, ...) are kept as-is.
+/// Records that are not changed are written back exactly as they were read, so that rewriting a zone file
+/// only touches the records that were actually modified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneFile {
+    entries: Vec<Entry>,
+    default_ttl: Option<TTL>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// An unmanaged entry that is written back unchanged.
+    /// `owner` is set for resource records, which may inherit their owner from the previous record if `inherits_owner` is set
+    Verbatim {
+        text: String,
+        owner: Option<String>,
+        inherits_owner: bool,
+    },
+    /// A managed record. `text` contains the original text of the record, or `None` for new records
+    Record {
+        record: DnsRecord,
+        text: Option<String>,
+        inherits_owner: bool,
+    },
+}
+
+impl ZoneFile {
+    /// Parse the content of a zone file. `origin` is used for relative names until a `$ORIGIN` directive is found
+    pub fn parse(content: &str, origin: &str) -> Result<ZoneFile, String> {
+        let mut origin = normalize_domain(origin.trim_end_matches('.'));
+        let mut default_ttl = None;
+        let mut last_owner: Option<String> = None;
+        let mut entries = Vec::new();
+
+        let mut lines = content.lines();
+        while let Some(first) = lines.next() {
+            // Entries may span multiple lines within parentheses
+            let mut text = first.to_owned();
+            let (mut data, mut depth) = strip_comment(first);
+            while depth > 0 {
+                let Some(next) = lines.next() else {
+                    return Err(format!("Unbalanced parentheses in entry: {}", text));
+                };
+                let (next_data, next_depth) = strip_comment(next);
+                text.push('\n');
+                text.push_str(next);
+                data.push(' ');
+                data.push_str(&next_data);
+                depth += next_depth;
+            }
+
+            let tokens = tokenize(&data)?;
+            if tokens.is_empty() {
+                entries.push(Entry::Verbatim {
+                    text,
+                    owner: None,
+                    inherits_owner: false,
+                });
+                continue;
+            }
+            if tokens[0].value.starts_with('$') && !tokens[0].quoted {
+                match (tokens[0].value.to_uppercase().as_str(), tokens.get(1)) {
+                    ("$ORIGIN", Some(name)) => origin = absolute_name(&name.value, &origin),
+                    ("$TTL", Some(ttl)) => {
+                        default_ttl = Some(
+                            parse_ttl(&ttl.value).ok_or(format!("Invalid $TTL: {}", ttl.value))?,
+                        )
+                    }
+                    ("$INCLUDE", _) => return Err("$INCLUDE directives are not supported".into()),
+                    _ => return Err(format!("Invalid directive: {}", text)),
+                }
+                entries.push(Entry::Verbatim {
+                    text,
+                    owner: None,
+                    inherits_owner: false,
+                });
+                continue;
+            }
+
+            let inherits_owner = first.starts_with(char::is_whitespace);
+            let mut tokens = tokens.into_iter().peekable();
+            let owner = if inherits_owner {
+                last_owner
+                    .clone()
+                    .ok_or(format!("Record without owner name: {}", text))?
+            } else {
+                // Checked above that there is at least one token
+                absolute_name(&tokens.next().unwrap().value, &origin)
+            };
+            last_owner = Some(owner.clone());
+
+            // TTL and class may be given in any order
+            let mut ttl = None;
+            while let Some(token) = tokens.peek() {
+                let is_class =
+                    ["IN", "CH", "HS", "CS"].contains(&token.value.to_uppercase().as_str());
+                match parse_ttl(&token.value) {
+                    Some(t) => ttl = Some(t),
+                    None if is_class => (),
+                    None => break,
+                }
+                tokens.next();
+            }
+            let Some(rtype) = tokens.next() else {
+                return Err(format!("Record without type: {}", text));
+            };
+            let rdata = tokens.collect::<Vec<_>>();
+
+            let content = match rtype.value.to_uppercase().as_str() {
+                "A" => RecordContent::A(
+                    single(&rdata)
+                        .and_then(|a| a.parse().ok())
+                        .ok_or(format!("Invalid A record: {}", text))?,
+                ),
+                "AAAA" => RecordContent::Aaaa(
+                    single(&rdata)
+                        .and_then(|a| a.parse().ok())
+                        .ok_or(format!("Invalid AAAA record: {}", text))?,
+                ),
+                "CNAME" => RecordContent::Cname(absolute_name(
+                    single(&rdata).ok_or(format!("Invalid CNAME record: {}", text))?,
+                    &origin,
+                )),
+                "TXT" if !rdata.is_empty() => {
+                    RecordContent::Txt(rdata.iter().map(|t| t.value.as_str()).collect())
+                }
+                "TXT" => return Err(format!("Invalid TXT record: {}", text)),
+                _ => {
+                    entries.push(Entry::Verbatim {
+                        text,
+                        owner: Some(owner),
+                        inherits_owner,
+                    });
+                    continue;
+                }
+            };
+            entries.push(Entry::Record {
+                record: DnsRecord {
+                    ttl: ttl.or(default_ttl),
+                    ..DnsRecord::new(owner, content)
+                },
+                text: Some(text),
+                inherits_owner,
+            });
+        }
+        Ok(ZoneFile {
+            entries,
+            default_ttl,
+        })
+    }
+
+    /// The TTL set by the last `$TTL` directive of the file, if any
+    pub fn default_ttl(&self) -> Option<TTL> {
+        self.default_ttl
+    }
+
+    /// All A, AAAA, TXT and CNAME records in the zone
+    pub fn records(&self) -> Vec<DnsRecord> {
+        self.entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::Record { record, .. } => Some(record.clone()),
+                Entry::Verbatim { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Add a new record at the end of the zone
+    pub fn add(&mut self, record: DnsRecord) {
+        self.entries.push(Entry::Record {
+            record,
+            text: None,
+            inherits_owner: false,
+        });
+    }
+
+    /// Remove all records matching the predicate. Returns the number of removed records
+    pub fn remove(&mut self, predicate: impl Fn(&DnsRecord) -> bool) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| match e {
+            Entry::Record { record, .. } => !predicate(record),
+            Entry::Verbatim { .. } => true,
+        });
+        before - self.entries.len()
+    }
+}
+
+impl Display for ZoneFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut last_owner: Option<&str> = None;
+        for entry in &self.entries {
+            match entry {
+                Entry::Verbatim {
+                    text,
+                    owner,
+                    inherits_owner,
+                } => {
+                    // The record this entry inherited its owner from may have been removed
+                    if *inherits_owner && owner.as_deref() != last_owner {
+                        write!(f, "{}.", owner.as_deref().unwrap_or_default())?;
+                    }
+                    writeln!(f, "{}", text)?;
+                    if owner.is_some() {
+                        last_owner = owner.as_deref();
+                    }
+                }
+                Entry::Record {
+                    record,
+                    text,
+                    inherits_owner,
+                } => {
+                    match text {
+                        Some(text) => {
+                            if *inherits_owner && Some(record.domain_name.as_str()) != last_owner {
+                                write!(f, "{}.", record.domain_name)?;
+                            }
+                            writeln!(f, "{}", text)?;
+                        }
+                        None => writeln!(f, "{}", render(record))?,
+                    }
+                    last_owner = Some(record.domain_name.as_str());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Render a record as a single zone file line, using absolute names
+fn render(record: &DnsRecord) -> String {
+    let (rtype, rdata) = match &record.content {
+        RecordContent::A(a) => ("A", a.to_string()),
+        RecordContent::Aaaa(aaaa) => ("AAAA", aaaa.to_string()),
+        RecordContent::Cname(cname) => ("CNAME", format!("{}.", cname)),
+        RecordContent::Txt(txt) => ("TXT", quote_txt(txt)),
+    };
+    match record.ttl {
+        Some(ttl) => format!("{}.\t{}\tIN\t{}\t{}", record.domain_name, ttl, rtype, rdata),
+        None => format!("{}.\tIN\t{}\t{}", record.domain_name, rtype, rdata),
+    }
+}
+
+/// Quote TXT content, splitting it into multiple strings if it exceeds the maximum length of a single string
+fn quote_txt(txt: &str) -> String {
+    let chars = txt.chars().collect::<Vec<_>>();
+    if chars.is_empty() {
+        return "\"\"".to_string();
+    }
+    chars
+        .chunks(TXT_CHUNK_SIZE)
+        .map(|chunk| {
+            let escaped = chunk
+                .iter()
+                .map(|c| match c {
+                    '"' | '\\' => format!("\\{}", c),
+                    c => c.to_string(),
+                })
+                .collect::<String>();
+            format!("\"{}\"", escaped)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Resolve a possibly relative name against the origin
+fn absolute_name(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_owned()
+    } else if let Some(absolute) = name.strip_suffix('.') {
+        normalize_domain(absolute)
+    } else {
+        normalize_domain(&format!("{}.{}", name, origin))
+    }
+}
+
+/// Parse a TTL, either in seconds or with BIND-style units (e.g. `1h30m`)
+fn parse_ttl(ttl: &str) -> Option<TTL> {
+    if !ttl.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(seconds) = ttl.parse() {
+        return Some(seconds);
+    }
+    let mut total: TTL = 0;
+    let mut number = String::new();
+    for c in ttl.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<TTL>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    number.is_empty().then_some(total)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    value: String,
+    quoted: bool,
+}
+
+fn single(rdata: &[Token]) -> Option<&str> {
+    match rdata {
+        [token] => Some(token.value.as_str()),
+        _ => None,
+    }
+}
+
+/// Remove the comment from a line. Returns the remaining text and the change in parenthesis depth
+fn strip_comment(line: &str) -> (String, i32) {
+    let mut stripped = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => break,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            _ => (),
+        }
+        stripped.push(c);
+    }
+    (stripped, depth)
+}
+
+/// Split an entry (without comments) into tokens, removing quotes and parentheses
+fn tokenize(data: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = data.chars();
+    let mut current: Option<Token> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(format!("Unterminated string: {}", data)),
+                    }
+                }
+                tokens.extend(current.take());
+                tokens.push(Token {
+                    value,
+                    quoted: true,
+                });
+            }
+            c if c.is_whitespace() || c == '(' || c == ')' => tokens.extend(current.take()),
+            c => current
+                .get_or_insert(Token {
+                    value: String::new(),
+                    quoted: false,
+                })
+                .value
+                .push(c),
+        }
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::{parse_ttl, ZoneFile};
+    use crate::provider::{DnsRecord, RecordContent};
+
+    const ZONE: &str = r#"$ORIGIN example.com.
+$TTL 3600
+@	IN	SOA	ns1.example.com. admin.example.com. (
+		2024010101 ; serial
+		3600 900 604800 300 )
+	IN	NS	ns1.example.com.
+; web server
+www	300	IN	A	10.1.1.1
+	IN	AAAA	fd42::1
+	IN	MX	10 mail
+alias	CNAME	www
+@	TXT	"v=spf1 -all" ; inline comment
+txt.example.com.	IN	TXT	"split " "in two" "with \"quotes\"; and semicolons"
+"#;
+
+    fn record(name: &str, ttl: u32, content: RecordContent) -> DnsRecord {
+        DnsRecord {
+            ttl: Some(ttl),
+            ..DnsRecord::new(name.to_string(), content)
+        }
+    }
+
+    #[test]
+    fn parses_managed_records() {
+        let zone = ZoneFile::parse(ZONE, "example.com").unwrap();
+
+        assert_eq!(zone.default_ttl(), Some(3600));
+        assert_eq!(
+            zone.records(),
+            vec![
+                record(
+                    "www.example.com",
+                    300,
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 1))
+                ),
+                record(
+                    "www.example.com",
+                    3600,
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 0, 0, 0, 0, 0, 0, 1))
+                ),
+                record(
+                    "alias.example.com",
+                    3600,
+                    RecordContent::Cname("www.example.com".to_string())
+                ),
+                record(
+                    "example.com",
+                    3600,
+                    RecordContent::Txt("v=spf1 -all".to_string())
+                ),
+                record(
+                    "txt.example.com",
+                    3600,
+                    RecordContent::Txt("split in twowith \"quotes\"; and semicolons".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_unchanged_zone() {
+        let zone = ZoneFile::parse(ZONE, "example.com").unwrap();
+        assert_eq!(zone.to_string(), ZONE);
+    }
+
+    #[test]
+    fn round_trips_added_records() {
+        let mut zone = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let added = vec![
+            record(
+                "new.example.com",
+                60,
+                RecordContent::A(Ipv4Addr::new(203, 0, 113, 1)),
+            ),
+            record(
+                "new.example.com",
+                60,
+                RecordContent::Txt("clouddns_nat_\"tenant\";rec: A".to_string()),
+            ),
+            record("long.example.com", 60, RecordContent::Txt("x".repeat(300))),
+            record(
+                "other.example.com",
+                60,
+                RecordContent::Cname("www.example.org".to_string()),
+            ),
+        ];
+        for r in &added {
+            zone.add(r.clone());
+        }
+
+        let reparsed = ZoneFile::parse(&zone.to_string(), "example.com").unwrap();
+        assert_eq!(reparsed.records(), zone.records());
+        assert!(reparsed.records().ends_with(&added));
+    }
+
+    #[test]
+    fn keeps_inherited_owners_when_removing_records() {
+        let mut zone = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let removed = zone.remove(|r| r.content == RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)));
+        assert_eq!(removed, 1);
+
+        let serialized = zone.to_string();
+        assert!(!serialized.contains("10.1.1.1"));
+        assert!(serialized.contains("www.example.com.\tIN\tAAAA\tfd42::1"));
+        let reparsed = ZoneFile::parse(&serialized, "example.com").unwrap();
+        assert_eq!(reparsed.records(), zone.records());
+        assert!(serialized.contains("\tIN\tMX\t10 mail"));
+    }
+
+    #[test]
+    fn uses_origin_without_directive() {
+        let zone = ZoneFile::parse("www 60 IN A 10.1.1.1\n", "example.com.").unwrap();
+        assert_eq!(zone.default_ttl(), None);
+        assert_eq!(
+            zone.records(),
+            vec![record(
+                "www.example.com",
+                60,
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 1))
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_zones() {
+        ZoneFile::parse("www IN A not-an-ip\n", "example.com").unwrap_err();
+        ZoneFile::parse("\tIN A 10.1.1.1\n", "example.com").unwrap_err();
+        ZoneFile::parse("@ IN SOA ns1 admin ( 1 2 3\n", "example.com").unwrap_err();
+        ZoneFile::parse("www IN TXT \"unterminated\n", "example.com").unwrap_err();
+        ZoneFile::parse("$INCLUDE other.zone\n", "example.com").unwrap_err();
+    }
+
+    #[test]
+    fn parses_ttl_units() {
+        assert_eq!(parse_ttl("300"), Some(300));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("IN"), None);
+        assert_eq!(parse_ttl("10x"), None);
+    }
+}
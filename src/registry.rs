@@ -4,15 +4,29 @@
 //!
 //! All registries must implement the [`ARegistry`] trait. Currently, the following registries are available:
 //! - [`TxtRegistry`]: Manages ownership via TXT records in the same zone as the A records
+//! - [`InMemoryRegistry`]: Tracks ownership in memory only, without writing anything
+//! - [`FileRegistry`]: Stores ownership in a JSON file on disk
+//! - `SqliteRegistry`: Stores ownership in a local SQLite database, shared safely between multiple processes (requires the `sqlite` feature)
+mod file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod txt;
 
 // Expose individual registry types for creation
-pub use txt::TxtRegistry;
+pub use file::{FileRegistry, FileRegistryConfig};
+pub use memory::InMemoryRegistry;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteRegistry, SqliteRegistryConfig};
+pub use txt::{OwnershipBackend, TxtRegistry, TxtRegistryConfig, DEFAULT_TXT_PREFIX};
 
 use itertools::Itertools;
 #[cfg(test)]
 use mockall::automock;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::SystemTime,
+};
 use thiserror::Error;
 
 /// Tracks the ownership of A records for [`Domain`]s.
@@ -24,6 +38,13 @@ pub trait ARegistry {
 
     /// Set the registry tenant name
     fn set_tenant(&mut self, tenant: String);
+    /// Re-read the current state of all domains from the registry's backend, discarding any cached state.
+    /// This picks up ownership changes made externally (e.g. manually edited ownership records) since the registry was created.
+    ///
+    /// Registries that do not cache any state may use the default implementation, which does nothing.
+    fn refresh(&mut self) -> Result<(), RegistryError> {
+        Ok(())
+    }
     //// Returns all domains that the registry knows about
     fn all_domains(&self) -> Vec<Domain>;
     /// Returns domains currently owned by this registry
@@ -40,6 +61,17 @@ pub trait ARegistry {
             .filter(|d| matches!(d.ownership(), Ownership::Taken))
             .collect_vec()
     }
+    /// Returns domains currently owned by another registry, along with the tenants that own them.
+    /// Domains without any owner are managed externally or excluded from management (e.g. wildcards),
+    /// domains with multiple owners have conflicting ownership records.
+    ///
+    /// The default implementation returns [`ARegistry::taken_domains()`] without any owners.
+    fn taken_domains_with_owners(&self) -> Vec<(Domain, Vec<String>)> {
+        self.taken_domains()
+            .into_iter()
+            .map(|d| (d, Vec::new()))
+            .collect_vec()
+    }
     /// Returns domains currently not owned by any registry
     fn available_domains(&self) -> Vec<Domain> {
         self.all_domains()
@@ -52,19 +84,88 @@ pub trait ARegistry {
     /// Returns a result containing [`Ok`] if the domain is claimed or a [`RegistryError`] if the domain could not be claimed.
     #[allow(clippy::needless_lifetimes)] // needed for mockall
     fn claim(&mut self, name: &str) -> Result<(), RegistryError>;
+    /// Claim a domain even if it is owned by other tenants, removing their ownership.
+    /// This is meant for recovering from conflicting ownership (e.g. after a botched migration) and is dangerous:
+    /// the previous owners are not notified and will fight over the domain if they are still running.
+    ///
+    /// Domains that are not taken are claimed as with [`ARegistry::claim()`].
+    /// The default implementation returns an error, as most registries can't override ownership.
+    #[allow(clippy::needless_lifetimes)] // needed for mockall
+    fn claim_force(&mut self, name: &str) -> Result<(), RegistryError> {
+        Err(RegistryError::ClaimError {
+            domain: name.to_string(),
+            reason: "Force-claiming is not supported by this registry".to_string(),
+        })
+    }
     /// Attempt to release a claimed domain with the registry's backend.
     /// Returns a result containing [`Ok`] if the domain is released or a [`RegistryError`] if the domain could not be released.
     #[allow(clippy::needless_lifetimes)] // needed for mockall
     fn release(&mut self, name: &str) -> Result<(), RegistryError>;
+    /// Release all domains currently owned by this registry, e.g. when decommissioning an instance.
+    /// Returns the names of all released domains, or an error describing every domain that could not be released.
+    /// Domains that were released successfully stay released, even if releasing others fails.
+    ///
+    /// The default implementation calls [`ARegistry::release()`] for each domain in [`ARegistry::owned_domains()`].
+    fn release_all(&mut self) -> Result<Vec<String>, RegistryError> {
+        let names = self.owned_domains().into_iter().map(|d| d.name).collect();
+        release_each(self, names)
+    }
+    /// Release all owned domains that no longer have any A records, e.g. because they were deleted out-of-band.
+    /// This removes ownership records that were left behind, so that the domains are available again.
+    /// Returns the names of all released domains, with the same error handling as [`ARegistry::release_all()`].
+    ///
+    /// The default implementation calls [`ARegistry::release()`] for each such domain in [`ARegistry::owned_domains()`].
+    fn prune_orphans(&mut self) -> Result<Vec<String>, RegistryError> {
+        let names = self
+            .owned_domains()
+            .into_iter()
+            .filter(|d| d.a.is_empty())
+            .map(|d| d.name)
+            .collect();
+        release_each(self, names)
+    }
+}
+
+/// Release each of the given domains, continuing after failures.
+/// Returns the names of all released domains, or an error describing every domain that could not be released
+fn release_each<R: ARegistry + ?Sized>(
+    registry: &mut R,
+    names: Vec<String>,
+) -> Result<Vec<String>, RegistryError> {
+    let mut released = Vec::new();
+    let mut failures = Vec::new();
+    for name in names {
+        match registry.release(&name) {
+            Ok(_) => released.push(name),
+            Err(e) => failures.push(e.to_string()),
+        }
+    }
+    if failures.is_empty() {
+        Ok(released)
+    } else {
+        Err(RegistryError::Internal(format!(
+            "Released {} domain(s), but encountered errors: {}",
+            released.len(),
+            failures.join(", ")
+        )))
+    }
 }
 
 /// Represents a single FQDN and its associated DNS records, as returned by a [`ARegistry`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Domain {
     pub name: String,
+    /// Comments attached to any of this domains records (see [`crate::provider::DnsRecord::comment`])
+    pub comments: Vec<String>,
     pub a: Vec<Ipv4Addr>,
+    /// The most recent modification time of any of this domains A records, if known
+    pub a_modified: Option<SystemTime>,
+    /// The highest TTL of any of this domains A records, if known
+    pub a_ttl: Option<crate::provider::TTL>,
     pub aaaa: Vec<Ipv6Addr>,
     pub txt: Vec<String>,
+    /// The target of this domains CNAME record, if it has one. Domains with a CNAME can't have any other records
+    pub cname: Option<String>,
     // Need to ble able to create domains with ownership in tests
     #[cfg(test)]
     pub a_ownership: Ownership,
@@ -75,6 +176,11 @@ impl Domain {
     pub fn ownership(&self) -> Ownership {
         self.a_ownership
     }
+
+    /// Whether this is a wildcard domain such as `*.example.com`
+    pub fn is_wildcard(&self) -> bool {
+        self.name == "*" || self.name.starts_with("*.")
+    }
 }
 
 /// Represents the current ownership status of a domain.
@@ -0,0 +1,452 @@
+//! Manage A record ownership in a JSON file on disk.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use log::{debug, info};
+
+use super::{txt::util::insert_rec_into_d, ARegistry, Domain, Ownership, RegistryError};
+use crate::provider::{normalize_domain, strip_root_dot, DnsProvider, RecordContent};
+
+/// Owned domains, keyed by tenant
+type OwnershipMap = BTreeMap<String, BTreeSet<String>>;
+
+/// The FileRegistry stores the ownership of domains in a local JSON file instead of the DNS zone itself.
+///
+/// The current A and AAAA records are still read from a provider, but ownership is determined by the contents of the file only.
+/// The file maps each tenant to the list of domains it owns, so that multiple instances may share a single file:
+/// ```json
+/// {"my-tenant": ["a.example.com", "b.example.com"]}
+/// ```
+///
+/// Domains owned by another tenant are taken. Domains not listed in the file are available if they don't have any A records.
+/// Every claim or release is written to disk immediately. The file is replaced atomically, so that it is never left in a partially written state.
+/// Before each write, the file is read again under an exclusive lock on `<path>.lock` and only our own change is applied to it,
+/// so that changes of other instances are never overwritten. Claiming a domain fails if another tenant claimed it in the meantime.
+///
+/// Use the [`FileRegistry::from_config()`] function to create a new registry.
+#[non_exhaustive]
+pub struct FileRegistry<'a> {
+    domains: HashMap<String, Domain>,
+    ownership: OwnershipMap,
+    config: FileRegistryConfig,
+    provider: &'a dyn DnsProvider,
+    dry_run: bool,
+}
+
+/// Configuration for a [`FileRegistry`]. Must be supplied when creating a registry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileRegistryConfig {
+    /// Unique identifier (tenant) of this registry instance, used as the key for our domains in the file
+    pub tenant: String,
+    /// Path to the ownership file. The file is created if it does not exist yet
+    pub path: PathBuf,
+}
+
+impl FileRegistry<'_> {
+    /// Create a new [`FileRegistry`] from the given configuration.
+    /// The provider is used to retrieve all records during creation and on [`ARegistry::refresh()`].
+    /// Returns an error if the ownership file can't be read or created.
+    pub fn from_config(
+        config: FileRegistryConfig,
+        provider: &dyn DnsProvider,
+    ) -> Result<Box<dyn ARegistry + '_>, RegistryError> {
+        let _lock = lock_ownership(&config.path)?;
+        let ownership = match read_ownership(&config.path)? {
+            Some(o) => o,
+            None => {
+                debug!("Creating new ownership file {}", config.path.display());
+                let o = OwnershipMap::new();
+                write_ownership(&config.path, &o)?;
+                o
+            }
+        };
+        let mut registry = FileRegistry {
+            domains: HashMap::new(),
+            ownership,
+            config,
+            provider,
+            dry_run: false,
+        };
+        registry.load_domains()?;
+        Ok(Box::new(registry))
+    }
+
+    /// Read all records from the provider and determine their ownership from the ownership file
+    fn load_domains(&mut self) -> Result<(), RegistryError> {
+        let mut domains: HashMap<String, Domain> = HashMap::new();
+        for rec in &self.provider.records().map_err(|e| e.to_string())? {
+            if let RecordContent::Txt(_) = rec.content {
+                // Ownership is not stored in TXT records, so we don't care about them
+                continue;
+            }
+            let name = normalize_domain(strip_root_dot(&rec.domain_name));
+            let d = domains.entry(name.to_owned()).or_insert_with(|| Domain {
+                name,
+                a_ttl: None,
+                a_modified: None,
+                comments: Vec::new(),
+                a: Vec::new(),
+                aaaa: Vec::new(),
+                txt: Vec::new(),
+                cname: None,
+                a_ownership: Ownership::Taken, // Safe default, overwritten below
+            });
+            insert_rec_into_d(rec, d);
+        }
+        for domain in domains.values_mut() {
+            domain.a_ownership = self.determine_ownership(domain);
+        }
+        self.domains = domains;
+        Ok(())
+    }
+
+    /// Determine the current ownership status for a given domain
+    fn determine_ownership(&self, domain: &Domain) -> Ownership {
+        let owners = self
+            .ownership
+            .iter()
+            .filter(|(_, domains)| domains.contains(&domain.name))
+            .map(|(tenant, _)| tenant)
+            .collect_vec();
+        match owners.as_slice() {
+            [] if domain.a.is_empty() => Ownership::Available,
+            [] => Ownership::Taken,
+            [owner] if **owner == self.config.tenant => Ownership::Owned,
+            _ => Ownership::Taken,
+        }
+    }
+
+    /// Update the ownership of a domain and persist the change to disk.
+    /// The file is read again before writing, so that we only change the ownership of this domain
+    fn set_owned(&mut self, name: &str, owned: bool) -> Result<(), String> {
+        // Held until the change has been written
+        let _lock = match self.dry_run {
+            true => None,
+            false => Some(lock_ownership(&self.config.path).map_err(|e| e.to_string())?),
+        };
+        let mut ownership = match self.dry_run {
+            true => self.ownership.clone(),
+            false => read_ownership(&self.config.path)
+                .map_err(|e| e.to_string())?
+                .unwrap_or_default(),
+        };
+        if owned {
+            let other_owners = ownership
+                .iter()
+                .filter(|(tenant, domains)| {
+                    **tenant != self.config.tenant && domains.contains(name)
+                })
+                .map(|(tenant, _)| tenant)
+                .collect_vec();
+            if !other_owners.is_empty() {
+                return Err(format!("Claimed by other instance: {:?}", other_owners));
+            }
+        }
+        let tenant_domains = ownership.entry(self.config.tenant.to_owned()).or_default();
+        if owned {
+            tenant_domains.insert(name.to_owned());
+        } else {
+            tenant_domains.remove(name);
+        }
+        if tenant_domains.is_empty() {
+            ownership.remove(&self.config.tenant);
+        }
+
+        if !self.dry_run {
+            write_ownership(&self.config.path, &ownership).map_err(|e| e.to_string())?;
+        }
+        self.ownership = ownership;
+        Ok(())
+    }
+}
+
+impl ARegistry for FileRegistry<'_> {
+    fn enable_dry_run(&mut self) -> Result<(), RegistryError> {
+        self.dry_run = true;
+        Ok(())
+    }
+
+    fn set_tenant(&mut self, tenant: String) {
+        self.config.tenant = tenant;
+    }
+
+    fn refresh(&mut self) -> Result<(), RegistryError> {
+        if !self.dry_run {
+            self.ownership = read_ownership(&self.config.path)?.unwrap_or_default();
+        }
+        self.load_domains()?;
+        debug!(
+            "Refreshed registry, now tracking {} domains",
+            self.domains.len()
+        );
+        Ok(())
+    }
+
+    fn all_domains(&self) -> Vec<Domain> {
+        self.domains.values().cloned().collect_vec()
+    }
+
+    fn claim(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(ownership) = self.domains.get(name).map(|d| d.a_ownership) else {
+            return Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match ownership {
+            Ownership::Owned => {
+                info!(
+                    "Attempted to claim domain {}, but it is already owned by us. Ignoring",
+                    name
+                );
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Owned by other instance".to_string(),
+            }),
+            Ownership::Available => {
+                self.set_owned(name, true)
+                    .map_err(|e| RegistryError::ClaimError {
+                        domain: name.to_string(),
+                        reason: e,
+                    })?;
+                if let Some(d) = self.domains.get_mut(name) {
+                    d.a_ownership = Ownership::Owned;
+                }
+                debug!("Successfully claimed domain {}", name);
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(ownership) = self.domains.get(name).map(|d| d.a_ownership) else {
+            return Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match ownership {
+            Ownership::Owned => {
+                self.set_owned(name, false)
+                    .map_err(|e| RegistryError::ReleaseError {
+                        domain: name.to_string(),
+                        reason: e,
+                    })?;
+                if let Some(d) = self.domains.get_mut(name) {
+                    d.a_ownership = Ownership::Available;
+                }
+                debug!("Sucessfully released domain {}", name);
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Owned by other instance".to_string(),
+            }),
+            Ownership::Available => {
+                info!("Attempted to release domain {}, but it is already not owned by anyone. Ignoring", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Read the ownership file. Returns `None` if the file does not exist
+fn read_ownership(path: &Path) -> Result<Option<OwnershipMap>, RegistryError> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(format!("Could not read ownership file {}: {}", path.display(), e).into())
+        }
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("Invalid ownership file {}: {}", path.display(), e).into())
+}
+
+/// Acquire an exclusive lock for modifying the ownership file, which is held until the returned file is dropped.
+/// A separate lock file is used, as the ownership file itself is replaced on every write
+fn lock_ownership(path: &Path) -> Result<File, RegistryError> {
+    let mut lock_name = path.file_name().unwrap_or_default().to_owned();
+    lock_name.push(".lock");
+    let lock_path = path.with_file_name(lock_name);
+
+    File::create(&lock_path)
+        .and_then(|f| f.lock().map(|_| f))
+        .map_err(|e| format!("Could not lock ownership file {}: {}", path.display(), e).into())
+}
+
+/// Write the ownership file by writing to a temporary file first and then moving it into place
+fn write_ownership(path: &Path, ownership: &OwnershipMap) -> Result<(), RegistryError> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let content = serde_json::to_string_pretty(ownership)
+        .map_err(|e| format!("Could not serialize ownership: {}", e))?;
+    fs::write(&tmp_path, content)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| format!("Could not write ownership file {}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{Ipv4Addr, Ipv6Addr},
+        path::PathBuf,
+    };
+
+    use crate::provider::{DnsRecord, MockDnsProvider, RecordContent};
+
+    use super::{FileRegistry, FileRegistryConfig};
+
+    fn provider() -> MockDnsProvider {
+        let mut mock = MockDnsProvider::new();
+        mock.expect_records().returning(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "available.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+                ),
+                DnsRecord::new(
+                    "other-owner.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 3)),
+                ),
+            ])
+        });
+        mock
+    }
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "clouddns-nat-helper-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn config(tenant: &str, path: &PathBuf) -> FileRegistryConfig {
+        FileRegistryConfig {
+            tenant: tenant.to_string(),
+            path: path.to_owned(),
+        }
+    }
+
+    #[test]
+    fn creates_missing_file() {
+        let path = path("create");
+        let provider = provider();
+
+        let rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+
+        assert!(path.exists());
+        assert!(rg.owned_domains().is_empty());
+        assert_eq!(rg.available_domains().len(), 1);
+        assert_eq!(rg.taken_domains().len(), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn persists_ownership_across_instances() {
+        let path = path("persist");
+        let provider = provider();
+
+        let mut rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        rg.claim("available.example.com").unwrap();
+        drop(rg);
+
+        let mut rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        assert_eq!(rg.owned_domains()[0].name, "available.example.com");
+        // Another tenant sees the domain as taken
+        let other = FileRegistry::from_config(config("other", &path), &provider).unwrap();
+        assert_eq!(other.taken_domains().len(), 3);
+
+        rg.release("available.example.com").unwrap();
+        drop(rg);
+        let rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        assert!(rg.owned_domains().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn respects_other_tenants() {
+        let path = path("tenants");
+        fs::write(
+            &path,
+            r#"{"other": ["other-owner.example.com"], "tenant": ["taken.example.com"]}"#,
+        )
+        .unwrap();
+        let provider = provider();
+
+        let mut rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+
+        assert_eq!(rg.owned_domains()[0].name, "taken.example.com");
+        rg.claim("other-owner.example.com").unwrap_err();
+        rg.release("other-owner.example.com").unwrap_err();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn does_not_write_in_dry_run() {
+        let path = path("dryrun");
+        let provider = provider();
+
+        let mut rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        rg.enable_dry_run().unwrap();
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains().len(), 1);
+        drop(rg);
+
+        let rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        assert!(rg.owned_domains().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn merges_changes_of_other_instances() {
+        let path = path("merge");
+        fs::write(&path, r#"{"other": ["other-owner.example.com"]}"#).unwrap();
+        let provider = provider();
+
+        let mut rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        let mut other = FileRegistry::from_config(config("other", &path), &provider).unwrap();
+        other.release("other-owner.example.com").unwrap();
+        // Must not restore the released domain of the other instance
+        rg.claim("available.example.com").unwrap();
+        // Already claimed by the first instance, even though it was available when loading
+        other.claim("available.example.com").unwrap_err();
+
+        let rg = FileRegistry::from_config(config("tenant", &path), &provider).unwrap();
+        assert_eq!(rg.owned_domains()[0].name, "available.example.com");
+        let other = FileRegistry::from_config(config("other", &path), &provider).unwrap();
+        assert!(other.owned_domains().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn errors_on_invalid_file() {
+        let path = path("invalid");
+        fs::write(&path, "not json").unwrap();
+        let provider = provider();
+
+        assert!(FileRegistry::from_config(config("tenant", &path), &provider).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
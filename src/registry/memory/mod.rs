@@ -0,0 +1,240 @@
+//! Manage A record ownership in memory, without persisting it anywhere.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use log::{debug, info};
+
+use super::{txt::util::insert_rec_into_d, ARegistry, Domain, Ownership, RegistryError};
+use crate::provider::{normalize_domain, strip_root_dot, DnsProvider};
+
+/// The InMemoryRegistry tracks the ownership of domains in memory only.
+///
+/// On creation, the registry reads all records from a provider once to build its list of domains.
+/// Domains without A records are available, all others are considered taken.
+/// Claiming or releasing a domain only updates the internal state of the registry, no changes are ever made through the provider.
+///
+/// As ownership is lost when the registry is dropped, domains claimed in a previous run are considered taken afterwards.
+/// This makes the registry suitable for single-instance deployments where the records are created on startup, and for tests.
+///
+/// Use the [`InMemoryRegistry::from_provider()`] function to create a new registry.
+#[non_exhaustive]
+pub struct InMemoryRegistry {
+    domains: HashMap<String, Domain>,
+    tenant: String,
+}
+
+impl InMemoryRegistry {
+    /// Create a new [`InMemoryRegistry`], seeded with the current records of the given provider.
+    /// The provider is not used after creation.
+    pub fn from_provider(
+        tenant: String,
+        provider: &dyn DnsProvider,
+    ) -> Result<Box<dyn ARegistry>, RegistryError> {
+        let mut domains: HashMap<String, Domain> = HashMap::new();
+        for rec in &provider.records().map_err(|e| e.to_string())? {
+            let name = normalize_domain(strip_root_dot(&rec.domain_name));
+            let d = domains.entry(name.to_owned()).or_insert_with(|| Domain {
+                name,
+                a_ttl: None,
+                a_modified: None,
+                comments: Vec::new(),
+                a: Vec::new(),
+                aaaa: Vec::new(),
+                txt: Vec::new(),
+                cname: None,
+                a_ownership: Ownership::Taken, // Safe default, overwritten below
+            });
+            insert_rec_into_d(rec, d);
+        }
+        for domain in domains.values_mut() {
+            domain.a_ownership = if domain.a.is_empty() {
+                Ownership::Available
+            } else {
+                Ownership::Taken
+            };
+        }
+
+        Ok(Box::new(InMemoryRegistry { domains, tenant }))
+    }
+}
+
+impl ARegistry for InMemoryRegistry {
+    fn enable_dry_run(&mut self) -> Result<(), RegistryError> {
+        // Nothing is ever written anywhere, so there is no difference to normal operation
+        Ok(())
+    }
+
+    fn set_tenant(&mut self, tenant: String) {
+        self.tenant = tenant;
+    }
+
+    fn all_domains(&self) -> Vec<Domain> {
+        self.domains.values().cloned().collect_vec()
+    }
+
+    fn claim(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(reg_d) = self.domains.get_mut(name) else {
+            return Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match reg_d.a_ownership {
+            Ownership::Owned => {
+                info!(
+                    "Attempted to claim domain {}, but it is already owned by us. Ignoring",
+                    name
+                );
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Owned by someone else".to_string(),
+            }),
+            Ownership::Available => {
+                reg_d.a_ownership = Ownership::Owned;
+                debug!("Claimed domain {} for tenant {}", name, self.tenant);
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(reg_d) = self.domains.get_mut(name) else {
+            return Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match reg_d.a_ownership {
+            Ownership::Owned => {
+                reg_d.a_ownership = Ownership::Available;
+                debug!("Released domain {} for tenant {}", name, self.tenant);
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Owned by someone else".to_string(),
+            }),
+            Ownership::Available => {
+                info!("Attempted to release domain {}, but it is already not owned by anyone. Ignoring", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::provider::{DnsRecord, MockDnsProvider, RecordContent};
+
+    use super::InMemoryRegistry;
+
+    fn provider() -> MockDnsProvider {
+        let mut mock = MockDnsProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "available.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+                ),
+            ])
+        });
+        // No expectations for apply(): the registry must never write anything
+        mock
+    }
+
+    #[test]
+    fn seeds_domains_from_provider() {
+        let rg = InMemoryRegistry::from_provider("tenant".to_string(), &provider()).unwrap();
+
+        assert_eq!(rg.all_domains().len(), 2);
+        assert!(rg.owned_domains().is_empty());
+        assert_eq!(rg.available_domains()[0].name, "available.example.com");
+        assert_eq!(rg.taken_domains()[0].name, "taken.example.com");
+    }
+
+    #[test]
+    fn merges_domains_with_trailing_dot() {
+        let mut mock = MockDnsProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "www.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "www.example.com.",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+                ),
+            ])
+        });
+
+        let rg = InMemoryRegistry::from_provider("tenant".to_string(), &mock).unwrap();
+
+        let domains = rg.all_domains();
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].name, "www.example.com");
+        assert_eq!(domains[0].a, vec![Ipv4Addr::new(10, 1, 1, 2)]);
+        assert_eq!(
+            domains[0].aaaa,
+            vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn claims_and_releases_available_domain() {
+        let mut rg = InMemoryRegistry::from_provider("tenant".to_string(), &provider()).unwrap();
+
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains()[0].name, "available.example.com");
+        // Claiming again is a no-op
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains().len(), 1);
+
+        rg.release("available.example.com").unwrap();
+        assert!(rg.owned_domains().is_empty());
+        assert_eq!(rg.available_domains().len(), 1);
+        // Releasing again is a no-op
+        rg.release("available.example.com").unwrap();
+    }
+
+    #[test]
+    fn releases_all_owned_domains() {
+        let mut rg = InMemoryRegistry::from_provider("tenant".to_string(), &provider()).unwrap();
+        rg.claim("available.example.com").unwrap();
+
+        assert_eq!(
+            rg.release_all().unwrap(),
+            vec!["available.example.com".to_string()]
+        );
+        assert!(rg.owned_domains().is_empty());
+        assert!(rg.release_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_on_taken_domain() {
+        let mut rg = InMemoryRegistry::from_provider("tenant".to_string(), &provider()).unwrap();
+
+        rg.claim("taken.example.com").unwrap_err();
+        rg.release("taken.example.com").unwrap_err();
+        assert_eq!(rg.taken_domains().len(), 1);
+    }
+
+    #[test]
+    fn errors_on_unknown_domain() {
+        let mut rg = InMemoryRegistry::from_provider("tenant".to_string(), &provider()).unwrap();
+
+        rg.claim("unknown.example.com").unwrap_err();
+        rg.release("unknown.example.com").unwrap_err();
+    }
+}
@@ -0,0 +1,359 @@
+//! Manage A record ownership in a local SQLite database.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use itertools::Itertools;
+use log::{debug, info};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use super::{txt::util::insert_rec_into_d, ARegistry, Domain, Ownership, RegistryError};
+use crate::provider::{normalize_domain, strip_root_dot, DnsProvider, RecordContent};
+
+/// The SqliteRegistry stores the ownership of domains in a local SQLite database instead of the DNS zone itself.
+///
+/// The current A and AAAA records are still read from a provider, but ownership is determined by the contents of the database only.
+/// The database contains one row per owned domain with the owning tenant and the time it was claimed.
+/// Claims and releases are performed in a transaction that checks the current owner in the database first,
+/// so that multiple processes on the same host can share one database without racing on ownership.
+///
+/// Domains owned by another tenant are taken. Domains not listed in the database are available if they don't have any A records.
+///
+/// Use the [`SqliteRegistry::from_config()`] function to create a new registry.
+#[non_exhaustive]
+pub struct SqliteRegistry<'a> {
+    domains: HashMap<String, Domain>,
+    conn: Connection,
+    config: SqliteRegistryConfig,
+    provider: &'a dyn DnsProvider,
+    dry_run: bool,
+}
+
+/// Configuration for a [`SqliteRegistry`]. Must be supplied when creating a registry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SqliteRegistryConfig {
+    /// Unique identifier (tenant) of this registry instance, stored with each owned domain
+    pub tenant: String,
+    /// Path to the database file, which is created if it does not exist yet. SQLite URIs (e.g. `file:ownership?mode=memory&cache=shared`) are supported as well
+    pub path: PathBuf,
+}
+
+impl SqliteRegistry<'_> {
+    /// Create a new [`SqliteRegistry`] from the given configuration.
+    /// The provider is used to retrieve all records during creation and on [`ARegistry::refresh()`].
+    /// Returns an error if the database can't be opened or created.
+    pub fn from_config(
+        config: SqliteRegistryConfig,
+        provider: &dyn DnsProvider,
+    ) -> Result<Box<dyn ARegistry + '_>, RegistryError> {
+        let conn = Connection::open(&config.path).map_err(|e| {
+            format!(
+                "Could not open ownership database {}: {}",
+                config.path.display(),
+                e
+            )
+        })?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS ownership (
+                domain TEXT PRIMARY KEY NOT NULL,
+                tenant TEXT NOT NULL,
+                claimed_at INTEGER NOT NULL
+            )",
+        )
+        .map_err(|e| format!("Could not create ownership table: {}", e))?;
+
+        let mut registry = SqliteRegistry {
+            domains: HashMap::new(),
+            conn,
+            config,
+            provider,
+            dry_run: false,
+        };
+        registry.load_domains()?;
+        Ok(Box::new(registry))
+    }
+
+    /// Read all records from the provider and determine their ownership from the database
+    fn load_domains(&mut self) -> Result<(), RegistryError> {
+        let owners = self.read_owners()?;
+        let mut domains: HashMap<String, Domain> = HashMap::new();
+        for rec in &self.provider.records().map_err(|e| e.to_string())? {
+            if let RecordContent::Txt(_) = rec.content {
+                // Ownership is not stored in TXT records, so we don't care about them
+                continue;
+            }
+            let name = normalize_domain(strip_root_dot(&rec.domain_name));
+            let d = domains.entry(name.to_owned()).or_insert_with(|| Domain {
+                name,
+                a_ttl: None,
+                a_modified: None,
+                comments: Vec::new(),
+                a: Vec::new(),
+                aaaa: Vec::new(),
+                txt: Vec::new(),
+                cname: None,
+                a_ownership: Ownership::Taken, // Safe default, overwritten below
+            });
+            insert_rec_into_d(rec, d);
+        }
+        for domain in domains.values_mut() {
+            domain.a_ownership = match owners.get(&domain.name) {
+                None if domain.a.is_empty() => Ownership::Available,
+                None => Ownership::Taken,
+                Some(owner) if *owner == self.config.tenant => Ownership::Owned,
+                Some(_) => Ownership::Taken,
+            };
+        }
+        self.domains = domains;
+        Ok(())
+    }
+
+    /// Read the owner of each domain from the database
+    fn read_owners(&self) -> Result<HashMap<String, String>, RegistryError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT domain, tenant FROM ownership")
+            .map_err(|e| format!("Could not read ownership database: {}", e))?;
+        let owners = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|rows| rows.collect::<Result<HashMap<_, _>, _>>())
+            .map_err(|e| format!("Could not read ownership database: {}", e))?;
+        Ok(owners)
+    }
+
+    /// Store ourselves as the owner of a domain, unless another tenant has claimed it in the meantime
+    fn insert_owner(&mut self, name: &str) -> Result<(), String> {
+        let claimed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // An immediate transaction takes the write lock right away, so no other process can claim the domain between our check and insert
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| e.to_string())?;
+        let owner: Option<String> = tx
+            .query_row(
+                "SELECT tenant FROM ownership WHERE domain = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        match owner {
+            Some(owner) if owner != self.config.tenant => {
+                return Err(format!("Owned by other instance {}", owner))
+            }
+            Some(_) => (),
+            None => {
+                tx.execute(
+                    "INSERT INTO ownership (domain, tenant, claimed_at) VALUES (?1, ?2, ?3)",
+                    params![name, self.config.tenant, claimed_at as i64],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Remove our ownership of a domain. Rows of other tenants are never removed
+    fn delete_owner(&mut self, name: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM ownership WHERE domain = ?1 AND tenant = ?2",
+                params![name, self.config.tenant],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl ARegistry for SqliteRegistry<'_> {
+    fn enable_dry_run(&mut self) -> Result<(), RegistryError> {
+        self.dry_run = true;
+        Ok(())
+    }
+
+    fn set_tenant(&mut self, tenant: String) {
+        self.config.tenant = tenant;
+    }
+
+    fn refresh(&mut self) -> Result<(), RegistryError> {
+        self.load_domains()?;
+        debug!(
+            "Refreshed registry, now tracking {} domains",
+            self.domains.len()
+        );
+        Ok(())
+    }
+
+    fn all_domains(&self) -> Vec<Domain> {
+        self.domains.values().cloned().collect_vec()
+    }
+
+    fn claim(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(ownership) = self.domains.get(name).map(|d| d.a_ownership) else {
+            return Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match ownership {
+            Ownership::Owned => {
+                info!(
+                    "Attempted to claim domain {}, but it is already owned by us. Ignoring",
+                    name
+                );
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ClaimError {
+                domain: name.to_string(),
+                reason: "Owned by other instance".to_string(),
+            }),
+            Ownership::Available => {
+                if !self.dry_run {
+                    self.insert_owner(name)
+                        .map_err(|e| RegistryError::ClaimError {
+                            domain: name.to_string(),
+                            reason: e,
+                        })?;
+                }
+                if let Some(d) = self.domains.get_mut(name) {
+                    d.a_ownership = Ownership::Owned;
+                }
+                debug!("Successfully claimed domain {}", name);
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&mut self, name: &str) -> Result<(), RegistryError> {
+        let Some(ownership) = self.domains.get(name).map(|d| d.a_ownership) else {
+            return Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Not in registry".to_string(),
+            });
+        };
+
+        match ownership {
+            Ownership::Owned => {
+                if !self.dry_run {
+                    self.delete_owner(name)
+                        .map_err(|e| RegistryError::ReleaseError {
+                            domain: name.to_string(),
+                            reason: e,
+                        })?;
+                }
+                if let Some(d) = self.domains.get_mut(name) {
+                    d.a_ownership = Ownership::Available;
+                }
+                debug!("Sucessfully released domain {}", name);
+                Ok(())
+            }
+            Ownership::Taken => Err(RegistryError::ReleaseError {
+                domain: name.to_string(),
+                reason: "Owned by other instance".to_string(),
+            }),
+            Ownership::Available => {
+                info!("Attempted to release domain {}, but it is already not owned by anyone. Ignoring", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        path::PathBuf,
+    };
+
+    use crate::provider::{DnsRecord, MockDnsProvider, RecordContent};
+
+    use super::{SqliteRegistry, SqliteRegistryConfig};
+
+    fn provider() -> MockDnsProvider {
+        let mut mock = MockDnsProvider::new();
+        mock.expect_records().returning(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "available.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+                ),
+            ])
+        });
+        mock
+    }
+
+    /// A shared in-memory database that lives as long as at least one connection to it is open
+    fn config(tenant: &str, db: &str) -> SqliteRegistryConfig {
+        SqliteRegistryConfig {
+            tenant: tenant.to_string(),
+            path: PathBuf::from(format!("file:{}?mode=memory&cache=shared", db)),
+        }
+    }
+
+    #[test]
+    fn claims_and_releases_domains() {
+        let provider = provider();
+
+        let mut rg = SqliteRegistry::from_config(config("tenant", "claim"), &provider).unwrap();
+        assert!(rg.owned_domains().is_empty());
+        assert_eq!(rg.available_domains().len(), 1);
+        assert_eq!(rg.taken_domains().len(), 1);
+
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains()[0].name, "available.example.com");
+        rg.claim("taken.example.com").unwrap_err();
+
+        rg.release("available.example.com").unwrap();
+        rg.refresh().unwrap();
+        assert!(rg.owned_domains().is_empty());
+    }
+
+    #[test]
+    fn rejects_conflicting_claims_between_instances() {
+        let provider = provider();
+
+        // Both instances are loaded before either claims, so both consider the domain available
+        let mut first =
+            SqliteRegistry::from_config(config("first", "conflict"), &provider).unwrap();
+        let mut second =
+            SqliteRegistry::from_config(config("second", "conflict"), &provider).unwrap();
+        assert_eq!(first.available_domains().len(), 1);
+        assert_eq!(second.available_domains().len(), 1);
+
+        first.claim("available.example.com").unwrap();
+        second.claim("available.example.com").unwrap_err();
+        assert!(second.owned_domains().is_empty());
+
+        // After refreshing, the other instance sees the domain as taken and can't release it
+        second.refresh().unwrap();
+        assert_eq!(second.taken_domains().len(), 2);
+        second.release("available.example.com").unwrap_err();
+        first.refresh().unwrap();
+        assert_eq!(first.owned_domains()[0].name, "available.example.com");
+    }
+
+    #[test]
+    fn does_not_write_in_dry_run() {
+        let provider = provider();
+
+        let mut rg = SqliteRegistry::from_config(config("tenant", "dryrun"), &provider).unwrap();
+        rg.enable_dry_run().unwrap();
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains().len(), 1);
+
+        rg.refresh().unwrap();
+        assert!(rg.owned_domains().is_empty());
+    }
+}
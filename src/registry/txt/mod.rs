@@ -1,15 +1,25 @@
 //! Manage A record ownership using domain TXT records.
 
-mod util;
+pub(super) mod util;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    time::{Duration, SystemTime},
+};
 
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use log::{debug, info, warn};
 
-use self::util::{insert_rec_into_d, txt_record_string, TXT_RECORD_IDENT};
+pub use self::util::TXT_RECORD_IDENT as DEFAULT_TXT_PREFIX;
+use self::util::{
+    insert_rec_into_d, parse_txt_record, timestamped_txt_record_string, txt_record_string,
+};
 use super::{ARegistry, Domain, Ownership, RegistryError};
-use crate::provider::Provider;
+use crate::{
+    dry_run::DryRun,
+    provider::{normalize_domain, strip_root_dot, Provider, RecordContent},
+};
 
 /// The TxtRegistry manages ownership for each domains A record via an associated TXT record
 /// containing a reference to this application.
@@ -21,38 +31,140 @@ use crate::provider::Provider;
 /// Changes to records are only allowed if this TXT record is present.
 ///
 /// Domains may be claimed if there is no current A record and no ownership TXT record currently exists.
+/// Optionally, domains whose A records only point to a set of known addresses may be claimed as well (see [`TxtRegistryConfig`]).
 ///
-/// Use the [`TxtRegistry::from_provider()`] function to create a new registry using a provider.
+/// If [`TxtRegistryConfig::stale_after`] is set, ownership records include a last-seen timestamp that is renewed by claiming an owned domain again.
+/// Ownership records whose timestamp has not been renewed in time are considered stale and ignored,
+/// so that domains of an instance that stopped running can be taken over by others.
+///
+/// The ownership records may optionally be moved to a subdomain of each domain (see [`TxtRegistryConfig::ownership_subdomain`]),
+/// to avoid cluttering the domains themselves with TXT records.
+///
+/// Use the [`TxtRegistry::from_provider()`] or [`TxtRegistry::from_config()`] functions to create a new registry using a provider.
 #[non_exhaustive]
 pub struct TxtRegistry<'a> {
     domains: HashMap<String, Domain>,
-    tenant: String,
+    config: TxtRegistryConfig,
     provider: &'a dyn Provider,
-    dry_run: bool,
+    dry_run: DryRun,
+}
+
+/// Configuration for a [`TxtRegistry`]. Must be supplied when creating a registry with [`TxtRegistry::from_config()`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TxtRegistryConfig {
+    /// Unique identifier (tenant) of this registry instance, stored in the ownership records
+    pub tenant: String,
+    /// Domains without an ownership record whose A records all point to one of these addresses are considered available instead of taken.
+    /// This allows taking over existing records (e.g. previously managed by hand) once they match the managed addresses.
+    /// If empty, any existing A record blocks management of an unowned domain.
+    pub adoptable_addresses: Vec<Ipv4Addr>,
+    /// Where to store ownership information
+    pub backend: OwnershipBackend,
+    /// Prefix that identifies ownership records of this application, see [`DEFAULT_TXT_PREFIX`] for the default.
+    /// Instances using different prefixes don't see each others ownership records
+    pub prefix: String,
+    /// Add a last-seen timestamp to ownership records and consider records that are older than this duration stale.
+    /// Domains whose ownership records are all stale may be claimed, regardless of their current A records.
+    /// Records without a timestamp (created by instances without this option) never become stale.
+    /// Only supported with [`OwnershipBackend::Txt`].
+    pub stale_after: Option<Duration>,
+    /// Store ownership records in a subdomain of each domain instead of the domain itself.
+    /// For example, with `_clouddns`, ownership of `www.example.com` is stored at `_clouddns.www.example.com`.
+    /// Only supported with [`OwnershipBackend::Txt`].
+    pub ownership_subdomain: Option<String>,
+    /// Re-read the records from the provider before claiming a domain and refuse to claim it if its AAAA records disappeared since the registry was loaded.
+    /// This prevents creating A records for domains that are being removed, at the cost of an additional request per claim
+    pub verify_aaaa_on_claim: bool,
+    /// Manage wildcard domains such as `*.example.com` like any other domain.
+    /// If disabled, wildcard domains are always considered taken, so they are never claimed, updated or released
+    pub manage_wildcards: bool,
+}
+
+/// Where a [`TxtRegistry`] stores its ownership information.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OwnershipBackend {
+    /// Store ownership in a separate TXT record for each domain
+    #[default]
+    Txt,
+    /// Store ownership directly on the A records, for providers that support it (see [`Provider::supports_native_ownership()`]).
+    /// No additional TXT records are created in this mode
+    Native,
 }
 
 impl TxtRegistry<'_> {
     /// Determine the current ownership status for a given domain
-    fn determine_ownership(domain: &Domain, tenant: &str) -> Ownership {
-        let owner_records: Vec<&String> = domain
-            .txt
+    fn determine_ownership(
+        domain: &Domain,
+        config: &TxtRegistryConfig,
+        now: SystemTime,
+    ) -> Ownership {
+        if domain.is_wildcard() && !config.manage_wildcards {
+            debug!("Domain {} is a wildcard, not managing it", domain.name);
+            return Ownership::Taken;
+        }
+        let candidates = match config.backend {
+            OwnershipBackend::Txt => &domain.txt,
+            OwnershipBackend::Native => &domain.comments,
+        };
+        // Multiple timestamped records of the same owner are not a conflict, only the most recent one counts
+        let mut owners: HashMap<String, Option<SystemTime>> = HashMap::new();
+        for txt in candidates
             .iter()
-            .filter(|txt| txt.as_str().starts_with(TXT_RECORD_IDENT))
-            .unique()
-            .collect();
+            .filter(|txt| txt.as_str().starts_with(config.prefix.as_str()))
+        {
+            let Some(record) = parse_txt_record(&config.prefix, txt) else {
+                // Possibly written by a newer version of this application, so we can't tell who owns the domain
+                warn!(
+                    "Domain {} has an ownership record in an unknown format: {:?}. Considering this domain taken",
+                    domain.name, txt
+                );
+                return Ownership::Taken;
+            };
+            let owner = match record.is_owned_by(&config.tenant) {
+                true => config.tenant.to_owned(),
+                false => record.tenant,
+            };
+            owners
+                .entry(owner)
+                .and_modify(|s| *s = s.zip(record.seen).map(|(a, b)| a.max(b)))
+                .or_insert(record.seen);
+        }
+        let (stale_records, owner_records): (Vec<String>, Vec<String>) =
+            owners.into_iter().partition_map(|(owner, seen)| {
+                if is_stale(seen, config.stale_after, now) {
+                    Either::Left(owner)
+                } else {
+                    Either::Right(owner)
+                }
+            });
 
         match owner_records.len() {
             0 => {
-                if domain.a.is_empty() {
+                if !stale_records.is_empty() {
+                    // The previous owner stopped renewing its ownership, we may take over
+                    debug!(
+                        "Domain {} only has stale ownership records: {:?}",
+                        domain.name, stale_records
+                    );
+                    Ownership::Available
+                } else if domain.a.is_empty() {
                     // No A records and no ownership - we can manage this one
                     Ownership::Available
+                } else if !config.adoptable_addresses.is_empty()
+                    && domain
+                        .a
+                        .iter()
+                        .all(|a| config.adoptable_addresses.contains(a))
+                {
+                    // A records only point to addresses that we are allowed to adopt
+                    Ownership::Available
                 } else {
                     // A records already present, seems like this domain is externally managed
                     Ownership::Taken
                 }
             }
             1 => {
-                if owner_records.contains(&&txt_record_string(tenant)) {
+                if owner_records[0] == config.tenant {
                     // We are the proud owner of this domain
                     Ownership::Owned
                 } else {
@@ -67,43 +179,119 @@ impl TxtRegistry<'_> {
         }
     }
 
-    /// Create a new [`TxtRegistry`] from a given provider
-    /// As the TxtRegistry uses TXT records in the same zone for ownership, it needs a provider to manage ownership.
-    /// This provider is also used to retrieve all records during creation
-    pub fn from_provider(
-        tenant: String,
+    /// Read all records from the provider and build a map of domains with their current ownership status
+    fn load_domains(
         provider: &dyn Provider,
-    ) -> Result<Box<dyn ARegistry + '_>, RegistryError> {
+        config: &TxtRegistryConfig,
+    ) -> Result<HashMap<String, Domain>, RegistryError> {
         let mut domains: HashMap<String, Domain> = HashMap::new();
-
-        // Create a map of all domains that we will watch over
-        for rec in &provider.records().map_err(|e| e.to_string())? {
-            if let Some(d) = domains.get_mut(&rec.domain_name) {
+        let now = SystemTime::now();
+
+        // Create a map of all domains that we will watch over.
+        // Only TXT records that could be ownership records are relevant to us
+        for rec in &provider
+            .registry_records(&config.prefix)
+            .map_err(|e| e.to_string())?
+        {
+            let name = match (&rec.content, &config.ownership_subdomain) {
+                (RecordContent::Txt(_), Some(sub)) => {
+                    match rec.domain_name.strip_prefix(&format!("{}.", sub)) {
+                        // Ownership records belong to the domain above the subdomain
+                        Some(name) => name,
+                        // Only records in the subdomain are ownership records
+                        None => continue,
+                    }
+                }
+                _ => rec.domain_name.as_str(),
+            };
+            // Providers may return the same domain in different forms (e.g. Unicode and punycode, with or without a trailing dot)
+            let name = normalize_domain(strip_root_dot(name));
+            let name = name.as_str();
+            if let Some(d) = domains.get_mut(name) {
                 // Update an existing domain
                 insert_rec_into_d(rec, d);
             } else {
                 // Create a new domain and insert the record
                 let mut d = Domain {
-                    name: rec.domain_name.to_owned(),
+                    name: name.to_owned(),
+                    a_ttl: None,
+                    a_modified: None,
+                    comments: Vec::new(),
                     a: Vec::new(),
                     aaaa: Vec::new(),
                     txt: Vec::new(),
+                    cname: None,
                     a_ownership: Ownership::Taken, // Safe default, overwritten below
                 };
                 insert_rec_into_d(rec, &mut d);
-                domains.insert(rec.domain_name.to_owned(), d);
+                domains.insert(name.to_owned(), d);
             }
         }
 
         for domain in domains.values_mut() {
-            domain.a_ownership = TxtRegistry::determine_ownership(domain, &tenant);
+            domain.a_ownership = TxtRegistry::determine_ownership(domain, config, now);
         }
+        Ok(domains)
+    }
+
+    /// Returns the ownership marker used by a given prefix and tenant.
+    /// This is the content of the ownership TXT record, or the record comment when using [`OwnershipBackend::Native`]
+    pub fn ownership_marker(prefix: &str, tenant: &str) -> String {
+        txt_record_string(prefix, tenant)
+    }
+
+    /// Create a new [`TxtRegistry`] from a given provider
+    /// As the TxtRegistry uses TXT records in the same zone for ownership, it needs a provider to manage ownership.
+    /// This provider is also used to retrieve all records during creation and on [`ARegistry::refresh()`]
+    pub fn from_provider(
+        tenant: String,
+        provider: &dyn Provider,
+    ) -> Result<Box<dyn ARegistry + '_>, RegistryError> {
+        TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant,
+                adoptable_addresses: Vec::new(),
+                backend: OwnershipBackend::Txt,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider,
+        )
+    }
+
+    /// Create a new [`TxtRegistry`] with additional configuration from a given provider.
+    /// See [`TxtRegistry::from_provider()`] for details.
+    pub fn from_config(
+        config: TxtRegistryConfig,
+        provider: &dyn Provider,
+    ) -> Result<Box<dyn ARegistry + '_>, RegistryError> {
+        if config.backend == OwnershipBackend::Native && !provider.supports_native_ownership() {
+            return Err(RegistryError::Internal(
+                "The selected provider does not support native ownership metadata".to_string(),
+            ));
+        }
+        if config.backend == OwnershipBackend::Native && config.stale_after.is_some() {
+            return Err(RegistryError::Internal(
+                "Stale ownership detection is not supported with native ownership metadata"
+                    .to_string(),
+            ));
+        }
+        if config.backend == OwnershipBackend::Native && config.ownership_subdomain.is_some() {
+            return Err(RegistryError::Internal(
+                "An ownership subdomain is not supported with native ownership metadata"
+                    .to_string(),
+            ));
+        }
+        let domains = TxtRegistry::load_domains(provider, &config)?;
 
         Ok(Box::new(TxtRegistry {
             domains,
-            tenant,
+            config,
             provider,
-            dry_run: false,
+            dry_run: DryRun::default(),
         }))
     }
 }
@@ -121,6 +309,27 @@ impl ARegistry for TxtRegistry<'_> {
         self.domains.values().cloned().collect_vec()
     }
 
+    fn taken_domains_with_owners(&self) -> Vec<(Domain, Vec<String>)> {
+        self.domains
+            .values()
+            .filter(|d| d.a_ownership == Ownership::Taken)
+            .map(|d| {
+                let candidates = match self.config.backend {
+                    OwnershipBackend::Txt => &d.txt,
+                    OwnershipBackend::Native => &d.comments,
+                };
+                let owners = candidates
+                    .iter()
+                    .filter_map(|rec| record_owner(&self.config, rec))
+                    .unique()
+                    .sorted()
+                    .collect_vec();
+                (d.clone(), owners)
+            })
+            .sorted_by(|(a, _), (b, _)| a.name.cmp(&b.name))
+            .collect_vec()
+    }
+
     fn claim(&mut self, name: &str) -> Result<(), super::RegistryError> {
         let Some(reg_d) = self.domains.get_mut(name) else {
             return Err(RegistryError::ClaimError {
@@ -128,13 +337,58 @@ impl ARegistry for TxtRegistry<'_> {
                 reason: "Not in registry".to_string(),
             });
         };
+        let now = SystemTime::now();
 
         match reg_d.a_ownership {
             Ownership::Owned => {
-                info!(
-                    "Attempted to claim domain {}, but it is already owned by us. Ignoring",
-                    name
-                );
+                let own_records = reg_d
+                    .txt
+                    .iter()
+                    .filter_map(|txt| parse_txt_record(&self.config.prefix, txt))
+                    .filter(|record| record.is_owned_by(&self.config.tenant))
+                    .collect_vec();
+                // Records in the legacy format are always replaced by ones in the current format
+                let has_legacy = self.config.backend == OwnershipBackend::Txt
+                    && own_records.iter().any(|record| record.legacy);
+                if !has_legacy {
+                    let Some(stale_after) = self.config.stale_after else {
+                        info!(
+                            "Attempted to claim domain {}, but it is already owned by us. Ignoring",
+                            name
+                        );
+                        return Ok(());
+                    };
+                    // Renew our ownership once half of the stale period has passed
+                    let last_seen = own_records.iter().filter_map(|record| record.seen).max();
+                    if last_seen
+                        .is_some_and(|seen| !is_stale(Some(seen), Some(stale_after / 2), now))
+                    {
+                        debug!("Ownership of domain {} is still fresh", name);
+                        return Ok(());
+                    }
+                }
+                self.dry_run.guard(|| {
+                    let old_records = reg_d
+                        .txt
+                        .iter()
+                        .filter(|txt| is_own_record(&self.config, txt))
+                        .cloned()
+                        .collect_vec();
+                    let record = ownership_record(&self.config, now);
+                    self.provider
+                        .create_txt_record(
+                            ownership_name(&self.config, &reg_d.name),
+                            record.to_owned(),
+                        )
+                        .map_err(|e| RegistryError::ClaimError {
+                            domain: name.to_string(),
+                            reason: format!("Provider Error: {}", e),
+                        })?;
+                    reg_d.txt.push(record);
+                    remove_records(self.provider, &self.config, reg_d, &old_records);
+                    Ok::<_, RegistryError>(())
+                })?;
+                debug!("Renewed ownership of domain {}", name);
                 Ok(())
             }
             Ownership::Taken => Err(RegistryError::ClaimError {
@@ -142,13 +396,50 @@ impl ARegistry for TxtRegistry<'_> {
                 reason: "Owned by other instance".to_string(),
             }),
             Ownership::Available => {
-                if !self.dry_run {
-                    self.provider
-                        .create_txt_record(reg_d.name.to_owned(), txt_record_string(&self.tenant))
+                if self.config.verify_aaaa_on_claim {
+                    let has_aaaa = self
+                        .provider
+                        .records()
                         .map_err(|e| RegistryError::ClaimError {
                             domain: name.to_string(),
-                            reason: format!("Provider Error: {}", e),
-                        })?;
+                            reason: format!("Could not verify AAAA records: {}", e),
+                        })?
+                        .iter()
+                        .any(|r| {
+                            normalize_domain(&r.domain_name) == name
+                                && matches!(r.content, RecordContent::Aaaa(_))
+                        });
+                    if !has_aaaa {
+                        return Err(RegistryError::ClaimError {
+                            domain: name.to_string(),
+                            reason: "Domain no longer has any AAAA records".to_string(),
+                        });
+                    }
+                }
+                // In native mode, ownership is attached to the A records by the provider once they are created
+                if self.config.backend == OwnershipBackend::Txt {
+                    self.dry_run.guard(|| {
+                        // Any ownership records that are still present must be stale, clean them up after claiming
+                        let stale_records = reg_d
+                            .txt
+                            .iter()
+                            .filter(|txt| txt.starts_with(self.config.prefix.as_str()))
+                            .cloned()
+                            .collect_vec();
+                        let record = ownership_record(&self.config, now);
+                        self.provider
+                            .create_txt_record(
+                                ownership_name(&self.config, &reg_d.name),
+                                record.to_owned(),
+                            )
+                            .map_err(|e| RegistryError::ClaimError {
+                                domain: name.to_string(),
+                                reason: format!("Provider Error: {}", e),
+                            })?;
+                        reg_d.txt.push(record);
+                        remove_records(self.provider, &self.config, reg_d, &stale_records);
+                        Ok::<_, RegistryError>(())
+                    })?;
                 }
                 reg_d.a_ownership = Ownership::Owned;
                 debug!("Successfully claimed domain {}", name);
@@ -157,6 +448,66 @@ impl ARegistry for TxtRegistry<'_> {
         }
     }
 
+    /// Removes the ownership records of all other tenants, including records in an unknown format.
+    /// Only supported with [`OwnershipBackend::Txt`]. Domains without any ownership records (i.e. managed externally) are never force-claimed
+    fn claim_force(&mut self, name: &str) -> Result<(), RegistryError> {
+        let claim_error = |reason: &str| RegistryError::ClaimError {
+            domain: name.to_string(),
+            reason: reason.to_string(),
+        };
+        if self.config.backend != OwnershipBackend::Txt {
+            return Err(claim_error(
+                "Force-claiming is only supported with TXT ownership records",
+            ));
+        }
+        let Some(ownership) = self.domains.get(name).map(|d| d.a_ownership) else {
+            return Err(claim_error("Not in registry"));
+        };
+        if ownership != Ownership::Taken {
+            return self.claim(name);
+        }
+        let reg_d = self.domains.get_mut(name).unwrap();
+        if reg_d.is_wildcard() && !self.config.manage_wildcards {
+            return Err(claim_error("Wildcard domains are not managed"));
+        }
+
+        let is_own = |txt: &String| is_own_record(&self.config, txt);
+        let foreign_records = reg_d
+            .txt
+            .iter()
+            .filter(|txt| txt.starts_with(self.config.prefix.as_str()) && !is_own(txt))
+            .cloned()
+            .collect_vec();
+        if foreign_records.is_empty() {
+            return Err(claim_error(
+                "No ownership records of other tenants found, domain is managed externally",
+            ));
+        }
+        warn!(
+            "Force-claiming domain {}, removing ownership records of other tenants: {:?}",
+            name, foreign_records
+        );
+        self.dry_run.guard(|| {
+            for record in &foreign_records {
+                self.provider
+                    .delete_txt_record(ownership_name(&self.config, &reg_d.name), record.to_owned())
+                    .map_err(|e| claim_error(&format!("Provider Error: {}", e)))?;
+                reg_d.txt.retain(|txt| txt != record);
+            }
+            if !reg_d.txt.iter().any(is_own) {
+                let record = ownership_record(&self.config, SystemTime::now());
+                self.provider
+                    .create_txt_record(ownership_name(&self.config, &reg_d.name), record.to_owned())
+                    .map_err(|e| claim_error(&format!("Provider Error: {}", e)))?;
+                reg_d.txt.push(record);
+            }
+            Ok::<_, RegistryError>(())
+        })?;
+        reg_d.a_ownership = Ownership::Owned;
+        info!("Force-claimed domain {}", name);
+        Ok(())
+    }
+
     fn release(&mut self, name: &str) -> Result<(), RegistryError> {
         let Some(reg_d) = self.domains.get_mut(name) else {
             return Err(RegistryError::ReleaseError {
@@ -167,13 +518,33 @@ impl ARegistry for TxtRegistry<'_> {
 
         match reg_d.a_ownership {
             Ownership::Owned => {
-                if !self.dry_run {
-                    self.provider
-                        .delete_txt_record(reg_d.name.to_owned(), txt_record_string(&self.tenant))
-                        .map_err(|e| RegistryError::ReleaseError {
-                            domain: name.to_string(),
-                            reason: format!("Provider Error: {}", e),
-                        })?;
+                // In native mode, ownership disappears together with the A records
+                if self.config.backend == OwnershipBackend::Txt {
+                    self.dry_run.guard(|| {
+                        let mut records = reg_d
+                            .txt
+                            .iter()
+                            .filter(|txt| is_own_record(&self.config, txt))
+                            .cloned()
+                            .collect_vec();
+                        if records.is_empty() {
+                            records
+                                .push(txt_record_string(&self.config.prefix, &self.config.tenant));
+                        }
+                        for record in records {
+                            self.provider
+                                .delete_txt_record(
+                                    ownership_name(&self.config, &reg_d.name),
+                                    record.to_owned(),
+                                )
+                                .map_err(|e| RegistryError::ReleaseError {
+                                    domain: name.to_string(),
+                                    reason: format!("Provider Error: {}", e),
+                                })?;
+                            reg_d.txt.retain(|txt| *txt != record);
+                        }
+                        Ok::<_, RegistryError>(())
+                    })?;
                 }
                 reg_d.a_ownership = Ownership::Available;
                 debug!("Sucessfully released domain {}", name);
@@ -191,114 +562,220 @@ impl ARegistry for TxtRegistry<'_> {
     }
 
     fn set_tenant(&mut self, tenant: String) {
-        self.tenant = tenant;
+        self.config.tenant = tenant;
+    }
+
+    fn refresh(&mut self) -> Result<(), RegistryError> {
+        self.domains = TxtRegistry::load_domains(self.provider, &self.config)?;
+        debug!(
+            "Refreshed registry, now tracking {} domains",
+            self.domains.len()
+        );
+        Ok(())
     }
 
     fn enable_dry_run(&mut self) -> Result<(), RegistryError> {
-        self.dry_run = true;
+        self.dry_run.enable();
         Ok(())
     }
 }
 
+/// Returns the ownership record to create for the configured tenant, timestamped if stale ownership detection is enabled
+fn ownership_record(config: &TxtRegistryConfig, now: SystemTime) -> String {
+    match config.stale_after {
+        Some(_) => timestamped_txt_record_string(&config.prefix, &config.tenant, now),
+        None => txt_record_string(&config.prefix, &config.tenant),
+    }
+}
+
+/// Returns the owner of an ownership record, attributing legacy records of the configured tenant to it
+fn record_owner(config: &TxtRegistryConfig, record: &str) -> Option<String> {
+    parse_txt_record(&config.prefix, record).map(|r| match r.is_owned_by(&config.tenant) {
+        true => config.tenant.to_owned(),
+        false => r.tenant,
+    })
+}
+
+/// Whether an ownership record belongs to the configured tenant
+fn is_own_record(config: &TxtRegistryConfig, record: &str) -> bool {
+    record_owner(config, record).is_some_and(|owner| owner == config.tenant)
+}
+
+/// Returns the name of the domain that stores the ownership records for a given domain
+fn ownership_name(config: &TxtRegistryConfig, domain: &str) -> String {
+    match &config.ownership_subdomain {
+        Some(sub) => format!("{}.{}", sub, domain),
+        None => domain.to_owned(),
+    }
+}
+
+/// Whether an ownership record last seen at the given time is stale. Records without a timestamp are never stale
+fn is_stale(seen: Option<SystemTime>, stale_after: Option<Duration>, now: SystemTime) -> bool {
+    match (seen, stale_after) {
+        (Some(seen), Some(stale_after)) => {
+            now.duration_since(seen).is_ok_and(|age| age > stale_after)
+        }
+        _ => false,
+    }
+}
+
+/// Delete outdated ownership records of a domain.
+/// Failures are only logged, as leftover records are stale and will be cleaned up on the next claim
+fn remove_records(
+    provider: &dyn Provider,
+    config: &TxtRegistryConfig,
+    domain: &mut Domain,
+    records: &[String],
+) {
+    for record in records {
+        match provider.delete_txt_record(ownership_name(config, &domain.name), record.to_owned()) {
+            Ok(_) => domain.txt.retain(|txt| txt != record),
+            Err(e) => warn!(
+                "Could not remove outdated ownership record {:?} of domain {}: {}",
+                record, domain.name, e
+            ),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use itertools::Itertools;
+    use mockall::Sequence;
 
     use crate::{
         provider::{DnsRecord, MockProvider, Provider, RecordContent},
         registry::Domain,
     };
 
-    use super::{util::txt_record_string, TxtRegistry};
+    use super::{
+        util::{
+            legacy_txt_record_string, parse_txt_record, timestamped_txt_record_string,
+            txt_record_owner, txt_record_string, OwnershipRecord,
+        },
+        OwnershipBackend, TxtRegistry, TxtRegistryConfig, DEFAULT_TXT_PREFIX,
+    };
 
     static TENANT: &str = "evil;test_tennant;name";
 
     fn records() -> Vec<DnsRecord> {
         vec![
-            DnsRecord {
-                domain_name: "owned.example.com".to_string(),
-                content: RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
-            },
-            DnsRecord {
-                domain_name: "owned.example.com".to_string(),
-                content: RecordContent::Txt(txt_record_string(TENANT)),
-            },
-            DnsRecord {
-                domain_name: "available.example.com".to_string(),
-                content: RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
-            },
-            DnsRecord {
-                domain_name: "taken.example.com".to_string(),
-                content: RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
-            },
-            DnsRecord {
-                domain_name: "other-owner.example.com".to_string(),
-                content: RecordContent::A(Ipv4Addr::new(10, 1, 1, 3)),
-            },
-            DnsRecord {
-                domain_name: "other-owner.example.com".to_string(),
-                content: RecordContent::Txt(txt_record_string("other_tenant")),
-            },
-            DnsRecord {
-                domain_name: "conflict.example.com".to_string(),
-                content: RecordContent::Txt(txt_record_string("other_tenant")),
-            },
-            DnsRecord {
-                domain_name: "conflict.example.com".to_string(),
-                content: RecordContent::Txt(txt_record_string(TENANT)),
-            },
-            DnsRecord {
-                domain_name: "conflict.example.com".to_string(),
-                content: RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 2)),
-            },
-            DnsRecord {
-                domain_name: "conflict.example.com".to_string(),
-                content: RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
-            },
+            DnsRecord::new(
+                "owned.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+            ),
+            DnsRecord::new(
+                "owned.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+            ),
+            DnsRecord::new(
+                "owned.example.com",
+                RecordContent::Txt("v=spf1 -all".to_string()),
+            ),
+            DnsRecord::new(
+                "available.example.com",
+                RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+            ),
+            DnsRecord::new(
+                "taken.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+            ),
+            DnsRecord::new(
+                "other-owner.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 3)),
+            ),
+            DnsRecord::new(
+                "other-owner.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant")),
+            ),
+            DnsRecord::new(
+                "conflict.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant")),
+            ),
+            DnsRecord::new(
+                "conflict.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+            ),
+            DnsRecord::new(
+                "conflict.example.com",
+                RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 2)),
+            ),
+            DnsRecord::new(
+                "conflict.example.com",
+                RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+            ),
         ]
     }
     fn owned_d() -> Domain {
         Domain {
             name: "owned.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 1)],
             aaaa: vec![],
-            txt: vec![txt_record_string(TENANT)],
+            txt: vec![txt_record_string(DEFAULT_TXT_PREFIX, TENANT)],
+            cname: None,
             a_ownership: crate::registry::Ownership::Owned,
         }
     }
     fn available_d() -> Domain {
         Domain {
             name: "available.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)],
             a: vec![],
             txt: vec![],
+            cname: None,
             a_ownership: crate::registry::Ownership::Available,
         }
     }
     fn taken_d() -> Domain {
         Domain {
             name: "taken.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 2)],
             aaaa: vec![],
             txt: vec![],
+            cname: None,
             a_ownership: crate::registry::Ownership::Taken,
         }
     }
     fn other_owner_d() -> Domain {
         Domain {
             name: "other-owner.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 3)],
             aaaa: vec![],
-            txt: vec![txt_record_string("other_tenant")],
+            txt: vec![txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant")],
+            cname: None,
             a_ownership: crate::registry::Ownership::Taken,
         }
     }
     fn conflict_d() -> Domain {
         Domain {
             name: "conflict.example.com".to_string(),
+            a_ttl: None,
+            a_modified: None,
+            comments: vec![],
             a: vec![Ipv4Addr::new(10, 1, 1, 2)],
             aaaa: vec![Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 2)],
-            txt: vec![txt_record_string(TENANT), txt_record_string("other_tenant")],
+            txt: vec![
+                txt_record_string(DEFAULT_TXT_PREFIX, TENANT),
+                txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant"),
+            ],
+            cname: None,
             a_ownership: crate::registry::Ownership::Taken,
         }
     }
@@ -315,6 +792,31 @@ mod tests {
         assert_eq!(rg.owned_domains().first().unwrap(), &owned_d());
     }
 
+    #[test]
+    fn detects_owned_idn_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "xn--mnchen-3ya.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "München.example.com",
+                    RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                ),
+            ])
+        });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        let owned = rg.owned_domains();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(owned[0].name, "xn--mnchen-3ya.example.com");
+        assert_eq!(owned[0].a, vec![Ipv4Addr::new(10, 1, 1, 1)]);
+    }
+
     #[test]
     fn claims_available_domain() {
         let mut mock = MockProvider::new();
@@ -335,6 +837,24 @@ mod tests {
         assert!(rg.owned_domains().contains(&available_d));
     }
 
+    #[test]
+    fn skips_writes_in_dry_run() {
+        // We intentionally do not expect create/delete_txt_record to be called in dry-run mode
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        rg.enable_dry_run().unwrap();
+
+        rg.claim(available_d().name.as_str()).unwrap();
+        rg.release(owned_d().name.as_str()).unwrap();
+
+        assert_eq!(rg.owned_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, available_d().name);
+    }
+
     #[test]
     fn ignores_claimm_on_owned_domain() {
         let mut mock = MockProvider::new();
@@ -397,6 +917,76 @@ mod tests {
         assert!(rg.owned_domains().is_empty());
     }
 
+    #[test]
+    fn releases_all_owned_domains() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_create_txt_record().return_once(|_, _| Ok(()));
+        mock.expect_delete_txt_record()
+            .withf(|_, content| content == &txt_record_string(DEFAULT_TXT_PREFIX, TENANT))
+            .times(2)
+            .returning(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        rg.claim(available_d().name.as_str()).unwrap();
+
+        let released = rg.release_all().unwrap();
+        assert_eq!(released.len(), 2);
+        assert!(released.contains(&owned_d().name));
+        assert!(released.contains(&available_d().name));
+        assert!(rg.owned_domains().is_empty());
+    }
+
+    #[test]
+    fn prunes_owned_domains_without_a_records() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            let mut records = records();
+            records.push(DnsRecord::new(
+                "orphan.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+            ));
+            Ok(records)
+        });
+        mock.expect_delete_txt_record()
+            .withf(|domain, content| {
+                domain == "orphan.example.com"
+                    && content == &txt_record_string(DEFAULT_TXT_PREFIX, TENANT)
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        assert_eq!(rg.owned_domains().len(), 2);
+
+        assert_eq!(
+            rg.prune_orphans().unwrap(),
+            vec!["orphan.example.com".to_string()]
+        );
+        // The healthy owned domain is untouched
+        assert_eq!(rg.owned_domains(), vec![owned_d()]);
+        assert!(rg.prune_orphans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_failed_releases() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_delete_txt_record()
+            .return_once(|_, _| Err("API error".to_string().into()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        rg.release_all().unwrap_err();
+        assert_eq!(rg.owned_domains(), vec![owned_d()]);
+    }
+
     #[test]
     fn ignores_release_on_available() {
         let mut mock = MockProvider::new();
@@ -469,4 +1059,1017 @@ mod tests {
         assert!(rg.owned_domains().len() == 1);
         assert!(rg.owned_domains().first().unwrap() == &owned_d());
     }
+
+    #[test]
+    fn matches_ownership_records_with_trailing_dot() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "dotted.example.com.",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "dotted.example.com",
+                    RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                ),
+            ])
+        });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(rg.all_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, "dotted.example.com");
+        assert_eq!(rg.owned_domains()[0].a, vec![Ipv4Addr::new(10, 1, 1, 1)]);
+    }
+
+    #[test]
+    fn force_claims_conflicting_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_delete_txt_record()
+            .withf(|domain, content| {
+                domain == "conflict.example.com"
+                    && content == &txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant")
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        // Our own ownership record is already present
+        mock.expect_create_txt_record().never();
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        rg.claim_force(conflict_d().name.as_str()).unwrap();
+
+        let mut claimed = conflict_d();
+        claimed.a_ownership = crate::registry::Ownership::Owned;
+        claimed.txt = vec![txt_record_string(DEFAULT_TXT_PREFIX, TENANT)];
+        assert!(rg.owned_domains().contains(&claimed));
+    }
+
+    #[test]
+    fn force_claims_domain_of_other_tenant() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let mut seq = Sequence::new();
+        mock.expect_delete_txt_record()
+            .withf(|domain, content| {
+                domain == "other-owner.example.com"
+                    && content == &txt_record_string(DEFAULT_TXT_PREFIX, "other_tenant")
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|_, _| Ok(()));
+        mock.expect_create_txt_record()
+            .withf(|domain, content| {
+                domain == "other-owner.example.com"
+                    && content == &txt_record_string(DEFAULT_TXT_PREFIX, TENANT)
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        rg.claim_force("other-owner.example.com").unwrap();
+        assert!(rg
+            .owned_domains()
+            .iter()
+            .any(|d| d.name == "other-owner.example.com"));
+    }
+
+    #[test]
+    fn refuses_to_force_claim_externally_managed_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_delete_txt_record().never();
+        mock.expect_create_txt_record().never();
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        rg.claim_force(taken_d().name.as_str()).unwrap_err();
+        rg.claim_force("unknown.example.com").unwrap_err();
+        assert!(rg.taken_domains().contains(&taken_d()));
+    }
+
+    #[test]
+    fn skips_force_claim_writes_in_dry_run() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_delete_txt_record().never();
+        mock.expect_create_txt_record().never();
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        rg.enable_dry_run().unwrap();
+
+        rg.claim_force(conflict_d().name.as_str()).unwrap();
+        assert!(rg
+            .owned_domains()
+            .iter()
+            .any(|d| d.name == conflict_d().name));
+    }
+
+    #[test]
+    fn refresh_picks_up_external_ownership() {
+        let mut mock = MockProvider::new();
+        let mut seq = Sequence::new();
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| Ok(records()));
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| {
+                let mut records = records();
+                records.push(DnsRecord::new(
+                    available_d().name,
+                    RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                ));
+                Ok(records)
+            });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        assert!(rg.owned_domains().len() == 1);
+
+        rg.refresh().unwrap();
+
+        let mut available_d = available_d();
+        available_d.txt = vec![txt_record_string(DEFAULT_TXT_PREFIX, TENANT)];
+        available_d.a_ownership = crate::registry::Ownership::Owned;
+        assert!(rg.owned_domains().len() == 2);
+        assert!(rg.owned_domains().contains(&available_d));
+    }
+
+    #[test]
+    fn refresh_picks_up_new_domains() {
+        let mut mock = MockProvider::new();
+        let mut seq = Sequence::new();
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| Ok(records()));
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| {
+                let mut records = records();
+                records.push(DnsRecord::new(
+                    "new.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 2, 2, 2, 2, 2, 2, 2)),
+                ));
+                Ok(records)
+            });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        let is_new = |d: &Domain| d.name == "new.example.com";
+        assert!(!rg.all_domains().iter().any(is_new));
+
+        rg.refresh().unwrap();
+
+        assert!(rg.available_domains().iter().any(is_new));
+    }
+
+    #[test]
+    fn carries_record_comments_into_domain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            let mut records = records();
+            records.push(DnsRecord {
+                comment: Some("managed by external-dns".to_string()),
+                ..DnsRecord::new(
+                    available_d().name,
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 5)),
+                )
+            });
+            Ok(records)
+        });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        let domain = rg
+            .all_domains()
+            .into_iter()
+            .find(|d| d.name == available_d().name)
+            .unwrap();
+        assert_eq!(domain.comments, vec!["managed by external-dns".to_string()]);
+        assert_eq!(rg.owned_domains(), vec![owned_d()]);
+    }
+
+    #[test]
+    fn adopts_domains_with_adoptable_addresses() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        mock.expect_create_txt_record().return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![Ipv4Addr::new(10, 1, 1, 2), Ipv4Addr::new(10, 1, 1, 3)],
+                backend: OwnershipBackend::Txt,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        // Unowned with a matching A record: adoptable
+        let mut taken_d = taken_d();
+        taken_d.a_ownership = crate::registry::Ownership::Available;
+        assert!(rg.available_domains().contains(&taken_d));
+        // Owned by another tenant or conflicting: still blocked, even though the address matches
+        assert!(rg.taken_domains().contains(&other_owner_d()));
+        assert!(rg.taken_domains().contains(&conflict_d()));
+
+        rg.claim(taken_d.name.as_str()).unwrap();
+        assert!(rg.owned_domains().len() == 2);
+    }
+
+    #[test]
+    fn foreign_a_records_block_adoption() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![Ipv4Addr::new(10, 9, 9, 9)],
+                backend: OwnershipBackend::Txt,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        assert!(rg.taken_domains().contains(&taken_d()));
+        rg.claim(taken_d().name.as_str()).unwrap_err();
+    }
+
+    #[test]
+    fn native_ownership_creates_no_txt_records() {
+        let mut mock = MockProvider::new();
+        mock.expect_supports_native_ownership().return_const(true);
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord {
+                    comment: Some(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                    ..DnsRecord::new(
+                        "owned.example.com",
+                        RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                    )
+                },
+                DnsRecord::new(
+                    available_d().name,
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+            ])
+        });
+        // No expectations for create/delete_txt_record: calling them fails the test
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![],
+                backend: OwnershipBackend::Native,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(rg.owned_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, owned_d().name);
+
+        rg.claim(available_d().name.as_str()).unwrap();
+        assert_eq!(rg.owned_domains().len(), 2);
+        rg.release(owned_d().name.as_str()).unwrap();
+        assert_eq!(rg.owned_domains().len(), 1);
+    }
+
+    #[test]
+    fn native_ownership_requires_provider_support() {
+        let mut mock = MockProvider::new();
+        mock.expect_supports_native_ownership().return_const(false);
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        assert!(TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![],
+                backend: OwnershipBackend::Native,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn detects_ownership_with_custom_prefix() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            let mut records = records();
+            records.push(DnsRecord::new(
+                available_d().name,
+                RecordContent::Txt(txt_record_string("external_dns", TENANT)),
+            ));
+            Ok(records)
+        });
+        mock.expect_delete_txt_record()
+            .withf(|domain, content| {
+                domain == "available.example.com"
+                    && content == &txt_record_string("external_dns", TENANT)
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![],
+                backend: OwnershipBackend::Txt,
+                prefix: "external_dns".to_string(),
+                stale_after: None,
+                ownership_subdomain: None,
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(rg.owned_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, available_d().name);
+        // Records with the default prefix are no longer considered ownership records
+        assert!(rg.taken_domains().iter().any(|d| d.name == owned_d().name));
+
+        rg.release(available_d().name.as_str()).unwrap();
+        assert!(rg.owned_domains().is_empty());
+    }
+
+    #[test]
+    fn distinguishes_tenants_with_separator() {
+        // Both tenant names used to produce the same ownership record
+        assert_ne!(
+            txt_record_string(DEFAULT_TXT_PREFIX, "a;b"),
+            txt_record_string(DEFAULT_TXT_PREFIX, "a_b")
+        );
+        assert_ne!(
+            txt_record_string(DEFAULT_TXT_PREFIX, "a;b"),
+            txt_record_string(DEFAULT_TXT_PREFIX, "a%3Bb")
+        );
+        assert_eq!(
+            txt_record_string(DEFAULT_TXT_PREFIX, TENANT)
+                .matches(';')
+                .count(),
+            2
+        );
+
+        // Only records in the legacy format may have been written with the separator replaced
+        let replaced = "evil_test_tennant_name";
+        assert!(!parse_txt_record(
+            DEFAULT_TXT_PREFIX,
+            &txt_record_string(DEFAULT_TXT_PREFIX, replaced)
+        )
+        .unwrap()
+        .is_owned_by(TENANT));
+
+        // Records written before tenants were encoded replaced the separator instead
+        let legacy = format!("{}_{};rec: A", DEFAULT_TXT_PREFIX, replaced);
+        let legacy_content = legacy.clone();
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(move || {
+            let mut records = records();
+            records.push(DnsRecord::new(
+                available_d().name,
+                RecordContent::Txt(legacy),
+            ));
+            Ok(records)
+        });
+        // Claiming renews the ownership record in the current format
+        mock.expect_create_txt_record()
+            .withf(|domain, content| {
+                *domain == available_d().name
+                    && *content == txt_record_string(DEFAULT_TXT_PREFIX, TENANT)
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_delete_txt_record()
+            .withf(move |domain, content| {
+                *domain == available_d().name && *content == legacy_content
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(
+            rg.owned_domains()
+                .into_iter()
+                .map(|d| d.name)
+                .sorted()
+                .collect_vec(),
+            vec![available_d().name, owned_d().name]
+        );
+        rg.claim(available_d().name.as_str()).unwrap();
+    }
+
+    #[test]
+    fn parses_owner_from_ownership_record() {
+        for tenant in [TENANT, "other_tenant", "a%3Bb", "100%"] {
+            assert_eq!(
+                txt_record_owner(
+                    DEFAULT_TXT_PREFIX,
+                    &txt_record_string(DEFAULT_TXT_PREFIX, tenant)
+                ),
+                Some(tenant.to_string())
+            );
+        }
+        assert_eq!(
+            txt_record_owner(
+                DEFAULT_TXT_PREFIX,
+                &timestamped_txt_record_string(DEFAULT_TXT_PREFIX, TENANT, SystemTime::now())
+            ),
+            Some(TENANT.to_string())
+        );
+        assert_eq!(
+            txt_record_owner("custom", &txt_record_string("custom", TENANT)),
+            Some(TENANT.to_string())
+        );
+    }
+
+    #[test]
+    fn ignores_non_ownership_records_when_parsing_owner() {
+        assert_eq!(txt_record_owner(DEFAULT_TXT_PREFIX, "v=spf1 -all"), None);
+        assert_eq!(
+            txt_record_owner(DEFAULT_TXT_PREFIX, &format!("{}_", DEFAULT_TXT_PREFIX)),
+            None
+        );
+        assert_eq!(
+            txt_record_owner("other", &txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_versioned_ownership_records() {
+        let seen = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            txt_record_string(DEFAULT_TXT_PREFIX, "a;b"),
+            "clouddns_nat=v1;owner:a%3Bb;rec:A"
+        );
+        assert_eq!(
+            parse_txt_record(
+                DEFAULT_TXT_PREFIX,
+                "clouddns_nat=v1;owner:a%3Bb;rec:A;seen:1700000000"
+            ),
+            Some(OwnershipRecord {
+                tenant: "a;b".to_string(),
+                seen: Some(seen),
+                legacy: false
+            })
+        );
+        assert_eq!(
+            parse_txt_record(
+                DEFAULT_TXT_PREFIX,
+                &timestamped_txt_record_string(DEFAULT_TXT_PREFIX, TENANT, seen)
+            ),
+            Some(OwnershipRecord {
+                tenant: TENANT.to_string(),
+                seen: Some(seen),
+                legacy: false
+            })
+        );
+        // Unknown versions and malformed fields are not parsed
+        for record in [
+            "clouddns_nat=v2;owner:tenant;rec:A",
+            "clouddns_nat=v1;owner:tenant",
+            "clouddns_nat=v1;tenant;rec:A",
+            "clouddns_nat=v1;owner:tenant;rec:AAAA",
+            "clouddns_nat=v1;owner:tenant;rec:A;seen:yesterday",
+            "clouddns_nat=v1;owner:tenant;rec:A;seen:1;extra",
+        ] {
+            assert_eq!(parse_txt_record(DEFAULT_TXT_PREFIX, record), None);
+        }
+    }
+
+    #[test]
+    fn parses_legacy_ownership_records() {
+        let seen = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            parse_txt_record(DEFAULT_TXT_PREFIX, "clouddns_nat_a%3Bb;rec: A"),
+            Some(OwnershipRecord {
+                tenant: "a;b".to_string(),
+                seen: None,
+                legacy: true
+            })
+        );
+        assert_eq!(
+            parse_txt_record(
+                DEFAULT_TXT_PREFIX,
+                "clouddns_nat_tenant;rec: A;seen: 1700000000"
+            ),
+            Some(OwnershipRecord {
+                tenant: "tenant".to_string(),
+                seen: Some(seen),
+                legacy: true
+            })
+        );
+        assert_eq!(
+            txt_record_owner(
+                DEFAULT_TXT_PREFIX,
+                &legacy_txt_record_string(DEFAULT_TXT_PREFIX, TENANT, Some(seen))
+            ),
+            Some(TENANT.to_string())
+        );
+        assert_eq!(
+            parse_txt_record(DEFAULT_TXT_PREFIX, "clouddns_nat_tenant;rec: A;seen: never"),
+            None
+        );
+    }
+
+    #[test]
+    fn recognizes_legacy_ownership_records() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "owned.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "owned.example.com",
+                    RecordContent::Txt(legacy_txt_record_string(DEFAULT_TXT_PREFIX, TENANT, None)),
+                ),
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::Txt(legacy_txt_record_string(
+                        DEFAULT_TXT_PREFIX,
+                        "other_tenant",
+                        None,
+                    )),
+                ),
+                DnsRecord::new(
+                    "unknown.example.com",
+                    RecordContent::Txt("clouddns_nat=v2;owner:evil;test_tennant;name".to_string()),
+                ),
+            ])
+        });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(
+            rg.owned_domains().into_iter().map(|d| d.name).collect_vec(),
+            vec!["owned.example.com"]
+        );
+        // Records in an unknown format may belong to anyone
+        assert_eq!(
+            rg.taken_domains()
+                .into_iter()
+                .map(|d| d.name)
+                .sorted()
+                .collect_vec(),
+            vec!["taken.example.com", "unknown.example.com"]
+        );
+    }
+
+    #[test]
+    fn replaces_legacy_record_on_renewal() {
+        let legacy = legacy_txt_record_string(
+            DEFAULT_TXT_PREFIX,
+            TENANT,
+            Some(SystemTime::now() - Duration::from_secs(3000)),
+        );
+        let legacy_content = legacy.clone();
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(move || {
+            Ok(vec![DnsRecord::new(
+                "owned.example.com",
+                RecordContent::Txt(legacy),
+            )])
+        });
+        mock.expect_create_txt_record()
+            .withf(|domain, content| {
+                domain == "owned.example.com"
+                    && content.starts_with(&txt_record_string(DEFAULT_TXT_PREFIX, TENANT))
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_delete_txt_record()
+            .withf(move |domain, content| {
+                domain == "owned.example.com" && *content == legacy_content
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(stale_config(), provider_mock.as_ref()).unwrap();
+
+        rg.claim("owned.example.com").unwrap();
+        assert_eq!(rg.owned_domains().len(), 1);
+    }
+
+    #[test]
+    fn reports_owners_of_taken_domains() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(
+            rg.taken_domains_with_owners()
+                .into_iter()
+                .map(|(d, owners)| (d.name, owners))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    conflict_d().name,
+                    vec![TENANT.to_string(), "other_tenant".to_string()]
+                ),
+                (other_owner_d().name, vec!["other_tenant".to_string()]),
+                (taken_d().name, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_tenant_with_separator() {
+        let created = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let c = created.clone();
+        mock.expect_create_txt_record()
+            .return_once(move |domain, content| {
+                c.lock()
+                    .unwrap()
+                    .push(DnsRecord::new(domain, RecordContent::Txt(content)));
+                Ok(())
+            });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+        let mut rg =
+            TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+        rg.claim(available_d().name.as_str()).unwrap();
+        drop(rg);
+
+        // A new registry reading the created record must detect our ownership
+        let mut mock = MockProvider::new();
+        let created = created.lock().unwrap().clone();
+        mock.expect_records().return_once(move || {
+            let mut records = records();
+            records.extend(created);
+            Ok(records)
+        });
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(rg.owned_domains().len(), 2);
+        assert!(rg
+            .owned_domains()
+            .iter()
+            .any(|d| d.name == available_d().name));
+    }
+
+    fn stale_config() -> TxtRegistryConfig {
+        TxtRegistryConfig {
+            tenant: TENANT.to_string(),
+            adoptable_addresses: vec![],
+            backend: OwnershipBackend::Txt,
+            prefix: DEFAULT_TXT_PREFIX.to_string(),
+            stale_after: Some(Duration::from_secs(3600)),
+            ownership_subdomain: None,
+            verify_aaaa_on_claim: false,
+            manage_wildcards: false,
+        }
+    }
+
+    fn ownership_txt(domain: &str, tenant: &str, age: Option<u64>) -> DnsRecord {
+        DnsRecord::new(
+            domain.to_string(),
+            RecordContent::Txt(match age {
+                Some(age) => timestamped_txt_record_string(
+                    DEFAULT_TXT_PREFIX,
+                    tenant,
+                    SystemTime::now() - Duration::from_secs(age),
+                ),
+                None => txt_record_string(DEFAULT_TXT_PREFIX, tenant),
+            }),
+        )
+    }
+
+    #[test]
+    fn takes_over_stale_ownership() {
+        let stale = ownership_txt("taken.example.com", "other_tenant", Some(7200));
+        let stale_content = match &stale.content {
+            RecordContent::Txt(txt) => txt.to_owned(),
+            _ => unreachable!(),
+        };
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(move || {
+            let mut records = records();
+            records.push(stale);
+            records.push(ownership_txt(
+                "other-owner.example.com",
+                "fresh_tenant",
+                Some(60),
+            ));
+            Ok(records)
+        });
+        mock.expect_create_txt_record()
+            .withf(|domain, content| {
+                domain == "taken.example.com"
+                    && content.starts_with(&txt_record_string(DEFAULT_TXT_PREFIX, TENANT))
+                    && content.contains("seen:")
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_delete_txt_record()
+            .withf(move |domain, content| {
+                domain == "taken.example.com" && *content == stale_content
+            })
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(stale_config(), provider_mock.as_ref()).unwrap();
+
+        // The stale owner no longer blocks the domain, despite its A records
+        assert!(rg
+            .available_domains()
+            .iter()
+            .any(|d| d.name == "taken.example.com"));
+        // Two live owners are still a conflict
+        assert!(rg
+            .taken_domains()
+            .iter()
+            .any(|d| d.name == "other-owner.example.com"));
+
+        rg.claim("taken.example.com").unwrap();
+        assert!(rg
+            .owned_domains()
+            .iter()
+            .any(|d| d.name == "taken.example.com"));
+    }
+
+    #[test]
+    fn never_considers_untimestamped_records_stale() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_config(stale_config(), provider_mock.as_ref()).unwrap();
+
+        assert_eq!(rg.owned_domains(), vec![owned_d()]);
+        assert!(rg.taken_domains().contains(&other_owner_d()));
+    }
+
+    #[test]
+    fn renews_old_ownership_on_claim() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            let mut records = records();
+            // Multiple records of the same owner are not a conflict
+            records.push(ownership_txt("available.example.com", TENANT, Some(2400)));
+            records.push(ownership_txt("available.example.com", TENANT, Some(3000)));
+            Ok(records)
+        });
+        mock.expect_create_txt_record()
+            .withf(|domain, content| domain == "available.example.com" && content.contains("seen:"))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        // Both outdated records are removed after renewing
+        mock.expect_delete_txt_record()
+            .withf(|domain, _| domain == "available.example.com")
+            .times(2)
+            .returning(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(stale_config(), provider_mock.as_ref()).unwrap();
+        assert_eq!(rg.owned_domains().len(), 2);
+
+        rg.claim("available.example.com").unwrap();
+        // The renewed record is fresh, claiming again does nothing
+        rg.claim("available.example.com").unwrap();
+        assert_eq!(rg.owned_domains().len(), 2);
+    }
+
+    #[test]
+    fn keeps_fresh_ownership_on_claim() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            let mut records = records();
+            records.push(ownership_txt("available.example.com", TENANT, Some(60)));
+            Ok(records)
+        });
+        // No expectations for create/delete_txt_record: calling them fails the test
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(stale_config(), provider_mock.as_ref()).unwrap();
+
+        rg.claim("available.example.com").unwrap();
+    }
+
+    #[test]
+    fn stores_ownership_in_subdomain() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| {
+            Ok(vec![
+                DnsRecord::new(
+                    "owned.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 1)),
+                ),
+                DnsRecord::new(
+                    "_clouddns.owned.example.com",
+                    RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                ),
+                // Ownership records directly on a domain are ignored
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::A(Ipv4Addr::new(10, 1, 1, 2)),
+                ),
+                DnsRecord::new(
+                    "taken.example.com",
+                    RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+                ),
+                DnsRecord::new(
+                    "available.example.com",
+                    RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+                ),
+            ])
+        });
+        mock.expect_create_txt_record()
+            .withf(|domain, _| domain == "_clouddns.available.example.com")
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_delete_txt_record()
+            .withf(|domain, _| domain == "_clouddns.owned.example.com")
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                tenant: TENANT.to_string(),
+                adoptable_addresses: vec![],
+                backend: OwnershipBackend::Txt,
+                prefix: DEFAULT_TXT_PREFIX.to_string(),
+                stale_after: None,
+                ownership_subdomain: Some("_clouddns".to_string()),
+                verify_aaaa_on_claim: false,
+                manage_wildcards: false,
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(rg.all_domains().len(), 3);
+        assert_eq!(rg.owned_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, "owned.example.com");
+        assert_eq!(rg.owned_domains()[0].a, vec![Ipv4Addr::new(10, 1, 1, 1)]);
+        assert!(rg
+            .taken_domains()
+            .iter()
+            .any(|d| d.name == "taken.example.com"));
+
+        rg.claim("available.example.com").unwrap();
+        rg.release("owned.example.com").unwrap();
+        assert_eq!(rg.owned_domains()[0].name, "available.example.com");
+    }
+
+    fn verifying_config() -> TxtRegistryConfig {
+        TxtRegistryConfig {
+            tenant: TENANT.to_string(),
+            adoptable_addresses: vec![],
+            backend: OwnershipBackend::Txt,
+            prefix: DEFAULT_TXT_PREFIX.to_string(),
+            stale_after: None,
+            ownership_subdomain: None,
+            verify_aaaa_on_claim: true,
+            manage_wildcards: false,
+        }
+    }
+
+    #[test]
+    fn refuses_claim_if_aaaa_vanished() {
+        let mut mock = MockProvider::new();
+        let mut seq = Sequence::new();
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| Ok(records()));
+        mock.expect_records()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|| {
+                Ok(records()
+                    .into_iter()
+                    .filter(|r| r.domain_name != available_d().name)
+                    .collect())
+            });
+        // No expectations for create_txt_record: calling it fails the test
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(verifying_config(), provider_mock.as_ref()).unwrap();
+
+        rg.claim(available_d().name.as_str()).unwrap_err();
+        assert_eq!(rg.owned_domains(), vec![owned_d()]);
+    }
+
+    #[test]
+    fn claims_if_aaaa_still_present() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().times(2).returning(|| Ok(records()));
+        mock.expect_create_txt_record().return_once(|_, _| Ok(()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let mut rg = TxtRegistry::from_config(verifying_config(), provider_mock.as_ref()).unwrap();
+
+        rg.claim(available_d().name.as_str()).unwrap();
+        assert_eq!(rg.owned_domains().len(), 2);
+    }
+
+    fn wildcard_records() -> Vec<DnsRecord> {
+        vec![
+            DnsRecord::new(
+                "*.example.com",
+                RecordContent::Aaaa(Ipv6Addr::new(0xfd42, 1, 1, 1, 1, 1, 1, 1)),
+            ),
+            DnsRecord::new(
+                "*.owned.example.com",
+                RecordContent::Txt(txt_record_string(DEFAULT_TXT_PREFIX, TENANT)),
+            ),
+        ]
+    }
+
+    #[test]
+    fn skips_wildcard_domains() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(wildcard_records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_provider(TENANT.to_string(), provider_mock.as_ref()).unwrap();
+
+        assert!(rg.owned_domains().is_empty());
+        assert!(rg.available_domains().is_empty());
+        assert_eq!(rg.taken_domains().len(), 2);
+    }
+
+    #[test]
+    fn manages_wildcard_domains_if_enabled() {
+        let mut mock = MockProvider::new();
+        mock.expect_records().return_once(|| Ok(wildcard_records()));
+        let provider_mock: Box<dyn Provider> = Box::new(mock);
+
+        let rg = TxtRegistry::from_config(
+            TxtRegistryConfig {
+                manage_wildcards: true,
+                verify_aaaa_on_claim: false,
+                ..verifying_config()
+            },
+            provider_mock.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(rg.owned_domains().len(), 1);
+        assert_eq!(rg.owned_domains()[0].name, "*.owned.example.com");
+        assert_eq!(rg.available_domains().len(), 1);
+        assert_eq!(rg.available_domains()[0].name, "*.example.com");
+    }
 }
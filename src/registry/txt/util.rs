@@ -1,24 +1,181 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use crate::{provider::DnsRecord, registry::Domain};
 
+/// Default prefix of ownership records, used unless configured otherwise
 pub const TXT_RECORD_IDENT: &str = "clouddns_nat";
 pub const TXT_RECORD_SEP: &str = ";";
-// Returns the TXT ownership record content for a given tenant
+/// Version of the ownership record format written by this version of the application
+pub const TXT_RECORD_VERSION: &str = "v1";
+const TXT_RECORD_OWNER_KEY: &str = "owner:";
+const TXT_RECORD_REC_KEY: &str = "rec:";
+const TXT_RECORD_SEEN_KEY: &str = "seen:";
+// Keys used by records written before the versioned format was introduced
+const LEGACY_TXT_RECORD_REC: &str = "rec: A";
+const LEGACY_TXT_RECORD_SEEN_KEY: &str = "seen: ";
+
+/// A parsed ownership record, see [`parse_txt_record()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnershipRecord {
+    /// The tenant that owns the domain
+    pub tenant: String,
+    /// When the owner last renewed this record, if it is timestamped
+    pub seen: Option<SystemTime>,
+    /// Whether the record uses the unversioned format used before `v1`
+    pub legacy: bool,
+}
+
+impl OwnershipRecord {
+    /// Whether this record belongs to the given tenant.
+    /// Legacy records were also written with the separator in the tenant replaced by `_`, so such records match as well
+    pub fn is_owned_by(&self, tenant: &str) -> bool {
+        self.tenant == tenant || (self.legacy && self.tenant == tenant.replace(TXT_RECORD_SEP, "_"))
+    }
+}
+
+// Returns the TXT ownership record content for a given prefix and tenant.
+// Records are structured as `<prefix>=<version>;owner:<tenant>;rec:A`, with the tenant percent-encoded so that it can't contain the separator.
 // Global function as we need to call it in new() before we can create our TxtRegistry
-pub fn txt_record_string(tenant: &str) -> String {
+pub fn txt_record_string(prefix: &str, tenant: &str) -> String {
     format!(
-        "{}_{}{}rec: A",
-        TXT_RECORD_IDENT,
-        tenant.replace(TXT_RECORD_SEP, "_"),
-        TXT_RECORD_SEP
+        "{}={}{}{}{}{}{}A",
+        prefix,
+        TXT_RECORD_VERSION,
+        TXT_RECORD_SEP,
+        TXT_RECORD_OWNER_KEY,
+        encode_tenant(tenant),
+        TXT_RECORD_SEP,
+        TXT_RECORD_REC_KEY,
     )
 }
 
+// Returns the TXT ownership record content for a given prefix and tenant, with an additional last-seen timestamp
+pub fn timestamped_txt_record_string(prefix: &str, tenant: &str, seen: SystemTime) -> String {
+    format!(
+        "{}{}{}{}",
+        txt_record_string(prefix, tenant),
+        TXT_RECORD_SEP,
+        TXT_RECORD_SEEN_KEY,
+        unix_secs(seen)
+    )
+}
+
+// Returns the ownership record content in the unversioned format used before `v1`: `<prefix>_<tenant>;rec: A[;seen: <secs>]`.
+// Such records are still recognized, but no longer created
+#[cfg(test)]
+pub fn legacy_txt_record_string(prefix: &str, tenant: &str, seen: Option<SystemTime>) -> String {
+    let record = format!(
+        "{}_{}{}{}",
+        prefix,
+        encode_tenant(tenant),
+        TXT_RECORD_SEP,
+        LEGACY_TXT_RECORD_REC
+    );
+    match seen {
+        Some(seen) => format!(
+            "{}{}{}{}",
+            record,
+            TXT_RECORD_SEP,
+            LEGACY_TXT_RECORD_SEEN_KEY,
+            unix_secs(seen)
+        ),
+        None => record,
+    }
+}
+
+/// Parse an ownership record with the given prefix, in either the current or the legacy format.
+/// Returns None if the record is not an ownership record or uses an unknown format version
+pub fn parse_txt_record(prefix: &str, record: &str) -> Option<OwnershipRecord> {
+    let rest = record.strip_prefix(prefix)?;
+    if let Some(fields) = rest.strip_prefix('=') {
+        parse_versioned(fields)
+    } else {
+        parse_legacy(rest.strip_prefix('_')?)
+    }
+}
+
+// `v1;owner:<tenant>;rec:A[;seen:<secs>]`
+fn parse_versioned(fields: &str) -> Option<OwnershipRecord> {
+    let mut fields = fields.split(TXT_RECORD_SEP);
+    if fields.next()? != TXT_RECORD_VERSION {
+        return None;
+    }
+    let tenant = fields.next()?.strip_prefix(TXT_RECORD_OWNER_KEY)?;
+    if fields.next()? != format!("{}A", TXT_RECORD_REC_KEY) {
+        return None;
+    }
+    let seen = match fields.next() {
+        Some(seen) => Some(parse_seen(seen.strip_prefix(TXT_RECORD_SEEN_KEY)?)?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(OwnershipRecord {
+        tenant: decode_tenant(tenant),
+        seen,
+        legacy: false,
+    })
+}
+
+// `<tenant>;rec: A[;seen: <secs>]`
+fn parse_legacy(fields: &str) -> Option<OwnershipRecord> {
+    let (fields, seen) = match fields.rsplit_once(TXT_RECORD_SEP) {
+        Some((plain, seen)) if seen.starts_with(LEGACY_TXT_RECORD_SEEN_KEY) => (
+            plain,
+            Some(parse_seen(seen.strip_prefix(LEGACY_TXT_RECORD_SEEN_KEY)?)?),
+        ),
+        _ => (fields, None),
+    };
+    let tenant = fields.strip_suffix(&format!("{}{}", TXT_RECORD_SEP, LEGACY_TXT_RECORD_REC))?;
+    Some(OwnershipRecord {
+        tenant: decode_tenant(tenant),
+        seen,
+        legacy: true,
+    })
+}
+
+// Returns the tenant that created an ownership record with the given prefix, or None if this is not an ownership record.
+// Accepts both plain and timestamped records, in the current and the legacy format
+#[cfg(test)]
+pub fn txt_record_owner(prefix: &str, record: &str) -> Option<String> {
+    parse_txt_record(prefix, record).map(|r| r.tenant)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn parse_seen(secs: &str) -> Option<SystemTime> {
+    Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?))
+}
+
+// Percent-encode the tenant name so that it can't contain the separator.
+// '%' is encoded as well to keep the encoding unambiguous, all other characters are kept as-is
+fn encode_tenant(tenant: &str) -> String {
+    tenant.replace('%', "%25").replace(TXT_RECORD_SEP, "%3B")
+}
+
+// Reverse of encode_tenant()
+fn decode_tenant(tenant: &str) -> String {
+    tenant.replace("%3B", TXT_RECORD_SEP).replace("%25", "%")
+}
+
 pub fn insert_rec_into_d(rec: &DnsRecord, d: &mut Domain) {
+    if let Some(comment) = &rec.comment {
+        if !d.comments.contains(comment) {
+            d.comments.push(comment.to_owned());
+        }
+    }
     match &rec.content {
         crate::provider::RecordContent::A(a) => {
             if !d.a.contains(a) {
                 d.a.push(a.to_owned());
             }
+            d.a_modified = d.a_modified.max(rec.modified_on);
+            d.a_ttl = d.a_ttl.max(rec.ttl);
         }
         crate::provider::RecordContent::Aaaa(aaaa) => {
             if !d.aaaa.contains(aaaa) {
@@ -30,5 +187,8 @@ pub fn insert_rec_into_d(rec: &DnsRecord, d: &mut Domain) {
                 d.txt.push(txt.to_owned());
             }
         }
+        crate::provider::RecordContent::Cname(cname) => {
+            d.cname = Some(cname.to_owned());
+        }
     }
 }